@@ -0,0 +1,116 @@
+extern crate criterion;
+extern crate fdt_rs;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fdt_rs::bench::Fixture;
+use fdt_rs::index::DevTreeIndex;
+use fdt_rs::modify::{ModifyTokenResponse, Serializer};
+use fdt_rs::prelude::*;
+
+fn fixtures() -> Vec<(&'static str, Fixture)> {
+    vec![
+        ("small", Fixture::small()),
+        ("medium", Fixture::medium()),
+        ("huge", Fixture::huge()),
+    ]
+}
+
+fn index_buf(fixture: &Fixture) -> Vec<u8> {
+    let layout = DevTreeIndex::get_layout(&fixture.devtree()).unwrap();
+    vec![0u8; layout.size() + layout.align()]
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, fixture) in fixtures() {
+        group.throughput(Throughput::Bytes(fixture.as_bytes().len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &fixture, |b, fixture| {
+            let fdt = fixture.devtree();
+            b.iter(|| fdt.items().count().unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn index(c: &mut Criterion) {
+    let mut group = c.benchmark_group("index");
+    for (name, fixture) in fixtures() {
+        group.throughput(Throughput::Bytes(fixture.as_bytes().len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &fixture, |b, fixture| {
+            let fdt = fixture.devtree();
+            let mut buf = index_buf(fixture);
+            b.iter(|| {
+                DevTreeIndex::new(fdt, &mut buf).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("search");
+    for (name, fixture) in fixtures() {
+        group.throughput(Throughput::Bytes(fixture.as_bytes().len() as u64));
+        group.bench_with_input(BenchmarkId::new("base", name), &fixture, |b, fixture| {
+            let fdt = fixture.devtree();
+            b.iter(|| {
+                fdt.compatible_nodes(Fixture::SEARCH_COMPATIBLE)
+                    .next()
+                    .unwrap()
+                    .unwrap()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("index", name), &fixture, |b, fixture| {
+            let mut buf = index_buf(fixture);
+            let index = DevTreeIndex::new(fixture.devtree(), &mut buf).unwrap();
+            b.iter(|| {
+                index
+                    .compatible_nodes(Fixture::SEARCH_COMPATIBLE)
+                    .next()
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn child_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("child_lookup");
+    for (name, fixture) in fixtures() {
+        let mut buf = index_buf(&fixture);
+        let index = DevTreeIndex::new(fixture.devtree(), &mut buf).unwrap();
+        // The last device of the last bus, the worst case for a linear scan.
+        let bus = index.root().children().last().unwrap();
+        let target = bus.children().last().unwrap().name().unwrap();
+        group.bench_with_input(BenchmarkId::new("linear", name), &target, |b, target| {
+            b.iter(|| {
+                bus.children()
+                    .find(|child| child.name().unwrap() == *target)
+                    .unwrap()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("sorted", name), &target, |b, target| {
+            b.iter(|| bus.child_by_name(target).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn modify(c: &mut Criterion) {
+    let mut group = c.benchmark_group("modify");
+    for (name, fixture) in fixtures() {
+        group.throughput(Throughput::Bytes(fixture.as_bytes().len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &fixture, |b, fixture| {
+            let fdt = fixture.devtree();
+            let mut out = vec![0u32; fixture.as_bytes().len() / 4 + 1];
+            let out = unsafe {
+                core::slice::from_raw_parts_mut(out.as_mut_ptr() as *mut u8, out.len() * 4)
+            };
+            b.iter(|| Serializer::modify(&fdt, out, |_| ModifyTokenResponse::Pass).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, index, search, child_lookup, modify);
+criterion_main!(benches);
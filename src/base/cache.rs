@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::string::{String, ToString};
+use std::sync::{OnceLock, PoisonError, RwLock};
+
+use crate::prelude::*;
+
+use crate::base::iters::DevTreeIter;
+use crate::base::{DevTree, DevTreeNode};
+use crate::error::{DevTreeError, Result};
+use crate::spec::Phandle;
+
+/// A [`DevTree`] which memoizes path and phandle lookups.
+///
+/// The base API re-parses the tree for every lookup. For long running programs which repeatedly
+/// query the same tree, this wrapper remembers the results so that repeated lookups take `O(1)`
+/// time, without building a [`DevTreeIndex`](crate::index::DevTreeIndex) by hand.
+///
+/// Lookups may be made from any number of threads at once.
+#[derive(Debug)]
+pub struct CachedDevTree<'dt> {
+    fdt: DevTree<'dt>,
+    /// The offset of each phandle's node, built by the first phandle lookup.
+    phandles: OnceLock<Result<HashMap<Phandle, usize>>>,
+    /// The offset of each node looked up by path, or `None` if there is no such node.
+    paths: RwLock<HashMap<String, Option<usize>>>,
+}
+
+impl<'dt> CachedDevTree<'dt> {
+    #[must_use]
+    pub fn new(fdt: DevTree<'dt>) -> Self {
+        Self {
+            fdt,
+            phandles: OnceLock::new(),
+            paths: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the wrapped [`DevTree`].
+    #[must_use]
+    pub fn fdt(&self) -> &DevTree<'dt> {
+        &self.fdt
+    }
+
+    /// Returns the node at the given absolute path (e.g. `/soc/uart@10000000`).
+    ///
+    /// As with libfdt, a path component without a unit address matches a node name with one.
+    pub fn node_by_path(&self, path: &str) -> Result<Option<DevTreeNode<'_, 'dt>>> {
+        let cached = self
+            .paths
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(path)
+            .copied();
+        let offset = match cached {
+            Some(offset) => offset,
+            None => {
+                let offset = self.fdt.node_offset_by_path(path)?;
+                self.paths
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(path.to_string(), offset);
+                offset
+            }
+        };
+        self.node_at(offset)
+    }
+
+    /// Returns the node whose `phandle` (or legacy `linux,phandle`) property matches the given
+    /// [`Phandle`].
+    ///
+    /// The first lookup indexes every phandle in the tree.
+    pub fn node_by_phandle(&self, phandle: Phandle) -> Result<Option<DevTreeNode<'_, 'dt>>> {
+        let phandles = self
+            .phandles
+            .get_or_init(|| find_phandles(&self.fdt))
+            .as_ref()
+            .map_err(|e| *e)?;
+        self.node_at(phandles.get(&phandle).copied())
+    }
+
+    fn node_at(&self, offset: Option<usize>) -> Result<Option<DevTreeNode<'_, 'dt>>> {
+        match offset {
+            Some(offset) => DevTreeIter::from_offset(&self.fdt, offset).next_node(),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Returns the offset of each node with a phandle. Where phandles are duplicated, the first
+/// node wins.
+fn find_phandles(fdt: &DevTree) -> Result<HashMap<Phandle, usize>> {
+    let mut phandles = HashMap::new();
+    let mut iter = fdt.items();
+    while let Some(prop) = iter.next_prop()? {
+        let name = prop.name()?;
+        if name == "phandle" || name == "linux,phandle" {
+            let offset = iter.node_offset().ok_or(DevTreeError::ParseError)?;
+            phandles.entry(prop.phandle(0)?).or_insert(offset);
+        }
+    }
+    Ok(phandles)
+}
@@ -1,6 +1,7 @@
 //! Iterative parsers of a [`DevTree`].
 use core::mem::size_of;
 use core::num::NonZeroUsize;
+use core::ptr::read_unaligned;
 use core::str::from_utf8;
 
 use crate::prelude::*;
@@ -8,6 +9,7 @@ use crate::prelude::*;
 use crate::base::parse::{next_devtree_token, ParsedTok};
 use crate::base::{DevTree, DevTreeItem, DevTreeNode, DevTreeProp};
 use crate::error::{DevTreeError, Result};
+use crate::name::stringlist_contains;
 use crate::spec::fdt_reserve_entry;
 
 // Re-export the basic parse iterator.
@@ -31,27 +33,24 @@ impl<'a, 'dt: 'a> DevTreeReserveEntryIter<'a, 'dt> {
         }
     }
 
-    /// Return the current offset as a fdt_reserve_entry reference.
+    /// Read the [`fdt_reserve_entry`] at the current offset.
     ///
-    /// # Safety
-    ///
-    /// The caller must verify that the current offset of this iterator is 32-bit aligned.
-    /// (Each field is 32-bit aligned and they may be read individually.)
-    unsafe fn read(&'a self) -> Result<&'dt fdt_reserve_entry> {
-        Ok(&*self.fdt.ptr_at(self.offset)?)
+    /// Reserve entries contain u64 fields, but the FDT only guarantees 32-bit alignment, so the
+    /// entry is copied out with an unaligned read.
+    fn read(&self) -> Result<fdt_reserve_entry> {
+        // Unsafe okay, ptr_at verified the entry fits within the buffer and read_unaligned
+        // doesn't require alignment.
+        unsafe { Ok(read_unaligned(self.fdt.ptr_at(self.offset)?)) }
     }
 }
 
 impl<'a, 'dt: 'a> Iterator for DevTreeReserveEntryIter<'a, 'dt> {
-    type Item = &'dt fdt_reserve_entry;
+    type Item = fdt_reserve_entry;
     fn next(&mut self) -> Option<Self::Item> {
         if self.offset > self.fdt.totalsize() {
             None
         } else {
-            // We guaruntee the read will be aligned to 32 bits because:
-            // - We construct with guarunteed 32-bit aligned offset
-            // - We always increment by an aligned amount
-            let ret = unsafe { self.read().unwrap() };
+            let ret = self.read().ok()?;
 
             if ret.address == 0.into() && ret.size == 0.into() {
                 return None;
@@ -121,6 +120,89 @@ impl<'s, 'a, 'dt: 'a> FallibleIterator for DevTreeCompatibleNodeIter<'s, 'a, 'dt
     }
 }
 
+/// An iterator over [`DevTreeNode`]s in depth first order, paired with their depth, see
+/// [`DevTree::nodes_with_depth`].
+///
+/// The root node is at depth 1.
+#[derive(Clone, PartialEq)]
+pub struct DevTreeDepthNodeIter<'a, 'dt: 'a> {
+    /// Offset of the next token to parse, or `None` once the first node has ended.
+    offset: Option<usize>,
+    /// Depth of the next token, relative to the first node's parent.
+    depth: usize,
+    fdt: &'a DevTree<'dt>,
+}
+
+impl<'a, 'dt: 'a> DevTreeDepthNodeIter<'a, 'dt> {
+    /// Create an iterator over the subtree of the node whose BeginNode token is at `offset`
+    /// (including the node itself), which is at depth 1.
+    pub(crate) fn from_offset(fdt: &'a DevTree<'dt>, offset: Option<usize>) -> Self {
+        Self {
+            offset,
+            depth: 0,
+            fdt,
+        }
+    }
+}
+
+impl<'a, 'dt: 'a> FallibleIterator for DevTreeDepthNodeIter<'a, 'dt> {
+    type Error = DevTreeError;
+    type Item = (usize, DevTreeNode<'a, 'dt>);
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        while let Some(offset) = self.offset {
+            let mut iter = DevTreeParseIter {
+                offset,
+                fdt: self.fdt,
+            };
+            let tok = iter.next()?;
+            self.offset = Some(iter.offset);
+            match tok {
+                Some(ParsedTok::BeginNode(_)) => {
+                    self.depth += 1;
+                    let node = DevTreeIter::from_offset(self.fdt, offset).next_node()?;
+                    return Ok(node.map(|node| (self.depth, node)));
+                }
+                Some(ParsedTok::EndNode) => {
+                    self.depth = self.depth.checked_sub(1).ok_or(DevTreeError::ParseError)?;
+                    if self.depth == 0 {
+                        self.offset = None;
+                    }
+                }
+                Some(_) => (),
+                None => return Err(DevTreeError::ParseError),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// An iterator over the immediate children of a [`DevTreeNode`], see
+/// [`DevTreeNode::children`].
+#[derive(Clone, PartialEq)]
+pub struct DevTreeNodeChildIter<'a, 'dt: 'a>(DevTreeDepthNodeIter<'a, 'dt>);
+
+impl<'a, 'dt: 'a> DevTreeNodeChildIter<'a, 'dt> {
+    /// Create an iterator over the children of the node whose BeginNode token is at `offset`.
+    pub(crate) fn new(fdt: &'a DevTree<'dt>, offset: Option<usize>) -> Self {
+        Self(DevTreeDepthNodeIter::from_offset(fdt, offset))
+    }
+}
+
+impl<'a, 'dt: 'a> FallibleIterator for DevTreeNodeChildIter<'a, 'dt> {
+    type Error = DevTreeError;
+    type Item = DevTreeNode<'a, 'dt>;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        while let Some((depth, node)) = self.0.next()? {
+            if depth == 2 {
+                return Ok(Some(node));
+            }
+        }
+        Ok(None)
+    }
+}
+
 impl<'a, 'dt: 'a> DevTreeIter<'a, 'dt> {
     pub fn new(fdt: &'a DevTree<'dt>) -> Self {
         Self {
@@ -130,6 +212,21 @@ impl<'a, 'dt: 'a> DevTreeIter<'a, 'dt> {
         }
     }
 
+    /// Create an iterator which begins parsing at the given offset of a token within the
+    /// structure block.
+    pub(crate) fn from_offset(fdt: &'a DevTree<'dt>, offset: usize) -> Self {
+        Self {
+            offset,
+            current_prop_parent_off: None,
+            fdt,
+        }
+    }
+
+    /// Returns the offset of the last opened node's BeginNode token.
+    pub(crate) fn node_offset(&self) -> Option<usize> {
+        self.current_prop_parent_off.map(NonZeroUsize::get)
+    }
+
     fn current_node_itr(&self) -> Option<DevTreeIter<'a, 'dt>> {
         self.current_prop_parent_off.map(|offset| DevTreeIter {
             fdt: self.fdt,
@@ -150,7 +247,8 @@ impl<'a, 'dt: 'a> DevTreeIter<'a, 'dt> {
         loop {
             let old_offset = self.offset;
             // Safe because we only pass offsets which are returned by next_devtree_token.
-            let res = unsafe { next_devtree_token(self.fdt.buf(), &mut self.offset)? };
+            let res = unsafe { next_devtree_token(self.fdt.buf(), &mut self.offset) }
+                .map_err(|err| self.fdt.truncated_or(err))?;
 
             match res {
                 Some(ParsedTok::BeginNode(node)) => {
@@ -224,7 +322,7 @@ impl<'a, 'dt: 'a> DevTreeIter<'a, 'dt> {
             loop {
                 match self.next_prop() {
                     Ok(Some(prop)) => {
-                        if prop.name()? == "compatible" && prop.str()? == string {
+                        if prop.name()? == "compatible" && stringlist_contains(prop.raw(), string) {
                             return Ok(Some(prop.node()));
                         }
                         continue;
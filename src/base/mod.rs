@@ -62,6 +62,9 @@
 //! }
 //! ```
 
+#[cfg(feature = "std")]
+#[doc(hidden)]
+pub mod cache;
 #[doc(hidden)]
 pub mod item;
 #[doc(hidden)]
@@ -74,6 +77,9 @@ pub mod tree;
 pub mod iters;
 pub mod parse;
 
+#[cfg(feature = "std")]
+#[doc(inline)]
+pub use cache::*;
 #[doc(inline)]
 pub use item::*;
 #[doc(inline)]
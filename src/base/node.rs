@@ -1,8 +1,16 @@
+use core::ops::Range;
+use core::str::from_utf8;
+
 #[cfg(doc)]
 use super::*;
 
-use crate::base::iters::{DevTreeIter, DevTreeNodePropIter};
-use crate::error::Result;
+use crate::base::iters::{DevTreeIter, DevTreeNodeChildIter, DevTreeNodePropIter};
+use crate::base::parse::{DevTreeParseIter, ParsedTok};
+use crate::base::{DevTree, DevTreeProp};
+use crate::error::{DevTreeError, Result};
+use crate::name;
+use crate::prelude::*;
+use crate::scratch::ScratchArena;
 
 /// A handle to a Device Tree Node within the device tree.
 #[derive(Clone)]
@@ -20,16 +28,198 @@ impl<'a, 'dt: 'a> PartialEq for DevTreeNode<'a, 'dt> {
 impl<'a, 'dt: 'a> DevTreeNode<'a, 'dt> {
     /// Returns the name of the `DevTreeNode` (including unit address tag)
     #[inline]
-    pub fn name(&'a self) -> Result<&'dt str> {
+    pub fn name(&self) -> Result<&'dt str> {
         self.name
     }
 
+    /// Returns the base name of this node, the part of its name before any `@` (e.g. `uart` of
+    /// `uart@10000000`), see [`base_name`](crate::name::base_name).
+    pub fn name_only(&self) -> Result<&'dt str> {
+        Ok(from_utf8(name::base_name(self.name()?.as_bytes()))?)
+    }
+
+    /// Returns the unit address of this node, the part of its name after the first `@` (e.g.
+    /// `10000000` of `uart@10000000`), or `None` if its name has none.
+    pub fn unit_address(&self) -> Result<Option<&'dt str>> {
+        match name::unit_address(self.name()?.as_bytes()) {
+            Some(address) => Ok(Some(from_utf8(address)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns whether this node's name matches `pattern`, with or without its unit address
+    /// (e.g. `uart` and `uart@10000000` both match `uart@10000000`), see
+    /// [`node_name_matches`](crate::name::node_name_matches).
+    pub fn name_matches(&self, pattern: &str) -> Result<bool> {
+        Ok(name::node_name_matches(self.name()?.as_bytes(), pattern))
+    }
+
     /// Returns an iterator over this node's children [`DevTreeProp`]
     #[must_use]
-    pub fn props(&'a self) -> DevTreeNodePropIter<'a, 'dt> {
+    pub fn props(&self) -> DevTreeNodePropIter<'a, 'dt> {
         DevTreeNodePropIter(self.parse_iter.clone())
     }
 
+    /// Returns an iterator over this node's immediate children, not their descendants.
+    #[must_use]
+    pub fn children(&self) -> DevTreeNodeChildIter<'a, 'dt> {
+        DevTreeNodeChildIter::new(self.fdt(), self.parse_iter.node_offset())
+    }
+
+    /// Returns this node's [`DevTreeProp`] with the given name (if it exists).
+    pub(crate) fn find_prop(&self, name: &str) -> Result<Option<DevTreeProp<'a, 'dt>>> {
+        self.props().find(|prop| Ok(prop.name()? == name))
+    }
+
+    /// Returns the parent of this node, or `None` if this is the root node.
+    ///
+    /// The base API has no back-references, so this re-parses the tree from its start. To walk
+    /// upward often (e.g. to find `#address-cells` or `interrupt-parent` for many nodes), build a
+    /// [`DevTreeIndex`](crate::index::DevTreeIndex), whose nodes link to their parents.
+    pub fn parent(&self) -> Result<Option<DevTreeNode<'a, 'dt>>> {
+        let target = match self.parse_iter.node_offset() {
+            Some(off) => off,
+            None => return Ok(None),
+        };
+        let fdt = self.fdt();
+
+        let depth = walk_begin_nodes(fdt, target, |_, _| ())?;
+        if depth == 1 {
+            return Ok(None);
+        }
+
+        // Our parent is the last node opened at the depth above us.
+        let mut parent_off = None;
+        walk_begin_nodes(fdt, target, |d, off| {
+            if d == depth - 1 {
+                parent_off = Some(off);
+            }
+        })?;
+        match parent_off {
+            Some(off) => DevTreeIter::from_offset(fdt, off).next_node(),
+            None => Err(DevTreeError::ParseError),
+        }
+    }
+
+    /// Returns the full path of this node (e.g. `/soc/uart@10000000`), built in `scratch`.
+    ///
+    /// As [`DevTreeNode::get_path`], but allocates exactly the path's length from `scratch`.
+    /// Returns [`DevTreeError::NotEnoughMemory`] if the path doesn't fit.
+    pub fn path<'s>(&self, scratch: &mut ScratchArena<'s>) -> Result<&'s str> {
+        let len = match self.get_path(&mut []) {
+            Err(DevTreeError::OutputBufferTooSmall { needed, .. }) => needed,
+            Err(err) => return Err(err),
+            // Every path needs at least its separator.
+            Ok(_) => return Err(DevTreeError::ParseError),
+        };
+        self.get_path(scratch.alloc_bytes(len)?)
+    }
+
+    /// Returns the full path of this node (e.g. `/soc/uart@10000000`), built in `buf`, as
+    /// libfdt's `fdt_get_path` does.
+    ///
+    /// The base API has no back-references, so this re-parses the tree from its start. Returns
+    /// [`DevTreeError::OutputBufferTooSmall`] with the length of the path if it doesn't fit.
+    pub fn get_path<'b>(&self, buf: &'b mut [u8]) -> Result<&'b str> {
+        let target = self
+            .parse_iter
+            .node_offset()
+            .ok_or(DevTreeError::ParseError)?;
+        let mut iter = self.fdt().parse_iter();
+        // The depth of the current node, and of the last node whose path fits in `buf`.
+        let mut depth = 0usize;
+        let mut path_depth = 0;
+        let mut len = 0;
+        loop {
+            let off = iter.offset;
+            match iter.next()? {
+                Some(ParsedTok::BeginNode(node)) => {
+                    depth += 1;
+                    if path_depth == depth - 1 {
+                        let name = node.name;
+                        // The root's name is empty, and has no separator of its own.
+                        if depth == 1 {
+                            path_depth += 1;
+                        } else if len + 1 + name.len() <= buf.len() {
+                            buf[len] = b'/';
+                            buf[len + 1..len + 1 + name.len()].copy_from_slice(name);
+                            len += 1 + name.len();
+                            path_depth += 1;
+                        }
+                    }
+                    if off == target {
+                        break;
+                    }
+                }
+                Some(ParsedTok::EndNode) => {
+                    if path_depth == depth {
+                        len = buf[..len].iter().rposition(|&c| c == b'/').unwrap_or(0);
+                        path_depth -= 1;
+                    }
+                    depth = depth.checked_sub(1).ok_or(DevTreeError::ParseError)?;
+                }
+                Some(_) => (),
+                None => return Err(DevTreeError::ParseError),
+            }
+        }
+
+        if path_depth == depth && (len > 0 || !buf.is_empty()) {
+            // The root's path is just the separator.
+            if len == 0 {
+                buf[0] = b'/';
+                len = 1;
+            }
+            return Ok(from_utf8(&buf[..len])?);
+        }
+        // Sum the lengths of the components of the path which didn't fit.
+        let mut needed = 0;
+        let mut node = self.clone();
+        while let Some(parent) = node.parent()? {
+            needed += 1 + node.name()?.len();
+            node = parent;
+        }
+        Err(DevTreeError::OutputBufferTooSmall {
+            needed: needed.max(1),
+            available: buf.len(),
+        })
+    }
+
+    /// Returns the range of [`DevTree::buf`] covering this node in the structure block, from its
+    /// BeginNode token up to and including its matching EndNode token.
+    ///
+    /// The range covers all of the node's properties and its entire subtree.
+    pub fn byte_range(&self) -> Result<Range<usize>> {
+        let start = self
+            .parse_iter
+            .node_offset()
+            .ok_or(DevTreeError::ParseError)?;
+        let mut iter = DevTreeParseIter {
+            offset: start,
+            fdt: self.fdt(),
+        };
+        let mut depth = 0usize;
+        while let Some(tok) = iter.next()? {
+            match tok {
+                ParsedTok::BeginNode(_) => depth += 1,
+                ParsedTok::EndNode => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(start..iter.offset);
+                    }
+                }
+                _ => (),
+            }
+        }
+        Err(DevTreeError::ParseError)
+    }
+
+    /// Returns the [`DevTree`] which contains this node.
+    #[inline]
+    #[must_use]
+    pub fn fdt(&self) -> &'a DevTree<'dt> {
+        self.parse_iter.fdt
+    }
+
     /// Returns the next [`DevTreeNode`] object with the provided compatible device tree property
     /// or `None` if none exists.
     ///
@@ -43,3 +233,32 @@ impl<'a, 'dt: 'a> DevTreeNode<'a, 'dt> {
         self.parse_iter.clone().next_compatible_node(string)
     }
 }
+
+/// Walk the structure block up to the BeginNode token at offset `until`, calling `f` with the
+/// depth and offset of every BeginNode token before it.
+///
+/// Returns the depth of the node at `until`. The root node is at depth 1.
+fn walk_begin_nodes<F>(fdt: &DevTree, until: usize, mut f: F) -> Result<usize>
+where
+    F: FnMut(usize, usize),
+{
+    let mut iter = fdt.parse_iter();
+    let mut depth = 0usize;
+    loop {
+        let off = iter.offset;
+        match iter.next()? {
+            Some(ParsedTok::BeginNode(_)) => {
+                depth += 1;
+                if off == until {
+                    return Ok(depth);
+                }
+                f(depth, off);
+            }
+            Some(ParsedTok::EndNode) => {
+                depth = depth.checked_sub(1).ok_or(DevTreeError::ParseError)?;
+            }
+            Some(_) => (),
+            None => return Err(DevTreeError::ParseError),
+        }
+    }
+}
@@ -32,10 +32,10 @@ pub unsafe fn next_devtree_token<'a>(
     buf: &'a [u8],
     off: &mut usize,
 ) -> Result<Option<ParsedTok<'a>>> {
-    // These are guaranteed.
-    // We only produce associated offsets that are aligned to 32 bits and within the buffer.
-    debug_assert!(buf.as_ptr().add(*off) as usize % size_of::<u32>() == 0);
-    debug_assert!(buf.len() > (*off + size_of::<u32>()));
+    // This is guaranteed.
+    // We only produce associated offsets that are aligned to 32 bits. Reads past the end of the
+    // buffer (e.g. of a truncated tree) return an error.
+    debug_assert!((buf.as_ptr().add(*off) as usize).is_multiple_of(size_of::<u32>()));
 
     let fdt_tok_val = buf.unsafe_read_be_u32(*off)?;
     *off += size_of::<u32>();
@@ -82,7 +82,6 @@ pub unsafe fn next_devtree_token<'a>(
             if name_offset > buf.len() {
                 return Err(DevTreeError::ParseError);
             }
-            let name_offset = name_offset;
 
             Ok(Some(ParsedTok::Prop(ParsedProp {
                 prop_buf,
@@ -154,5 +153,6 @@ impl<'dt, 'a: 'dt> FallibleIterator for DevTreeParseIter<'dt, 'a> {
         // Safe because we're passing an unmodified (by us) offset.
         // next_devtree_token guaruntees alignment and out-of-bounds won't occur.
         unsafe { next_devtree_token(self.fdt.buf(), &mut self.offset) }
+            .map_err(|err| self.fdt.truncated_or(err))
     }
 }
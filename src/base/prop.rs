@@ -41,7 +41,6 @@ impl<'r, 'dt: 'r> PropReader<'dt> for DevTreeProp<'r, 'dt> {
     }
 
     /// Returns the node which this property is attached to
-    #[must_use]
     fn node(&self) -> DevTreeNode<'r, 'dt> {
         unsafe {
             // Unsafe unwrap okay.
@@ -1,27 +1,28 @@
 #[cfg(doc)]
-use crate::base::parse::ParsedTok;
-#[cfg(doc)]
 use crate::base::*;
 
 use core::mem::size_of;
 use core::ptr;
 use core::slice;
 
+use crate::base::parse::ParsedTok;
 use crate::error::{DevTreeError, Result};
+use crate::name::{node_name_eq, node_name_matches};
 
+use crate::prelude::*;
 use crate::priv_util::SliceRead;
-use crate::spec::{fdt_header, FDT_MAGIC};
+use crate::spec::{fdt_header, Phandle, FDT_MAGIC};
 
 use fallible_iterator::FallibleIterator;
 
 use super::iters::{
-    DevTreeCompatibleNodeIter, DevTreeIter, DevTreeNodeIter, DevTreeParseIter, DevTreePropIter,
-    DevTreeReserveEntryIter,
+    DevTreeCompatibleNodeIter, DevTreeDepthNodeIter, DevTreeIter, DevTreeNodeIter,
+    DevTreeParseIter, DevTreePropIter, DevTreeReserveEntryIter,
 };
 use super::DevTreeNode;
 
 const fn is_aligned<T>(offset: usize) -> bool {
-    offset % size_of::<T>() == 0
+    offset.is_multiple_of(size_of::<T>())
 }
 
 const fn verify_offset_aligned<T>(offset: usize) -> Result<usize> {
@@ -80,7 +81,8 @@ impl<'dt> DevTree<'dt> {
     /// read to take place, the provided buffer must be at least [`Self::MIN_HEADER_SIZE`] long.
     ///
     /// Once known, the user should resize the raw byte slice to this function's return value and
-    /// pass that slice to [`DevTree::new()`].
+    /// pass that slice to [`DevTree::new()`]. Returns [`DevTreeError::ParseError`] if the
+    /// `totalsize` is too small to hold the header.
     ///
     /// # Example
     ///
@@ -102,7 +104,11 @@ impl<'dt> DevTree<'dt> {
 
         // Verify provided buffer magic
         Self::verify_magic(buf)?;
-        Ok(get_be32_field!(totalsize, fdt_header, buf)? as usize)
+        let totalsize = get_be32_field!(totalsize, fdt_header, buf)? as usize;
+        if totalsize < Self::MIN_HEADER_SIZE {
+            return Err(DevTreeError::ParseError);
+        }
+        Ok(totalsize)
     }
 
     /// Construct the parseable DevTree object from the provided byte slice without any check. This
@@ -140,6 +146,55 @@ impl<'dt> DevTree<'dt> {
         }
     }
 
+    /// As [`DevTree::new`], but returns [`DevTreeError::Truncated`] with the tree's
+    /// `totalsize` if the buffer is shorter, so the caller can fetch or map the rest and try
+    /// again.
+    ///
+    /// # Safety
+    ///
+    /// Callers of this method the must guarantee the following:
+    ///
+    /// - The passed buffer is 32-bit aligned.
+    /// - The passed buffer is of at least [`DevTree::MIN_HEADER_SIZE`] bytes in length
+    #[inline]
+    pub unsafe fn new_complete(buf: &'dt [u8]) -> Result<Self> {
+        let totalsize = Self::read_totalsize(buf)?;
+        if totalsize > buf.len() {
+            return Err(DevTreeError::Truncated {
+                needed: totalsize,
+                available: buf.len(),
+            });
+        }
+        Self::new(&buf[..totalsize])
+    }
+
+    /// Construct a DevTree from a buffer which may hold only the start of the tree (e.g. when
+    /// only its first page has been mapped so far).
+    ///
+    /// The header is always available, and the blocks of the tree which fit in the buffer may be
+    /// used as usual. [`DevTree::has_struct_block`] and [`DevTree::has_strings_block`] tell
+    /// which do. Parsing past the end of the buffer returns [`DevTreeError::Truncated`], and the
+    /// memory reservation entries end early where the buffer does.
+    ///
+    /// Returns [`DevTreeError::Truncated`] if the buffer doesn't hold the whole header.
+    ///
+    /// # Safety
+    ///
+    /// Callers of this method the must guarantee the following:
+    ///
+    /// - The passed buffer is 32-bit aligned.
+    #[inline]
+    pub unsafe fn new_partial(buf: &'dt [u8]) -> Result<Self> {
+        if buf.len() < Self::MIN_HEADER_SIZE {
+            return Err(DevTreeError::Truncated {
+                needed: Self::MIN_HEADER_SIZE,
+                available: buf.len(),
+            });
+        }
+        let totalsize = Self::read_totalsize(buf)?;
+        Self::from_safe_slice(&buf[..totalsize.min(buf.len())])
+    }
+
     /// Construct the parseable DevTree object from a raw byte pointer
     ///
     /// # Safety
@@ -161,70 +216,155 @@ impl<'dt> DevTree<'dt> {
     #[inline]
     #[must_use]
     pub fn totalsize(&self) -> usize {
-        unsafe { get_be32_field!(totalsize, fdt_header, self.buf).unwrap() as usize }
+        get_be32_field!(totalsize, fdt_header, self.buf).unwrap() as usize
     }
 
     /// Returns the rsvmap offset field of the Device Tree
     #[inline]
     #[must_use]
     pub fn off_mem_rsvmap(&self) -> usize {
-        unsafe { get_be32_field!(off_mem_rsvmap, fdt_header, self.buf).unwrap() as usize }
+        get_be32_field!(off_mem_rsvmap, fdt_header, self.buf).unwrap() as usize
     }
 
     /// Returns the dt_struct offset field of the Device Tree
     #[inline]
     #[must_use]
     pub fn off_dt_struct(&self) -> usize {
-        unsafe { get_be32_field!(off_dt_struct, fdt_header, self.buf).unwrap() as usize }
+        get_be32_field!(off_dt_struct, fdt_header, self.buf).unwrap() as usize
     }
 
     /// Returns the dt_strings offset field of the Device Tree
     #[inline]
     #[must_use]
     pub fn off_dt_strings(&self) -> usize {
-        unsafe { get_be32_field!(off_dt_strings, fdt_header, self.buf).unwrap() as usize }
+        get_be32_field!(off_dt_strings, fdt_header, self.buf).unwrap() as usize
     }
 
     /// Returns the magic field of the Device Tree
     #[inline]
     #[must_use]
     pub fn magic(&self) -> u32 {
-        unsafe { get_be32_field!(magic, fdt_header, self.buf).unwrap() }
+        get_be32_field!(magic, fdt_header, self.buf).unwrap()
     }
 
     /// Returns the version field of the Device Tree
     #[inline]
     #[must_use]
     pub fn version(&self) -> u32 {
-        unsafe { get_be32_field!(version, fdt_header, self.buf).unwrap() }
+        get_be32_field!(version, fdt_header, self.buf).unwrap()
     }
 
     /// Returns the boot_cpuid_phys field of the Device Tree
     #[inline]
     #[must_use]
     pub fn boot_cpuid_phys(&self) -> u32 {
-        unsafe { get_be32_field!(boot_cpuid_phys, fdt_header, self.buf).unwrap() }
+        get_be32_field!(boot_cpuid_phys, fdt_header, self.buf).unwrap()
     }
 
     /// Returns the last_comp_version field of the Device Tree
     #[inline]
     #[must_use]
     pub fn last_comp_version(&self) -> u32 {
-        unsafe { get_be32_field!(last_comp_version, fdt_header, self.buf).unwrap() }
+        get_be32_field!(last_comp_version, fdt_header, self.buf).unwrap()
     }
 
     /// Returns the size_dt_strings field of the Device Tree
     #[inline]
     #[must_use]
     pub fn size_dt_strings(&self) -> u32 {
-        unsafe { get_be32_field!(size_dt_strings, fdt_header, self.buf).unwrap() }
+        get_be32_field!(size_dt_strings, fdt_header, self.buf).unwrap()
     }
 
     /// Returns the size_dt_struct field of the Device Tree
     #[inline]
     #[must_use]
     pub fn size_dt_struct(&self) -> u32 {
-        unsafe { get_be32_field!(size_dt_struct, fdt_header, self.buf).unwrap() }
+        get_be32_field!(size_dt_struct, fdt_header, self.buf).unwrap()
+    }
+
+    /// Copy the tree into `buf` (e.g. out of memory a later boot stage will overwrite),
+    /// returning a DevTree over the copy.
+    ///
+    /// `buf` must be 32-bit aligned, else [`DevTreeError::InvalidParameter`] is returned, and at
+    /// least the tree's `totalsize` long, else [`DevTreeError::OutputBufferTooSmall`] is. A tree
+    /// which isn't [complete](DevTree::is_complete) can't be copied and returns
+    /// [`DevTreeError::Truncated`].
+    pub fn copy_to<'b>(&self, buf: &'b mut [u8]) -> Result<DevTree<'b>> {
+        let totalsize = self.totalsize();
+        if !self.is_complete() {
+            return Err(DevTreeError::Truncated {
+                needed: totalsize,
+                available: self.buf.len(),
+            });
+        }
+        verify_offset_aligned::<u32>(buf.as_ptr() as usize)
+            .map_err(|_| DevTreeError::InvalidParameter("Unaligned buffer provided"))?;
+        let available = buf.len();
+        let dest = buf
+            .get_mut(..totalsize)
+            .ok_or(DevTreeError::OutputBufferTooSmall {
+                needed: totalsize,
+                available,
+            })?;
+        dest.copy_from_slice(&self.buf[..totalsize]);
+        // Safe as the copy is aligned and exactly the tree's totalsize.
+        unsafe { DevTree::new(dest) }
+    }
+
+    /// Returns whether the buffer holds the whole tree, up to its `totalsize`. Only trees
+    /// constructed with [`DevTree::new_partial`] (or from a buffer shorter than their
+    /// `totalsize`) may not.
+    #[inline]
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.buf.len() >= self.totalsize()
+    }
+
+    /// Returns whether the buffer holds the whole structure block, so that nodes and properties
+    /// (but not necessarily their names) may be parsed.
+    ///
+    /// The size of the structure block is only recorded by version 17 trees and later, so for
+    /// older trees this is whether the tree is complete.
+    #[must_use]
+    pub fn has_struct_block(&self) -> bool {
+        if self.version() < 17 {
+            return self.is_complete();
+        }
+        self.off_dt_struct() + self.size_dt_struct() as usize <= self.buf.len()
+    }
+
+    /// Returns whether the buffer holds the whole strings block, which property names are read
+    /// from.
+    #[must_use]
+    pub fn has_strings_block(&self) -> bool {
+        self.off_dt_strings() + self.size_dt_strings() as usize <= self.buf.len()
+    }
+
+    /// Returns `err`, or [`DevTreeError::Truncated`] if the tree isn't complete, for errors from
+    /// reads which may have gone past the end of the buffer.
+    #[inline]
+    pub(crate) fn truncated_or(&self, err: DevTreeError) -> DevTreeError {
+        match self.is_complete() {
+            true => err,
+            false => DevTreeError::Truncated {
+                needed: self.totalsize(),
+                available: self.buf.len(),
+            },
+        }
+    }
+
+    /// Returns the bytes after the structure and strings blocks, up to the tree's `totalsize`.
+    ///
+    /// This is usually empty, or free space left by `dtc -p`. Some toolchains keep vendor data
+    /// there, which [`TrailingData`](crate::modify::TrailingData) can carry into a modified
+    /// copy of the tree.
+    #[must_use]
+    pub fn trailing_data(&self) -> &'dt [u8] {
+        let struct_end = self.off_dt_struct() + self.size_dt_struct() as usize;
+        let strings_end = self.off_dt_strings() + self.size_dt_strings() as usize;
+        self.buf
+            .get(struct_end.max(strings_end)..self.totalsize())
+            .unwrap_or(&[])
     }
 
     /// Returns a typed `*const T` to the given offset in the Device Tree buffer.
@@ -243,9 +383,16 @@ impl<'dt> DevTree<'dt> {
         }
     }
 
+    /// Returns a copy of the header, e.g. to hand it to another component as it is laid out in
+    /// the tree.
+    #[must_use]
+    pub fn header(&self) -> fdt_header {
+        fdt_header::from_bytes(self.buf).unwrap()
+    }
+
     /// Returns an iterator over the Dev Tree "5.3 Memory Reservation Blocks"
     #[must_use]
-    pub fn reserved_entries(&self) -> DevTreeReserveEntryIter {
+    pub fn reserved_entries(&self) -> DevTreeReserveEntryIter<'_, 'dt> {
         DevTreeReserveEntryIter::new(self)
     }
 
@@ -254,6 +401,13 @@ impl<'dt> DevTree<'dt> {
         DevTreeNodeIter(DevTreeIter::new(self))
     }
 
+    /// Returns an iterator over [`DevTreeNode`] objects in the same order as
+    /// [`DevTree::nodes`], paired with their depth. The root node is at depth 1.
+    #[must_use]
+    pub fn nodes_with_depth(&self) -> DevTreeDepthNodeIter<'_, 'dt> {
+        DevTreeDepthNodeIter::from_offset(self, Some(self.off_dt_struct()))
+    }
+
     #[must_use]
     pub fn props(&self) -> DevTreePropIter<'_, 'dt> {
         DevTreePropIter(DevTreeIter::new(self))
@@ -270,8 +424,8 @@ impl<'dt> DevTree<'dt> {
         DevTreeParseIter::new(self)
     }
 
-    /// Returns the first [`DevTreeNode`] object with the provided compatible device tree property
-    /// or `None` if none exists.
+    /// Returns an iterator over the [`DevTreeNode`]s whose `compatible` string list contains
+    /// `string`, see [`stringlist_contains`](crate::name::stringlist_contains).
     pub fn compatible_nodes<'s, 'a: 's>(
         &'a self,
         string: &'s str,
@@ -290,4 +444,123 @@ impl<'dt> DevTree<'dt> {
     pub fn root(&self) -> Result<Option<DevTreeNode<'_, 'dt>>> {
         self.nodes().next()
     }
+
+    /// Returns the largest phandle defined by a `phandle` (or legacy `linux,phandle`) property,
+    /// or 0 if there are none.
+    ///
+    /// New nodes may use phandles above it without colliding, see
+    /// [`PhandleAllocator`](crate::modify::PhandleAllocator).
+    pub fn max_phandle(&self) -> Result<Phandle> {
+        let mut max = 0;
+        let mut iter = self.props();
+        while let Some(prop) = iter.next()? {
+            let name = prop.name()?;
+            if name == "phandle" || name == "linux,phandle" {
+                max = max.max(prop.phandle(0)?);
+            }
+        }
+        Ok(max)
+    }
+
+    /// Returns the [`DevTreeNode`] whose `phandle` (or legacy `linux,phandle`) property matches
+    /// the given [`Phandle`], or `None` if no node has it.
+    ///
+    /// This resolves references such as `interrupt-parent`, `clocks` and `dmas`. Each lookup
+    /// scans the tree from its start. Where phandles are duplicated, the first node wins.
+    pub fn node_by_phandle(&self, phandle: Phandle) -> Result<Option<DevTreeNode<'_, 'dt>>> {
+        let mut iter = self.props();
+        while let Some(prop) = iter.next()? {
+            let name = prop.name()?;
+            if (name == "phandle" || name == "linux,phandle") && prop.phandle(0)? == phandle {
+                return Ok(Some(prop.node()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the [`DevTreeNode`] at the given absolute path (e.g. `/soc/uart@10000000`), or
+    /// `None` if there's no such node.
+    ///
+    /// As with libfdt, a path component without a unit address (e.g. `/soc/uart`) matches a
+    /// node name with one. Where several nodes match, the first in tree order is returned.
+    ///
+    /// Returns [`DevTreeError::InvalidParameter`] if the path isn't absolute.
+    pub fn node_at_path(&self, path: &str) -> Result<Option<DevTreeNode<'_, 'dt>>> {
+        match self.node_offset_by_path(path)? {
+            Some(offset) => DevTreeIter::from_offset(self, offset).next_node(),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the offset of the node at the given absolute path.
+    ///
+    /// As with libfdt, a path component without a unit address matches a node name with one.
+    pub(crate) fn node_offset_by_path(&self, path: &str) -> Result<Option<usize>> {
+        self.find_path(path, node_name_matches)
+    }
+
+    /// As [`DevTree::node_offset_by_path`], but node names must match path components exactly,
+    /// including their unit addresses.
+    pub(crate) fn node_offset_by_exact_path(&self, path: &str) -> Result<Option<usize>> {
+        self.find_path(path, |name, component| {
+            node_name_eq(name, component.as_bytes())
+        })
+    }
+
+    /// As [`DevTree::node_offset_by_path`], but the path is given as its components, which may
+    /// be empty (e.g. from splitting an absolute path at each `/`).
+    pub(crate) fn node_offset_by_components<'p>(
+        &self,
+        components: impl Iterator<Item = &'p str>,
+    ) -> Result<Option<usize>> {
+        self.find_components(components, node_name_matches)
+    }
+
+    fn find_path(&self, path: &str, matches: fn(&[u8], &str) -> bool) -> Result<Option<usize>> {
+        if !path.starts_with('/') {
+            return Err(DevTreeError::InvalidParameter("Path must be absolute"));
+        }
+        self.find_components(path.split('/'), matches)
+    }
+
+    fn find_components<'p>(
+        &self,
+        components: impl Iterator<Item = &'p str>,
+        matches: fn(&[u8], &str) -> bool,
+    ) -> Result<Option<usize>> {
+        let mut components = components.filter(|c| !c.is_empty()).peekable();
+
+        // The deepest node matched so far (initially the root) is at depth `matched + 1`.
+        let mut matched = 0;
+        let mut depth = 0;
+        let mut iter = self.parse_iter();
+        loop {
+            let offset = iter.offset;
+            match iter.next()? {
+                Some(ParsedTok::BeginNode(node)) => {
+                    depth += 1;
+                    if depth == 1 && components.peek().is_none() {
+                        return Ok(Some(offset));
+                    }
+                    if depth == matched + 2
+                        && components.peek().is_some_and(|c| matches(node.name, c))
+                    {
+                        matched += 1;
+                        components.next();
+                        if components.peek().is_none() {
+                            return Ok(Some(offset));
+                        }
+                    }
+                }
+                Some(ParsedTok::EndNode) => {
+                    if depth == matched + 1 {
+                        return Ok(None);
+                    }
+                    depth -= 1;
+                }
+                Some(_) => (),
+                None => return Ok(None),
+            }
+        }
+    }
 }
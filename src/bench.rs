@@ -0,0 +1,210 @@
+//! Synthetic device trees for benchmarking.
+//!
+//! The benchmarks in `benches/` use these fixtures to measure parse, index, search, and modify
+//! throughput. They're public so downstream crates can benchmark against the same trees.
+//!
+//! A fixture is a root node with an interrupt controller and a number of `simple-bus` nodes, each
+//! of which has a number of devices. The last device of the last bus is compatible with
+//! [`Fixture::SEARCH_COMPATIBLE`], which no other node is.
+use core::mem::size_of;
+
+use crate::base::DevTree;
+use crate::spec::{fdt_header, FdtTok, FDT_MAGIC};
+
+/// A generated device tree blob, stored with the alignment [`DevTree::new`] requires.
+pub struct Fixture {
+    words: Vec<u32>,
+    len: usize,
+}
+
+impl Fixture {
+    /// The compatible string of the one node which benchmarks search for.
+    pub const SEARCH_COMPATIBLE: &'static str = "bench,search-target";
+
+    /// A tree of 4 buses with 8 devices each.
+    #[must_use]
+    pub fn small() -> Self {
+        Self::generate(4, 8)
+    }
+
+    /// A tree of 16 buses with 32 devices each.
+    #[must_use]
+    pub fn medium() -> Self {
+        Self::generate(16, 32)
+    }
+
+    /// A tree of 64 buses with 256 devices each.
+    #[must_use]
+    pub fn huge() -> Self {
+        Self::generate(64, 256)
+    }
+
+    /// Generate a tree of `buses` buses with `devices_per_bus` devices each.
+    #[must_use]
+    pub fn generate(buses: usize, devices_per_bus: usize) -> Self {
+        let mut gen = Generator::default();
+        gen.begin_node("");
+        gen.prop_u32("#address-cells", 1);
+        gen.prop_u32("#size-cells", 1);
+        gen.prop_str("compatible", "bench,board");
+        gen.prop_str("model", "fdt-rs benchmark fixture");
+
+        gen.begin_node("interrupt-controller@0");
+        gen.prop_str("compatible", "bench,intc");
+        gen.prop_u32("#interrupt-cells", 1);
+        gen.prop("interrupt-controller", &[]);
+        gen.prop_u32("phandle", 1);
+        gen.end_node();
+
+        for bus in 0..buses {
+            let bus_addr = (bus as u32 + 1) << 24;
+            gen.begin_node(&format!("bus@{:x}", bus_addr));
+            gen.prop_str("compatible", "simple-bus");
+            gen.prop_u32("#address-cells", 1);
+            gen.prop_u32("#size-cells", 1);
+            gen.prop("ranges", &[]);
+
+            for dev in 0..devices_per_bus {
+                let addr = bus_addr + (dev as u32) * 0x1000;
+                let last = bus + 1 == buses && dev + 1 == devices_per_bus;
+                gen.begin_node(&format!("device@{:x}", addr));
+                if last {
+                    gen.prop_str("compatible", Self::SEARCH_COMPATIBLE);
+                } else {
+                    gen.prop_str("compatible", &format!("bench,device-{}", dev % 16));
+                }
+                gen.prop_cells("reg", &[addr, 0x1000]);
+                gen.prop_u32("interrupt-parent", 1);
+                gen.prop_u32("interrupts", (bus * devices_per_bus + dev) as u32);
+                gen.prop_str("status", "okay");
+                gen.end_node();
+            }
+            gen.end_node();
+        }
+        gen.end_node();
+        gen.finish()
+    }
+
+    /// Returns the device tree blob.
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        // Safe since any u32 is a valid sequence of u8 and len <= words.len() * 4.
+        unsafe { core::slice::from_raw_parts(self.words.as_ptr() as *const u8, self.len) }
+    }
+
+    /// Returns a [`DevTree`] over the device tree blob.
+    #[must_use]
+    pub fn devtree(&self) -> DevTree<'_> {
+        // Safe since the blob is aligned and was generated to be a valid device tree.
+        unsafe { DevTree::new(self.as_bytes()) }.unwrap()
+    }
+}
+
+#[derive(Default)]
+struct Generator {
+    structs: Vec<u8>,
+    strings: Vec<u8>,
+}
+
+impl Generator {
+    fn u32(&mut self, val: u32) {
+        self.structs.extend_from_slice(&val.to_be_bytes());
+    }
+
+    fn align(&mut self) {
+        while !self.structs.len().is_multiple_of(size_of::<u32>()) {
+            self.structs.push(0);
+        }
+    }
+
+    fn begin_node(&mut self, name: &str) {
+        self.u32(FdtTok::BeginNode as u32);
+        self.structs.extend_from_slice(name.as_bytes());
+        self.structs.push(0);
+        self.align();
+    }
+
+    fn end_node(&mut self) {
+        self.u32(FdtTok::EndNode as u32);
+    }
+
+    fn string_offset(&mut self, name: &str) -> u32 {
+        let mut off = 0;
+        for s in self.strings.split(|&c| c == 0) {
+            if s == name.as_bytes() {
+                return off as u32;
+            }
+            off += s.len() + 1;
+        }
+        let off = self.strings.len();
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        off as u32
+    }
+
+    fn prop(&mut self, name: &str, value: &[u8]) {
+        let nameoff = self.string_offset(name);
+        self.u32(FdtTok::Prop as u32);
+        self.u32(value.len() as u32);
+        self.u32(nameoff);
+        self.structs.extend_from_slice(value);
+        self.align();
+    }
+
+    fn prop_u32(&mut self, name: &str, val: u32) {
+        self.prop(name, &val.to_be_bytes());
+    }
+
+    fn prop_cells(&mut self, name: &str, cells: &[u32]) {
+        let value: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+        self.prop(name, &value);
+    }
+
+    fn prop_str(&mut self, name: &str, val: &str) {
+        let mut value = val.as_bytes().to_vec();
+        value.push(0);
+        self.prop(name, &value);
+    }
+
+    fn finish(mut self) -> Fixture {
+        self.u32(FdtTok::End as u32);
+
+        let off_mem_rsvmap = size_of::<fdt_header>();
+        // An empty memory reservation block is a single zeroed entry.
+        let off_dt_struct = off_mem_rsvmap + 2 * size_of::<u64>();
+        let off_dt_strings = off_dt_struct + self.structs.len();
+        let totalsize = off_dt_strings + self.strings.len();
+
+        let mut blob = Vec::with_capacity(totalsize);
+        for field in &[
+            FDT_MAGIC,
+            totalsize as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            17,
+            16,
+            0,
+            self.strings.len() as u32,
+            self.structs.len() as u32,
+        ] {
+            blob.extend_from_slice(&field.to_be_bytes());
+        }
+        blob.resize(off_dt_struct, 0);
+        blob.extend_from_slice(&self.structs);
+        blob.extend_from_slice(&self.strings);
+
+        let words = blob
+            .chunks(size_of::<u32>())
+            .map(|c| {
+                let mut word = [0; 4];
+                word[..c.len()].copy_from_slice(c);
+                u32::from_ne_bytes(word)
+            })
+            .collect();
+        Fixture {
+            words,
+            len: totalsize,
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use crate::prelude::*;
+
+use crate::base::iters::DevTreeIter;
+use crate::base::{DevTree, DevTreeNode};
+use crate::error::{DevTreeError, Result};
+
+/// Path of the node whose properties map alias names to node paths.
+const ALIASES_PATH: &str = "/aliases";
+
+impl<'dt> DevTree<'dt> {
+    /// Returns the path the alias `name` (e.g. `serial0`) stands for, from the property of the
+    /// same name of `/aliases`, or `None` if there's no such alias.
+    pub fn alias(&self, name: &str) -> Result<Option<&'dt str>> {
+        let aliases = match self.node_at_path(ALIASES_PATH)? {
+            Some(aliases) => aliases,
+            None => return Ok(None),
+        };
+        match aliases.find_prop(name)? {
+            Some(prop) => Ok(Some(prop.str()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the [`DevTreeNode`] at the given path, which is either absolute (as with
+    /// [`DevTree::node_at_path`]) or begins with an alias (e.g. `serial0` or `i2c1/eeprom`), or
+    /// `None` if there's no such node or alias.
+    ///
+    /// As with libfdt, the alias must stand for an absolute path, else
+    /// [`DevTreeError::InvalidParameter`] is returned.
+    pub fn resolve_path(&self, path: &str) -> Result<Option<DevTreeNode<'_, 'dt>>> {
+        if path.starts_with('/') {
+            return self.node_at_path(path);
+        }
+        let (name, rest) = path.split_once('/').unwrap_or((path, ""));
+        let alias = match self.alias(name)? {
+            Some(alias) => alias,
+            None => return Ok(None),
+        };
+        if !alias.starts_with('/') {
+            return Err(DevTreeError::InvalidParameter(
+                "Alias must be an absolute path",
+            ));
+        }
+        match self.node_offset_by_components(alias.split('/').chain(rest.split('/')))? {
+            Some(offset) => DevTreeIter::from_offset(self, offset).next_node(),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the node of the console named by the `stdout-path` (or legacy
+    /// `linux,stdout-path`) property of `/chosen`, with its options, see
+    /// [`Chosen::stdout_path`](crate::bindings::Chosen::stdout_path).
+    pub fn stdout(&self) -> Result<Option<(DevTreeNode<'_, 'dt>, Option<&'dt str>)>> {
+        match self.chosen()? {
+            Some(chosen) => chosen.stdout_path(),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::base::{DevTree, DevTreeNode};
+use crate::bindings::MemoryRange;
+#[cfg(doc)]
+use crate::error::DevTreeError;
+use crate::error::Result;
+
+/// The number of ranges of RAM a [`BootInfo`] holds.
+pub const MAX_MEMORY_RANGES: usize = 8;
+
+/// The facts a kernel or hypervisor most commonly needs from the device tree it's booted with,
+/// gathered by [`BootInfo::from_devtree`].
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::base::*;
+/// use fdt_rs::bindings::*;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let info = BootInfo::from_devtree(&devtree).unwrap();
+/// assert_eq!(info.memory(), [MemoryRange::new(0x8000_0000, 0x800_0000)]);
+/// assert_eq!(info.timebase_frequency, Some(10_000_000));
+/// ```
+#[derive(Clone)]
+pub struct BootInfo<'a, 'dt: 'a> {
+    memory: [MemoryRange; MAX_MEMORY_RANGES],
+    num_memory: usize,
+    /// The initial ramdisk, see [`Chosen::initrd_range`](crate::bindings::Chosen::initrd_range).
+    pub initrd: Option<MemoryRange>,
+    /// The kernel command line, see [`Chosen::bootargs`](crate::bindings::Chosen::bootargs).
+    pub bootargs: Option<&'dt str>,
+    /// The console, and its options, see [`DevTree::stdout`].
+    pub stdout: Option<(DevTreeNode<'a, 'dt>, Option<&'dt str>)>,
+    /// The physical ID of the boot CPU, from the header.
+    pub boot_cpuid_phys: u32,
+    /// The `cpu` node whose `reg` is [`BootInfo::boot_cpuid_phys`].
+    pub boot_cpu: Option<DevTreeNode<'a, 'dt>>,
+    /// The frequency of the boot CPU's timebase, see [`DevTreeNode::timebase_frequency`], or
+    /// else [`DevTree::timebase_frequency`].
+    pub timebase_frequency: Option<u64>,
+}
+
+impl<'a, 'dt: 'a> BootInfo<'a, 'dt> {
+    /// Gather the boot information of `fdt`.
+    ///
+    /// Returns [`DevTreeError::ParseError`] if a property read is malformed (e.g. an initrd
+    /// with only a start, or which ends before it starts), or
+    /// [`DevTreeError::NotEnoughMemory`] if there are more than [`MAX_MEMORY_RANGES`] ranges of
+    /// RAM.
+    pub fn from_devtree(fdt: &'a DevTree<'dt>) -> Result<Self> {
+        let mut memory = [MemoryRange::default(); MAX_MEMORY_RANGES];
+        let num_memory = fdt.memory_ranges(&mut memory)?.len();
+        let mut info = Self {
+            memory,
+            num_memory,
+            initrd: None,
+            bootargs: None,
+            stdout: fdt.stdout()?,
+            boot_cpuid_phys: fdt.boot_cpuid_phys(),
+            boot_cpu: None,
+            timebase_frequency: None,
+        };
+
+        if let Some(chosen) = fdt.chosen()? {
+            info.initrd = chosen.initrd_range()?;
+            info.bootargs = chosen.bootargs()?;
+        }
+
+        if let Some(cpus) = fdt.cpus()? {
+            let boot_cpu = cpus.by_id(u64::from(info.boot_cpuid_phys))?;
+            info.boot_cpu = boot_cpu.map(|cpu| cpu.node());
+            info.timebase_frequency = match &info.boot_cpu {
+                Some(cpu) => cpu.timebase_frequency()?,
+                None => fdt.timebase_frequency()?,
+            };
+        }
+        Ok(info)
+    }
+
+    /// Returns the ranges of RAM described by the available `memory` nodes, see
+    /// [`DevTree::memory_ranges`].
+    #[must_use]
+    pub fn memory(&self) -> &[MemoryRange] {
+        &self.memory[..self.num_memory]
+    }
+}
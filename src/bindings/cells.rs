@@ -0,0 +1,118 @@
+use core::mem::size_of;
+
+use crate::prelude::*;
+
+use crate::base::{DevTreeNode, DevTreeProp};
+use crate::error::{DevTreeError, Result};
+use crate::priv_util::SliceWrite;
+
+/// Default `#address-cells` value for a node without the property, per the specification.
+pub(crate) const DEFAULT_ADDRESS_CELLS: usize = 2;
+/// Default `#size-cells` value for a node without the property, per the specification.
+pub(crate) const DEFAULT_SIZE_CELLS: usize = 1;
+
+/// Returns the `#address-cells` value of `node`, which sizes the addresses of its children.
+pub(crate) fn address_cells(node: &DevTreeNode) -> Result<usize> {
+    cells_or(node, "#address-cells", DEFAULT_ADDRESS_CELLS)
+}
+
+/// Returns the `#size-cells` value of `node`, which sizes the lengths of its children.
+pub(crate) fn size_cells(node: &DevTreeNode) -> Result<usize> {
+    cells_or(node, "#size-cells", DEFAULT_SIZE_CELLS)
+}
+
+fn cells_or(node: &DevTreeNode, name: &str, default: usize) -> Result<usize> {
+    match node.find_prop(name)? {
+        Some(prop) => Ok(prop.u32(0)? as usize),
+        None => Ok(default),
+    }
+}
+
+/// Read a quantity of `num_cells` big-endian cells which begins at cell `index` of `buf`.
+///
+/// Quantities wider than 64 bits are truncated to their least significant 64 bits. (This drops
+/// e.g. the `phys.hi` cell of a PCI address.) Read them whole with [`read_wide_cells`].
+pub(crate) fn read_cells(buf: &[u8], index: usize, num_cells: usize) -> Result<u64> {
+    let mut val = 0u64;
+    for i in index..index + num_cells {
+        let cell = buf
+            .read_be_u32(i * size_of::<u32>())
+            .or(Err(DevTreeError::InvalidOffset))?;
+        val = (val << 32) | u64::from(cell);
+    }
+    Ok(val)
+}
+
+/// The most cells [`read_wide_cells`] reads.
+pub(crate) const MAX_WIDE_CELLS: usize = size_of::<u128>() / size_of::<u32>();
+
+/// Read a quantity of up to [`MAX_WIDE_CELLS`] big-endian cells which begins at cell `index` of
+/// `buf`, as [`read_cells`] does but without truncating 3 and 4 cell quantities.
+///
+/// Returns [`DevTreeError::ParseError`] if the quantity is wider than [`MAX_WIDE_CELLS`].
+pub(crate) fn read_wide_cells(buf: &[u8], index: usize, num_cells: usize) -> Result<u128> {
+    if num_cells > MAX_WIDE_CELLS {
+        return Err(DevTreeError::ParseError);
+    }
+    let mut val = 0u128;
+    for i in index..index + num_cells {
+        let cell = buf
+            .read_be_u32(i * size_of::<u32>())
+            .or(Err(DevTreeError::InvalidOffset))?;
+        val = (val << 32) | u128::from(cell);
+    }
+    Ok(val)
+}
+
+/// Read a property whose value is a number of one or two cells (e.g. a frequency).
+pub(crate) fn read_number(prop: &DevTreeProp) -> Result<u64> {
+    match prop.length() {
+        4 | 8 => read_cells(prop.raw(), 0, prop.length() / size_of::<u32>()),
+        _ => Err(DevTreeError::ParseError),
+    }
+}
+
+/// Write `value` as `num_cells` big-endian cells, starting at cell `index` of `buf`, which must
+/// be large enough.
+pub(crate) fn write_cells(buf: &mut [u8], index: usize, value: u64, num_cells: usize) {
+    for i in 0..num_cells {
+        // The least significant cell is last.
+        let shift = 32 * (num_cells - 1 - i);
+        let cell = value.checked_shr(shift as u32).unwrap_or(0) as u32;
+        let _ = buf.write_be_u32((index + i) * size_of::<u32>(), cell);
+    }
+}
+
+/// Properties whose values are lists of `<phandle specifier...>` entries, and the property of
+/// the referenced provider which gives the number of cells in its specifiers.
+const SPECIFIER_PROPS: &[(&str, &str)] = &[
+    ("clocks", "#clock-cells"),
+    ("assigned-clocks", "#clock-cells"),
+    ("assigned-clock-parents", "#clock-cells"),
+    ("resets", "#reset-cells"),
+    ("dmas", "#dma-cells"),
+    ("power-domains", "#power-domain-cells"),
+    ("phys", "#phy-cells"),
+    ("pwms", "#pwm-cells"),
+    ("mboxes", "#mbox-cells"),
+    ("iommus", "#iommu-cells"),
+    ("io-channels", "#io-channel-cells"),
+    ("interconnects", "#interconnect-cells"),
+    ("interrupts-extended", "#interrupt-cells"),
+    ("thermal-sensors", "#thermal-sensor-cells"),
+    ("sound-dai", "#sound-dai-cells"),
+    ("hwlocks", "#hwlock-cells"),
+    ("nvmem-cells", "#nvmem-cell-cells"),
+];
+
+/// Returns the name of the provider property which sizes the specifiers of the property `name`,
+/// if its value is a list of `<phandle specifier...>` entries.
+pub(crate) fn specifier_cells_name(name: &str) -> Option<&'static str> {
+    if name == "gpios" || name.ends_with("-gpios") {
+        return Some("#gpio-cells");
+    }
+    SPECIFIER_PROPS
+        .iter()
+        .find(|(prop, _)| *prop == name)
+        .map(|&(_, cells)| cells)
+}
@@ -0,0 +1,100 @@
+use crate::prelude::*;
+
+use crate::base::{DevTree, DevTreeNode};
+use crate::bindings::cells::read_number;
+use crate::bindings::MemoryRange;
+use crate::error::{DevTreeError, Result};
+
+/// Path of the node whose properties pass parameters from the firmware to the OS.
+pub(crate) const CHOSEN_PATH: &str = "/chosen";
+
+/// The `/chosen` node, whose properties pass parameters from the firmware or bootloader to the
+/// OS, see [`DevTree::chosen`].
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::prelude::*;
+/// use fdt_rs::base::*;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let chosen = devtree.chosen().unwrap().unwrap();
+/// assert_eq!(chosen.bootargs().unwrap(), Some(""));
+/// let (console, options) = chosen.stdout_path().unwrap().unwrap();
+/// assert_eq!(console.name().unwrap(), "uart@10000000");
+/// assert_eq!(options, None);
+/// assert_eq!(chosen.initrd_range().unwrap(), None);
+/// ```
+#[derive(Clone)]
+pub struct Chosen<'a, 'dt: 'a> {
+    node: DevTreeNode<'a, 'dt>,
+}
+
+impl<'a, 'dt: 'a> Chosen<'a, 'dt> {
+    /// Returns the `/chosen` node itself, e.g. to read other properties.
+    #[must_use]
+    pub fn node(&self) -> DevTreeNode<'a, 'dt> {
+        self.node.clone()
+    }
+
+    /// Returns the kernel command line, from the `bootargs` property.
+    pub fn bootargs(&self) -> Result<Option<&'dt str>> {
+        match self.node.find_prop("bootargs")? {
+            Some(prop) => Ok(Some(prop.str()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the node of the console named by the `stdout-path` (or legacy
+    /// `linux,stdout-path`) property, with the options which follow the path after a `:` (e.g.
+    /// `115200n8` of `serial0:115200n8`), if any.
+    ///
+    /// Returns `None` if there's no such property or it names no node. The path is resolved as
+    /// with [`DevTree::resolve_path`], so it may begin with an alias.
+    pub fn stdout_path(&self) -> Result<Option<(DevTreeNode<'a, 'dt>, Option<&'dt str>)>> {
+        let prop = match self.node.find_prop("stdout-path")? {
+            Some(prop) => prop,
+            None => match self.node.find_prop("linux,stdout-path")? {
+                Some(prop) => prop,
+                None => return Ok(None),
+            },
+        };
+        let value = prop.str()?;
+        let (path, options) = match value.split_once(':') {
+            Some((path, options)) => (path, Some(options)),
+            None => (value, None),
+        };
+        Ok(self
+            .node
+            .fdt()
+            .resolve_path(path)?
+            .map(|node| (node, options)))
+    }
+
+    /// Returns the initial ramdisk, from the `linux,initrd-start` and `linux,initrd-end`
+    /// properties, each of which may be 32 or 64 bits.
+    ///
+    /// Returns [`DevTreeError::ParseError`] if only one of them is present, or the ramdisk ends
+    /// before it starts.
+    pub fn initrd_range(&self) -> Result<Option<MemoryRange>> {
+        let start = self.node.find_prop("linux,initrd-start")?;
+        let end = self.node.find_prop("linux,initrd-end")?;
+        match (start, end) {
+            (Some(start), Some(end)) => {
+                let (start, end) = (read_number(&start)?, read_number(&end)?);
+                let size = end.checked_sub(start).ok_or(DevTreeError::ParseError)?;
+                Ok(Some(MemoryRange::new(start, size)))
+            }
+            (None, None) => Ok(None),
+            _ => Err(DevTreeError::ParseError),
+        }
+    }
+}
+
+impl<'dt> DevTree<'dt> {
+    /// Returns the `/chosen` node, or `None` if the tree doesn't have one.
+    pub fn chosen(&self) -> Result<Option<Chosen<'_, 'dt>>> {
+        Ok(self.node_at_path(CHOSEN_PATH)?.map(|node| Chosen { node }))
+    }
+}
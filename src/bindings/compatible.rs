@@ -0,0 +1,51 @@
+use crate::prelude::*;
+
+use crate::base::DevTreeNode;
+use crate::error::Result;
+
+/// Returns the entry of `table` which best matches a `compatible` property value, as Linux's
+/// `of_match_node` matches a driver's `of_match_table`.
+///
+/// The `compatible` strings are listed from most to least specific, so the best entry is the one
+/// matching the earliest string of the list. Entries matching the same string are tried in
+/// table order. Each string must match an entry's string whole.
+///
+/// # Example
+///
+/// ```
+/// use fdt_rs::bindings::*;
+///
+/// let table = [("ns16550a", 1), ("snps,dw-apb-uart", 2)];
+/// let value = b"snps,dw-apb-uart\0ns16550a\0";
+/// assert_eq!(match_compatible(value, &table), Some(&("snps,dw-apb-uart", 2)));
+/// assert_eq!(match_compatible(b"arm,pl011\0", &table), None);
+/// ```
+#[must_use]
+pub fn match_compatible<'t, 'c, T>(
+    value: &[u8],
+    table: &'t [(&'c str, T)],
+) -> Option<&'t (&'c str, T)> {
+    let value = value.strip_suffix(&[0]).unwrap_or(value);
+    if value.is_empty() {
+        return None;
+    }
+    value.split(|&c| c == 0).find_map(|string| {
+        table
+            .iter()
+            .find(|(compatible, _)| compatible.as_bytes() == string)
+    })
+}
+
+impl<'a, 'dt: 'a> DevTreeNode<'a, 'dt> {
+    /// Returns the entry of `table` which best matches this node's `compatible` property, see
+    /// [`match_compatible`], or `None` if none does or the node has no such property.
+    pub fn match_table<'t, 'c, T>(
+        &self,
+        table: &'t [(&'c str, T)],
+    ) -> Result<Option<&'t (&'c str, T)>> {
+        match self.find_prop("compatible")? {
+            Some(prop) => Ok(match_compatible(prop.raw(), table)),
+            None => Ok(None),
+        }
+    }
+}
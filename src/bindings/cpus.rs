@@ -0,0 +1,179 @@
+use crate::prelude::*;
+
+use crate::base::iters::DevTreeNodeChildIter;
+use crate::base::{DevTree, DevTreeNode};
+use crate::error::{DevTreeError, Result};
+use crate::name::device_type_matches;
+
+use super::cells::address_cells;
+
+/// Path of the node whose children describe the CPUs.
+pub(crate) const CPUS_PATH: &str = "/cpus";
+
+/// Returns whether `node` is a `cpu` node.
+pub(crate) fn is_cpu(node: &DevTreeNode) -> Result<bool> {
+    match node.find_prop("device_type")? {
+        Some(prop) => Ok(device_type_matches(prop.raw(), "cpu")),
+        None => Ok(false),
+    }
+}
+
+/// The `/cpus` node, whose `cpu` children describe the CPUs (or hardware threads), see
+/// [`DevTree::cpus`].
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::prelude::*;
+/// use fdt_rs::base::*;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let cpus = devtree.cpus().unwrap().unwrap();
+/// let mut iter = cpus.iter();
+/// let cpu = iter.next().unwrap().unwrap();
+/// assert_eq!(cpu.id().unwrap(), 0);
+/// assert!(cpu.is_enabled().unwrap());
+/// assert_eq!(cpu.compatible().unwrap(), Some("riscv"));
+/// assert_eq!(cpu.enable_method().unwrap(), None);
+/// assert!(iter.next().unwrap().is_none());
+/// ```
+#[derive(Clone)]
+pub struct Cpus<'a, 'dt: 'a> {
+    node: DevTreeNode<'a, 'dt>,
+    /// The `#address-cells` of `/cpus`, which sizes the IDs in the CPUs' `reg`.
+    address_cells: usize,
+}
+
+impl<'a, 'dt: 'a> Cpus<'a, 'dt> {
+    /// Returns the `/cpus` node itself.
+    #[must_use]
+    pub fn node(&self) -> DevTreeNode<'a, 'dt> {
+        self.node.clone()
+    }
+
+    /// Returns an iterator over the CPUs: the children of `/cpus` whose `device_type` is `cpu`.
+    /// Other children (e.g. `cpu-map`) are skipped.
+    #[must_use]
+    pub fn iter(&self) -> CpuIter<'a, 'dt> {
+        CpuIter {
+            nodes: self.node.children(),
+            address_cells: self.address_cells,
+        }
+    }
+
+    /// Returns the CPU whose ID is `id` (e.g. the boot CPU's
+    /// [`DevTree::boot_cpuid_phys`]), if there's one.
+    pub fn by_id(&self, id: u64) -> Result<Option<Cpu<'a, 'dt>>> {
+        self.iter().find(|cpu| Ok(cpu.id()? == id))
+    }
+}
+
+/// A `cpu` node.
+#[derive(Clone)]
+pub struct Cpu<'a, 'dt: 'a> {
+    node: DevTreeNode<'a, 'dt>,
+    address_cells: usize,
+}
+
+impl<'a, 'dt: 'a> Cpu<'a, 'dt> {
+    /// Returns the `cpu` node itself.
+    #[must_use]
+    pub fn node(&self) -> DevTreeNode<'a, 'dt> {
+        self.node.clone()
+    }
+
+    /// Returns the CPU's ID (e.g. its RISC-V hart ID or ARM MPIDR affinity bits), the first
+    /// entry of its `reg`.
+    ///
+    /// Unlike other nodes' `reg`, a CPU's has no sizes: `/cpus` has `#size-cells = <0>`, and the
+    /// IDs are sized by its `#address-cells` alone. Returns [`DevTreeError::ParseError`] if the
+    /// node has no `reg`, or the IDs aren't 1 or 2 cells.
+    pub fn id(&self) -> Result<u64> {
+        self.thread_id(0)?.ok_or(DevTreeError::ParseError)
+    }
+
+    /// Returns the ID of the CPU's hardware thread `index`, the entry `index` of its `reg`, or
+    /// `None` if it has fewer threads. Errors are as for [`Cpu::id`].
+    pub fn thread_id(&self, index: usize) -> Result<Option<u64>> {
+        if !(1..=2).contains(&self.address_cells) {
+            return Err(DevTreeError::ParseError);
+        }
+        let prop = self
+            .node
+            .find_prop("reg")?
+            .ok_or(DevTreeError::ParseError)?;
+        let mut id = 0;
+        for i in 0..self.address_cells {
+            id = match prop.u32(index * self.address_cells + i) {
+                Ok(cell) => (id << 32) | u64::from(cell),
+                Err(_) => return Ok(None),
+            };
+        }
+        Ok(Some(id))
+    }
+
+    /// Returns whether the CPU is enabled, see [`DevTreeNode::is_enabled`].
+    ///
+    /// A disabled CPU may still be brought online through its
+    /// [`enable method`](Cpu::enable_method).
+    pub fn is_enabled(&self) -> Result<bool> {
+        self.node.is_enabled()
+    }
+
+    /// Returns the CPU's most specific `compatible` string (e.g. `arm,cortex-a53`), if it has
+    /// one. To match the whole list, use [`DevTreeNode::match_table`].
+    pub fn compatible(&self) -> Result<Option<&'dt str>> {
+        self.str_prop("compatible")
+    }
+
+    /// Returns how secondary CPUs are brought online (e.g. `psci` or `spin-table`), from the
+    /// `enable-method` property, if it has one.
+    pub fn enable_method(&self) -> Result<Option<&'dt str>> {
+        self.str_prop("enable-method")
+    }
+
+    fn str_prop(&self, name: &str) -> Result<Option<&'dt str>> {
+        match self.node.find_prop(name)? {
+            Some(prop) => Ok(Some(prop.str()?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// An iterator over the [`Cpu`]s of `/cpus`, see [`Cpus::iter`].
+#[derive(Clone)]
+pub struct CpuIter<'a, 'dt: 'a> {
+    nodes: DevTreeNodeChildIter<'a, 'dt>,
+    address_cells: usize,
+}
+
+impl<'a, 'dt: 'a> FallibleIterator for CpuIter<'a, 'dt> {
+    type Error = DevTreeError;
+    type Item = Cpu<'a, 'dt>;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        while let Some(node) = self.nodes.next()? {
+            if is_cpu(&node)? {
+                return Ok(Some(Cpu {
+                    node,
+                    address_cells: self.address_cells,
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'dt> DevTree<'dt> {
+    /// Returns the `/cpus` node, or `None` if the tree doesn't have one.
+    pub fn cpus(&self) -> Result<Option<Cpus<'_, 'dt>>> {
+        match self.node_at_path(CPUS_PATH)? {
+            Some(node) => Ok(Some(Cpus {
+                address_cells: address_cells(&node)?,
+                node,
+            })),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use core::mem::size_of;
+
+use crate::prelude::*;
+
+use crate::base::DevTreeNode;
+use crate::error::{DevTreeError, Result};
+
+use super::cells::{address_cells, read_cells, size_cells};
+
+/// An entry of a bus node's `dma-ranges` property.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DmaRange {
+    /// Start of the range in the bus' (child) address space.
+    pub child_addr: u64,
+    /// Start of the range in the bus' parent's address space.
+    pub parent_addr: u64,
+    /// Length of the range.
+    pub size: u64,
+}
+
+/// An inclusive range of CPU physical addresses which a device is able to reach by DMA.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DmaWindow {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// An iterator over the [`DmaRange`] entries of a bus node's `dma-ranges` property.
+#[derive(Clone)]
+pub struct DmaRangeIter<'dt> {
+    propbuf: &'dt [u8],
+    index: usize,
+    child_cells: usize,
+    parent_cells: usize,
+    size_cells: usize,
+}
+
+impl<'dt> FallibleIterator for DmaRangeIter<'dt> {
+    type Error = DevTreeError;
+    type Item = DmaRange;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        let entry_cells = self.child_cells + self.parent_cells + self.size_cells;
+        if self.index * size_of::<u32>() >= self.propbuf.len() || entry_cells == 0 {
+            return Ok(None);
+        }
+
+        let child_addr = read_cells(self.propbuf, self.index, self.child_cells)?;
+        let parent_addr = read_cells(
+            self.propbuf,
+            self.index + self.child_cells,
+            self.parent_cells,
+        )?;
+        let size = read_cells(
+            self.propbuf,
+            self.index + self.child_cells + self.parent_cells,
+            self.size_cells,
+        )?;
+        self.index += entry_cells;
+
+        Ok(Some(DmaRange {
+            child_addr,
+            parent_addr,
+            size,
+        }))
+    }
+}
+
+impl<'a, 'dt: 'a> DevTreeNode<'a, 'dt> {
+    /// Returns true if DMA from this device is coherent with the CPU caches.
+    ///
+    /// The nearest `dma-coherent` or `dma-noncoherent` property of this node or its ancestors
+    /// decides. Devices are non-coherent if neither property is found.
+    pub fn is_dma_coherent(&self) -> Result<bool> {
+        let mut node = Some(self.clone());
+        while let Some(cur) = node {
+            if cur.find_prop("dma-coherent")?.is_some() {
+                return Ok(true);
+            }
+            if cur.find_prop("dma-noncoherent")?.is_some() {
+                return Ok(false);
+            }
+            node = cur.parent()?;
+        }
+        Ok(false)
+    }
+
+    /// Returns an iterator over the entries of this bus node's `dma-ranges` property.
+    ///
+    /// Returns `None` if the node doesn't have the property. An empty iterator means the bus'
+    /// address space is identity mapped onto its parent's.
+    pub fn dma_ranges(&self) -> Result<Option<DmaRangeIter<'dt>>> {
+        let prop = match self.find_prop("dma-ranges")? {
+            Some(prop) => prop,
+            None => return Ok(None),
+        };
+        let parent_cells = match self.parent()? {
+            Some(parent) => address_cells(&parent)?,
+            None => return Err(DevTreeError::ParseError),
+        };
+        Ok(Some(DmaRangeIter {
+            propbuf: prop.raw(),
+            index: 0,
+            child_cells: address_cells(self)?,
+            parent_cells,
+            size_cells: size_cells(self)?,
+        }))
+    }
+
+    /// Returns the window of CPU physical addresses this device can reach by DMA.
+    ///
+    /// The window is computed by translating the device's (unrestricted) bus address space
+    /// through the `dma-ranges` of every bus up to the root node. Buses without `dma-ranges`, or
+    /// with an empty `dma-ranges`, are treated as identity mapped. If a bus' ranges are
+    /// discontiguous the smallest window covering all of them is returned.
+    ///
+    /// Returns `None` if no address is reachable.
+    pub fn dma_window(&self) -> Result<Option<DmaWindow>> {
+        let mut window = DmaWindow {
+            start: 0,
+            end: u64::MAX,
+        };
+
+        let mut bus = self.parent()?;
+        while let Some(cur) = bus {
+            if let Some(mut ranges) = cur.dma_ranges()? {
+                // An empty dma-ranges is an identity mapping.
+                if !ranges.propbuf.is_empty() {
+                    let mut translated: Option<DmaWindow> = None;
+                    while let Some(range) = ranges.next()? {
+                        if let Some(part) = translate(&window, &range) {
+                            translated = Some(match translated {
+                                Some(w) => DmaWindow {
+                                    start: w.start.min(part.start),
+                                    end: w.end.max(part.end),
+                                },
+                                None => part,
+                            });
+                        }
+                    }
+                    window = match translated {
+                        Some(w) => w,
+                        None => return Ok(None),
+                    };
+                }
+            }
+            bus = cur.parent()?;
+        }
+        Ok(Some(window))
+    }
+}
+
+/// Translate the part of `window` covered by `range` into the range's parent address space.
+fn translate(window: &DmaWindow, range: &DmaRange) -> Option<DmaWindow> {
+    if range.size == 0 {
+        return None;
+    }
+    let range_end = range.child_addr.saturating_add(range.size - 1);
+    let start = window.start.max(range.child_addr);
+    let end = window.end.min(range_end);
+    if start > end {
+        return None;
+    }
+    Some(DmaWindow {
+        start: (start - range.child_addr).saturating_add(range.parent_addr),
+        end: (end - range.child_addr).saturating_add(range.parent_addr),
+    })
+}
@@ -0,0 +1,207 @@
+use crate::prelude::*;
+
+use crate::base::{DevTree, DevTreeNode};
+use crate::bindings::MemoryRange;
+use crate::error::{DevTreeError, Result};
+use crate::modify::{DevTreeModifier, MetadataNode, MetadataProp, MetadataValue};
+
+/// Path of the node which describes OP-TEE.
+const OPTEE_PATH: &str = "/firmware/optee";
+/// Path of the node which describes the coreboot tables.
+const COREBOOT_PATH: &str = "/firmware/coreboot";
+/// Path of the node of U-Boot's runtime options.
+const UBOOT_OPTIONS_PATH: &str = "/options/u-boot";
+
+/// How the OS calls into OP-TEE, per the `method` property of `/firmware/optee`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpteeMethod {
+    /// Secure monitor calls.
+    Smc,
+    /// Hypervisor calls.
+    Hvc,
+}
+
+static OPTEE_SMC_PROPS: [MetadataProp<'static>; 2] = [
+    MetadataProp::new("compatible", MetadataValue::Str("linaro,optee-tz")),
+    MetadataProp::new("method", MetadataValue::Str("smc")),
+];
+static OPTEE_HVC_PROPS: [MetadataProp<'static>; 2] = [
+    MetadataProp::new("compatible", MetadataValue::Str("linaro,optee-tz")),
+    MetadataProp::new("method", MetadataValue::Str("hvc")),
+];
+static OPTEE_SMC: MetadataNode<'static> = MetadataNode::new("optee", &OPTEE_SMC_PROPS);
+static OPTEE_HVC: MetadataNode<'static> = MetadataNode::new("optee", &OPTEE_HVC_PROPS);
+
+impl OpteeMethod {
+    /// Returns the value of the `method` property for this method.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OpteeMethod::Smc => "smc",
+            OpteeMethod::Hvc => "hvc",
+        }
+    }
+
+    /// Returns an `optee` node using this method, to add to `/firmware`.
+    #[must_use]
+    pub fn node(self) -> &'static MetadataNode<'static> {
+        match self {
+            OpteeMethod::Smc => &OPTEE_SMC,
+            OpteeMethod::Hvc => &OPTEE_HVC,
+        }
+    }
+}
+
+/// The `/firmware/optee` node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Optee {
+    pub method: OpteeMethod,
+}
+
+/// The `/firmware/coreboot` node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Coreboot {
+    /// The coreboot tables.
+    pub tables: MemoryRange,
+    /// The whole CBMEM area, which contains the tables, if given.
+    pub cbmem: Option<MemoryRange>,
+}
+
+/// The `/options/u-boot` node, which holds options for U-Boot to read at runtime.
+#[derive(Clone, PartialEq)]
+pub struct UBootOptions<'a, 'dt: 'a> {
+    node: DevTreeNode<'a, 'dt>,
+}
+
+impl<'a, 'dt: 'a> UBootOptions<'a, 'dt> {
+    /// Returns the `/options/u-boot` node itself.
+    #[must_use]
+    pub fn node(&self) -> &DevTreeNode<'a, 'dt> {
+        &self.node
+    }
+
+    /// Returns the `bootcmd` property, the command to run when autoboot starts.
+    pub fn bootcmd(&self) -> Result<Option<&'dt str>> {
+        self.node.find_prop("bootcmd")?.map(|p| p.str()).transpose()
+    }
+
+    /// Returns the `bootdelay-sec` property, the number of seconds to wait before autoboot.
+    pub fn bootdelay_sec(&self) -> Result<Option<u32>> {
+        self.node
+            .find_prop("bootdelay-sec")?
+            .map(|p| p.u32(0))
+            .transpose()
+    }
+
+    /// Returns the `bootscr-address` property, the address of the boot script.
+    pub fn bootscr_address(&self) -> Result<Option<u64>> {
+        self.node
+            .find_prop("bootscr-address")?
+            .map(|p| p.u64(0))
+            .transpose()
+    }
+
+    /// Returns the `bootscr-ram-offset` property, the offset of the boot script from the start
+    /// of RAM.
+    pub fn bootscr_ram_offset(&self) -> Result<Option<u64>> {
+        self.node
+            .find_prop("bootscr-ram-offset")?
+            .map(|p| p.u64(0))
+            .transpose()
+    }
+
+    /// Returns whether the `silent-console` property asks for console output to be suppressed.
+    pub fn silent_console(&self) -> Result<bool> {
+        match self.node.find_prop("silent-console")? {
+            Some(prop) => Ok(prop.u32(0)? != 0),
+            None => Ok(false),
+        }
+    }
+}
+
+impl<'dt> DevTree<'dt> {
+    /// Returns the `/firmware/optee` node (if it exists).
+    pub fn optee(&self) -> Result<Option<Optee>> {
+        let node = match self.node_at_path(OPTEE_PATH)? {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        let prop = node.find_prop("method")?.ok_or(DevTreeError::ParseError)?;
+        let method = match prop.str()? {
+            "smc" => OpteeMethod::Smc,
+            "hvc" => OpteeMethod::Hvc,
+            _ => return Err(DevTreeError::ParseError),
+        };
+        Ok(Some(Optee { method }))
+    }
+
+    /// Returns the `/firmware/coreboot` node (if it exists).
+    ///
+    /// The regions are sized by the `#address-cells` and `#size-cells` of `/firmware`.
+    pub fn coreboot(&self) -> Result<Option<Coreboot>> {
+        let node = match self.node_at_path(COREBOOT_PATH)? {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        let mut reg = node.reg()?.ok_or(DevTreeError::ParseError)?;
+        let mut next_region = || -> Result<Option<MemoryRange>> {
+            match reg.next()? {
+                Some(entry) => Ok(Some(MemoryRange::new(entry.address()?, entry.size()?))),
+                None => Ok(None),
+            }
+        };
+        let tables = next_region()?.ok_or(DevTreeError::ParseError)?;
+        Ok(Some(Coreboot {
+            tables,
+            cbmem: next_region()?,
+        }))
+    }
+
+    /// Returns the `/options/u-boot` node (if it exists).
+    pub fn uboot_options(&self) -> Result<Option<UBootOptions<'_, 'dt>>> {
+        Ok(self
+            .node_at_path(UBOOT_OPTIONS_PATH)?
+            .map(|node| UBootOptions { node }))
+    }
+}
+
+impl<'s, 'm> DevTreeModifier<'s, 'm> {
+    /// Add a `/firmware/optee` node using the given method.
+    ///
+    /// The `/firmware` node must already exist.
+    pub fn add_optee(&mut self, method: OpteeMethod) -> Result<&mut Self> {
+        self.add_node("/firmware", method.node())
+    }
+
+    /// Set the `bootcmd` property of `/options/u-boot`.
+    pub fn set_uboot_bootcmd(&mut self, bootcmd: &'m str) -> Result<&mut Self> {
+        self.set_prop(UBOOT_OPTIONS_PATH, "bootcmd", MetadataValue::Str(bootcmd))
+    }
+
+    /// Set the `bootdelay-sec` property of `/options/u-boot`.
+    pub fn set_uboot_bootdelay_sec(&mut self, secs: u32) -> Result<&mut Self> {
+        self.set_prop(
+            UBOOT_OPTIONS_PATH,
+            "bootdelay-sec",
+            MetadataValue::U32(secs),
+        )
+    }
+
+    /// Set the `bootscr-address` property of `/options/u-boot`.
+    pub fn set_uboot_bootscr_address(&mut self, address: u64) -> Result<&mut Self> {
+        self.set_prop(
+            UBOOT_OPTIONS_PATH,
+            "bootscr-address",
+            MetadataValue::U64(address),
+        )
+    }
+
+    /// Set the `silent-console` property of `/options/u-boot`.
+    pub fn set_uboot_silent_console(&mut self, silent: bool) -> Result<&mut Self> {
+        self.set_prop(
+            UBOOT_OPTIONS_PATH,
+            "silent-console",
+            MetadataValue::U32(silent.into()),
+        )
+    }
+}
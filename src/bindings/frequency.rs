@@ -0,0 +1,63 @@
+use crate::prelude::*;
+
+use crate::base::{DevTree, DevTreeNode};
+use crate::bindings::cells::read_number;
+use crate::bindings::cpus::{is_cpu, CPUS_PATH};
+use crate::error::Result;
+
+impl<'dt> DevTree<'dt> {
+    /// Returns the frequency in Hz of the CPUs' timebase, from the `timebase-frequency` property
+    /// of `/cpus`, or else of the first `cpu` node which has one.
+    ///
+    /// The value may be one or two cells. Returns `None` if no node has the property. A CPU
+    /// whose timebase differs has its own, see [`DevTreeNode::timebase_frequency`].
+    pub fn timebase_frequency(&self) -> Result<Option<u64>> {
+        let cpus = match self.node_at_path(CPUS_PATH)? {
+            Some(cpus) => cpus,
+            None => return Ok(None),
+        };
+        if let Some(frequency) = cpus.frequency("timebase-frequency")? {
+            return Ok(Some(frequency));
+        }
+        let mut nodes = cpus.children();
+        while let Some(node) = nodes.next()? {
+            if is_cpu(&node)? {
+                if let Some(frequency) = node.frequency("timebase-frequency")? {
+                    return Ok(Some(frequency));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'a, 'dt: 'a> DevTreeNode<'a, 'dt> {
+    /// Returns the frequency in Hz of this `cpu` node's timebase, from its own
+    /// `timebase-frequency` property, or else that of its parent (`/cpus`).
+    ///
+    /// The value may be one or two cells. Returns `None` if neither node has the property.
+    pub fn timebase_frequency(&self) -> Result<Option<u64>> {
+        if let Some(frequency) = self.frequency("timebase-frequency")? {
+            return Ok(Some(frequency));
+        }
+        match self.parent()? {
+            Some(parent) => parent.frequency("timebase-frequency"),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the frequency in Hz of this node's clock, from its `clock-frequency` property.
+    ///
+    /// The value may be one or two cells. Returns `None` if the node doesn't have the property.
+    pub fn clock_frequency(&self) -> Result<Option<u64>> {
+        self.frequency("clock-frequency")
+    }
+
+    /// Returns the value of the frequency property `name`, if the node has it.
+    fn frequency(&self, name: &str) -> Result<Option<u64>> {
+        match self.find_prop(name)? {
+            Some(prop) => Ok(Some(read_number(&prop)?)),
+            None => Ok(None),
+        }
+    }
+}
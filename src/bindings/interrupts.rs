@@ -0,0 +1,378 @@
+use core::mem::size_of;
+
+use crate::prelude::*;
+
+use crate::base::{DevTree, DevTreeNode};
+use crate::error::{DevTreeError, Result};
+use crate::spec::Phandle;
+
+use super::cells::DEFAULT_ADDRESS_CELLS;
+
+/// An interrupt of a node: the controller it's delivered to and its specifier.
+///
+/// The number and meaning of the specifier's cells are given by the controller's
+/// `#interrupt-cells` and binding.
+#[derive(Clone)]
+pub struct Interrupt<'a, 'dt: 'a> {
+    controller: DevTreeNode<'a, 'dt>,
+    specifier: &'dt [u8],
+    /// The unit address which accompanies the specifier through an `interrupt-map`: the
+    /// device's `reg`, or the parent unit address of the last map entry. Missing cells are 0.
+    address: &'dt [u8],
+}
+
+impl<'a, 'dt: 'a> Interrupt<'a, 'dt> {
+    /// Returns the interrupt controller (or nexus) the specifier is for.
+    #[must_use]
+    pub fn controller(&self) -> DevTreeNode<'a, 'dt> {
+        self.controller.clone()
+    }
+
+    /// Returns the number of specifier cells.
+    #[must_use]
+    pub fn num_cells(&self) -> usize {
+        self.specifier.len() / size_of::<u32>()
+    }
+
+    /// Returns the specifier cell at the given index.
+    pub fn cell(&self, index: usize) -> Result<u32> {
+        self.specifier
+            .read_be_u32(index * size_of::<u32>())
+            .or(Err(DevTreeError::InvalidOffset))
+    }
+
+    /// Returns the raw specifier cells.
+    #[must_use]
+    pub fn specifier(&self) -> &'dt [u8] {
+        self.specifier
+    }
+
+    /// Returns the interrupt as delivered to an interrupt controller, translating it through
+    /// the `interrupt-map` of each interrupt nexus on the way (e.g. a PCI host bridge routing
+    /// INTx pins), as Linux's `of_irq_parse_raw` does.
+    ///
+    /// At each nexus, the unit address and specifier are masked by `interrupt-map-mask` and
+    /// matched against the child unit address and specifier of each `interrupt-map` entry. The
+    /// unit address is sized by the nexus' `#address-cells`, or that of its nearest ancestor
+    /// which has one. A nexus without an `interrupt-map` passes the interrupt to its own
+    /// interrupt parent unchanged.
+    ///
+    /// Returns [`DevTreeError::ParseError`] if no entry matches, or if a map is malformed or
+    /// references a phandle which isn't defined.
+    pub fn resolve(&self) -> Result<Interrupt<'a, 'dt>> {
+        let mut irq = self.clone();
+        for _ in 0..MAX_INTERRUPT_NESTING {
+            let nexus = &irq.controller;
+            if nexus.find_prop("interrupt-controller")?.is_some() {
+                return Ok(irq);
+            }
+            irq = match nexus.find_prop("interrupt-map")? {
+                Some(map) => irq.map(map.raw())?,
+                None => Interrupt {
+                    controller: nexus.interrupt_parent()?.ok_or(DevTreeError::ParseError)?,
+                    ..irq
+                },
+            };
+        }
+        Err(DevTreeError::ParseError)
+    }
+
+    /// Returns the parent interrupt of the entry of `map`, the `interrupt-map` of this
+    /// interrupt's controller, which matches it.
+    fn map(&self, map: &'dt [u8]) -> Result<Interrupt<'a, 'dt>> {
+        let nexus = &self.controller;
+        let mut entries = InterruptMapIter::new(nexus, map)?;
+        let address_cells = entries.address_cells;
+        let child_cells = address_cells + self.num_cells();
+        let mask = nexus
+            .find_prop("interrupt-map-mask")?
+            .map(|mask| mask.raw());
+        if mask.is_some_and(|mask| mask.len() != child_cells * size_of::<u32>()) {
+            return Err(DevTreeError::ParseError);
+        }
+        // The unit address, then the specifier.
+        let cell = |index: usize| -> Result<u32> {
+            if index < address_cells {
+                Ok(self
+                    .address
+                    .read_be_u32(index * size_of::<u32>())
+                    .unwrap_or(0))
+            } else {
+                self.cell(index - address_cells)
+            }
+        };
+
+        while let Some(entry) = entries.next()? {
+            let mut matches = true;
+            for i in 0..child_cells {
+                let mask = match mask {
+                    Some(mask) => mask.read_be_u32(i * size_of::<u32>())?,
+                    None => !0,
+                };
+                let child = entry
+                    .child
+                    .read_be_u32(i * size_of::<u32>())
+                    .or(Err(DevTreeError::ParseError))?;
+                matches &= (child ^ cell(i)?) & mask == 0;
+            }
+            if matches {
+                return Ok(Interrupt {
+                    controller: entry.parent,
+                    specifier: entry.specifier,
+                    address: entry.address,
+                });
+            }
+        }
+        Err(DevTreeError::ParseError)
+    }
+}
+
+/// An entry of an interrupt nexus' `interrupt-map`.
+pub(crate) struct InterruptMapEntry<'a, 'dt: 'a> {
+    /// The child unit address and specifier.
+    pub(crate) child: &'dt [u8],
+    /// The offset in the map of the interrupt parent's phandle.
+    pub(crate) phandle_offset: usize,
+    pub(crate) parent: DevTreeNode<'a, 'dt>,
+    /// The parent unit address.
+    pub(crate) address: &'dt [u8],
+    /// The offset in the map of the parent specifier.
+    pub(crate) specifier_offset: usize,
+    pub(crate) specifier: &'dt [u8],
+}
+
+/// An iterator over the entries of an interrupt nexus' `interrupt-map`.
+///
+/// Each child unit address is sized by the nexus' `#address-cells`, or that of its nearest
+/// ancestor which has one, as Linux's `of_irq_parse_raw` sizes it. Each parent unit address is
+/// sized by the parent's own `#address-cells`, or is empty if it has none.
+pub(crate) struct InterruptMapIter<'a, 'dt: 'a> {
+    nexus: DevTreeNode<'a, 'dt>,
+    map: &'dt [u8],
+    offset: usize,
+    /// The number of cells of each child unit address.
+    pub(crate) address_cells: usize,
+    /// The number of cells of each child unit address and specifier.
+    child_cells: usize,
+}
+
+impl<'a, 'dt: 'a> InterruptMapIter<'a, 'dt> {
+    /// Returns an iterator over `map`, the `interrupt-map` of `nexus`.
+    ///
+    /// Returns [`DevTreeError::ParseError`] if the nexus has no `#interrupt-cells`.
+    pub(crate) fn new(nexus: &DevTreeNode<'a, 'dt>, map: &'dt [u8]) -> Result<Self> {
+        let address_cells = inherited_address_cells(nexus)?;
+        let interrupt_cells = interrupt_cells(nexus)?.ok_or(DevTreeError::ParseError)?;
+        Ok(Self {
+            nexus: nexus.clone(),
+            map,
+            offset: 0,
+            address_cells,
+            child_cells: address_cells + interrupt_cells,
+        })
+    }
+
+    /// Returns the next `len` bytes of the map.
+    fn take(&mut self, len: usize) -> Result<&'dt [u8]> {
+        let bytes = self
+            .map
+            .get(self.offset..self.offset + len)
+            .ok_or(DevTreeError::ParseError)?;
+        self.offset += len;
+        Ok(bytes)
+    }
+}
+
+impl<'a, 'dt: 'a> FallibleIterator for InterruptMapIter<'a, 'dt> {
+    type Error = DevTreeError;
+    type Item = InterruptMapEntry<'a, 'dt>;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        if self.offset >= self.map.len() {
+            return Ok(None);
+        }
+
+        let child = self.take(self.child_cells * size_of::<u32>())?;
+        let phandle_offset = self.offset;
+        let phandle = self.take(size_of::<Phandle>())?.read_be_u32(0)?;
+        let parent = self
+            .nexus
+            .fdt()
+            .node_by_phandle(phandle)?
+            .ok_or(DevTreeError::ParseError)?;
+        // A parent without #address-cells has no unit address cells.
+        let address_cells = match parent.find_prop("#address-cells")? {
+            Some(prop) => prop.u32(0)? as usize,
+            None => 0,
+        };
+        let address = self.take(address_cells * size_of::<u32>())?;
+        let specifier_offset = self.offset;
+        let specifier_cells = interrupt_cells(&parent)?.ok_or(DevTreeError::ParseError)?;
+        let specifier = self.take(specifier_cells * size_of::<u32>())?;
+        Ok(Some(InterruptMapEntry {
+            child,
+            phandle_offset,
+            parent,
+            address,
+            specifier_offset,
+            specifier,
+        }))
+    }
+}
+
+/// The most interrupt nexuses [`Interrupt::resolve`] passes an interrupt through, and the most
+/// steps [`DevTreeNode::interrupt_parent`] takes, which bounds the walk of a tree whose maps or
+/// `interrupt-parent`s form a cycle.
+const MAX_INTERRUPT_NESTING: usize = 16;
+
+/// An iterator over the [`Interrupt`]s of a node, see [`DevTreeNode::interrupts`].
+#[derive(Clone)]
+pub struct InterruptIter<'a, 'dt: 'a> {
+    fdt: &'a DevTree<'dt>,
+    propbuf: &'dt [u8],
+    offset: usize,
+    /// The `reg` of the interrupts' node.
+    address: &'dt [u8],
+    /// The interrupt parent of an `interrupts` property, or `None` for `interrupts-extended`,
+    /// whose entries each start with their controller's phandle.
+    parent: Option<DevTreeNode<'a, 'dt>>,
+}
+
+impl<'a, 'dt: 'a> FallibleIterator for InterruptIter<'a, 'dt> {
+    type Error = DevTreeError;
+    type Item = Interrupt<'a, 'dt>;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        if self.offset >= self.propbuf.len() {
+            return Ok(None);
+        }
+
+        let controller = match &self.parent {
+            Some(parent) => parent.clone(),
+            None => {
+                let phandle = self.propbuf.read_be_u32(self.offset)?;
+                self.offset += size_of::<Phandle>();
+                self.fdt
+                    .node_by_phandle(phandle)?
+                    .ok_or(DevTreeError::ParseError)?
+            }
+        };
+        let num_cells = interrupt_cells(&controller)?.ok_or(DevTreeError::ParseError)?;
+
+        let end = self.offset + num_cells * size_of::<u32>();
+        let specifier = self
+            .propbuf
+            .get(self.offset..end)
+            .ok_or(DevTreeError::ParseError)?;
+        self.offset = end;
+
+        Ok(Some(Interrupt {
+            controller,
+            specifier,
+            address: self.address,
+        }))
+    }
+}
+
+/// Returns the `#address-cells` value of `node`, or else of its nearest ancestor which has the
+/// property, or else the default, as Linux's `of_irq_parse_raw` finds it.
+fn inherited_address_cells(node: &DevTreeNode) -> Result<usize> {
+    let mut node = node.clone();
+    loop {
+        if let Some(prop) = node.find_prop("#address-cells")? {
+            return Ok(prop.u32(0)? as usize);
+        }
+        node = match node.parent()? {
+            Some(parent) => parent,
+            None => return Ok(DEFAULT_ADDRESS_CELLS),
+        };
+    }
+}
+
+/// Returns the `#interrupt-cells` value of `node`, if it has the property.
+fn interrupt_cells(node: &DevTreeNode) -> Result<Option<usize>> {
+    match node.find_prop("#interrupt-cells")? {
+        Some(prop) => Ok(Some(prop.u32(0)? as usize)),
+        None => Ok(None),
+    }
+}
+
+impl<'a, 'dt: 'a> DevTreeNode<'a, 'dt> {
+    /// Returns this node's interrupt parent, as Linux's `of_irq_find_parent` finds it: the node
+    /// referenced by `interrupt-parent`, or else the parent node, repeated until a node with
+    /// `#interrupt-cells` is found.
+    ///
+    /// Returns `None` if there's no such node, or [`DevTreeError::ParseError`] if an
+    /// `interrupt-parent` references a phandle which isn't defined, or the walk doesn't end
+    /// (e.g. at an `interrupt-parent` which references its own node).
+    pub fn interrupt_parent(&self) -> Result<Option<DevTreeNode<'a, 'dt>>> {
+        let mut node = self.clone();
+        for _ in 0..MAX_INTERRUPT_NESTING {
+            let next = match node.find_prop("interrupt-parent")? {
+                Some(prop) => Some(
+                    self.fdt()
+                        .node_by_phandle(prop.u32(0)?)?
+                        .ok_or(DevTreeError::ParseError)?,
+                ),
+                None => node.parent()?,
+            };
+            node = match next {
+                Some(next) => next,
+                None => return Ok(None),
+            };
+            if interrupt_cells(&node)?.is_some() {
+                return Ok(Some(node));
+            }
+        }
+        Err(DevTreeError::ParseError)
+    }
+
+    /// Returns an iterator over this node's interrupts.
+    ///
+    /// As with Linux, the interrupts are read from `interrupts-extended` if the node has it, whose
+    /// entries each start with the phandle of their controller. Otherwise they're read from
+    /// `interrupts`, whose entries are all for the node's
+    /// [interrupt parent](DevTreeNode::interrupt_parent). Each specifier is sized by its
+    /// controller's `#interrupt-cells`. Returns `None` if the node has neither property.
+    ///
+    /// A controller may be an interrupt nexus, whose `interrupt-map` translates the specifier
+    /// for another controller. The translation is applied by [`Interrupt::resolve`].
+    ///
+    /// Returns [`DevTreeError::ParseError`] if the node needs an interrupt parent but has none.
+    /// Iteration returns [`DevTreeError::ParseError`] if an entry's phandle isn't defined, or
+    /// the property ends part way through a specifier.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use fdt_rs::doctest::FDT;
+    /// use fdt_rs::prelude::*;
+    /// use fdt_rs::base::*;
+    ///
+    /// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    /// let uart = devtree.node_at_path("/uart@10000000").unwrap().unwrap();
+    /// let irq = uart.interrupts().unwrap().unwrap().next().unwrap().unwrap();
+    /// assert_eq!(irq.controller().name().unwrap(), "interrupt-controller@c000000");
+    /// assert_eq!(irq.num_cells(), 1);
+    /// assert_eq!(irq.cell(0).unwrap(), 10);
+    /// ```
+    pub fn interrupts(&self) -> Result<Option<InterruptIter<'a, 'dt>>> {
+        let (prop, parent) = match self.find_prop("interrupts-extended")? {
+            Some(prop) => (prop, None),
+            None => match self.find_prop("interrupts")? {
+                Some(prop) => (
+                    prop,
+                    Some(self.interrupt_parent()?.ok_or(DevTreeError::ParseError)?),
+                ),
+                None => return Ok(None),
+            },
+        };
+        Ok(Some(InterruptIter {
+            fdt: self.fdt(),
+            propbuf: prop.raw(),
+            offset: 0,
+            address: self.find_prop("reg")?.map_or(&[], |reg| reg.raw()),
+            parent,
+        }))
+    }
+}
@@ -0,0 +1,400 @@
+use crate::prelude::*;
+
+use crate::base::iters::{DevTreeIter, DevTreeNodeIter};
+use crate::base::parse::{DevTreeParseIter, ParsedTok};
+use crate::base::{DevTree, DevTreeNode};
+use crate::bindings::RegPairIter;
+use crate::error::{DevTreeError, Result};
+use crate::modify::{MemReservation, MemReserveEdits};
+use crate::name::device_type_matches;
+
+/// Path of the node whose children describe reserved memory regions.
+pub(crate) const RESERVED_MEMORY_PATH: &str = "/reserved-memory";
+
+/// A range of physical memory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryRange {
+    pub address: u64,
+    pub size: u64,
+}
+
+impl MemoryRange {
+    #[must_use]
+    pub const fn new(address: u64, size: u64) -> Self {
+        Self { address, size }
+    }
+
+    /// Returns the address just past the end of the range.
+    ///
+    /// Ranges which extend past the end of the address space end at [`u64::MAX`].
+    #[must_use]
+    pub fn end(&self) -> u64 {
+        self.address.saturating_add(self.size)
+    }
+
+    /// Returns whether the range overlaps `other`. Empty ranges overlap nothing.
+    #[must_use]
+    pub fn overlaps(&self, other: &MemoryRange) -> bool {
+        self.size != 0
+            && other.size != 0
+            && self.address < other.end()
+            && other.address < self.end()
+    }
+}
+
+/// Ranges written to the start of a caller provided slice.
+struct RangeList<'o> {
+    ranges: &'o mut [MemoryRange],
+    len: usize,
+}
+
+impl<'o> RangeList<'o> {
+    fn push(&mut self, range: MemoryRange) -> Result<()> {
+        let slot = self
+            .ranges
+            .get_mut(self.len)
+            .ok_or(DevTreeError::NotEnoughMemory)?;
+        *slot = range;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Remove `hole` from every range, splitting those it lies within.
+    fn subtract(&mut self, hole: MemoryRange) -> Result<()> {
+        for i in 0..self.len {
+            let range = self.ranges[i];
+            if !range.overlaps(&hole) {
+                continue;
+            }
+            let below = MemoryRange::new(range.address, hole.address.saturating_sub(range.address));
+            let above = MemoryRange::new(hole.end(), range.end().saturating_sub(hole.end()));
+            self.ranges[i] = below;
+            if above.size != 0 {
+                if below.size == 0 {
+                    self.ranges[i] = above;
+                } else {
+                    self.push(above)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop empty ranges and sort the rest by address.
+    fn compact(&mut self) {
+        let mut len = 0;
+        for i in 0..self.len {
+            if self.ranges[i].size != 0 {
+                self.ranges[len] = self.ranges[i];
+                len += 1;
+            }
+        }
+        self.len = len;
+        self.ranges[..len].sort_unstable_by_key(|range| range.address);
+    }
+
+    fn into_slice(self) -> &'o mut [MemoryRange] {
+        &mut self.ranges[..self.len]
+    }
+}
+
+/// Places payloads (e.g. an initrd or spin tables) in the free memory of a device tree.
+///
+/// Each allocation is carved out of the tree's [free memory](DevTree::free_memory) and recorded
+/// as a reservation, to add to the tree's memory reservation block with
+/// [`MemoryAllocator::mem_reserve_edits`].
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::base::*;
+/// use fdt_rs::bindings::*;
+/// use fdt_rs::modify::*;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let mut free = [MemoryRange::default(); 8];
+/// let mut reserved = [MemReservation::default(); 2];
+/// let mut allocator = MemoryAllocator::new(&devtree, &mut free, &mut reserved).unwrap();
+/// let initrd = allocator.allocate(0x100_0000, 0x1000, Some(0x1_0000_0000)).unwrap();
+///
+/// let options = ModifyOptions {
+///     mem_reserve: allocator.mem_reserve_edits(),
+///     ..ModifyOptions::default()
+/// };
+/// let mut buf = vec![0u32; FDT.len() / 4 + 8];
+/// let out = unsafe {
+///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4)
+/// };
+/// let size =
+///     Serializer::modify_with_options(&devtree, out, &options, |_| ModifyTokenResponse::Pass)
+///         .unwrap();
+/// let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+/// assert!(modified.mem_reserve_map().unwrap().overlaps(initrd, 1).unwrap());
+/// ```
+pub struct MemoryAllocator<'o> {
+    free: RangeList<'o>,
+    reserved: &'o mut [MemReservation],
+    num_reserved: usize,
+}
+
+impl<'o> MemoryAllocator<'o> {
+    /// Create an allocator over the free memory of `fdt`.
+    ///
+    /// `free` holds the free ranges, and needs an element for each range of
+    /// [`DevTree::free_memory`] plus one for each allocation which splits a range. `reserved`
+    /// needs an element for each allocation.
+    pub fn new(
+        fdt: &DevTree,
+        free: &'o mut [MemoryRange],
+        reserved: &'o mut [MemReservation],
+    ) -> Result<Self> {
+        Ok(Self {
+            free: fdt.free_memory_list(free)?,
+            reserved,
+            num_reserved: 0,
+        })
+    }
+
+    /// Allocate `size` bytes at an address which is a multiple of `align`, ending at or below
+    /// `max_address` if given. The lowest such address is used.
+    ///
+    /// Returns [`DevTreeError::InvalidParameter`] if `size` is zero or `align` isn't a power of
+    /// two, and [`DevTreeError::NotEnoughMemory`] if there's no room for the allocation or to
+    /// record it.
+    pub fn allocate(&mut self, size: u64, align: u64, max_address: Option<u64>) -> Result<u64> {
+        if size == 0 || !align.is_power_of_two() {
+            return Err(DevTreeError::InvalidParameter(
+                "Size must be nonzero and alignment a power of two",
+            ));
+        }
+        let limit = max_address.unwrap_or(u64::MAX);
+        let address = self
+            .free
+            .ranges
+            .iter()
+            .take(self.free.len)
+            .filter_map(|range| {
+                let start = range.address.checked_add(align - 1)? & !(align - 1);
+                let end = start.checked_add(size)?;
+                if end <= range.end() && end <= limit {
+                    Some(start)
+                } else {
+                    None
+                }
+            })
+            .min()
+            .ok_or(DevTreeError::NotEnoughMemory)?;
+
+        // Check for room up front, so a failed allocation leaves the free ranges untouched.
+        let splits = self
+            .free()
+            .iter()
+            .any(|range| range.address < address && address + size < range.end());
+        if splits && self.free.len == self.free.ranges.len() {
+            return Err(DevTreeError::NotEnoughMemory);
+        }
+        let slot = self
+            .reserved
+            .get_mut(self.num_reserved)
+            .ok_or(DevTreeError::NotEnoughMemory)?;
+        self.free.subtract(MemoryRange::new(address, size))?;
+        self.free.compact();
+        *slot = MemReservation::new(address, size);
+        self.num_reserved += 1;
+        Ok(address)
+    }
+
+    /// Returns the free ranges which remain, sorted by address.
+    #[must_use]
+    pub fn free(&self) -> &[MemoryRange] {
+        &self.free.ranges[..self.free.len]
+    }
+
+    /// Returns the allocations made so far.
+    #[must_use]
+    pub fn reservations(&self) -> &[MemReservation] {
+        &self.reserved[..self.num_reserved]
+    }
+
+    /// Returns edits which add the allocations to a tree's memory reservation block, for
+    /// [`ModifyOptions::mem_reserve`](crate::modify::ModifyOptions::mem_reserve).
+    #[must_use]
+    pub fn mem_reserve_edits(&self) -> MemReserveEdits<'_> {
+        MemReserveEdits {
+            append: self.reservations(),
+            ..MemReserveEdits::default()
+        }
+    }
+}
+
+/// Call `f` with each entry of the `reg` property of `node`.
+pub(crate) fn for_each_reg(
+    node: &DevTreeNode,
+    mut f: impl FnMut(MemoryRange) -> Result<()>,
+) -> Result<()> {
+    if let Some(mut reg) = node.reg()? {
+        while let Some(entry) = reg.next()? {
+            f(MemoryRange::new(entry.address()?, entry.size()?))?;
+        }
+    }
+    Ok(())
+}
+
+/// An iterator over the `(address, size)` ranges of RAM described by a tree's available `memory`
+/// nodes, see [`DevTree::memory_regions`].
+#[derive(Clone)]
+pub struct MemoryRegionIter<'a, 'dt: 'a> {
+    nodes: DevTreeNodeIter<'a, 'dt>,
+    /// The `reg` of the memory node being iterated.
+    reg: Option<RegPairIter<'dt>>,
+}
+
+impl<'a, 'dt: 'a> FallibleIterator for MemoryRegionIter<'a, 'dt> {
+    type Error = DevTreeError;
+    type Item = (u64, u64);
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        loop {
+            if let Some(reg) = &mut self.reg {
+                if let Some(pair) = reg.next()? {
+                    return Ok(Some(pair));
+                }
+                self.reg = None;
+            }
+            let node = match self.nodes.next()? {
+                Some(node) => node,
+                None => return Ok(None),
+            };
+            if is_memory(&node)? && node.is_enabled()? {
+                self.reg = node.reg_pairs()?;
+            }
+        }
+    }
+}
+
+/// Returns whether `node` is a `memory` node.
+pub(crate) fn is_memory(node: &DevTreeNode) -> Result<bool> {
+    match node.find_prop("device_type")? {
+        Some(prop) => Ok(device_type_matches(prop.raw(), "memory")),
+        None => Ok(false),
+    }
+}
+
+impl<'dt> DevTree<'dt> {
+    /// Returns an iterator over the `(address, size)` ranges of RAM described by the available
+    /// nodes whose `device_type` is `memory`.
+    ///
+    /// Each node's ranges are read from its `reg`, sized by its parent's (usually the root's)
+    /// `#address-cells` and `#size-cells`, see [`DevTreeNode::reg_pairs`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use fdt_rs::doctest::FDT;
+    /// use fdt_rs::prelude::*;
+    /// use fdt_rs::base::*;
+    ///
+    /// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    /// let mut regions = devtree.memory_regions();
+    /// assert_eq!(regions.next().unwrap(), Some((0x8000_0000, 0x800_0000)));
+    /// assert_eq!(regions.next().unwrap(), None);
+    /// ```
+    #[must_use]
+    pub fn memory_regions(&self) -> MemoryRegionIter<'_, 'dt> {
+        MemoryRegionIter {
+            nodes: self.nodes(),
+            reg: None,
+        }
+    }
+
+    /// Copy the ranges of RAM described by the available `memory` nodes into the start of `out`.
+    ///
+    /// Returns the part of `out` written, or [`DevTreeError::NotEnoughMemory`] if there are more
+    /// ranges than fit.
+    pub fn memory_ranges<'o>(&self, out: &'o mut [MemoryRange]) -> Result<&'o mut [MemoryRange]> {
+        let mut list = RangeList {
+            ranges: out,
+            len: 0,
+        };
+        self.collect_memory(&mut list)?;
+        Ok(list.into_slice())
+    }
+
+    /// Copy the usable ranges of RAM into the start of `out`, sorted by address.
+    ///
+    /// These are the ranges of the available `memory` nodes, less the entries of the memory
+    /// reservation block and the statically placed (`reg`) children of `/reserved-memory`.
+    /// Dynamically placed reserved memory regions (with only a `size`) aren't allocated yet, so
+    /// aren't subtracted.
+    ///
+    /// Returns the part of `out` written. Reservations may split a range in two, so `out` may
+    /// need more elements than there are ranges of RAM. Returns
+    /// [`DevTreeError::NotEnoughMemory`] if it runs out.
+    pub fn free_memory<'o>(&self, out: &'o mut [MemoryRange]) -> Result<&'o mut [MemoryRange]> {
+        Ok(self.free_memory_list(out)?.into_slice())
+    }
+
+    fn free_memory_list<'o>(&self, out: &'o mut [MemoryRange]) -> Result<RangeList<'o>> {
+        let mut list = RangeList {
+            ranges: out,
+            len: 0,
+        };
+        self.collect_memory(&mut list)?;
+
+        let mut entries = self.mem_reserve_entries();
+        while let Some(entry) = entries.next()? {
+            list.subtract(MemoryRange::new(entry.address()?, entry.size()?))?;
+        }
+        self.for_each_reserved_memory_node(|node| {
+            for_each_reg(&node, |range| list.subtract(range))
+        })?;
+        list.compact();
+        Ok(list)
+    }
+
+    fn collect_memory(&self, list: &mut RangeList) -> Result<()> {
+        let mut regions = self.memory_regions();
+        while let Some((address, size)) = regions.next()? {
+            list.push(MemoryRange::new(address, size))?;
+        }
+        Ok(())
+    }
+
+    /// Call `f` with each available child of `/reserved-memory`.
+    fn for_each_reserved_memory_node(
+        &self,
+        mut f: impl FnMut(DevTreeNode<'_, 'dt>) -> Result<()>,
+    ) -> Result<()> {
+        let offset = match self.node_offset_by_path(RESERVED_MEMORY_PATH)? {
+            Some(offset) => offset,
+            None => return Ok(()),
+        };
+        let mut iter = DevTreeParseIter { offset, fdt: self };
+        let mut depth = 0usize;
+        loop {
+            let offset = iter.offset;
+            match iter.next()? {
+                Some(ParsedTok::BeginNode(_)) => {
+                    depth += 1;
+                    if depth == 2 {
+                        let node = DevTreeIter::from_offset(self, offset)
+                            .next_node()?
+                            .ok_or(DevTreeError::ParseError)?;
+                        if node.is_enabled()? {
+                            f(node)?;
+                        }
+                    }
+                }
+                Some(ParsedTok::EndNode) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                Some(_) => (),
+                None => return Err(DevTreeError::ParseError),
+            }
+        }
+    }
+}
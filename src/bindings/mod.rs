@@ -0,0 +1,72 @@
+//! Helpers which interpret common device tree bindings.
+//!
+//! # Overview
+//!
+//! The [`base`][crate::base] and [`index`][crate::index] modules provide access to the raw nodes
+//! and properties of a device tree. This module builds on top of the [`base`][crate::base]
+//! module to decode properties whose meaning is defined by a binding (e.g. `pinctrl-<N>`) so
+//! that every consumer doesn't have to reimplement the same lookups.
+
+pub(crate) mod cells;
+
+#[doc(hidden)]
+pub mod aliases;
+#[doc(hidden)]
+pub mod boot_info;
+#[doc(hidden)]
+pub mod chosen;
+#[doc(hidden)]
+pub mod compatible;
+#[doc(hidden)]
+pub mod cpus;
+#[doc(hidden)]
+pub mod dma;
+#[doc(hidden)]
+pub mod firmware;
+#[doc(hidden)]
+pub mod frequency;
+#[doc(hidden)]
+pub mod interrupts;
+#[doc(hidden)]
+pub mod memory;
+#[doc(hidden)]
+pub mod pci;
+#[doc(hidden)]
+pub mod pinctrl;
+#[doc(hidden)]
+pub mod power_domain;
+#[doc(hidden)]
+pub mod provider;
+#[doc(hidden)]
+pub mod reg;
+#[doc(hidden)]
+pub mod reset;
+#[doc(hidden)]
+pub mod status;
+
+#[doc(inline)]
+pub use boot_info::*;
+#[doc(inline)]
+pub use chosen::*;
+#[doc(inline)]
+pub use compatible::*;
+#[doc(inline)]
+pub use cpus::*;
+#[doc(inline)]
+pub use dma::*;
+#[doc(inline)]
+pub use firmware::*;
+#[doc(inline)]
+pub use interrupts::*;
+#[doc(inline)]
+pub use memory::*;
+#[doc(inline)]
+pub use pci::*;
+#[doc(inline)]
+pub use pinctrl::*;
+#[doc(inline)]
+pub use provider::*;
+#[doc(inline)]
+pub use reg::*;
+#[doc(inline)]
+pub use status::*;
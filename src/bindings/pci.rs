@@ -0,0 +1,209 @@
+use core::mem::size_of;
+
+use crate::prelude::*;
+
+use crate::base::DevTreeNode;
+use crate::error::{DevTreeError, Result};
+use crate::priv_util::SliceRead;
+
+use super::cells::{address_cells, read_cells};
+use super::reg::{RangeIter, RegIter};
+
+/// The number of cells of a PCI address: `phys.hi`, `phys.mid` and `phys.lo`.
+pub const PCI_ADDRESS_CELLS: usize = 3;
+
+/// The address space of a [`PciAddress`], from the `ss` bits of its `phys.hi` cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PciSpace {
+    Config,
+    Io,
+    Memory32,
+    Memory64,
+}
+
+/// A PCI address, as encoded in three cells by the PCI bus binding (IEEE 1275).
+///
+/// The `phys.hi` cell packs the address' flags and space with the bus, device and function
+/// numbers (`npt000ss bbbbbbbb dddddfff rrrrrrrr`). `phys.mid` and `phys.lo` hold a 64-bit
+/// address within the space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PciAddress {
+    /// The `phys.hi` cell.
+    pub hi: u32,
+    /// The address within the space, from the `phys.mid` and `phys.lo` cells.
+    pub address: u64,
+}
+
+impl PciAddress {
+    #[must_use]
+    pub const fn new(hi: u32, address: u64) -> Self {
+        Self { hi, address }
+    }
+
+    /// Read the address whose cells begin at cell `index` of `buf`.
+    fn read(buf: &[u8], index: usize) -> Result<Self> {
+        let hi = buf
+            .read_be_u32(index * size_of::<u32>())
+            .or(Err(DevTreeError::InvalidOffset))?;
+        Ok(Self::new(hi, read_cells(buf, index + 1, 2)?))
+    }
+
+    /// Returns the address space.
+    #[must_use]
+    pub fn space(&self) -> PciSpace {
+        match (self.hi >> 24) & 0x3 {
+            0 => PciSpace::Config,
+            1 => PciSpace::Io,
+            2 => PciSpace::Memory32,
+            _ => PciSpace::Memory64,
+        }
+    }
+
+    /// Returns whether the address is relocatable, i.e. the `n` bit is clear.
+    #[must_use]
+    pub fn is_relocatable(&self) -> bool {
+        self.hi & (1 << 31) == 0
+    }
+
+    /// Returns whether the memory is prefetchable (the `p` bit).
+    #[must_use]
+    pub fn is_prefetchable(&self) -> bool {
+        self.hi & (1 << 30) != 0
+    }
+
+    /// Returns whether the address is aliased, or below 1 MB for memory or 64 KB for I/O (the
+    /// `t` bit).
+    #[must_use]
+    pub fn is_aliased(&self) -> bool {
+        self.hi & (1 << 29) != 0
+    }
+
+    /// Returns the bus number.
+    #[must_use]
+    pub fn bus(&self) -> u8 {
+        (self.hi >> 16) as u8
+    }
+
+    /// Returns the device number.
+    #[must_use]
+    pub fn device(&self) -> u8 {
+        ((self.hi >> 11) & 0x1f) as u8
+    }
+
+    /// Returns the function number.
+    #[must_use]
+    pub fn function(&self) -> u8 {
+        ((self.hi >> 8) & 0x7) as u8
+    }
+
+    /// Returns the configuration space register number (e.g. of a BAR).
+    #[must_use]
+    pub fn register(&self) -> u8 {
+        self.hi as u8
+    }
+}
+
+/// An entry of a PCI device node's `reg` property.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PciReg {
+    /// The address of the region, whose bus, device and function are the device's.
+    pub address: PciAddress,
+    /// Length of the region.
+    pub size: u64,
+}
+
+/// An entry of a PCI host bridge's `ranges` property.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PciRange {
+    /// Start of the range in the PCI address space.
+    pub child: PciAddress,
+    /// Start of the range in the host bridge's parent's address space.
+    pub parent: u64,
+    /// Length of the range.
+    pub size: u64,
+}
+
+/// An iterator over the [`PciReg`] entries of a PCI device node's `reg` property.
+#[derive(Clone)]
+pub struct PciRegIter<'dt> {
+    entries: RegIter<'dt>,
+}
+
+impl<'dt> FallibleIterator for PciRegIter<'dt> {
+    type Error = DevTreeError;
+    type Item = PciReg;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        match self.entries.next()? {
+            Some(entry) => Ok(Some(PciReg {
+                address: PciAddress::read(entry.raw(), 0)?,
+                size: entry.size()?,
+            })),
+            None => Ok(None),
+        }
+    }
+}
+
+/// An iterator over the [`PciRange`] entries of a PCI host bridge's `ranges` property.
+#[derive(Clone)]
+pub struct PciRangeIter<'dt> {
+    entries: RangeIter<'dt>,
+}
+
+impl<'dt> FallibleIterator for PciRangeIter<'dt> {
+    type Error = DevTreeError;
+    type Item = PciRange;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        match self.entries.next()? {
+            Some(entry) => Ok(Some(PciRange {
+                child: PciAddress::read(entry.raw(), 0)?,
+                parent: entry.parent()?,
+                size: entry.size()?,
+            })),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<'a, 'dt: 'a> DevTreeNode<'a, 'dt> {
+    /// Returns an iterator over the entries of this PCI device node's `reg` property.
+    ///
+    /// Returns `None` if the node doesn't have the property, or [`DevTreeError::ParseError`] if
+    /// its parent's addresses aren't [`PCI_ADDRESS_CELLS`] cells.
+    pub fn pci_reg(&self) -> Result<Option<PciRegIter<'dt>>> {
+        let parent = self.parent()?.ok_or(DevTreeError::ParseError)?;
+        if address_cells(&parent)? != PCI_ADDRESS_CELLS {
+            return Err(DevTreeError::ParseError);
+        }
+        Ok(self.reg()?.map(|entries| PciRegIter { entries }))
+    }
+
+    /// Returns an iterator over the entries of this PCI host bridge's `ranges` property (e.g.
+    /// its I/O and memory windows).
+    ///
+    /// Returns `None` if the node doesn't have the property, or [`DevTreeError::ParseError`] if
+    /// its addresses aren't [`PCI_ADDRESS_CELLS`] cells.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use fdt_rs::doctest::FDT;
+    /// use fdt_rs::prelude::*;
+    /// use fdt_rs::base::*;
+    /// use fdt_rs::bindings::*;
+    ///
+    /// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    /// let pci = devtree.node_at_path("/soc/pci@30000000").unwrap().unwrap();
+    /// let mut ranges = pci.pci_ranges().unwrap().unwrap();
+    /// let io = ranges.next().unwrap().unwrap();
+    /// assert_eq!(io.child.space(), PciSpace::Io);
+    /// assert_eq!((io.parent, io.size), (0x300_0000, 0x1_0000));
+    /// ```
+    pub fn pci_ranges(&self) -> Result<Option<PciRangeIter<'dt>>> {
+        if address_cells(self)? != PCI_ADDRESS_CELLS {
+            return Err(DevTreeError::ParseError);
+        }
+        Ok(self.ranges()?.map(|entries| PciRangeIter { entries }))
+    }
+}
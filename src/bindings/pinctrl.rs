@@ -0,0 +1,151 @@
+use core::mem::size_of;
+
+use crate::prelude::*;
+
+use crate::base::{DevTree, DevTreeNode, DevTreeProp};
+use crate::error::{DevTreeError, Result};
+use crate::spec::Phandle;
+
+const PINCTRL_PREFIX: &str = "pinctrl-";
+
+/// A pin configuration state of a device node, as described by its `pinctrl-<N>` and
+/// `pinctrl-names` properties.
+#[derive(Clone)]
+pub struct PinctrlState<'a, 'dt: 'a> {
+    id: usize,
+    name: Option<&'dt str>,
+    prop: DevTreeProp<'a, 'dt>,
+    fdt: &'a DevTree<'dt>,
+}
+
+impl<'a, 'dt: 'a> PinctrlState<'a, 'dt> {
+    /// Returns the state's ID, `N` in `pinctrl-<N>`.
+    #[must_use]
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns the state's name from the `pinctrl-names` property (if one was given).
+    #[must_use]
+    pub fn name(&self) -> Option<&'dt str> {
+        self.name
+    }
+
+    /// Returns the number of pin configuration nodes referenced by this state.
+    #[must_use]
+    pub fn num_configs(&self) -> usize {
+        self.prop.length() / size_of::<Phandle>()
+    }
+
+    /// Returns the phandle of the pin configuration node at the given index.
+    pub fn config_phandle(&self, index: usize) -> Result<Phandle> {
+        self.prop.phandle(index)
+    }
+
+    /// Returns an iterator over the pin configuration nodes referenced by this state.
+    #[must_use]
+    pub fn configs(&self) -> PinctrlConfigIter<'a, 'dt> {
+        PinctrlConfigIter {
+            state: self.clone(),
+            index: 0,
+        }
+    }
+}
+
+/// An iterator over the configuration nodes of a [`PinctrlState`].
+#[derive(Clone)]
+pub struct PinctrlConfigIter<'a, 'dt: 'a> {
+    state: PinctrlState<'a, 'dt>,
+    index: usize,
+}
+
+impl<'a, 'dt: 'a> FallibleIterator for PinctrlConfigIter<'a, 'dt> {
+    type Error = DevTreeError;
+    type Item = DevTreeNode<'a, 'dt>;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        if self.index >= self.state.num_configs() {
+            return Ok(None);
+        }
+        let phandle = self.state.config_phandle(self.index)?;
+        self.index += 1;
+
+        // A state which references a missing node indicates an invalid device tree.
+        self.state
+            .fdt
+            .node_by_phandle(phandle)?
+            .ok_or(DevTreeError::ParseError)
+            .map(Some)
+    }
+}
+
+/// An iterator over the [`PinctrlState`]s of a device node, in order of their IDs.
+#[derive(Clone)]
+pub struct PinctrlStateIter<'a, 'dt: 'a> {
+    node: DevTreeNode<'a, 'dt>,
+    id: usize,
+}
+
+impl<'a, 'dt: 'a> PinctrlStateIter<'a, 'dt> {
+    /// Create an iterator over the pin configuration states of `node`.
+    #[must_use]
+    pub fn new(node: DevTreeNode<'a, 'dt>) -> Self {
+        Self { node, id: 0 }
+    }
+
+    fn find_state_prop(&self) -> Result<Option<DevTreeProp<'a, 'dt>>> {
+        let id = self.id;
+        self.node.props().find(|prop| {
+            Ok(match prop.name()?.strip_prefix(PINCTRL_PREFIX) {
+                Some(suffix) => suffix.parse::<usize>() == Ok(id),
+                None => false,
+            })
+        })
+    }
+
+    fn find_state_name(&self) -> Result<Option<&'dt str>> {
+        match self.node.find_prop("pinctrl-names")? {
+            Some(names) => names.iter_str().nth(self.id),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<'a, 'dt: 'a> FallibleIterator for PinctrlStateIter<'a, 'dt> {
+    type Error = DevTreeError;
+    type Item = PinctrlState<'a, 'dt>;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        // States are numbered contiguously from zero.
+        let prop = match self.find_state_prop()? {
+            Some(prop) => prop,
+            None => return Ok(None),
+        };
+        if !prop.length().is_multiple_of(size_of::<Phandle>()) {
+            return Err(DevTreeError::ParseError);
+        }
+
+        let state = PinctrlState {
+            id: self.id,
+            name: self.find_state_name()?,
+            prop,
+            fdt: self.node.fdt(),
+        };
+        self.id += 1;
+        Ok(Some(state))
+    }
+}
+
+impl<'a, 'dt: 'a> DevTreeNode<'a, 'dt> {
+    /// Returns an iterator over this node's [`PinctrlState`]s.
+    #[must_use]
+    pub fn pinctrl_states(&self) -> PinctrlStateIter<'a, 'dt> {
+        PinctrlStateIter::new(self.clone())
+    }
+
+    /// Returns the [`PinctrlState`] with the given name in `pinctrl-names` (if it exists).
+    pub fn pinctrl_state(&self, name: &str) -> Result<Option<PinctrlState<'a, 'dt>>> {
+        self.pinctrl_states()
+            .find(|state| Ok(state.name() == Some(name)))
+    }
+}
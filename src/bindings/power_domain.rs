@@ -0,0 +1,20 @@
+use crate::base::DevTreeNode;
+use crate::error::Result;
+
+use super::{ProviderRef, ProviderRefIter};
+
+impl<'a, 'dt: 'a> DevTreeNode<'a, 'dt> {
+    /// Returns an iterator over the power domains listed in this node's `power-domains`
+    /// property.
+    ///
+    /// Each specifier is sized by the `#power-domain-cells` property of its provider.
+    pub fn power_domains(&self) -> Result<ProviderRefIter<'a, 'dt>> {
+        ProviderRefIter::new(self, "power-domains", "#power-domain-cells")
+    }
+
+    /// Returns the power domain with the given name in `power-domain-names` (if it exists).
+    pub fn power_domain(&self, name: &str) -> Result<Option<ProviderRef<'a, 'dt>>> {
+        self.power_domains()?
+            .find_by_name(self, "power-domain-names", name)
+    }
+}
@@ -0,0 +1,211 @@
+use core::mem::size_of;
+
+use crate::prelude::*;
+
+use crate::base::{DevTree, DevTreeNode};
+use crate::error::{DevTreeError, Result};
+use crate::spec::Phandle;
+
+/// A reference from a consumer node to a provider node, as found in properties of the form
+/// `<phandle specifier...>` (e.g. `power-domains` or `resets`).
+///
+/// The number of specifier cells is given by a `#<foo>-cells` property of the provider.
+#[derive(Clone)]
+pub struct ProviderRef<'a, 'dt: 'a> {
+    provider: DevTreeNode<'a, 'dt>,
+    phandle: Phandle,
+    specifier: &'dt [u8],
+}
+
+impl<'a, 'dt: 'a> ProviderRef<'a, 'dt> {
+    /// Returns the referenced provider node.
+    #[must_use]
+    pub fn provider(&self) -> DevTreeNode<'a, 'dt> {
+        self.provider.clone()
+    }
+
+    /// Returns the phandle of the provider node.
+    #[must_use]
+    pub fn phandle(&self) -> Phandle {
+        self.phandle
+    }
+
+    /// Returns the number of specifier cells which follow the phandle.
+    #[must_use]
+    pub fn num_cells(&self) -> usize {
+        self.specifier.len() / size_of::<u32>()
+    }
+
+    /// Returns the specifier cell at the given index.
+    pub fn cell(&self, index: usize) -> Result<u32> {
+        self.specifier
+            .read_be_u32(index * size_of::<u32>())
+            .or(Err(DevTreeError::InvalidOffset))
+    }
+
+    /// Returns the raw specifier cells.
+    #[must_use]
+    pub fn specifier(&self) -> &'dt [u8] {
+        self.specifier
+    }
+}
+
+/// An iterator over the [`ProviderRef`]s of a `<phandle specifier...>` property.
+#[derive(Clone)]
+pub struct ProviderRefIter<'a, 'dt: 'a> {
+    fdt: &'a DevTree<'dt>,
+    propbuf: &'dt [u8],
+    offset: usize,
+    cells_name: &'static str,
+}
+
+impl<'a, 'dt: 'a> ProviderRefIter<'a, 'dt> {
+    /// Create an iterator over the `prop_name` property of `node`, using the provider's
+    /// `cells_name` property to size each specifier.
+    ///
+    /// If the node doesn't have the property, the iterator is empty.
+    pub(crate) fn new(
+        node: &DevTreeNode<'a, 'dt>,
+        prop_name: &str,
+        cells_name: &'static str,
+    ) -> Result<Self> {
+        Ok(Self {
+            fdt: node.fdt(),
+            propbuf: node.find_prop(prop_name)?.map_or(&[], |prop| prop.raw()),
+            offset: 0,
+            cells_name,
+        })
+    }
+
+    /// Returns the [`ProviderRef`] at the position of `name` within the node's `names_prop`
+    /// string list property (if it exists).
+    pub(crate) fn find_by_name(
+        mut self,
+        node: &DevTreeNode<'a, 'dt>,
+        names_prop: &str,
+        name: &str,
+    ) -> Result<Option<ProviderRef<'a, 'dt>>> {
+        let names = match node.find_prop(names_prop)? {
+            Some(names) => names,
+            None => return Ok(None),
+        };
+        let index = match names.iter_str().position(|n| Ok(n == name))? {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        // Placeholders have names too, so count them.
+        for _ in 0..index {
+            if self.next_entry()?.is_none() {
+                return Ok(None);
+            }
+        }
+        Ok(self.next_entry()?.flatten())
+    }
+
+    /// Returns the next entry of the list, which is `None` for a placeholder.
+    ///
+    /// A phandle of 0 is a placeholder for a missing provider, and has no specifier.
+    fn next_entry(&mut self) -> Result<Option<Option<ProviderRef<'a, 'dt>>>> {
+        if self.offset >= self.propbuf.len() {
+            return Ok(None);
+        }
+
+        let phandle = self.propbuf.read_be_u32(self.offset)?;
+        let start = self.offset + size_of::<Phandle>();
+        if phandle == 0 {
+            self.offset = start;
+            return Ok(Some(None));
+        }
+
+        // A reference to a missing provider, or to a provider which doesn't describe its
+        // specifier size, indicates an invalid device tree.
+        let provider = self
+            .fdt
+            .node_by_phandle(phandle)?
+            .ok_or(DevTreeError::ParseError)?;
+        let num_cells = provider
+            .find_prop(self.cells_name)?
+            .ok_or(DevTreeError::ParseError)?
+            .u32(0)? as usize;
+
+        let end = num_cells
+            .checked_mul(size_of::<u32>())
+            .and_then(|len| start.checked_add(len))
+            .ok_or(DevTreeError::ParseError)?;
+        let specifier = self
+            .propbuf
+            .get(start..end)
+            .ok_or(DevTreeError::ParseError)?;
+        self.offset = end;
+
+        Ok(Some(Some(ProviderRef {
+            provider,
+            phandle,
+            specifier,
+        })))
+    }
+}
+
+impl<'a, 'dt: 'a> FallibleIterator for ProviderRefIter<'a, 'dt> {
+    type Error = DevTreeError;
+    type Item = ProviderRef<'a, 'dt>;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        loop {
+            match self.next_entry()? {
+                Some(Some(provider_ref)) => return Ok(Some(provider_ref)),
+                Some(None) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<'a, 'dt: 'a> DevTreeNode<'a, 'dt> {
+    /// Returns an iterator over the references of this node's `prop_name` property, a list of
+    /// `<phandle specifier...>` entries (e.g. `clocks`, `dmas` or `reset-gpios`).
+    ///
+    /// Each specifier is sized by the `cells_name` property of its provider (e.g.
+    /// `#clock-cells`). If the node doesn't have the property, the iterator is empty.
+    /// Placeholder entries of phandle 0 are skipped. Iteration returns
+    /// [`DevTreeError::ParseError`] if a provider can't be found or doesn't have the
+    /// `cells_name` property, or if the list ends part way through a specifier.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use fdt_rs::doctest::FDT;
+    /// use fdt_rs::prelude::*;
+    /// use fdt_rs::base::*;
+    ///
+    /// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    /// let clint = devtree.node_at_path("/soc/clint@2000000").unwrap().unwrap();
+    /// let mut refs = clint
+    ///     .provider_refs("interrupts-extended", "#interrupt-cells")
+    ///     .unwrap();
+    /// let timer = refs.next().unwrap().unwrap();
+    /// assert_eq!(timer.provider().name().unwrap(), "interrupt-controller");
+    /// assert_eq!(timer.cell(0).unwrap(), 3);
+    /// ```
+    pub fn provider_refs(
+        &self,
+        prop_name: &str,
+        cells_name: &'static str,
+    ) -> Result<ProviderRefIter<'a, 'dt>> {
+        ProviderRefIter::new(self, prop_name, cells_name)
+    }
+
+    /// Returns the reference of this node's `prop_name` property (see
+    /// [`DevTreeNode::provider_refs`]) at the position of `name` within its `names_prop` string
+    /// list property (e.g. `clock-names` or `dma-names`), if it exists.
+    pub fn provider_ref_by_name(
+        &self,
+        prop_name: &str,
+        cells_name: &'static str,
+        names_prop: &str,
+        name: &str,
+    ) -> Result<Option<ProviderRef<'a, 'dt>>> {
+        self.provider_refs(prop_name, cells_name)?
+            .find_by_name(self, names_prop, name)
+    }
+}
@@ -0,0 +1,443 @@
+use core::mem::size_of;
+
+use crate::prelude::*;
+
+use crate::base::{DevTree, DevTreeNode};
+use crate::error::{DevTreeError, Result};
+use crate::modify::MemReservation;
+use crate::spec::fdt_reserve_entry;
+
+use super::cells::{address_cells, read_cells, read_wide_cells, size_cells};
+
+/// An entry of a node's `reg` property.
+///
+/// The entry borrows the property's bytes. Its fields are only converted from big endian when
+/// they're read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegEntry<'dt> {
+    buf: &'dt [u8],
+    address_cells: usize,
+}
+
+impl<'dt> RegEntry<'dt> {
+    /// Returns the start of the register range in the parent's address space.
+    ///
+    /// An address wider than 64 bits is truncated to its least significant 64 bits, see
+    /// [`RegEntry::wide_address`].
+    pub fn address(&self) -> Result<u64> {
+        read_cells(self.buf, 0, self.address_cells)
+    }
+
+    /// Returns the length of the register range.
+    pub fn size(&self) -> Result<u64> {
+        read_cells(self.buf, self.address_cells, self.size_cells())
+    }
+
+    /// Returns the start of the register range, of up to 4 cells (e.g. of a system controller
+    /// whose `#address-cells` is 3).
+    ///
+    /// Returns [`DevTreeError::ParseError`] if the address is wider than 4 cells.
+    pub fn wide_address(&self) -> Result<u128> {
+        read_wide_cells(self.buf, 0, self.address_cells)
+    }
+
+    /// Returns the length of the register range, of up to 4 cells.
+    ///
+    /// Returns [`DevTreeError::ParseError`] if the length is wider than 4 cells.
+    pub fn wide_size(&self) -> Result<u128> {
+        read_wide_cells(self.buf, self.address_cells, self.size_cells())
+    }
+
+    fn size_cells(&self) -> usize {
+        self.buf.len() / size_of::<u32>() - self.address_cells
+    }
+
+    /// Returns the raw bytes of the entry.
+    #[must_use]
+    pub fn raw(&self) -> &'dt [u8] {
+        self.buf
+    }
+}
+
+/// An entry of a bus node's `ranges` property.
+///
+/// The entry borrows the property's bytes. Its fields are only converted from big endian when
+/// they're read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RangeEntry<'dt> {
+    buf: &'dt [u8],
+    child_cells: usize,
+    parent_cells: usize,
+}
+
+impl<'dt> RangeEntry<'dt> {
+    /// Returns the start of the range in the bus' (child) address space.
+    ///
+    /// An address wider than 64 bits (e.g. a PCI address) is truncated to its least significant
+    /// 64 bits, see [`RangeEntry::wide_child`].
+    pub fn child(&self) -> Result<u64> {
+        read_cells(self.buf, 0, self.child_cells)
+    }
+
+    /// Returns the start of the range in the bus' parent's address space.
+    pub fn parent(&self) -> Result<u64> {
+        read_cells(self.buf, self.child_cells, self.parent_cells)
+    }
+
+    /// Returns the length of the range.
+    pub fn size(&self) -> Result<u64> {
+        let index = self.child_cells + self.parent_cells;
+        read_cells(self.buf, index, self.buf.len() / size_of::<u32>() - index)
+    }
+
+    /// Returns the start of the range in the bus' address space, of up to 4 cells.
+    ///
+    /// Returns [`DevTreeError::ParseError`] if the address is wider than 4 cells.
+    pub fn wide_child(&self) -> Result<u128> {
+        read_wide_cells(self.buf, 0, self.child_cells)
+    }
+
+    /// Returns the start of the range in the bus' parent's address space, of up to 4 cells.
+    ///
+    /// Returns [`DevTreeError::ParseError`] if the address is wider than 4 cells.
+    pub fn wide_parent(&self) -> Result<u128> {
+        read_wide_cells(self.buf, self.child_cells, self.parent_cells)
+    }
+
+    /// Returns the length of the range, of up to 4 cells.
+    ///
+    /// Returns [`DevTreeError::ParseError`] if the length is wider than 4 cells.
+    pub fn wide_size(&self) -> Result<u128> {
+        let index = self.child_cells + self.parent_cells;
+        read_wide_cells(self.buf, index, self.buf.len() / size_of::<u32>() - index)
+    }
+
+    /// Returns the raw bytes of the entry.
+    #[must_use]
+    pub fn raw(&self) -> &'dt [u8] {
+        self.buf
+    }
+}
+
+/// An entry of the memory reservation block.
+///
+/// The entry borrows the device tree's bytes. Its fields are only converted from big endian when
+/// they're read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemReserveEntry<'dt> {
+    buf: &'dt [u8],
+}
+
+impl<'dt> MemReserveEntry<'dt> {
+    /// Returns the start of the reserved memory.
+    pub fn address(&self) -> Result<u64> {
+        read_cells(self.buf, 0, 2)
+    }
+
+    /// Returns the length of the reserved memory.
+    pub fn size(&self) -> Result<u64> {
+        read_cells(self.buf, 2, 2)
+    }
+
+    /// Returns a copy of the entry, as used by [`MemReserveEdits`][crate::modify::MemReserveEdits].
+    pub fn reservation(&self) -> Result<MemReservation> {
+        Ok(MemReservation::new(self.address()?, self.size()?))
+    }
+
+    /// Returns the raw bytes of the entry.
+    #[must_use]
+    pub fn raw(&self) -> &'dt [u8] {
+        self.buf
+    }
+}
+
+/// The entries of a device tree's memory reservation block, up to its terminating empty entry.
+///
+/// To change the block, copy the entries out with [`MemReserveMap::copy_into`], edit the copy
+/// and serialize the tree with [`MemReserveEdits::rewrite`].
+///
+/// [`MemReserveEdits::rewrite`]: crate::modify::MemReserveEdits::rewrite
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemReserveMap<'dt> {
+    buf: &'dt [u8],
+}
+
+impl<'dt> MemReserveMap<'dt> {
+    const ENTRY_LEN: usize = size_of::<fdt_reserve_entry>();
+
+    /// Returns the number of entries.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.buf.len() / Self::ENTRY_LEN
+    }
+
+    /// Returns whether the block has no entries.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Returns the entry at `index` (if it exists).
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<MemReserveEntry<'dt>> {
+        let start = index.checked_mul(Self::ENTRY_LEN)?;
+        self.buf
+            .get(start..start + Self::ENTRY_LEN)
+            .map(|buf| MemReserveEntry { buf })
+    }
+
+    /// Returns an iterator over the entries.
+    #[must_use]
+    pub fn iter(&self) -> MemReserveEntryIter<'dt> {
+        MemReserveEntryIter {
+            entries: EntryIter {
+                buf: self.buf,
+                offset: 0,
+                entry_len: Self::ENTRY_LEN,
+            },
+        }
+    }
+
+    /// Returns the first entry which overlaps the `size` bytes at `address` (if any).
+    pub fn overlapping(&self, address: u64, size: u64) -> Result<Option<MemReserveEntry<'dt>>> {
+        self.iter()
+            .find(|entry| Ok(entry.reservation()?.overlaps(address, size)))
+    }
+
+    /// Returns whether any entry overlaps the `size` bytes at `address`.
+    pub fn overlaps(&self, address: u64, size: u64) -> Result<bool> {
+        Ok(self.overlapping(address, size)?.is_some())
+    }
+
+    /// Copy the entries into the start of `out`, returning the part of `out` written.
+    ///
+    /// Returns [`DevTreeError::NotEnoughMemory`] if `out` has fewer than
+    /// [`MemReserveMap::len`] elements.
+    pub fn copy_into<'o>(&self, out: &'o mut [MemReservation]) -> Result<&'o mut [MemReservation]> {
+        let out = out
+            .get_mut(..self.len())
+            .ok_or(DevTreeError::NotEnoughMemory)?;
+        for (i, slot) in out.iter_mut().enumerate() {
+            // Unwrap okay, `i` is less than the number of entries.
+            *slot = self.get(i).unwrap().reservation()?;
+        }
+        Ok(out)
+    }
+}
+
+/// Splits a property value into entries of `entry_len` bytes.
+#[derive(Clone)]
+struct EntryIter<'dt> {
+    buf: &'dt [u8],
+    offset: usize,
+    entry_len: usize,
+}
+
+impl<'dt> EntryIter<'dt> {
+    fn next_entry(&mut self) -> Result<Option<&'dt [u8]>> {
+        if self.offset >= self.buf.len() || self.entry_len == 0 {
+            return Ok(None);
+        }
+        let entry = self
+            .buf
+            .get(self.offset..self.offset + self.entry_len)
+            .ok_or(DevTreeError::ParseError)?;
+        self.offset += self.entry_len;
+        Ok(Some(entry))
+    }
+}
+
+/// An iterator over the [`RegEntry`] entries of a node's `reg` property.
+#[derive(Clone)]
+pub struct RegIter<'dt> {
+    entries: EntryIter<'dt>,
+    address_cells: usize,
+}
+
+impl<'dt> FallibleIterator for RegIter<'dt> {
+    type Error = DevTreeError;
+    type Item = RegEntry<'dt>;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        Ok(self.entries.next_entry()?.map(|buf| RegEntry {
+            buf,
+            address_cells: self.address_cells,
+        }))
+    }
+}
+
+/// An iterator over the `(address, size)` pairs of a node's `reg` property, see
+/// [`DevTreeNode::reg_pairs`].
+#[derive(Clone)]
+pub struct RegPairIter<'dt> {
+    entries: RegIter<'dt>,
+}
+
+impl<'dt> FallibleIterator for RegPairIter<'dt> {
+    type Error = DevTreeError;
+    type Item = (u64, u64);
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        match self.entries.next()? {
+            Some(entry) => Ok(Some((entry.address()?, entry.size()?))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// An iterator over the [`RangeEntry`] entries of a bus node's `ranges` property.
+#[derive(Clone)]
+pub struct RangeIter<'dt> {
+    entries: EntryIter<'dt>,
+    child_cells: usize,
+    parent_cells: usize,
+}
+
+impl<'dt> FallibleIterator for RangeIter<'dt> {
+    type Error = DevTreeError;
+    type Item = RangeEntry<'dt>;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        Ok(self.entries.next_entry()?.map(|buf| RangeEntry {
+            buf,
+            child_cells: self.child_cells,
+            parent_cells: self.parent_cells,
+        }))
+    }
+}
+
+/// An iterator over the [`MemReserveEntry`] entries of a device tree's memory reservation block.
+#[derive(Clone)]
+pub struct MemReserveEntryIter<'dt> {
+    entries: EntryIter<'dt>,
+}
+
+impl<'dt> FallibleIterator for MemReserveEntryIter<'dt> {
+    type Error = DevTreeError;
+    type Item = MemReserveEntry<'dt>;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        // The block is terminated by an empty entry.
+        match self.entries.next_entry()? {
+            Some(buf) if buf.iter().any(|&b| b != 0) => Ok(Some(MemReserveEntry { buf })),
+            _ => {
+                self.entries.offset = self.entries.buf.len();
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl<'dt> DevTree<'dt> {
+    /// Returns a view of the entries of the memory reservation block.
+    ///
+    /// Returns [`DevTreeError::ParseError`] if the block isn't terminated by an empty entry
+    /// within the device tree.
+    pub fn mem_reserve_map(&self) -> Result<MemReserveMap<'dt>> {
+        let block = self
+            .buf()
+            .get(self.off_mem_rsvmap()..self.totalsize())
+            .ok_or(DevTreeError::ParseError)?;
+        let len = block
+            .chunks_exact(size_of::<fdt_reserve_entry>())
+            .position(|entry| entry.iter().all(|&b| b == 0))
+            .ok_or(DevTreeError::ParseError)?;
+        Ok(MemReserveMap {
+            buf: &block[..len * size_of::<fdt_reserve_entry>()],
+        })
+    }
+
+    /// Returns an iterator over the entries of the memory reservation block.
+    ///
+    /// Unlike [`DevTree::reserved_entries`], the entries borrow the device tree's bytes rather
+    /// than copying them.
+    #[must_use]
+    pub fn mem_reserve_entries(&self) -> MemReserveEntryIter<'dt> {
+        let start = self.off_mem_rsvmap();
+        MemReserveEntryIter {
+            entries: EntryIter {
+                buf: self.buf().get(start..).unwrap_or(&[]),
+                offset: 0,
+                entry_len: size_of::<fdt_reserve_entry>(),
+            },
+        }
+    }
+}
+
+impl<'a, 'dt: 'a> DevTreeNode<'a, 'dt> {
+    /// Returns an iterator over the entries of this node's `reg` property.
+    ///
+    /// The entries are sized by the parent node's `#address-cells` and `#size-cells`. Returns
+    /// `None` if the node doesn't have the property.
+    pub fn reg(&self) -> Result<Option<RegIter<'dt>>> {
+        let prop = match self.find_prop("reg")? {
+            Some(prop) => prop,
+            None => return Ok(None),
+        };
+        let parent = self.parent()?.ok_or(DevTreeError::ParseError)?;
+        let address_cells = address_cells(&parent)?;
+        Ok(Some(RegIter {
+            entries: EntryIter {
+                buf: prop.raw(),
+                offset: 0,
+                entry_len: (address_cells + size_cells(&parent)?) * size_of::<u32>(),
+            },
+            address_cells,
+        }))
+    }
+
+    /// Returns an iterator over the `(address, size)` pairs of this node's `reg` property.
+    ///
+    /// As with [`DevTreeNode::reg`], the pairs are sized by the parent node's `#address-cells`
+    /// and `#size-cells`, which aren't inherited from further up the tree and default to 2 and 1.
+    /// Returns [`DevTreeError::ParseError`] if either is more than 2 cells, which don't fit a
+    /// `u64` (e.g. PCI addresses), or if the property isn't a whole number of entries.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use fdt_rs::doctest::FDT;
+    /// use fdt_rs::prelude::*;
+    /// use fdt_rs::base::*;
+    ///
+    /// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    /// let uart = devtree.node_at_path("/uart@10000000").unwrap().unwrap();
+    /// let mut reg = uart.reg_pairs().unwrap().unwrap();
+    /// assert_eq!(reg.next().unwrap(), Some((0x1000_0000, 0x100)));
+    /// assert_eq!(reg.next().unwrap(), None);
+    /// ```
+    pub fn reg_pairs(&self) -> Result<Option<RegPairIter<'dt>>> {
+        let entries = match self.reg()? {
+            Some(entries) => entries,
+            None => return Ok(None),
+        };
+        let cells = entries.entries.entry_len / size_of::<u32>();
+        if entries.address_cells > 2 || cells - entries.address_cells > 2 {
+            return Err(DevTreeError::ParseError);
+        }
+        Ok(Some(RegPairIter { entries }))
+    }
+
+    /// Returns an iterator over the entries of this bus node's `ranges` property.
+    ///
+    /// Returns `None` if the node doesn't have the property. An empty iterator means the bus'
+    /// address space is identity mapped onto its parent's.
+    pub fn ranges(&self) -> Result<Option<RangeIter<'dt>>> {
+        let prop = match self.find_prop("ranges")? {
+            Some(prop) => prop,
+            None => return Ok(None),
+        };
+        let parent = self.parent()?.ok_or(DevTreeError::ParseError)?;
+        let child_cells = address_cells(self)?;
+        let parent_cells = address_cells(&parent)?;
+        Ok(Some(RangeIter {
+            entries: EntryIter {
+                buf: prop.raw(),
+                offset: 0,
+                entry_len: (child_cells + parent_cells + size_cells(self)?) * size_of::<u32>(),
+            },
+            child_cells,
+            parent_cells,
+        }))
+    }
+}
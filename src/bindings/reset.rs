@@ -0,0 +1,18 @@
+use crate::base::DevTreeNode;
+use crate::error::Result;
+
+use super::{ProviderRef, ProviderRefIter};
+
+impl<'a, 'dt: 'a> DevTreeNode<'a, 'dt> {
+    /// Returns an iterator over the reset lines listed in this node's `resets` property.
+    ///
+    /// Each specifier is sized by the `#reset-cells` property of its provider.
+    pub fn resets(&self) -> Result<ProviderRefIter<'a, 'dt>> {
+        ProviderRefIter::new(self, "resets", "#reset-cells")
+    }
+
+    /// Returns the reset line with the given name in `reset-names` (if it exists).
+    pub fn reset(&self, name: &str) -> Result<Option<ProviderRef<'a, 'dt>>> {
+        self.resets()?.find_by_name(self, "reset-names", name)
+    }
+}
@@ -0,0 +1,74 @@
+use crate::prelude::*;
+
+use crate::base::iters::DevTreeNodeIter;
+use crate::base::{DevTree, DevTreeNode};
+use crate::error::{DevTreeError, Result};
+
+/// An iterator adapter which skips the nodes which aren't enabled, see
+/// [`DevTreeNode::is_enabled`].
+///
+/// Only each node's own `status` is checked, so the children of a disabled node are still
+/// yielded if they're enabled themselves.
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::prelude::*;
+/// use fdt_rs::base::*;
+/// use fdt_rs::bindings::*;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let soc = devtree.node_at_path("/soc").unwrap().unwrap();
+/// let enabled = EnabledNodeIter::new(soc.children()).count().unwrap();
+/// assert_eq!(enabled, soc.children().count().unwrap());
+/// ```
+#[derive(Clone)]
+pub struct EnabledNodeIter<I> {
+    iter: I,
+}
+
+impl<I> EnabledNodeIter<I> {
+    /// Create an iterator over the enabled nodes yielded by `iter`.
+    pub fn new(iter: I) -> Self {
+        Self { iter }
+    }
+}
+
+impl<'a, 'dt: 'a, I> FallibleIterator for EnabledNodeIter<I>
+where
+    I: FallibleIterator<Item = DevTreeNode<'a, 'dt>, Error = DevTreeError>,
+{
+    type Error = DevTreeError;
+    type Item = DevTreeNode<'a, 'dt>;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        while let Some(node) = self.iter.next()? {
+            if node.is_enabled()? {
+                return Ok(Some(node));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'a, 'dt: 'a> DevTreeNode<'a, 'dt> {
+    /// Returns whether this node's `status` property marks the device as enabled: it's `okay`
+    /// (or the legacy `ok`), or absent. Any other value (`disabled`, `reserved`, `fail` or
+    /// `fail-<condition>`) means the device isn't to be used.
+    pub fn is_enabled(&self) -> Result<bool> {
+        match self.find_prop("status")? {
+            Some(prop) => Ok(matches!(prop.str()?, "okay" | "ok")),
+            None => Ok(true),
+        }
+    }
+}
+
+impl<'dt> DevTree<'dt> {
+    /// Returns an iterator over the enabled [`DevTreeNode`] objects, in the same order as
+    /// [`DevTree::nodes`].
+    #[must_use]
+    pub fn enabled_nodes(&self) -> EnabledNodeIter<DevTreeNodeIter<'_, 'dt>> {
+        EnabledNodeIter::new(self.nodes())
+    }
+}
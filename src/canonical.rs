@@ -0,0 +1,221 @@
+//! A canonical text dump of a device tree, for snapshot (golden file) tests of generated trees.
+//!
+//! Unlike a DTS listing, which is meant for people to read, the dump is meant to be compared.
+//! Two trees with the same contents give the same dump, however they're laid out:
+//!
+//! * Nodes are sorted by name, depth first, and properties by name. Memory reservations are
+//!   sorted by address.
+//! * Each line stands alone. Every node and property line starts with the node's full path, so
+//!   a diff shows where each change is.
+//! * Property values are written in full, with the type [`infer_prop_type`] guesses, in a fixed
+//!   width hex or quoted form.
+//! * The block layout (offsets and sizes), `Nop` tokens and the order of the strings block
+//!   aren't included.
+//!
+//! The format is versioned by [`CANONICAL_VERSION`], which is written on the first line. It is
+//! only changed, along with the version, when the dump would otherwise be wrong.
+//!
+//! # Format
+//!
+//! ```text
+//! fdt-canonical 1
+//! header version 17
+//! header last-comp-version 16
+//! header boot-cpuid-phys 0x00000000
+//! memreserve 0x0000000080000000 0x0000000000001000
+//! node /
+//! prop / #address-cells u32 0x00000002
+//! prop / compatible string "riscv-virtio"
+//! node /chosen
+//! prop /chosen bootargs empty
+//! ```
+//!
+//! Property values are one of:
+//!
+//! * `empty`
+//! * `string "..."` or `string-list "..." "..."`
+//! * `u32 0x00000001 ...` or `u64 0x0000000000000001 ...`
+//! * `bytes 01 02 ...`
+//!
+//! Bytes of names and strings which aren't printable (or are `"` or `\`) are written as `\xNN`.
+//!
+//! # Example
+//!
+//! ```
+//! # use fdt_rs::doctest::FDT;
+//! use fdt_rs::base::*;
+//!
+//! let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+//! let mut dump = String::new();
+//! devtree.write_canonical(&mut dump).unwrap();
+//! assert!(dump.starts_with("fdt-canonical 1\n"));
+//! assert!(dump.contains("\nprop /chosen stdout-path string \"/uart@10000000\"\n"));
+//! ```
+use core::fmt::Write;
+use core::str::from_utf8;
+
+use crate::prelude::*;
+
+use crate::base::parse::{DevTreeParseIter, ParsedTok};
+use crate::base::DevTree;
+use crate::error::{DevTreeError, Result};
+use crate::infer::{infer_prop_type, PropType};
+use crate::modify::sorted::{next_sorted, NodeContents};
+use crate::modify::MAX_DEPTH;
+use crate::priv_util::SliceRead;
+
+/// The version of the format written by [`DevTree::write_canonical`].
+pub const CANONICAL_VERSION: u32 = 1;
+
+/// Write `bytes`, escaping those which aren't printable.
+fn write_escaped(w: &mut dyn Write, bytes: &[u8]) -> Result<()> {
+    for &c in bytes {
+        if (c.is_ascii_graphic() || c == b' ') && c != b'"' && c != b'\\' {
+            w.write_char(c as char)?;
+        } else {
+            write!(w, "\\x{:02x}", c)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the path of the node whose path components (excluding the root) are `path`.
+fn write_path(w: &mut dyn Write, path: &[&[u8]]) -> Result<()> {
+    if path.is_empty() {
+        w.write_char('/')?;
+    }
+    for component in path {
+        w.write_char('/')?;
+        write_escaped(w, component)?;
+    }
+    Ok(())
+}
+
+fn write_value(w: &mut dyn Write, name: &str, value: &[u8]) -> Result<()> {
+    match infer_prop_type(name, value) {
+        PropType::Empty => w.write_str("empty")?,
+        ty @ (PropType::String | PropType::StringList) => {
+            w.write_str(match ty {
+                PropType::String => "string",
+                _ => "string-list",
+            })?;
+            for s in value[..value.len() - 1].split(|&c| c == 0) {
+                w.write_str(" \"")?;
+                write_escaped(w, s)?;
+                w.write_char('"')?;
+            }
+        }
+        PropType::U32Array => {
+            w.write_str("u32")?;
+            for i in (0..value.len()).step_by(4) {
+                write!(w, " 0x{:08x}", value.read_be_u32(i)?)?;
+            }
+        }
+        PropType::U64Array => {
+            w.write_str("u64")?;
+            for i in (0..value.len()).step_by(8) {
+                write!(w, " 0x{:016x}", value.read_be_u64(i)?)?;
+            }
+        }
+        PropType::Bytes => {
+            w.write_str("bytes")?;
+            for b in value {
+                write!(w, " {:02x}", b)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl<'dt> DevTree<'dt> {
+    /// Write the canonical text dump of the tree described in the
+    /// [module documentation](crate::canonical) to `w`.
+    ///
+    /// Sorting is done by re-parsing rather than in memory, so takes time quadratic in the size
+    /// of the tree. Returns [`DevTreeError::SinkError`] if `w` fails.
+    pub fn write_canonical(&self, w: &mut dyn Write) -> Result<()> {
+        writeln!(w, "fdt-canonical {}", CANONICAL_VERSION)?;
+        writeln!(w, "header version {}", self.version())?;
+        writeln!(w, "header last-comp-version {}", self.last_comp_version())?;
+        writeln!(w, "header boot-cpuid-phys 0x{:08x}", self.boot_cpuid_phys())?;
+
+        let reservations = || {
+            self.reserved_entries()
+                .enumerate()
+                .map(|(i, entry)| Ok((u64::from(entry.address), u64::from(entry.size), i)))
+        };
+        let mut last = None;
+        while let Some(entry) = next_sorted(reservations(), |&entry| entry, last.as_ref())? {
+            writeln!(w, "memreserve 0x{:016x} 0x{:016x}", entry.0, entry.1)?;
+            last = Some(entry);
+        }
+
+        let mut iter = self.parse_iter();
+        loop {
+            match iter.next()? {
+                Some(ParsedTok::BeginNode(_)) => {
+                    let mut path = [&[][..]; MAX_DEPTH];
+                    return self.write_canonical_node(w, iter.offset, &mut path, 0);
+                }
+                Some(ParsedTok::Nop) => (),
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Write the node whose contents start at `offset` (just after its BeginNode token), and its
+    /// subtree. The node's path components (excluding the root) are `path[..depth]`.
+    fn write_canonical_node<'p>(
+        &self,
+        w: &mut dyn Write,
+        offset: usize,
+        path: &mut [&'p [u8]; MAX_DEPTH],
+        depth: usize,
+    ) -> Result<()>
+    where
+        'dt: 'p,
+    {
+        w.write_str("node ")?;
+        write_path(w, &path[..depth])?;
+        w.write_char('\n')?;
+
+        let mut last = None;
+        while let Some((name, off, prop)) = next_sorted(
+            NodeContents::props(self, offset),
+            |&(name, off, _)| (name, off),
+            last.as_ref(),
+        )? {
+            let name_str = from_utf8(name)?;
+            w.write_str("prop ")?;
+            write_path(w, &path[..depth])?;
+            w.write_char(' ')?;
+            write_escaped(w, name)?;
+            w.write_char(' ')?;
+            write_value(w, name_str, prop.prop_buf)?;
+            w.write_char('\n')?;
+            last = Some((name, off));
+        }
+
+        let mut last = None;
+        while let Some(child) = next_sorted(
+            NodeContents::children(self, offset),
+            |&child| child,
+            last.as_ref(),
+        )? {
+            if depth + 1 == MAX_DEPTH {
+                return Err(DevTreeError::InvalidParameter(
+                    "Device tree is nested too deeply",
+                ));
+            }
+            path[depth] = child.0;
+            let mut iter = DevTreeParseIter {
+                offset: child.1,
+                fdt: self,
+            };
+            iter.next()?;
+            self.write_canonical_node(w, iter.offset, path, depth + 1)?;
+            last = Some(child);
+        }
+        Ok(())
+    }
+}
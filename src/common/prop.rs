@@ -5,6 +5,7 @@ use crate::prelude::*;
 
 use crate::base::DevTree;
 use crate::error::DevTreeError;
+use crate::infer::{infer_prop_type, PropType, PropValue};
 use crate::spec::Phandle;
 
 use crate::error::Result;
@@ -72,6 +73,18 @@ pub trait PropReader<'dt> {
             .or(Err(DevTreeError::InvalidOffset))
     }
 
+    /// Read a big-endian [`u64`] from the two cells starting at cell `index` of this property's
+    /// value, e.g. an address of a `reg` entry whose cells don't start on an 8 byte boundary.
+    ///
+    /// If the cells aren't both within this property's value an [`Err`] containing
+    /// [`DevTreeError::InvalidOffset`] will be returned.
+    #[inline]
+    fn u64_at_cell(&self, index: usize) -> Result<u64> {
+        self.propbuf()
+            .read_be_u64(index * size_of::<u32>())
+            .or(Err(DevTreeError::InvalidOffset))
+    }
+
     /// A Phandle is simply defined as a u32 value, as such this method performs the same action as
     /// [`self.u32`]
     #[inline]
@@ -94,7 +107,50 @@ pub trait PropReader<'dt> {
         self.iter_str().next()?.ok_or(DevTreeError::ParseError)
     }
 
-    /// Returns the property as a string fallible_iterator.
+    /// Returns the property's value as a list of [`Phandle`]s (e.g. of `interrupt-affinity` or
+    /// `cooling-device`).
+    ///
+    /// Returns [`DevTreeError::ParseError`] if the value isn't a whole number of cells. Props of
+    /// a [`DevTreeIndex`](crate::index::DevTreeIndex) also check every phandle refers to a node
+    /// of the tree, and return [`DevTreeError::ParseError`] if one doesn't.
+    fn as_phandle_list(&self) -> Result<CellIter<'dt>> {
+        let list = CellIter::new(self.propbuf())?;
+        for phandle in list.clone() {
+            if !self.is_known_phandle(phandle)? {
+                return Err(DevTreeError::ParseError);
+            }
+        }
+        Ok(list)
+    }
+
+    /// Returns an iterator over the cells of the property's value (e.g. of `interrupts` or
+    /// `clocks`).
+    ///
+    /// Returns [`DevTreeError::ParseError`] if the value isn't a whole number of cells.
+    fn iter_u32(&self) -> Result<CellIter<'dt>> {
+        CellIter::new(self.propbuf())
+    }
+
+    /// Returns an iterator over the property's value as 64 bit values of two cells each (e.g. of
+    /// `reg` on a platform whose addresses and sizes are two cells).
+    ///
+    /// Returns [`DevTreeError::ParseError`] if the value isn't a whole number of pairs of cells.
+    fn iter_u64(&self) -> Result<CellIter<'dt, 2>> {
+        CellIter::new(self.propbuf())
+    }
+
+    /// Returns whether `phandle` refers to a node of the tree, if the reader can tell cheaply.
+    /// Otherwise every phandle is assumed to.
+    #[doc(hidden)]
+    fn is_known_phandle(&self, _phandle: Phandle) -> Result<bool> {
+        Ok(true)
+    }
+
+    /// Returns an iterator over the strings of a string list property (e.g. `compatible`,
+    /// `clock-names` or `reg-names`), splitting its value on the null terminators.
+    ///
+    /// Iteration returns [`DevTreeError::ParseError`] on reaching a string which isn't null
+    /// terminated, or a [`DevTreeError::StrError`] if a string isn't UTF-8.
     /// # Safety
     ///
     /// See the safety note of [`PropReader::u32`]
@@ -111,11 +167,60 @@ pub trait PropReader<'dt> {
     fn raw(&self) -> &'dt [u8] {
         self.propbuf()
     }
+
+    /// Returns the guessed type of the property's value, see [`crate::infer`].
+    #[inline]
+    fn inferred_type(&self) -> Result<PropType> {
+        Ok(infer_prop_type(self.name()?, self.propbuf()))
+    }
+
+    /// Returns the property's value decoded as its [guessed type](PropReader::inferred_type).
+    #[inline]
+    fn value_typed(&self) -> Result<PropValue<'dt>> {
+        self.value_as(self.inferred_type()?)
+    }
+
+    /// Returns the property's value decoded as `ty`, for properties whose type is known (e.g.
+    /// from a binding) rather than guessed.
+    ///
+    /// A single value of an array type is decoded as [`PropValue::U32`] or
+    /// [`PropValue::U64`]. Returns [`DevTreeError::ParseError`] if the value isn't of the type:
+    /// e.g. a [`PropType::String`] which isn't a single null terminated UTF-8 string, or a
+    /// [`PropType::U64Array`] which isn't a multiple of 8 bytes long.
+    fn value_as(&self, ty: PropType) -> Result<PropValue<'dt>> {
+        let value = self.propbuf();
+        let cells = |size: usize| match value.len() {
+            len if len == 0 || len % size != 0 => Err(DevTreeError::ParseError),
+            len if len == size => Ok(None),
+            _ => Ok(Some(PropValue::CellArray(value))),
+        };
+        Ok(match ty {
+            PropType::Empty if value.is_empty() => PropValue::Empty,
+            PropType::Empty => return Err(DevTreeError::ParseError),
+            PropType::String => match value.strip_suffix(&[0]) {
+                Some(s) if !s.contains(&0) => PropValue::Str(from_utf8(s)?),
+                _ => return Err(DevTreeError::ParseError),
+            },
+            PropType::StringList if value.last() == Some(&0) => {
+                PropValue::StrList(StringPropIter::new(value))
+            }
+            PropType::StringList => return Err(DevTreeError::ParseError),
+            PropType::U32Array => match cells(size_of::<u32>())? {
+                Some(array) => array,
+                None => PropValue::U32(self.u32(0)?),
+            },
+            PropType::U64Array => match cells(size_of::<u64>())? {
+                Some(array) => array,
+                None => PropValue::U64(self.u64(0)?),
+            },
+            PropType::Bytes => PropValue::Bytes(value),
+        })
+    }
 }
 
 use fallible_iterator::FallibleIterator;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct StringPropIter<'dt> {
     offset: usize,
     propbuf: &'dt [u8],
@@ -145,3 +250,49 @@ impl<'dt> FallibleIterator for StringPropIter<'dt> {
         Ok(Some(from_utf8(u8_slice)?))
     }
 }
+
+/// An iterator over the values of `N` cells each of a property, see [`PropReader::iter_u32`],
+/// [`PropReader::iter_u64`] and [`PropReader::as_phandle_list`].
+#[derive(Debug, Clone)]
+pub struct CellIter<'dt, const N: usize = 1> {
+    offset: usize,
+    propbuf: &'dt [u8],
+}
+
+impl<'dt, const N: usize> CellIter<'dt, N> {
+    /// Returns [`DevTreeError::ParseError`] if `propbuf` isn't a whole number of values.
+    fn new(propbuf: &'dt [u8]) -> Result<Self> {
+        if !propbuf.len().is_multiple_of(N * size_of::<u32>()) {
+            return Err(DevTreeError::ParseError);
+        }
+        Ok(Self { offset: 0, propbuf })
+    }
+
+    /// Returns the offset of the next value, and advances past it.
+    fn advance(&mut self) -> Option<usize> {
+        let offset = self.offset;
+        if offset >= self.propbuf.len() {
+            return None;
+        }
+        self.offset += N * size_of::<u32>();
+        Some(offset)
+    }
+}
+
+impl<'dt> Iterator for CellIter<'dt, 1> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.advance()?;
+        self.propbuf.read_be_u32(offset).ok()
+    }
+}
+
+impl<'dt> Iterator for CellIter<'dt, 2> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.advance()?;
+        self.propbuf.read_be_u64(offset).ok()
+    }
+}
@@ -0,0 +1,317 @@
+//! Checks of a device tree against rules of the [devicetree specification], reported as a list
+//! of violations which tools (e.g. a CI job checking board device trees) can act on.
+//!
+//! Each [`Violation`] names the [`Rule`] broken, with a stable [`Rule::id`] and the
+//! specification section it comes from, its [`Severity`], and the node (and property, if any)
+//! at fault. [`DevTree::check_compliance`] hands each violation to a callback and returns a
+//! [`ComplianceSummary`] to gate on.
+//!
+//! The checks cover:
+//!
+//! * Node and property names (section 2.2).
+//! * The values of the standard properties `status`, `phandle`, `#address-cells`, `#size-cells`
+//!   and `reg` (section 2.3).
+//! * The required properties of the root node, and the required `/cpus` and `memory` nodes
+//!   (chapter 3).
+//!
+//! # Example
+//!
+//! ```
+//! # use fdt_rs::doctest::FDT;
+//! use fdt_rs::base::*;
+//! use fdt_rs::compliance::*;
+//! use fdt_rs::scratch::ScratchArena;
+//!
+//! let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+//! let summary = devtree
+//!     .check_compliance(|violation| {
+//!         let mut buf = [0u8; 256];
+//!         let path = violation.node.path(&mut ScratchArena::new(&mut buf))?;
+//!         println!("{:?} {} {}", violation.severity(), violation.rule.id(), path);
+//!         Ok(())
+//!     })
+//!     .unwrap();
+//! assert!(summary.is_compliant());
+//! ```
+//!
+//! [devicetree specification]: https://www.devicetree.org/specifications/
+use crate::prelude::*;
+
+use crate::base::{DevTree, DevTreeNode, DevTreeProp};
+use crate::bindings::cells::{address_cells, size_cells};
+use crate::error::Result;
+use crate::name::{base_name, device_type_matches, unit_address};
+
+/// How seriously a [`Rule`] is broken.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The tree is likely to work, but doesn't follow a recommendation of the specification.
+    Warning,
+    /// The tree breaks a requirement of the specification.
+    Error,
+}
+
+/// A rule of the devicetree specification which [`DevTree::check_compliance`] checks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// Node names are 1 to 31 characters from `[0-9a-zA-Z,._+-]` starting with a letter,
+    /// followed by an optional unit address.
+    NodeName,
+    /// Property names are 1 to 31 characters from `[0-9a-zA-Z,._+?#-]`.
+    PropertyName,
+    /// A node with a `reg` property has a unit address, and a node with a unit address has a
+    /// `reg` (or, for a bus, `ranges`) property.
+    UnitAddress,
+    /// `status` is `okay`, `disabled`, `reserved`, `fail` or `fail-` followed by a condition.
+    Status,
+    /// `phandle` is a single cell, other than 0 and `0xffffffff`, unique within the tree.
+    Phandle,
+    /// `#address-cells` and `#size-cells` are single cells.
+    Cells,
+    /// `reg` is a whole number of entries, sized by the parent's `#address-cells` and
+    /// `#size-cells`.
+    Reg,
+    /// The root node has `#address-cells`, `#size-cells`, `model` and `compatible` properties.
+    RootProperties,
+    /// The tree has a `/cpus` node.
+    CpusNode,
+    /// The tree has at least one `memory` node.
+    MemoryNode,
+}
+
+impl Rule {
+    /// Returns a short, stable identifier of the rule (e.g. `node-name`).
+    #[must_use]
+    pub fn id(&self) -> &'static str {
+        match self {
+            Rule::NodeName => "node-name",
+            Rule::PropertyName => "property-name",
+            Rule::UnitAddress => "unit-address",
+            Rule::Status => "status",
+            Rule::Phandle => "phandle",
+            Rule::Cells => "cells",
+            Rule::Reg => "reg",
+            Rule::RootProperties => "root-properties",
+            Rule::CpusNode => "cpus-node",
+            Rule::MemoryNode => "memory-node",
+        }
+    }
+
+    /// Returns the section of the specification (v0.4) the rule comes from.
+    #[must_use]
+    pub fn section(&self) -> &'static str {
+        match self {
+            Rule::NodeName => "2.2.1",
+            Rule::PropertyName => "2.2.4",
+            Rule::UnitAddress => "2.2.1.1",
+            Rule::Status => "2.3.4",
+            Rule::Phandle => "2.3.3",
+            Rule::Cells => "2.3.5",
+            Rule::Reg => "2.3.6",
+            Rule::RootProperties => "3.2",
+            Rule::CpusNode => "3.7",
+            Rule::MemoryNode => "3.4",
+        }
+    }
+
+    /// Returns how seriously a violation of the rule is taken.
+    #[must_use]
+    pub fn severity(&self) -> Severity {
+        match self {
+            Rule::UnitAddress => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+/// A violation of a [`Rule`] found by [`DevTree::check_compliance`].
+#[derive(Clone)]
+pub struct Violation<'a, 'dt: 'a> {
+    pub rule: Rule,
+    /// The node at fault. Violations of rules about the tree as a whole are reported against
+    /// the root node.
+    pub node: DevTreeNode<'a, 'dt>,
+    /// The name of the property at fault, or of a missing required property (if any).
+    pub prop: Option<&'dt str>,
+}
+
+impl<'a, 'dt: 'a> Violation<'a, 'dt> {
+    /// Returns the severity of the violated rule.
+    #[must_use]
+    pub fn severity(&self) -> Severity {
+        self.rule.severity()
+    }
+}
+
+/// The number of violations of each severity found by [`DevTree::check_compliance`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ComplianceSummary {
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+impl ComplianceSummary {
+    /// Returns whether no errors were found. Warnings don't affect compliance.
+    #[must_use]
+    pub fn is_compliant(&self) -> bool {
+        self.errors == 0
+    }
+}
+
+/// Returns whether `c` may appear in a node name.
+fn is_node_name_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b",._+-".contains(&c)
+}
+
+/// Returns whether `name` is a valid node name (not including the root's empty name).
+pub(crate) fn is_valid_node_name(name: &[u8]) -> bool {
+    let base = base_name(name);
+    let base_ok = (1..=31).contains(&base.len())
+        && base[0].is_ascii_alphabetic()
+        && base.iter().all(|&c| is_node_name_char(c));
+    let unit_ok = unit_address(name)
+        .is_none_or(|unit| !unit.is_empty() && unit.iter().all(|&c| is_node_name_char(c)));
+    base_ok && unit_ok
+}
+
+pub(crate) fn is_valid_prop_name(name: &str) -> bool {
+    (1..=31).contains(&name.len())
+        && name
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b",._+?#-".contains(&c))
+}
+
+fn is_valid_status(value: &[u8]) -> bool {
+    match value.strip_suffix(&[0]) {
+        Some(status) => {
+            matches!(status, b"okay" | b"disabled" | b"reserved" | b"fail")
+                || status.starts_with(b"fail-")
+        }
+        None => false,
+    }
+}
+
+/// Returns the phandle held by a single cell property, if it is a valid one.
+fn phandle_value(prop: &DevTreeProp) -> Option<u32> {
+    match prop.length() {
+        4 => prop.u32(0).ok().filter(|&p| p != 0 && p != u32::MAX),
+        _ => None,
+    }
+}
+
+/// Returns whether a node before `node` in `fdt` defines `phandle`.
+fn phandle_defined_before(fdt: &DevTree, node: &DevTreeNode, phandle: u32) -> Result<bool> {
+    let mut nodes = fdt.nodes();
+    while let Some(other) = nodes.next()? {
+        if other == *node {
+            return Ok(false);
+        }
+        if let Some(prop) = other.find_prop("phandle")? {
+            if phandle_value(&prop) == Some(phandle) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+impl<'dt> DevTree<'dt> {
+    /// Check the tree against the rules of the devicetree specification described in the
+    /// [module documentation](crate::compliance), calling `f` with each violation in tree order.
+    ///
+    /// Errors returned by `f` stop the check and are returned. Checking for duplicate phandles
+    /// re-parses the tree up to each phandle, so takes time quadratic in the size of the tree.
+    pub fn check_compliance<'a, F>(&'a self, mut f: F) -> Result<ComplianceSummary>
+    where
+        F: FnMut(&Violation<'a, 'dt>) -> Result<()>,
+    {
+        let mut summary = ComplianceSummary::default();
+        let mut report = |rule: Rule, node: &DevTreeNode<'a, 'dt>, prop: Option<&'dt str>| {
+            match rule.severity() {
+                Severity::Error => summary.errors += 1,
+                Severity::Warning => summary.warnings += 1,
+            }
+            f(&Violation {
+                rule,
+                node: node.clone(),
+                prop,
+            })
+        };
+
+        let root = match self.root()? {
+            Some(root) => root,
+            None => return Ok(summary),
+        };
+        for &name in &["#address-cells", "#size-cells", "model", "compatible"] {
+            if root.find_prop(name)?.is_none() {
+                report(Rule::RootProperties, &root, Some(name))?;
+            }
+        }
+
+        let mut has_cpus = false;
+        let mut has_memory = false;
+        let mut nodes = self.nodes();
+        while let Some(node) = nodes.next()? {
+            let parent = node.parent()?;
+            let name = node.name()?;
+            if let Some(parent) = &parent {
+                if !is_valid_node_name(name.as_bytes()) {
+                    report(Rule::NodeName, &node, None)?;
+                }
+                let has_reg = node.find_prop("reg")?.is_some();
+                let has_unit_address = unit_address(name.as_bytes()).is_some();
+                let mismatched = if has_unit_address {
+                    !has_reg && node.find_prop("ranges")?.is_none()
+                } else {
+                    has_reg
+                };
+                if mismatched {
+                    report(Rule::UnitAddress, &node, None)?;
+                }
+                has_cpus |= name == "cpus" && *parent == root;
+            }
+
+            let mut props = node.props();
+            while let Some(prop) = props.next()? {
+                let prop_name = prop.name()?;
+                if !is_valid_prop_name(prop_name) {
+                    report(Rule::PropertyName, &node, Some(prop_name))?;
+                }
+                let (rule, valid) = match prop_name {
+                    "status" => (Rule::Status, is_valid_status(prop.propbuf())),
+                    "phandle" => (
+                        Rule::Phandle,
+                        match phandle_value(&prop) {
+                            Some(phandle) => !phandle_defined_before(self, &node, phandle)?,
+                            None => false,
+                        },
+                    ),
+                    "#address-cells" | "#size-cells" => (Rule::Cells, prop.length() == 4),
+                    "reg" => match &parent {
+                        Some(parent) => {
+                            let entry_len = (address_cells(parent)? + size_cells(parent)?) * 4;
+                            (Rule::Reg, entry_len != 0 && prop.length() % entry_len == 0)
+                        }
+                        None => (Rule::Reg, true),
+                    },
+                    "device_type" => {
+                        has_memory |= device_type_matches(prop.propbuf(), "memory");
+                        continue;
+                    }
+                    _ => continue,
+                };
+                if !valid {
+                    report(rule, &node, Some(prop_name))?;
+                }
+            }
+        }
+
+        if !has_cpus {
+            report(Rule::CpusNode, &root, None)?;
+        }
+        if !has_memory {
+            report(Rule::MemoryNode, &root, None)?;
+        }
+        Ok(summary)
+    }
+}
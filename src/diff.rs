@@ -0,0 +1,258 @@
+//! A structural comparison of two device trees, e.g. to check that patching a tree only
+//! changed what it was meant to.
+//!
+//! [`DevTree::diff`] walks both trees in lockstep and returns a [`DiffRecord`] for each
+//! difference:
+//!
+//! * A node of one tree with no child of the same name at the same path of the other is added
+//!   or removed, along with its subtree. Its descendants aren't reported separately.
+//! * A property of one tree's node with no property of the same name in the other's is added
+//!   or removed.
+//! * A property whose value differs between the trees is changed.
+//!
+//! Nodes and properties are matched by name, so only the contents of the trees are compared.
+//! Their order, the block layout, `Nop` tokens, the header and the memory reservations aren't.
+//! Names should be unique within a node; where they aren't, only the first node or property of
+//! each name is compared.
+//!
+//! No allocator is required. Differences are found by re-parsing rather than in memory, so
+//! take time quadratic in the size of the trees.
+//!
+//! # Example
+//!
+//! ```
+//! # use fdt_rs::doctest::FDT;
+//! use fdt_rs::prelude::*;
+//! use fdt_rs::base::*;
+//! use fdt_rs::diff::DiffRecord;
+//! use fdt_rs::modify::*;
+//!
+//! let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+//! let mut buf = vec![0u32; FDT.len() / 4];
+//! let out = unsafe {
+//!     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, FDT.len())
+//! };
+//! let size = Serializer::modify(&devtree, out, |tok| match tok {
+//!     ModifyParsedTok::BeginNode(node, _) if node.name == b"cpus" => ModifyTokenResponse::Drop,
+//!     _ => ModifyTokenResponse::Pass,
+//! })
+//! .unwrap();
+//! let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+//!
+//! let mut diff = devtree.diff(&modified);
+//! match diff.next().unwrap() {
+//!     Some(DiffRecord::NodeRemoved(node)) => assert_eq!(node.name().unwrap(), "cpus"),
+//!     _ => panic!("Expected the cpus node to be removed"),
+//! }
+//! assert!(diff.next().unwrap().is_none());
+//! ```
+use core::str::from_utf8;
+
+use crate::prelude::*;
+
+use crate::base::iters::DevTreeIter;
+use crate::base::{DevTree, DevTreeNode, DevTreeProp};
+use crate::error::{DevTreeError, Result};
+use crate::modify::sorted::{contents, next_sorted, root_offset, NodeContents};
+use crate::modify::MAX_DEPTH;
+
+/// A difference between two device trees, see the [module documentation](crate::diff).
+///
+/// Nodes and properties of the first (old) tree are removed or changed, and those of the
+/// second (new) tree are added or changed.
+#[derive(Clone)]
+pub enum DiffRecord<'a, 'dt> {
+    /// A node of the new tree, with its subtree.
+    NodeAdded(DevTreeNode<'a, 'dt>),
+    /// A node of the old tree, with its subtree.
+    NodeRemoved(DevTreeNode<'a, 'dt>),
+    /// A property of the new tree.
+    PropAdded(DevTreeProp<'a, 'dt>),
+    /// A property of the old tree.
+    PropRemoved(DevTreeProp<'a, 'dt>),
+    /// A property whose value differs between the trees.
+    PropChanged {
+        old: DevTreeProp<'a, 'dt>,
+        new: DevTreeProp<'a, 'dt>,
+    },
+}
+
+/// A node which both trees have, whose contents are being compared.
+#[derive(Clone, Copy, Default)]
+struct Frame<'dt> {
+    /// The offsets of the node's BeginNode tokens in the old and new trees.
+    old: usize,
+    new: usize,
+    /// Whether all of the node's properties have been compared.
+    props_done: bool,
+    /// The name of the last property (or, once they're done, child) compared.
+    last: Option<&'dt [u8]>,
+}
+
+/// An iterator over the differences between two device trees, see [`DevTree::diff`].
+pub struct DevTreeDiff<'a, 'dt> {
+    old: &'a DevTree<'dt>,
+    new: &'a DevTree<'dt>,
+    started: bool,
+    stack: [Frame<'dt>; MAX_DEPTH],
+    depth: usize,
+}
+
+/// Returns the node of `fdt` whose BeginNode token is at `offset`.
+fn node_at<'a, 'dt>(fdt: &'a DevTree<'dt>, offset: usize) -> Result<DevTreeNode<'a, 'dt>> {
+    DevTreeIter::from_offset(fdt, offset)
+        .next_node()?
+        .ok_or(DevTreeError::ParseError)
+}
+
+/// Returns the property `name` of the node of `fdt` whose BeginNode token is at `offset`.
+fn prop_at<'a, 'dt>(
+    fdt: &'a DevTree<'dt>,
+    offset: usize,
+    name: &[u8],
+) -> Result<DevTreeProp<'a, 'dt>> {
+    node_at(fdt, offset)?
+        .find_prop(from_utf8(name)?)?
+        .ok_or(DevTreeError::ParseError)
+}
+
+/// Returns which of the next names of the old and new trees comes first: the old tree's, the
+/// new tree's, or both if they're equal.
+fn first<T, U>(old: &Option<(&[u8], T)>, new: &Option<(&[u8], U)>) -> (bool, bool) {
+    match (old, new) {
+        (Some((o, _)), Some((n, _))) => (o <= n, n <= o),
+        (old, new) => (old.is_some(), new.is_some()),
+    }
+}
+
+impl<'a, 'dt> DevTreeDiff<'a, 'dt> {
+    fn new(old: &'a DevTree<'dt>, new: &'a DevTree<'dt>) -> Self {
+        Self {
+            old,
+            new,
+            started: false,
+            stack: [Frame::default(); MAX_DEPTH],
+            depth: 0,
+        }
+    }
+
+    /// Start comparing the contents of a node which both trees have.
+    fn push(&mut self, old: usize, new: usize) -> Result<()> {
+        if self.depth == MAX_DEPTH {
+            return Err(DevTreeError::InvalidParameter(
+                "Device tree is nested too deeply",
+            ));
+        }
+        self.stack[self.depth] = Frame {
+            old,
+            new,
+            ..Frame::default()
+        };
+        self.depth += 1;
+        Ok(())
+    }
+}
+
+impl<'a, 'dt> FallibleIterator for DevTreeDiff<'a, 'dt> {
+    type Error = DevTreeError;
+    type Item = DiffRecord<'a, 'dt>;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        let (old, new) = (self.old, self.new);
+        if !self.started {
+            self.started = true;
+            self.push(root_offset(old)?, root_offset(new)?)?;
+        }
+        while let Some(top) = self.depth.checked_sub(1) {
+            let frame = &mut self.stack[top];
+            let (old_contents, new_contents) =
+                (contents(old, frame.old)?, contents(new, frame.new)?);
+
+            if !frame.props_done {
+                let key = |&(name, _, _): &(&'dt [u8], usize, _)| name;
+                let old_prop = next_sorted(
+                    NodeContents::props(old, old_contents),
+                    key,
+                    frame.last.as_ref(),
+                )?
+                .map(|(name, _, prop)| (name, prop));
+                let new_prop = next_sorted(
+                    NodeContents::props(new, new_contents),
+                    key,
+                    frame.last.as_ref(),
+                )?
+                .map(|(name, _, prop)| (name, prop));
+                let record = match (first(&old_prop, &new_prop), old_prop, new_prop) {
+                    (_, None, None) => {
+                        frame.props_done = true;
+                        frame.last = None;
+                        continue;
+                    }
+                    ((true, true), Some((name, o)), Some((_, n))) => {
+                        frame.last = Some(name);
+                        if o.prop_buf == n.prop_buf {
+                            continue;
+                        }
+                        DiffRecord::PropChanged {
+                            old: prop_at(old, frame.old, name)?,
+                            new: prop_at(new, frame.new, name)?,
+                        }
+                    }
+                    ((true, _), Some((name, _)), _) => {
+                        frame.last = Some(name);
+                        DiffRecord::PropRemoved(prop_at(old, frame.old, name)?)
+                    }
+                    (_, _, Some((name, _))) => {
+                        frame.last = Some(name);
+                        DiffRecord::PropAdded(prop_at(new, frame.new, name)?)
+                    }
+                    _ => return Err(DevTreeError::ParseError),
+                };
+                return Ok(Some(record));
+            }
+
+            let key = |&(name, _): &(&'dt [u8], usize)| name;
+            let old_child = next_sorted(
+                NodeContents::children(old, old_contents),
+                key,
+                frame.last.as_ref(),
+            )?;
+            let new_child = next_sorted(
+                NodeContents::children(new, new_contents),
+                key,
+                frame.last.as_ref(),
+            )?;
+            let record = match (first(&old_child, &new_child), old_child, new_child) {
+                (_, None, None) => {
+                    self.depth -= 1;
+                    continue;
+                }
+                ((true, true), Some((name, o)), Some((_, n))) => {
+                    frame.last = Some(name);
+                    self.push(o, n)?;
+                    continue;
+                }
+                ((true, _), Some((name, o)), _) => {
+                    frame.last = Some(name);
+                    DiffRecord::NodeRemoved(node_at(old, o)?)
+                }
+                (_, _, Some((name, n))) => {
+                    frame.last = Some(name);
+                    DiffRecord::NodeAdded(node_at(new, n)?)
+                }
+                _ => return Err(DevTreeError::ParseError),
+            };
+            return Ok(Some(record));
+        }
+        Ok(None)
+    }
+}
+
+impl<'dt> DevTree<'dt> {
+    /// Returns an iterator over the differences between this (old) tree and `new`, described
+    /// in the [module documentation](crate::diff).
+    #[must_use]
+    pub fn diff<'a>(&'a self, new: &'a DevTree<'dt>) -> DevTreeDiff<'a, 'dt> {
+        DevTreeDiff::new(self, new)
+    }
+}
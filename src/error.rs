@@ -2,8 +2,12 @@
 
 #[cfg(doc)]
 use crate::index::DevTreeIndex;
+#[cfg(doc)]
+use crate::modify::{DevTreeModifier, Patch};
+#[cfg(doc)]
+use crate::scratch::ScratchArena;
 
-use crate::priv_util::SliceReadError;
+use crate::priv_util::{SliceReadError, SliceWriteError};
 use core::fmt;
 use core::result;
 use core::str::Utf8Error;
@@ -28,8 +32,47 @@ pub enum DevTreeError {
     /// `str` sequences were encounter.
     StrError(Utf8Error),
 
-    /// There wasn't enough memory to create a [`DevTreeIndex`].
+    /// There wasn't enough memory to create a [`DevTreeIndex`], or left in a [`ScratchArena`].
     NotEnoughMemory,
+
+    /// The buffer holds only the first `available` bytes of a device tree (or its header) of
+    /// `needed` bytes.
+    Truncated {
+        needed: usize,
+        available: usize,
+    },
+
+    /// The output buffer is too small to serialize the device tree into.
+    ///
+    /// `needed` is the size which the write that failed required. The complete device tree may
+    /// need more.
+    OutputBufferTooSmall {
+        needed: usize,
+        available: usize,
+    },
+
+    /// A [`DevTreeModifier`]'s edits would make the device tree larger than its size budget.
+    ///
+    /// `edit` is the index of the first edit after which the projected size is over budget, or
+    /// `None` if the unmodified tree already is. `needed` is the size with every edit applied.
+    SizeBudgetExceeded {
+        edit: Option<usize>,
+        needed: usize,
+        budget: usize,
+    },
+
+    /// The sink a device tree was being serialized (or dumped) to failed.
+    SinkError,
+
+    /// A serialized [`DevTreeIndex`] was built from a different device tree than the one it's
+    /// being loaded for.
+    IndexMismatch,
+
+    /// A [`Patch`] doesn't apply to the device tree it's being applied to. `record` is the index
+    /// of the first record whose node or old value doesn't match.
+    PatchMismatch {
+        record: usize,
+    },
 }
 
 impl From<SliceReadError> for DevTreeError {
@@ -38,6 +81,22 @@ impl From<SliceReadError> for DevTreeError {
     }
 }
 
+impl From<SliceWriteError> for DevTreeError {
+    fn from(e: SliceWriteError) -> DevTreeError {
+        match e {
+            SliceWriteError::UnexpectedEndOfOutput(needed, available) => {
+                DevTreeError::OutputBufferTooSmall { needed, available }
+            }
+        }
+    }
+}
+
+impl From<fmt::Error> for DevTreeError {
+    fn from(_: fmt::Error) -> DevTreeError {
+        DevTreeError::SinkError
+    }
+}
+
 impl From<Utf8Error> for DevTreeError {
     fn from(e: Utf8Error) -> DevTreeError {
         DevTreeError::StrError(e)
@@ -61,10 +120,44 @@ impl fmt::Display for DevTreeError {
                 write!(f, "Failed to parse device tree string: {}", utf_err)
             }
 
-            DevTreeError::NotEnoughMemory => write!(
+            DevTreeError::NotEnoughMemory => {
+                write!(f, "Unable to fit the allocation into the provided buffer.")
+            }
+            DevTreeError::Truncated { needed, available } => write!(
+                f,
+                "Device tree truncated: {} bytes needed, {} available.",
+                needed, available
+            ),
+            DevTreeError::OutputBufferTooSmall { needed, available } => write!(
                 f,
-                "Unable to fit device tree index into the provided buffer."
+                "Output buffer too small: {} bytes needed, {} available.",
+                needed, available
             ),
+            DevTreeError::SizeBudgetExceeded {
+                edit: Some(edit),
+                needed,
+                budget,
+            } => write!(
+                f,
+                "Edit {} exceeds the size budget: {} bytes needed, {} allowed.",
+                edit, needed, budget
+            ),
+            DevTreeError::SizeBudgetExceeded {
+                edit: None,
+                needed,
+                budget,
+            } => write!(
+                f,
+                "Device tree exceeds the size budget: {} bytes needed, {} allowed.",
+                needed, budget
+            ),
+            DevTreeError::SinkError => write!(f, "Failed to write device tree to the sink."),
+            DevTreeError::IndexMismatch => {
+                write!(f, "Serialized index does not match the device tree.")
+            }
+            DevTreeError::PatchMismatch { record } => {
+                write!(f, "Patch record {} does not match the device tree.", record)
+            }
         }
     }
 }
@@ -4,6 +4,7 @@ use crate::prelude::*;
 
 use super::tree::DTINode;
 use super::{DevTreeIndex, DevTreeIndexItem, DevTreeIndexNode, DevTreeIndexProp};
+use crate::name::stringlist_contains;
 
 /***********************************/
 /***********  Node Siblings  *******/
@@ -210,7 +211,7 @@ impl<'a, 'i: 'a, 'dt: 'i> DevTreeIndexIter<'a, 'i, 'dt> {
             // Iterate through all remaining properties in the tree looking for the compatible
             // string.
             while let Some(prop) = self.next_prop() {
-                if prop.name().ok()? == "compatible" && prop.str().ok()? == string {
+                if prop.name().ok()? == "compatible" && stringlist_contains(prop.raw(), string) {
                     return Some(prop.node());
                 }
             }
@@ -13,6 +13,10 @@
 //! which operate on an optimized index. Some operations such as finding a node's parent may
 //! require `O(n^2)` time. To avoid this issue, we provide this module and related utilites.
 //!
+//! The index also keeps each node's children sorted by name, so
+//! [`DevTreeIndexNode::child_by_name`] finds a child of even a very wide node (e.g. hundreds of
+//! devices under `/soc`) in `O(log n)` time rather than by a linear scan.
+//!
 //! # Examples
 //!
 //! The same [`IterableDevTree`] trait used to implement [`DevTree`] methods is also implemented by
@@ -64,6 +68,7 @@ pub mod item;
 pub mod node;
 #[doc(hidden)]
 pub mod prop;
+mod serialize;
 #[doc(hidden)]
 pub mod tree;
 
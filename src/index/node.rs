@@ -4,6 +4,7 @@ use core::str::from_utf8;
 use super::iters::{DevTreeIndexIter, DevTreeIndexNodePropIter, DevTreeIndexNodeSiblingIter};
 use super::tree::{DTINode, DevTreeIndex};
 use crate::error::DevTreeError;
+use crate::name;
 
 #[derive(Clone)]
 pub struct DevTreeIndexNode<'a, 'i: 'a, 'dt: 'i> {
@@ -30,6 +31,27 @@ impl<'a, 'i: 'a, 'dt: 'i> DevTreeIndexNode<'a, 'i, 'dt> {
         from_utf8(self.node.name).map_err(DevTreeError::StrError)
     }
 
+    /// Returns the base name of this node, the part of its name before any `@`.
+    pub fn name_only(&self) -> Result<&'dt str, DevTreeError> {
+        from_utf8(name::base_name(self.node.name)).map_err(DevTreeError::StrError)
+    }
+
+    /// Returns the unit address of this node, the part of its name after the first `@`, or
+    /// `None` if its name has none.
+    pub fn unit_address(&self) -> Result<Option<&'dt str>, DevTreeError> {
+        name::unit_address(self.node.name)
+            .map(from_utf8)
+            .transpose()
+            .map_err(DevTreeError::StrError)
+    }
+
+    /// Returns whether this node's name matches `pattern`, with or without its unit address,
+    /// see [`node_name_matches`](crate::name::node_name_matches).
+    #[must_use]
+    pub fn name_matches(&self, pattern: &str) -> bool {
+        name::node_name_matches(self.node.name, pattern)
+    }
+
     pub fn siblings(&self) -> DevTreeIndexNodeSiblingIter<'a, 'i, 'dt> {
         DevTreeIndexNodeSiblingIter::from(DevTreeIndexIter::from_node(self.clone()))
     }
@@ -51,6 +73,26 @@ impl<'a, 'i: 'a, 'dt: 'i> DevTreeIndexNode<'a, 'i, 'dt> {
         }
     }
 
+    /// Returns the number of children of this node.
+    #[must_use]
+    pub fn num_children(&self) -> usize {
+        self.node.num_children()
+    }
+
+    /// Returns the child of this node whose name matches `name`, or `None` if there's none.
+    ///
+    /// As with [`DevTree::node_at_path`](crate::base::DevTree::node_at_path), a name without a
+    /// unit address (e.g. `uart`) matches a node name with one, and where several children
+    /// match, the first in tree order is returned. The index keeps each node's children sorted
+    /// by name, so the lookup takes time logarithmic in the number of children rather than
+    /// linear.
+    #[must_use]
+    pub fn child_by_name(&self, name: &str) -> Option<Self> {
+        self.node
+            .child_by_name(name)
+            .map(|child| Self::new(self.index, child))
+    }
+
     /// Returns true if `self` is a parent of the other [`DevTreeIndexNode`]
     pub fn is_parent_of(&self, other: &Self) -> bool {
         if let Some(parent) = &other.parent() {
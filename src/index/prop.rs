@@ -4,6 +4,8 @@ use crate::prelude::*;
 
 use crate::base::parse::ParsedProp;
 use crate::base::DevTree;
+use crate::error::Result;
+use crate::spec::Phandle;
 
 use super::tree::{DTINode, DTIProp, DevTreeIndex};
 use super::DevTreeIndexNode;
@@ -26,7 +28,7 @@ impl<'a, 'i: 'a, 'dt: 'i> PartialEq for DevTreeIndexProp<'a, 'i, 'dt> {
     }
 }
 
-impl<'r, 'a: 'r, 'i: 'a, 'dt: 'i> DevTreeIndexProp<'a, 'i, 'dt> {
+impl<'a, 'i: 'a, 'dt: 'i> DevTreeIndexProp<'a, 'i, 'dt> {
     pub(super) fn new(
         index: &'a DevTreeIndex<'i, 'dt>,
         node: &'a DTINode<'i, 'dt>,
@@ -57,6 +59,10 @@ impl<'a, 'i: 'a, 'dt: 'i> PropReader<'dt> for DevTreeIndexProp<'a, 'i, 'dt> {
     fn node(&self) -> DevTreeIndexNode<'a, 'i, 'dt> {
         DevTreeIndexNode::new(self.index, self.node)
     }
+
+    fn is_known_phandle(&self, phandle: Phandle) -> Result<bool> {
+        Ok(self.index.node_by_phandle(phandle).is_some())
+    }
 }
 
 impl<'dt> From<&ParsedProp<'dt>> for DTIProp<'dt> {
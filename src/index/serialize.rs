@@ -0,0 +1,267 @@
+use core::alloc::Layout;
+use core::mem::size_of;
+use core::ops::Range;
+use core::ptr;
+
+use num_traits::FromPrimitive;
+
+use crate::prelude::*;
+
+use super::tree::{DTINode, DevTreeIndex};
+use crate::base::parse::{ParsedBeginNode, ParsedProp, ParsedTok};
+use crate::base::DevTree;
+use crate::error::DevTreeError;
+use crate::priv_util::{SliceRead, SliceWrite};
+use crate::spec::FdtTok;
+
+// A serialized index is a header followed by the index's tokens, all big endian. Node names and
+// property values are stored as offsets into the device tree, so the index can only be loaded for
+// the device tree it was built from. The header records the tree's size and hash to check that.
+//
+// Header: magic, version, tree hash (u64), tree size, node count, property count.
+// BeginNode: token, name offset, name length.
+// Prop: token, value offset, value length, name offset in the strings block.
+// EndNode: token.
+const INDEX_MAGIC: u32 = 0x4644_5449;
+const INDEX_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 7 * size_of::<u32>();
+const BEGIN_NODE_SIZE: usize = 3 * size_of::<u32>();
+const PROP_SIZE: usize = 4 * size_of::<u32>();
+const END_NODE_SIZE: usize = size_of::<u32>();
+
+/// 64 bit FNV-1a hash of the device tree.
+fn hash(buf: &[u8]) -> u64 {
+    buf.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Checks the header of a serialized index, returning its node and property counts.
+fn read_header(blob: &[u8]) -> Result<(usize, usize), DevTreeError> {
+    if blob.read_be_u32(0)? != INDEX_MAGIC || blob.read_be_u32(4)? != INDEX_VERSION {
+        return Err(DevTreeError::ParseError);
+    }
+    Ok((
+        blob.read_be_u32(20)? as usize,
+        blob.read_be_u32(24)? as usize,
+    ))
+}
+
+fn same_node(a: Option<&DTINode>, b: Option<&DTINode>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => ptr::eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Parses the tokens of a serialized index.
+///
+/// The blob isn't trusted: every name and value must be where the device tree's own tokens put
+/// it, and the tokens must match the header's node and property counts.
+struct SerializedTokIter<'b, 'dt> {
+    blob: &'b [u8],
+    offset: usize,
+    fdt: &'dt [u8],
+    /// The structure block of `fdt`.
+    structs: Range<usize>,
+    /// The node and property counts left, per the header.
+    nodes: usize,
+    props: usize,
+}
+
+impl<'b, 'dt> SerializedTokIter<'b, 'dt> {
+    fn read_u32(&mut self) -> Result<usize, DevTreeError> {
+        let val = self.blob.read_be_u32(self.offset)?;
+        self.offset += size_of::<u32>();
+        Ok(val as usize)
+    }
+
+    /// Reads the offset and length of a slice of `fdt`, which must follow a token of `header`
+    /// cells (the token included) within the structure block.
+    fn read_fdt_slice(&mut self, header: usize) -> Result<(usize, &'dt [u8]), DevTreeError> {
+        let off = self.read_u32()?;
+        let len = self.read_u32()?;
+        let start = off.checked_sub(header * size_of::<u32>());
+        let end = off.checked_add(len);
+        match (start, end) {
+            (Some(start), Some(end))
+                if off.is_multiple_of(size_of::<u32>())
+                    && start >= self.structs.start
+                    && end <= self.structs.end =>
+            {
+                Ok((start, &self.fdt[off..end]))
+            }
+            _ => Err(DevTreeError::ParseError),
+        }
+    }
+
+    /// Returns whether the token at `offset` of `fdt` is `tok`.
+    fn is_tok(&self, offset: usize, tok: FdtTok) -> Result<bool, DevTreeError> {
+        Ok(self.fdt.read_be_u32(offset)? == tok as u32)
+    }
+}
+
+impl<'b, 'dt> FallibleIterator for SerializedTokIter<'b, 'dt> {
+    type Error = DevTreeError;
+    type Item = ParsedTok<'dt>;
+
+    fn next(&mut self) -> Result<Option<Self::Item>, Self::Error> {
+        if self.offset == self.blob.len() {
+            if self.nodes != 0 || self.props != 0 {
+                return Err(DevTreeError::ParseError);
+            }
+            return Ok(None);
+        }
+        match FdtTok::from_u32(self.read_u32()? as u32) {
+            Some(FdtTok::BeginNode) => {
+                // The name follows a BeginNode token, and is null terminated.
+                let (tok, name) = self.read_fdt_slice(1)?;
+                let end = tok + size_of::<u32>() + name.len();
+                if !self.is_tok(tok, FdtTok::BeginNode)? || self.fdt.get(end) != Some(&0) {
+                    return Err(DevTreeError::ParseError);
+                }
+                self.nodes = self.nodes.checked_sub(1).ok_or(DevTreeError::ParseError)?;
+                Ok(Some(ParsedTok::BeginNode(ParsedBeginNode { name })))
+            }
+            Some(FdtTok::Prop) => {
+                // The value follows a Prop token, its length and its name offset.
+                let (tok, prop_buf) = self.read_fdt_slice(3)?;
+                let name_offset = self.read_u32()?;
+                if !self.is_tok(tok, FdtTok::Prop)?
+                    || self.fdt.read_be_u32(tok + 4)? as usize != prop_buf.len()
+                    || self.fdt.read_be_u32(tok + 8)? as usize != name_offset
+                {
+                    return Err(DevTreeError::ParseError);
+                }
+                self.props = self.props.checked_sub(1).ok_or(DevTreeError::ParseError)?;
+                Ok(Some(ParsedTok::Prop(ParsedProp {
+                    prop_buf,
+                    name_offset,
+                })))
+            }
+            Some(FdtTok::EndNode) => Ok(Some(ParsedTok::EndNode)),
+            _ => Err(DevTreeError::ParseError),
+        }
+    }
+}
+
+struct BlobWriter<'b> {
+    blob: &'b mut [u8],
+    offset: usize,
+}
+
+impl<'b> BlobWriter<'b> {
+    fn write_u32(&mut self, val: usize) -> Result<(), DevTreeError> {
+        self.blob.write_be_u32(self.offset, val as u32)?;
+        self.offset += size_of::<u32>();
+        Ok(())
+    }
+}
+
+impl<'i, 'dt: 'i> DevTreeIndex<'i, 'dt> {
+    /// Returns the size of the blob [`DevTreeIndex::serialize`] writes.
+    #[must_use]
+    pub fn serialized_size(&self) -> usize {
+        let mut size = HEADER_SIZE;
+        let mut next = Some(self.root().node);
+        while let Some(node) = next {
+            size += BEGIN_NODE_SIZE + END_NODE_SIZE + node.num_props * PROP_SIZE;
+            next = node.next_dfs();
+        }
+        size
+    }
+
+    /// Serialize the index into `blob`, returning the number of bytes written.
+    ///
+    /// The blob may be stored and reloaded with [`DevTreeIndex::load`] for the same device tree,
+    /// skipping the parse required to build the index. Its size is given by
+    /// [`DevTreeIndex::serialized_size`].
+    pub fn serialize(&self, blob: &mut [u8]) -> Result<usize, DevTreeError> {
+        let fdt = self.buf();
+        let offset_of = |s: &[u8]| s.as_ptr() as usize - fdt.as_ptr() as usize;
+
+        let mut writer = BlobWriter {
+            blob,
+            offset: HEADER_SIZE,
+        };
+        let mut nodes = 0;
+        let mut props = 0;
+        // The last node written, which may still need to be closed.
+        let mut prev: Option<&DTINode> = None;
+        let mut next = Some(self.root().node);
+        loop {
+            // Close `prev` and any of its ancestors which aren't also the next node's.
+            let parent = next.and_then(DTINode::parent);
+            let mut open = prev;
+            while let Some(node) = open {
+                if same_node(open, parent) {
+                    break;
+                }
+                writer.write_u32(FdtTok::EndNode as usize)?;
+                open = node.parent();
+            }
+
+            let node = match next {
+                Some(node) => node,
+                None => break,
+            };
+            writer.write_u32(FdtTok::BeginNode as usize)?;
+            writer.write_u32(offset_of(node.name))?;
+            writer.write_u32(node.name.len())?;
+            for idx in 0..node.num_props {
+                // Unsafe okay, idx is within the node's props.
+                let prop = unsafe { node.prop_unchecked(idx) };
+                writer.write_u32(FdtTok::Prop as usize)?;
+                writer.write_u32(offset_of(prop.propbuf))?;
+                writer.write_u32(prop.propbuf.len())?;
+                writer.write_u32(prop.nameoff)?;
+            }
+            nodes += 1;
+            props += node.num_props;
+            prev = Some(node);
+            next = node.next_dfs();
+        }
+
+        let size = writer.offset;
+        let blob = writer.blob;
+        blob.write_be_u32(0, INDEX_MAGIC)?;
+        blob.write_be_u32(4, INDEX_VERSION)?;
+        blob.write_be_u64(8, hash(fdt))?;
+        blob.write_be_u32(16, fdt.len() as u32)?;
+        blob.write_be_u32(20, nodes as u32)?;
+        blob.write_be_u32(24, props as u32)?;
+        Ok(size)
+    }
+
+    /// Returns the layout of the buffer needed to [`DevTreeIndex::load`] a serialized index.
+    pub fn get_serialized_layout(blob: &[u8]) -> Result<Layout, DevTreeError> {
+        let (nodes, props) = read_header(blob)?;
+        Ok(Self::layout_for(nodes, props))
+    }
+
+    /// Rebuild an index of `fdt` serialized with [`DevTreeIndex::serialize`] into `buf`.
+    ///
+    /// Returns [`DevTreeError::IndexMismatch`] if the index was built from a different device
+    /// tree, or [`DevTreeError::ParseError`] if the blob is corrupt: if a node name or property
+    /// value it records isn't one of the device tree's, or its counts are wrong.
+    pub fn load(fdt: DevTree<'dt>, blob: &[u8], buf: &'i mut [u8]) -> Result<Self, DevTreeError> {
+        let (nodes, props) = read_header(blob)?;
+        if blob.read_be_u32(16)? as usize != fdt.buf().len()
+            || blob.read_be_u64(8)? != hash(fdt.buf())
+        {
+            return Err(DevTreeError::IndexMismatch);
+        }
+
+        let struct_start = fdt.off_dt_struct();
+        let mut iter = SerializedTokIter {
+            blob: blob.get(HEADER_SIZE..).ok_or(DevTreeError::ParseError)?,
+            offset: 0,
+            fdt: fdt.buf(),
+            structs: struct_start..struct_start + fdt.size_dt_struct() as usize,
+            nodes,
+            props,
+        };
+        Self::from_tokens(fdt, buf, &mut iter)
+    }
+}
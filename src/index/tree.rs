@@ -1,7 +1,8 @@
 use core::alloc::Layout;
 use core::marker::PhantomData;
 use core::mem::{align_of, size_of};
-use core::ptr::null_mut;
+use core::ptr::{self, null, null_mut};
+use core::slice;
 
 use crate::prelude::*;
 
@@ -14,6 +15,8 @@ use crate::base::iters::DevTreeIter;
 use crate::base::parse::{DevTreeParseIter, ParsedBeginNode, ParsedProp, ParsedTok};
 use crate::base::DevTree;
 use crate::error::DevTreeError;
+use crate::name::last_component;
+use crate::spec::Phandle;
 
 unsafe fn aligned_ptr_in<T>(buf: &mut [u8], offset: usize) -> Result<*mut T, DevTreeError> {
     // Get the aligned offset
@@ -35,6 +38,9 @@ pub(super) struct DTIProp<'dt> {
 pub struct DevTreeIndex<'i, 'dt: 'i> {
     fdt: DevTree<'dt>,
     root: *const DTINode<'i, 'dt>,
+    // Every node, sorted by phandle and then in tree order.
+    phandles: *const &'i DTINode<'i, 'dt>,
+    num_nodes: usize,
 }
 
 struct DTIBuilder<'i, 'dt: 'i> {
@@ -61,6 +67,14 @@ pub(super) struct DTINode<'i, 'dt: 'i> {
     // NOTE: We store props like C arrays. Props are a packed array after each node.
     // This is the number of props after this node in memory.
     pub(super) num_props: usize,
+
+    // The node's children sorted by name, for binary searches. Children of the same name are in
+    // tree order. The arrays of every node are packed after the last node's props.
+    children: *const &'i Self,
+    num_children: usize,
+
+    // The value of the node's `phandle` (or `linux,phandle`) property, or 0 if it has none.
+    phandle: Phandle,
     _index: PhantomData<&'i u8>,
 }
 
@@ -76,7 +90,7 @@ impl<'i, 'dt: 'i> DTINode<'i, 'dt> {
     }
 
     pub fn next_dfs(&self) -> Option<&'i DTINode<'i, 'dt>> {
-        unsafe { self.first_child().or_else(|| self.next.as_ref()) }
+        unsafe { self.first_child().or(self.next.as_ref()) }
     }
 
     pub fn next_sibling(&self) -> Option<&'i DTINode<'i, 'dt>> {
@@ -93,6 +107,53 @@ impl<'i, 'dt: 'i> DTINode<'i, 'dt> {
     pub fn parent(&self) -> Option<&'i DTINode<'i, 'dt>> {
         unsafe { self.parent.as_ref() }
     }
+
+    pub fn num_children(&self) -> usize {
+        self.num_children
+    }
+
+    /// Returns the node's children sorted by name.
+    fn sorted_children(&self) -> &'i [&'i DTINode<'i, 'dt>] {
+        if self.children.is_null() {
+            return &[];
+        }
+        // Unsafe okay, the index built `num_children` references at `children`.
+        unsafe { slice::from_raw_parts(self.children, self.num_children) }
+    }
+
+    /// Returns the first child in tree order whose name matches `pattern`, as libfdt matches
+    /// path components (see [`node_name_matches`](crate::name::node_name_matches)).
+    ///
+    /// The candidates are the children named `pattern` exactly and, if it has no unit address,
+    /// those named `pattern@` followed by any unit address. Each is a run of the sorted
+    /// children found by binary search.
+    pub fn child_by_name(&self, pattern: &str) -> Option<&'i DTINode<'i, 'dt>> {
+        let children = self.sorted_children();
+        let pattern = pattern.as_bytes();
+        let key = |child: &&DTINode<'i, 'dt>| last_component(child.name);
+
+        let start = children.partition_point(|child| key(child) < pattern);
+        let mut found = children
+            .get(start)
+            .filter(|child| key(child) == pattern)
+            .copied();
+        if !pattern.contains(&b'@') {
+            let start =
+                children.partition_point(|child| key(child).iter().lt(pattern.iter().chain(b"@")));
+            let with_address = children[start..].iter().take_while(|child| {
+                key(child)
+                    .strip_prefix(pattern)
+                    .is_some_and(|rest| rest.first() == Some(&b'@'))
+            });
+            // Nodes are allocated in tree order, so the lowest address is the first node.
+            for &child in with_address {
+                if found.is_none_or(|found| ptr::from_ref(child) < ptr::from_ref(found)) {
+                    found = Some(child);
+                }
+            }
+        }
+        found
+    }
 }
 
 impl<'i, 'dt: 'i> DTIBuilder<'i, 'dt> {
@@ -122,6 +183,11 @@ impl<'i, 'dt: 'i> DTIBuilder<'i, 'dt> {
 
                 name: node.name,
                 num_props: 0,
+                // set once every node has been parsed
+                children: null(),
+                num_children: 0,
+                // set once every node has been parsed
+                phandle: 0,
                 _index: PhantomData,
             };
 
@@ -210,6 +276,88 @@ impl<'i, 'dt: 'i> DTIBuilder<'i, 'dt> {
 
         Ok(())
     }
+
+    /// Build the sorted array of children of every node, once all nodes have been parsed.
+    ///
+    /// # Safety
+    ///
+    /// `root` must be the root node built by this builder.
+    unsafe fn sort_children(&mut self, root: *mut DTINode<'i, 'dt>) -> Result<(), DevTreeError> {
+        let mut next = root;
+        while !next.is_null() {
+            let mut first: *mut &DTINode = null_mut();
+            let mut count = 0;
+            let mut child = (*next).first_child();
+            while let Some(node) = child {
+                // The pointers are the same size as their alignment, so each slot follows the
+                // last.
+                let slot = self.allocate_aligned_ptr::<&DTINode>()?;
+                *slot = node;
+                if first.is_null() {
+                    first = slot;
+                }
+                count += 1;
+                child = node.next_sibling();
+            }
+            if count > 0 {
+                slice::from_raw_parts_mut(first, count).sort_unstable_by(|a, b| {
+                    last_component(a.name)
+                        .cmp(last_component(b.name))
+                        .then(ptr::from_ref(*a).cmp(&ptr::from_ref(*b)))
+                });
+            }
+            (*next).children = first as *const &DTINode;
+            (*next).num_children = count;
+            next = match (*next).next_dfs() {
+                Some(node) => node as *const DTINode as *mut DTINode,
+                None => null_mut(),
+            };
+        }
+        Ok(())
+    }
+
+    /// Read the phandle of every node and build the array of all nodes sorted by phandle, once
+    /// all nodes have been parsed. Returns the array and its length.
+    ///
+    /// # Safety
+    ///
+    /// `root` must be the root node built by this builder.
+    unsafe fn sort_phandles(
+        &mut self,
+        fdt: &DevTree<'dt>,
+        root: *mut DTINode<'i, 'dt>,
+    ) -> Result<(*const &'i DTINode<'i, 'dt>, usize), DevTreeError> {
+        let mut first: *mut &DTINode = null_mut();
+        let mut count = 0;
+        let mut next = root;
+        while !next.is_null() {
+            for idx in 0..(*next).num_props {
+                let prop = (*next).prop_unchecked(idx);
+                let name = fdt
+                    .buf()
+                    .read_bstring0(fdt.off_dt_strings() + prop.nameoff)?;
+                // `phandle` takes precedence over the legacy `linux,phandle`.
+                if name == b"phandle" || (name == b"linux,phandle" && (*next).phandle == 0) {
+                    (*next).phandle = prop.propbuf.read_be_u32(0)?;
+                }
+            }
+            let slot = self.allocate_aligned_ptr::<&DTINode>()?;
+            *slot = &*next;
+            if first.is_null() {
+                first = slot;
+            }
+            count += 1;
+            next = match (*next).next_dfs() {
+                Some(node) => node as *const DTINode as *mut DTINode,
+                None => null_mut(),
+            };
+        }
+        // Nodes are allocated in tree order, so where phandles are duplicated the first node
+        // sorts first.
+        slice::from_raw_parts_mut(first, count)
+            .sort_unstable_by_key(|node| (node.phandle, ptr::from_ref(*node)));
+        Ok((first as *const &DTINode, count))
+    }
 }
 
 impl<'i, 'dt: 'i> DevTreeIndex<'i, 'dt> {
@@ -221,10 +369,13 @@ impl<'i, 'dt: 'i> DevTreeIndex<'i, 'dt> {
     //   - This parsing method only requires a single allocation. (The buffer given as buf)
     //   - This parsing method only requires a single iteration over the FDT.
     // - It is very easy to test in isolation; parsing is entirely enclosed to this module.
-    unsafe fn init_builder<'a>(
+    unsafe fn init_builder<I>(
         buf: &'i mut [u8],
-        iter: &mut DevTreeParseIter<'a, 'dt>,
-    ) -> Result<DTIBuilder<'i, 'dt>, DevTreeError> {
+        iter: &mut I,
+    ) -> Result<DTIBuilder<'i, 'dt>, DevTreeError>
+    where
+        I: FallibleIterator<Item = ParsedTok<'dt>, Error = DevTreeError>,
+    {
         let mut builder = DTIBuilder {
             front_off: 0,
             buf,
@@ -247,9 +398,6 @@ impl<'i, 'dt: 'i> DevTreeIndex<'i, 'dt> {
     }
 
     pub fn get_layout(fdt: &'i DevTree<'dt>) -> Result<Layout, DevTreeError> {
-        // Size may require alignment of DTINode.
-        let mut size = 0usize;
-
         // We assert this because it makes size calculations easier.
         // We don't have to worry about re-aligning between props and nodes.
         // If they didn't have the same alignment, we would have to keep track
@@ -265,34 +413,56 @@ impl<'i, 'dt: 'i> DevTreeIndex<'i, 'dt> {
         // + align_of::<DTINode> + size_of::<DTINode>
         // + size_of::<DTINode>
         const_assert_eq!(align_of::<DTINode>(), align_of::<DTIProp>());
+        const_assert_eq!(align_of::<DTINode>(), align_of::<*const DTINode>());
 
+        let mut nodes = 0;
+        let mut props = 0;
         let mut iter = DevTreeIter::new(fdt);
         while let Some(item) = iter.next()? {
             match item {
-                DevTreeItem::Node(_) => size += size_of::<DTINode>(),
-                DevTreeItem::Prop(_) => size += size_of::<DTIProp>(),
+                DevTreeItem::Node(_) => nodes += 1,
+                DevTreeItem::Prop(_) => props += 1,
             }
         }
+        Ok(Self::layout_for(nodes, props))
+    }
+
+    /// Returns the layout of an index of `nodes` nodes and `props` properties.
+    pub(super) fn layout_for(nodes: usize, props: usize) -> Layout {
+        // Each node but the root is also in its parent's sorted array of children, and every node
+        // is in the array sorted by phandle.
+        let size = nodes * size_of::<DTINode>()
+            + nodes.saturating_sub(1) * size_of::<*const DTINode>()
+            + nodes * size_of::<*const DTINode>()
+            + props * size_of::<DTIProp>();
 
         // Unsafe okay.
         // - Size is not likely to be usize::MAX. (There's no way we find that many nodes.)
         // - Align is a result of align_of, so it will be a non-zero power of two
-        unsafe {
-            Ok(Layout::from_size_align_unchecked(
-                size,
-                align_of::<DTINode>(),
-            ))
-        }
+        unsafe { Layout::from_size_align_unchecked(size, align_of::<DTINode>()) }
     }
 
     pub fn new(fdt: DevTree<'dt>, buf: &'i mut [u8]) -> Result<Self, DevTreeError> {
         let mut iter = DevTreeParseIter::new(&fdt);
+        Self::from_tokens(fdt, buf, &mut iter)
+    }
 
-        let mut builder = unsafe { Self::init_builder(buf, &mut iter) }?;
-
-        let this = Self {
+    /// Build the index of `fdt` from its tokens, as parsed by `iter`.
+    pub(super) fn from_tokens<I>(
+        fdt: DevTree<'dt>,
+        buf: &'i mut [u8],
+        iter: &mut I,
+    ) -> Result<Self, DevTreeError>
+    where
+        I: FallibleIterator<Item = ParsedTok<'dt>, Error = DevTreeError>,
+    {
+        let mut builder = unsafe { Self::init_builder(buf, iter) }?;
+
+        let mut this = Self {
             fdt,
             root: builder.cur_node,
+            phandles: null(),
+            num_nodes: 0,
         };
 
         // The builder should have setup a root node or returned an Err.
@@ -316,6 +486,13 @@ impl<'i, 'dt: 'i> DevTreeIndex<'i, 'dt> {
                 ParsedTok::Nop => continue,
             }
         }
+        // Unsafe okay, the root was built by the builder.
+        unsafe { builder.sort_children(this.root as *mut DTINode) }?;
+        // Unsafe okay, as above.
+        let (phandles, num_nodes) =
+            unsafe { builder.sort_phandles(&this.fdt, this.root as *mut DTINode) }?;
+        this.phandles = phandles;
+        this.num_nodes = num_nodes;
         Ok(this)
     }
 
@@ -328,6 +505,26 @@ impl<'i, 'dt: 'i> DevTreeIndex<'i, 'dt> {
         &self.fdt
     }
 
+    /// Returns the [`DevTreeIndexNode`] whose `phandle` (or legacy `linux,phandle`) property
+    /// matches the given [`Phandle`], or `None` if no node has it.
+    ///
+    /// The lookup is a binary search of the nodes, which the index sorts by phandle when it is
+    /// built. Where phandles are duplicated, the first node in tree order wins.
+    #[must_use]
+    pub fn node_by_phandle(&self, phandle: Phandle) -> Option<DevTreeIndexNode<'_, 'i, 'dt>> {
+        // Nodes without a phandle sort as 0, which is never a valid phandle.
+        if phandle == 0 {
+            return None;
+        }
+        // Unsafe okay, the index built `num_nodes` references at `phandles`.
+        let nodes = unsafe { slice::from_raw_parts(self.phandles, self.num_nodes) };
+        let start = nodes.partition_point(|node| node.phandle < phandle);
+        nodes
+            .get(start)
+            .filter(|node| node.phandle == phandle)
+            .map(|node| DevTreeIndexNode::new(self, node))
+    }
+
     #[must_use]
     pub fn nodes(&self) -> DevTreeIndexNodeIter<'_, 'i, 'dt> {
         DevTreeIndexNodeIter(self.items())
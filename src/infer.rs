@@ -0,0 +1,185 @@
+//! Heuristics which guess the type of a property's value, as `dtc` and `fdtdump` do when
+//! decompiling a device tree.
+//!
+//! A device tree doesn't record the types of its values, so tools which print them (e.g. as
+//! DTS) guess from the bytes. [`infer_value_type`] applies the same rules as `dtc`:
+//!
+//! * A value of printable, non-empty, null terminated strings is a string (or a list of them).
+//! * Otherwise, a value which is a multiple of 4 bytes long is an array of cells.
+//! * Anything else is bytes.
+//!
+//! [`infer_prop_type`] also takes the property's name into account, for the few standard
+//! properties whose cells are known to pair up into 64 bit values. It backs
+//! [`PropReader::inferred_type`], [`PropReader::value_typed`], which decodes the value as a
+//! [`PropValue`], and [`format_prop`], which logs a single property as a line of DTS.
+//!
+//! # Example
+//!
+//! ```
+//! use fdt_rs::infer::*;
+//!
+//! assert_eq!(infer_value_type(b"okay\0"), PropType::String);
+//! assert_eq!(infer_value_type(b"ns16550a\0serial\0"), PropType::StringList);
+//! assert_eq!(infer_value_type(&[0, 0, 0, 1]), PropType::U32Array);
+//! assert_eq!(infer_prop_type("cpu-release-addr", &[0; 8]), PropType::U64Array);
+//!
+//! let mut line = String::new();
+//! format_prop("compatible", b"ns16550a\0", &mut line).unwrap();
+//! assert_eq!(line, "compatible = \"ns16550a\"");
+//! ```
+use core::fmt::Write;
+
+use crate::common::prop::StringPropIter;
+#[cfg(doc)]
+use crate::error::DevTreeError;
+use crate::error::Result;
+#[cfg(doc)]
+use crate::prelude::PropReader;
+use crate::priv_util::SliceRead;
+
+/// The guessed type of a property's value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PropType {
+    /// No value, as for a boolean property.
+    Empty,
+    /// A single null terminated string.
+    String,
+    /// Several null terminated strings.
+    StringList,
+    /// Big endian 32 bit cells.
+    U32Array,
+    /// Big endian 64 bit values, each of two cells.
+    U64Array,
+    /// Anything else.
+    Bytes,
+}
+
+/// A property's value, decoded as the type [`PropReader::value_typed`] guesses or
+/// [`PropReader::value_as`] is given.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PropValue<'dt> {
+    /// No value, as for a boolean property.
+    Empty,
+    /// A single cell.
+    U32(u32),
+    /// A single 64 bit value of two cells.
+    U64(u64),
+    /// A single string.
+    Str(&'dt str),
+    /// Several strings.
+    StrList(StringPropIter<'dt>),
+    /// Big endian 32 bit cells (or 64 bit values of two cells) other than a single one. Its
+    /// length is a multiple of the value size.
+    CellArray(&'dt [u8]),
+    /// Anything else.
+    Bytes(&'dt [u8]),
+}
+
+/// Properties whose values are 64 bit quantities when they're 8 bytes long.
+const U64_PROPS: &[&str] = &["cpu-release-addr", "linux,initrd-start", "linux,initrd-end"];
+
+/// Returns the number of strings in `value` if it consists only of printable, non-empty, null
+/// terminated strings.
+fn count_strings(value: &[u8]) -> Option<usize> {
+    let value = value.strip_suffix(&[0])?;
+    let mut count = 0;
+    for s in value.split(|&c| c == 0) {
+        if s.is_empty() || !s.iter().all(|&c| c.is_ascii_graphic() || c == b' ') {
+            return None;
+        }
+        count += 1;
+    }
+    Some(count)
+}
+
+/// Guess the type of a property value from its bytes alone, as `dtc` does.
+#[must_use]
+pub fn infer_value_type(value: &[u8]) -> PropType {
+    if value.is_empty() {
+        return PropType::Empty;
+    }
+    match count_strings(value) {
+        Some(1) => PropType::String,
+        Some(_) => PropType::StringList,
+        None if value.len().is_multiple_of(4) => PropType::U32Array,
+        None => PropType::Bytes,
+    }
+}
+
+/// Guess the type of the value of the property `name`.
+///
+/// As [`infer_value_type`], except that the values of properties known to hold a 64 bit
+/// quantity (e.g. `cpu-release-addr`) are [`PropType::U64Array`] when they're 8 bytes long.
+#[must_use]
+pub fn infer_prop_type(name: &str, value: &[u8]) -> PropType {
+    match infer_value_type(value) {
+        PropType::U32Array if value.len() == 8 && U64_PROPS.contains(&name) => PropType::U64Array,
+        inferred => inferred,
+    }
+}
+
+/// Write the property `name` with `value` to `w` as DTS (without the trailing `;`), in the form
+/// [`infer_prop_type`] guesses:
+///
+/// * `status = "okay"` or `compatible = "ns16550a", "serial"`
+/// * `reg = <0x10000000 0x100>`
+/// * `cpu-release-addr = /bits/ 64 <0x80000000>`
+/// * `local-mac-address = [02 00 00 00 00 01]`
+/// * `interrupt-controller`, for an empty value
+///
+/// `"` and `\` are escaped within strings. Returns
+/// [`DevTreeError::SinkError`] if `w` fails.
+pub fn format_prop(name: &str, value: &[u8], w: &mut dyn Write) -> Result<()> {
+    w.write_str(name)?;
+    match infer_prop_type(name, value) {
+        PropType::Empty => return Ok(()),
+        PropType::String | PropType::StringList => {
+            w.write_str(" = ")?;
+            for (i, s) in value[..value.len() - 1].split(|&c| c == 0).enumerate() {
+                if i > 0 {
+                    w.write_str(", ")?;
+                }
+                w.write_char('"')?;
+                // Strings are printable, so only need quotes and backslashes escaped.
+                for &c in s {
+                    if c == b'"' || c == b'\\' {
+                        w.write_char('\\')?;
+                    }
+                    w.write_char(c as char)?;
+                }
+                w.write_char('"')?;
+            }
+        }
+        PropType::U32Array => {
+            w.write_str(" = <")?;
+            for i in (0..value.len()).step_by(4) {
+                if i > 0 {
+                    w.write_char(' ')?;
+                }
+                write!(w, "{:#x}", value.read_be_u32(i)?)?;
+            }
+            w.write_char('>')?;
+        }
+        PropType::U64Array => {
+            w.write_str(" = /bits/ 64 <")?;
+            for i in (0..value.len()).step_by(8) {
+                if i > 0 {
+                    w.write_char(' ')?;
+                }
+                write!(w, "{:#x}", value.read_be_u64(i)?)?;
+            }
+            w.write_char('>')?;
+        }
+        PropType::Bytes => {
+            w.write_str(" = [")?;
+            for (i, b) in value.iter().enumerate() {
+                if i > 0 {
+                    w.write_char(' ')?;
+                }
+                write!(w, "{:02x}", b)?;
+            }
+            w.write_char(']')?;
+        }
+    }
+    Ok(())
+}
@@ -6,6 +6,15 @@
 //! * [Low-level FDT parsing utilities to build your own library](base::parse)
 //! * [Simple utilites based on in-order parsing of the FDT](base)
 //! * [Performant utilities which leverage an index built over the FDT](index)
+//! * [Utilities to serialize a modified copy of the FDT](modify)
+//! * [Helpers which interpret common device tree bindings](bindings)
+//! * [Checks against the rules of the devicetree specification](compliance)
+//! * [Checks for overlapping address ranges of devices, RAM and reservations](overlap)
+//! * [A canonical text dump for snapshot tests](canonical)
+//! * [A structural diff of two trees](diff)
+//! * [Node name matching rules shared with libfdt](name)
+//! * [Heuristics which guess the types of property values](infer)
+//! * [Caller provided scratch memory for helpers which need it](scratch)
 //!
 //! ## Features
 //!
@@ -18,6 +27,9 @@
 //! default-features = false
 //! ```
 //!
+//! Without `std`, the `alloc` feature adds the helpers which allocate, such as
+//! [`Serializer::modify_to_vec`](modify::Serializer::modify_to_vec).
+//!
 //! ## Examples
 //!
 //!
@@ -25,6 +37,9 @@
 #![allow(clippy::as_conversions)]
 #![cfg_attr(not(feature = "std"), no_std)]
 
+#[cfg(feature = "alloc")]
+#[macro_use]
+extern crate alloc;
 #[cfg(feature = "std")]
 extern crate core;
 extern crate endian_type_rs as endian_type;
@@ -36,9 +51,20 @@ extern crate fallible_iterator;
 extern crate unsafe_unwrap;
 
 pub mod base;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod bindings;
+pub mod canonical;
+pub mod compliance;
+pub mod diff;
 pub mod error;
 pub mod index;
+pub mod infer;
+pub mod modify;
+pub mod name;
+pub mod overlap;
 pub mod prelude;
+pub mod scratch;
 pub mod spec;
 
 #[doc(hidden)]
@@ -0,0 +1,235 @@
+use core::mem::size_of;
+
+use crate::prelude::*;
+
+use crate::base::{DevTree, DevTreeNode};
+use crate::bindings::cells::{address_cells, write_cells};
+use crate::error::{DevTreeError, Result};
+use crate::modify::renumber::source_node;
+use crate::modify::{
+    ModifyContext, ModifyOptions, ModifyParsedTok, ModifyStage, ModifyTokenResponse, Serializer,
+};
+
+/// A window of `size` bytes of host addresses starting at `host`, which is mapped to guest
+/// addresses starting at `guest`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AddressWindow {
+    pub host: u64,
+    pub guest: u64,
+    pub size: u64,
+}
+
+impl AddressWindow {
+    #[must_use]
+    pub const fn new(host: u64, guest: u64, size: u64) -> Self {
+        Self { host, guest, size }
+    }
+
+    /// Returns the guest address of the `size` bytes at the host `address`, or `None` if
+    /// `address` isn't within the window.
+    ///
+    /// Returns [`DevTreeError::InvalidParameter`] if the bytes cross the end of the window.
+    pub fn translate(&self, address: u64, size: u64) -> Result<Option<u64>> {
+        let offset = match address.checked_sub(self.host) {
+            Some(offset) if offset < self.size => offset,
+            _ => return Ok(None),
+        };
+        if size > self.size - offset {
+            return Err(DevTreeError::InvalidParameter(
+                "Region crosses the end of an address window",
+            ));
+        }
+        self.guest
+            .checked_add(offset)
+            .map(Some)
+            .ok_or(DevTreeError::InvalidParameter("Guest address overflows"))
+    }
+}
+
+/// Rewrites the addresses of selected nodes of a [`DevTree`] according to a table of
+/// [`AddressWindow`]s, e.g. when a VMM maps a pass-through device at a different guest physical
+/// address than its host's.
+///
+/// For each selected node (given by its absolute path, e.g. `/soc/serial@1000`):
+///
+/// * The address of each `reg` entry is rewritten.
+/// * For a bus, the parent address of each `ranges` entry is rewritten. Its child addresses are
+///   left as they are, so the addresses of its children move with it.
+///
+/// Addresses outside every window are left as they are. Sizes, and the number of cells of each
+/// address, are never changed. Only the least significant two cells of a wider address (e.g. a
+/// PCI address) are rewritten.
+///
+/// The remapper may be applied directly, or as a stage of a
+/// [`ModifyPipeline`](crate::modify::ModifyPipeline). It always reads the source tree's values.
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::prelude::*;
+/// use fdt_rs::base::*;
+/// use fdt_rs::modify::*;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let mut buf = vec![0u32; FDT.len() / 4];
+/// let out = unsafe {
+///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, FDT.len())
+/// };
+///
+/// // Move the uart from 0x1000_0000 to 0x2000_0000.
+/// let windows = [AddressWindow::new(0x1000_0000, 0x2000_0000, 0x1000)];
+/// let mut remapper = AddressRemapper::new(&devtree, &windows, &["/uart@10000000"]);
+/// let size = remapper.apply(out, &ModifyOptions::default()).unwrap();
+///
+/// let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+/// let uart = modified
+///     .nodes()
+///     .find(|n| Ok(n.name()? == "uart@10000000"))
+///     .unwrap()
+///     .unwrap();
+/// let reg = uart.reg().unwrap().unwrap().next().unwrap().unwrap();
+/// assert_eq!(reg.address().unwrap(), 0x2000_0000);
+/// assert_eq!(reg.size().unwrap(), 0x100);
+/// ```
+pub struct AddressRemapper<'f, 'a, 'dt> {
+    fdt: &'a DevTree<'dt>,
+    windows: &'f [AddressWindow],
+    paths: &'f [&'f str],
+    /// The node whose properties are being passed to [`ModifyStage::respond`], if it's selected.
+    node: Option<DevTreeNode<'a, 'dt>>,
+    /// The first error found while rewriting addresses.
+    error: Option<DevTreeError>,
+}
+
+impl<'f, 'a, 'dt> AddressRemapper<'f, 'a, 'dt> {
+    /// Create a remapper for `fdt`, which rewrites the addresses of the nodes at `paths`
+    /// according to `windows`.
+    #[must_use]
+    pub fn new(fdt: &'a DevTree<'dt>, windows: &'f [AddressWindow], paths: &'f [&'f str]) -> Self {
+        Self {
+            fdt,
+            windows,
+            paths,
+            node: None,
+            error: None,
+        }
+    }
+
+    /// Serialize a copy of the tree with the selected nodes' addresses rewritten into `buf`, as
+    /// [`Serializer::modify_with_options`] does.
+    ///
+    /// Returns [`DevTreeError::InvalidParameter`] if a region crosses the end of a window, or
+    /// its guest address doesn't fit in the address' cells.
+    pub fn apply(&mut self, buf: &mut [u8], options: &ModifyOptions) -> Result<usize> {
+        let fdt = self.fdt;
+        let size =
+            Serializer::modify_with_context(fdt, buf, options, |ctx, tok| self.respond(ctx, tok))?;
+        self.check()?;
+        Ok(size)
+    }
+
+    /// Returns the first error found while rewriting the addresses of the tokens passed to
+    /// [`ModifyStage::respond`], and forgets it.
+    ///
+    /// Check this after serializing with the remapper as a stage of a pipeline.
+    pub fn check(&mut self) -> Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the guest address of the `size` bytes at the host `address`, or `None` if it
+    /// isn't within any window.
+    fn translate(&self, address: u64, size: u64) -> Result<Option<u64>> {
+        for window in self.windows {
+            if let Some(guest) = window.translate(address, size)? {
+                return Ok(Some(guest));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Rewrite the address of `address_cells` cells at cell `index` of `out`, if it's within a
+    /// window.
+    fn rewrite_address(
+        &self,
+        out: &mut [u8],
+        index: usize,
+        address_cells: usize,
+        address: u64,
+        size: u64,
+    ) -> Result<()> {
+        let guest = match self.translate(address, size)? {
+            Some(guest) => guest,
+            None => return Ok(()),
+        };
+        if address_cells == 1 && guest > u64::from(u32::MAX) {
+            return Err(DevTreeError::InvalidParameter(
+                "Guest address doesn't fit in its cells",
+            ));
+        }
+        // Any cells above the least significant two (e.g. PCI's phys.hi) are left as they are.
+        let cells = address_cells.min(2);
+        write_cells(out, index + address_cells - cells, guest, cells);
+        Ok(())
+    }
+
+    /// Rewrite the addresses held by the property `name` of the selected `node` to `out`, which
+    /// holds a copy of the property's value.
+    fn rewrite(&self, node: &DevTreeNode<'a, 'dt>, name: &[u8], out: &mut [u8]) -> Result<()> {
+        let parent_cells =
+            || -> Result<usize> { address_cells(&node.parent()?.ok_or(DevTreeError::ParseError)?) };
+        let mut index = 0;
+        match name {
+            b"reg" => {
+                let address_cells = parent_cells()?;
+                let mut entries = node.reg()?.ok_or(DevTreeError::ParseError)?;
+                while let Some(entry) = entries.next()? {
+                    let (address, size) = (entry.address()?, entry.size()?);
+                    self.rewrite_address(out, index, address_cells, address, size)?;
+                    index += entry.raw().len() / size_of::<u32>();
+                }
+            }
+            b"ranges" => {
+                let (child_cells, parent_cells) = (address_cells(node)?, parent_cells()?);
+                let mut entries = node.ranges()?.ok_or(DevTreeError::ParseError)?;
+                while let Some(entry) = entries.next()? {
+                    let (address, size) = (entry.parent()?, entry.size()?);
+                    self.rewrite_address(out, index + child_cells, parent_cells, address, size)?;
+                    index += entry.raw().len() / size_of::<u32>();
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl<'dt, 'r> ModifyStage<'dt, 'r> for AddressRemapper<'_, '_, 'dt> {
+    fn respond(
+        &mut self,
+        ctx: &ModifyContext<'_, 'dt>,
+        tok: ModifyParsedTok<'_, 'dt>,
+    ) -> ModifyTokenResponse<'r> {
+        let result = match tok {
+            ModifyParsedTok::BeginNode(node, _) if self.paths.iter().any(|p| ctx.is_at(p)) => {
+                source_node(self.fdt, &node).map(|node| self.node = node)
+            }
+            ModifyParsedTok::BeginNode(..) => {
+                self.node = None;
+                Ok(())
+            }
+            ModifyParsedTok::Prop(_, name, value_buf) => match &self.node {
+                Some(node) => self.rewrite(node, name, value_buf),
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            self.error.get_or_insert(err);
+        }
+        ModifyTokenResponse::Pass
+    }
+}
@@ -0,0 +1,391 @@
+#[cfg(doc)]
+use crate::base::DevTree;
+
+use crate::compliance::{is_valid_node_name, is_valid_prop_name};
+use crate::error::{DevTreeError, Result};
+use crate::modify::{MemReservation, MetadataValue, PhandleStyle, Serializer};
+use crate::scratch::ScratchArena;
+
+/// Where an [`FdtBuilder`] is in the tree it's writing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Adding memory reservations, before the root node.
+    Reservations,
+    /// Within a node, whose properties may still be added.
+    Props,
+    /// Within a node, after the start of its first child.
+    Children,
+    /// After the end of the root node.
+    Done,
+}
+
+/// A cell of a property written by [`FdtBuilder::prop_cells`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropCell<'l> {
+    /// A literal value.
+    U32(u32),
+    /// The phandle of the node with this label, as `<&label>` is in DTS.
+    Ref(&'l str),
+}
+
+/// Writes a new device tree into a caller provided buffer, one token at a time.
+///
+/// Unlike [`Serializer`], which copies an existing [`DevTree`], the builder starts from nothing.
+/// As with libfdt's sequential write functions, the tree is written in order: memory
+/// reservations first, then the root node. Each node's properties must be added before its
+/// children. Property names are stored once each in the strings block.
+///
+/// No allocator is required. Names are kept at the end of the buffer until
+/// [`FdtBuilder::finish`] moves them into place after the structure block.
+///
+/// # Labels
+///
+/// As in DTS, nodes may be [labelled](FdtBuilder::label) and referred to by label from
+/// [property cells](FdtBuilder::prop_cells) rather than by phandle. Each labelled node is given
+/// the next unused phandle. References are resolved by [`FdtBuilder::finish`], so may refer to
+/// nodes which come later in the tree. The labels and references are recorded in memory given
+/// by [`FdtBuilder::reserve_labels`].
+///
+/// # Validation
+///
+/// The order of calls is always checked. With [validation](FdtBuilder::set_validation) on,
+/// names are also checked as they're added, so a generation bug is reported by the call which
+/// made it rather than by the tree's consumer.
+///
+/// # Example
+///
+/// ```
+/// use fdt_rs::base::*;
+/// use fdt_rs::modify::*;
+/// use fdt_rs::prelude::*;
+///
+/// let mut buf = [0u32; 128];
+/// let out = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 512) };
+/// let mut builder = FdtBuilder::new(out);
+/// builder.add_reservation(0x8000_0000, 0x1000).unwrap();
+/// builder.begin_node("").unwrap();
+/// builder.prop_u32("#address-cells", 1).unwrap();
+/// builder.prop_u32("#size-cells", 1).unwrap();
+/// builder.prop_str("compatible", "vmm,virt").unwrap();
+/// builder.begin_node("memory@80000000").unwrap();
+/// builder.prop_str("device_type", "memory").unwrap();
+/// builder.prop_bytes("reg", &[0x80, 0, 0, 0, 0x10, 0, 0, 0]).unwrap();
+/// builder.end_node().unwrap();
+/// builder.end_node().unwrap();
+/// let size = builder.finish().unwrap();
+///
+/// let devtree = unsafe { DevTree::new(&out[..size]) }.unwrap();
+/// assert_eq!(devtree.nodes().count().unwrap(), 2);
+/// ```
+pub struct FdtBuilder<'o> {
+    ser: Serializer<'o, 'static>,
+    state: State,
+    /// Depth of the current node. The root node is at depth 1.
+    depth: usize,
+    off_mem_rsvmap: usize,
+    off_dt_struct: usize,
+    boot_cpuid_phys: u32,
+    phandle_style: PhandleStyle,
+    /// The largest phandle written so far.
+    max_phandle: u32,
+    /// Each label and the phandle of the node it labels.
+    labels: &'o mut [(&'o str, u32)],
+    num_labels: usize,
+    /// The offset and label of each cell which refers to a label.
+    refs: &'o mut [(usize, &'o str)],
+    num_refs: usize,
+    /// Whether names are checked, see [`FdtBuilder::set_validation`].
+    validate: bool,
+    /// The offset of the current node's first property.
+    props_start: usize,
+}
+
+impl<'o> FdtBuilder<'o> {
+    /// Create a builder which writes into `buf`.
+    ///
+    /// `buf` should be 32-bit aligned if the output is to be parsed with [`DevTree::new`].
+    #[must_use]
+    pub fn new(buf: &'o mut [u8]) -> Self {
+        let ser = Serializer::new_empty(buf);
+        let off_mem_rsvmap = ser.offset();
+        Self {
+            ser,
+            state: State::Reservations,
+            depth: 0,
+            off_mem_rsvmap,
+            off_dt_struct: off_mem_rsvmap,
+            boot_cpuid_phys: 0,
+            phandle_style: PhandleStyle::Phandle,
+            max_phandle: 0,
+            labels: &mut [],
+            num_labels: 0,
+            refs: &mut [],
+            num_refs: 0,
+            validate: false,
+            props_start: 0,
+        }
+    }
+
+    /// Allocate room from `scratch` to record up to `labels` labels and `refs` references to
+    /// them, see [`FdtBuilder::label`] and [`FdtBuilder::prop_cells`].
+    ///
+    /// Any labels and references already recorded are forgotten, so call this before the root
+    /// node is started.
+    pub fn reserve_labels(
+        &mut self,
+        scratch: &mut ScratchArena<'o>,
+        labels: usize,
+        refs: usize,
+    ) -> Result<()> {
+        self.labels = scratch.alloc_slice(labels, ("", 0))?;
+        self.refs = scratch.alloc_slice(refs, (0, ""))?;
+        self.num_labels = 0;
+        self.num_refs = 0;
+        Ok(())
+    }
+
+    /// Set the physical ID of the boot CPU, written to the header.
+    pub fn set_boot_cpuid_phys(&mut self, boot_cpuid_phys: u32) {
+        self.boot_cpuid_phys = boot_cpuid_phys;
+    }
+
+    /// Set which properties [`FdtBuilder::prop_phandle`] writes. Defaults to
+    /// [`PhandleStyle::Phandle`].
+    pub fn set_phandle_style(&mut self, style: PhandleStyle) {
+        self.phandle_style = style;
+    }
+
+    /// Check names as they're added, returning [`DevTreeError::InvalidParameter`] from the call
+    /// which adds:
+    ///
+    /// * A node name which isn't valid according to the devicetree specification (e.g. one with a
+    ///   character other than `[0-9a-zA-Z,._+-]`, or longer than 31 characters before its unit
+    ///   address).
+    /// * A property name which isn't valid according to the specification.
+    /// * A property with the same name as another of the same node.
+    ///
+    /// Finding duplicate properties re-reads the node's properties, so takes time quadratic in
+    /// their number.
+    pub fn set_validation(&mut self, validate: bool) {
+        self.validate = validate;
+    }
+
+    /// Add an entry to the memory reservation block.
+    ///
+    /// Returns [`DevTreeError::InvalidParameter`] once the root node has been started.
+    pub fn add_reservation(&mut self, address: u64, size: u64) -> Result<()> {
+        if self.state != State::Reservations {
+            return Err(DevTreeError::InvalidParameter(
+                "Reservations must be added before the root node",
+            ));
+        }
+        self.ser
+            .serialize_mem_reservation(MemReservation::new(address, size))
+    }
+
+    /// Start a node named `name`, as a child of the current node. The root node's name is
+    /// empty.
+    ///
+    /// Returns [`DevTreeError::InvalidParameter`] if the name is invalid (or, for the root node,
+    /// not empty) or the root node has already ended.
+    pub fn begin_node(&mut self, name: &str) -> Result<()> {
+        match self.state {
+            State::Reservations => {
+                self.ser.serialize_mem_reservation_end()?;
+                self.off_dt_struct = self.ser.offset();
+            }
+            State::Done => {
+                return Err(DevTreeError::InvalidParameter(
+                    "A device tree has a single root node",
+                ))
+            }
+            State::Props | State::Children => (),
+        }
+        if self.depth == 0 && !name.is_empty() {
+            return Err(DevTreeError::InvalidParameter(
+                "The root node's name must be empty",
+            ));
+        }
+        if self.validate && self.depth > 0 && !is_valid_node_name(name.as_bytes()) {
+            return Err(DevTreeError::InvalidParameter("Invalid node name"));
+        }
+        self.ser.serialize_new_begin_node(name.as_bytes())?;
+        self.props_start = self.ser.offset();
+        self.state = State::Props;
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// End the current node.
+    ///
+    /// Returns [`DevTreeError::InvalidParameter`] if there is no node to end.
+    pub fn end_node(&mut self) -> Result<()> {
+        if self.depth == 0 {
+            return Err(DevTreeError::InvalidParameter("No node to end"));
+        }
+        self.ser.serialize_new_end_node()?;
+        self.depth -= 1;
+        self.state = match self.depth {
+            0 => State::Done,
+            _ => State::Children,
+        };
+        Ok(())
+    }
+
+    /// Start a `simple-bus` node whose children's addresses have `address_cells` cells and
+    /// sizes `size_cells` cells. Its `ranges` is empty, so its addresses are those of its
+    /// parent.
+    ///
+    /// Further properties and children may be added to the bus before it's ended with
+    /// [`FdtBuilder::end_node`].
+    pub fn begin_simple_bus(
+        &mut self,
+        name: &str,
+        address_cells: u32,
+        size_cells: u32,
+    ) -> Result<()> {
+        self.begin_node(name)?;
+        self.prop_str("compatible", "simple-bus")?;
+        self.prop_u32("#address-cells", address_cells)?;
+        self.prop_u32("#size-cells", size_cells)?;
+        self.prop_bytes("ranges", &[])
+    }
+
+    /// Add a property holding a single cell to the current node.
+    pub fn prop_u32(&mut self, name: &str, value: u32) -> Result<()> {
+        self.prop(name, &MetadataValue::U32(value))
+    }
+
+    /// Add a property holding a string to the current node. The null terminator is added.
+    pub fn prop_str(&mut self, name: &str, value: &str) -> Result<()> {
+        self.prop(name, &MetadataValue::Str(value))
+    }
+
+    /// Add a property holding raw bytes to the current node.
+    pub fn prop_bytes(&mut self, name: &str, value: &[u8]) -> Result<()> {
+        self.prop(name, &MetadataValue::Bytes(value))
+    }
+
+    /// Give the current node the phandle `phandle`, written in the builder's phandle style.
+    pub fn prop_phandle(&mut self, phandle: u32) -> Result<()> {
+        for name in self.phandle_style.names() {
+            self.check_prop(name)?;
+        }
+        self.ser
+            .serialize_new_phandle(self.phandle_style, phandle)?;
+        self.max_phandle = self.max_phandle.max(phandle);
+        Ok(())
+    }
+
+    /// Label the current node, giving it the next unused phandle (which is returned).
+    ///
+    /// Like the node's other properties, this must come before its children. Returns
+    /// [`DevTreeError::InvalidParameter`] if the label is already in use, or
+    /// [`DevTreeError::NotEnoughMemory`] if there's no room to record it, see
+    /// [`FdtBuilder::reserve_labels`].
+    pub fn label(&mut self, label: &'o str) -> Result<u32> {
+        for name in self.phandle_style.names() {
+            self.check_prop(name)?;
+        }
+        if self.phandle_of(label).is_some() {
+            return Err(DevTreeError::InvalidParameter("Duplicate label"));
+        }
+        let phandle = self
+            .max_phandle
+            .checked_add(1)
+            .filter(|&p| p != u32::MAX)
+            .ok_or(DevTreeError::InvalidParameter("Out of phandles"))?;
+        let slot = self
+            .labels
+            .get_mut(self.num_labels)
+            .ok_or(DevTreeError::NotEnoughMemory)?;
+        *slot = (label, phandle);
+        self.num_labels += 1;
+        self.prop_phandle(phandle)?;
+        Ok(phandle)
+    }
+
+    /// Add a property of cells to the current node, some of which may refer to labelled
+    /// nodes.
+    ///
+    /// Returns [`DevTreeError::NotEnoughMemory`] if there's no room to record the references,
+    /// see [`FdtBuilder::reserve_labels`].
+    pub fn prop_cells(&mut self, name: &str, cells: &[PropCell<'o>]) -> Result<()> {
+        self.check_prop(name)?;
+        let num_refs = cells
+            .iter()
+            .filter(|cell| matches!(cell, PropCell::Ref(_)))
+            .count();
+        if self.refs.len() - self.num_refs < num_refs {
+            return Err(DevTreeError::NotEnoughMemory);
+        }
+        self.ser.serialize_new_prop_header(name, cells.len() * 4)?;
+        for cell in cells {
+            let value = match *cell {
+                PropCell::U32(value) => value,
+                PropCell::Ref(label) => {
+                    self.refs[self.num_refs] = (self.ser.offset(), label);
+                    self.num_refs += 1;
+                    // Filled in once the labelled node is known.
+                    0
+                }
+            };
+            self.ser.serialize_value(&value.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Returns the phandle of the node with `label`, if it has been labelled.
+    fn phandle_of(&self, label: &str) -> Option<u32> {
+        self.labels[..self.num_labels]
+            .iter()
+            .find(|(l, _)| *l == label)
+            .map(|&(_, phandle)| phandle)
+    }
+
+    fn prop(&mut self, name: &str, value: &MetadataValue) -> Result<()> {
+        self.check_prop(name)?;
+        self.ser.serialize_new_prop(name, value)
+    }
+
+    /// Returns [`DevTreeError::InvalidParameter`] if the property `name` can't be added to the
+    /// current node.
+    fn check_prop(&self, name: &str) -> Result<()> {
+        if self.state != State::Props {
+            return Err(DevTreeError::InvalidParameter(
+                "Properties must be added to a node before its children",
+            ));
+        }
+        if self.validate {
+            if !is_valid_prop_name(name) {
+                return Err(DevTreeError::InvalidParameter("Invalid property name"));
+            }
+            if self.ser.has_prop_since(self.props_start, name)? {
+                return Err(DevTreeError::InvalidParameter("Duplicate property name"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the strings block and header, completing the device tree.
+    ///
+    /// Returns the size of the device tree, or [`DevTreeError::InvalidParameter`] if the root
+    /// node hasn't been ended or a label which is referred to isn't defined.
+    pub fn finish(mut self) -> Result<usize> {
+        if self.state != State::Done {
+            return Err(DevTreeError::InvalidParameter("Root node not ended"));
+        }
+        for i in 0..self.num_refs {
+            let (off, label) = self.refs[i];
+            let phandle = self
+                .phandle_of(label)
+                .ok_or(DevTreeError::InvalidParameter("Undefined label"))?;
+            self.ser.rewrite_u32(off, phandle)?;
+        }
+        self.ser.finish_new(
+            self.off_mem_rsvmap,
+            self.off_dt_struct,
+            self.boot_cpuid_phys,
+        )
+    }
+}
@@ -0,0 +1,190 @@
+//! A catalog of device trees stored back-to-back in one buffer (e.g. the per-board or per-guest
+//! variants a firmware image keeps in a single flash partition).
+//!
+//! Each tree starts at an offset from the start of the buffer which is a multiple of the
+//! catalog's alignment, and the bytes between trees are zeroed. There's no index, as every tree's
+//! header records its size: the trees are enumerated by stepping from one header to the next.
+use crate::prelude::*;
+
+use crate::base::DevTree;
+use crate::error::{DevTreeError, Result};
+use crate::modify::{ModifyTokenResponse, Serializer};
+
+/// Returns whether `align` is a valid catalog alignment: a power of two of at least 4 bytes.
+fn check_align(align: usize) -> Result<()> {
+    if align < 4 || !align.is_power_of_two() {
+        return Err(DevTreeError::InvalidParameter(
+            "Catalog alignment must be a power of two of at least 4",
+        ));
+    }
+    Ok(())
+}
+
+/// Returns `offset` rounded up to a multiple of `align`, a power of two.
+fn align_up(offset: usize, align: usize) -> usize {
+    (offset + align - 1) & !(align - 1)
+}
+
+/// Writes a catalog of device trees into a caller provided buffer.
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::prelude::*;
+/// use fdt_rs::base::*;
+/// use fdt_rs::modify::*;
+/// use fdt_rs::scratch::ScratchArena;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let mut buf = vec![0u32; FDT.len()];
+/// let out = unsafe {
+///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4)
+/// };
+///
+/// // Store the tree as is, and a variant with a different command line.
+/// let mut mem = [0u8; 256];
+/// let mut scratch = ScratchArena::new(&mut mem);
+/// let mut modifier = DevTreeModifier::new(&mut scratch, 1).unwrap();
+/// modifier
+///     .set_prop("/chosen", "bootargs", MetadataValue::Str("console=ttyS0"))
+///     .unwrap();
+/// let mut writer = DevTreeCatalogWriter::new(out, 256).unwrap();
+/// writer.push(&devtree).unwrap();
+/// writer.push_with(|buf| modifier.apply(&devtree, buf)).unwrap();
+/// let size = writer.finish();
+///
+/// let mut trees = unsafe { DevTreeCatalogIter::new(&out[..size], 256) }.unwrap();
+/// assert_eq!(trees.next().unwrap().unwrap().totalsize(), FDT.len());
+/// let variant = trees.next().unwrap().unwrap();
+/// let chosen = variant.node_at_path("/chosen").unwrap().unwrap();
+/// let bootargs = chosen.props().find(|p| Ok(p.name()? == "bootargs")).unwrap().unwrap();
+/// assert_eq!(bootargs.str().unwrap(), "console=ttyS0");
+/// assert!(trees.next().unwrap().is_none());
+/// ```
+#[derive(Debug)]
+pub struct DevTreeCatalogWriter<'o> {
+    buf: &'o mut [u8],
+    align: usize,
+    len: usize,
+}
+
+impl<'o> DevTreeCatalogWriter<'o> {
+    /// Create a writer of a catalog whose trees are `align` bytes aligned, which must be a power
+    /// of two of at least 4, else [`DevTreeError::InvalidParameter`] is returned.
+    ///
+    /// `buf` should be at least `align` bytes aligned if the trees are to be parsed where they're
+    /// written.
+    pub fn new(buf: &'o mut [u8], align: usize) -> Result<Self> {
+        check_align(align)?;
+        Ok(Self { buf, align, len: 0 })
+    }
+
+    /// Append an unmodified copy of `fdt` to the catalog, returning its offset.
+    pub fn push(&mut self, fdt: &DevTree) -> Result<usize> {
+        self.push_with(|buf| Serializer::modify(fdt, buf, |_| ModifyTokenResponse::Pass))
+    }
+
+    /// Append the tree `f` writes to the catalog, returning its offset.
+    ///
+    /// `f` is passed the rest of the buffer from the tree's aligned offset on, and returns the
+    /// size of the tree it wrote (e.g. with [`Serializer::modify`],
+    /// [`DevTreeModifier::apply`](crate::modify::DevTreeModifier::apply) or an
+    /// [`FdtBuilder`](crate::modify::FdtBuilder)). If it fails with
+    /// [`DevTreeError::OutputBufferTooSmall`], the sizes are made relative to the whole catalog.
+    pub fn push_with<F>(&mut self, f: F) -> Result<usize>
+    where
+        F: FnOnce(&mut [u8]) -> Result<usize>,
+    {
+        let offset = align_up(self.len, self.align);
+        if offset > self.buf.len() {
+            return Err(DevTreeError::OutputBufferTooSmall {
+                needed: offset,
+                available: self.buf.len(),
+            });
+        }
+        let size = f(&mut self.buf[offset..]).map_err(|err| match err {
+            DevTreeError::OutputBufferTooSmall { needed, available } => {
+                DevTreeError::OutputBufferTooSmall {
+                    needed: offset + needed,
+                    available: offset + available,
+                }
+            }
+            err => err,
+        })?;
+        if size < DevTree::MIN_HEADER_SIZE || size > self.buf.len() - offset {
+            return Err(DevTreeError::InvalidParameter(
+                "Size of the written tree is out of bounds",
+            ));
+        }
+        for byte in &mut self.buf[self.len..offset] {
+            *byte = 0;
+        }
+        self.len = offset + size;
+        Ok(offset)
+    }
+
+    /// Returns the size of the catalog, up to the end of its last tree.
+    #[must_use]
+    pub fn finish(self) -> usize {
+        self.len
+    }
+}
+
+/// An iterator over the trees of a catalog written by a [`DevTreeCatalogWriter`].
+///
+/// Iteration ends at the end of the buffer, or at the first aligned offset which doesn't start
+/// with the device tree magic (e.g. the erased flash after the last tree).
+#[derive(Clone, Debug)]
+pub struct DevTreeCatalogIter<'dt> {
+    buf: &'dt [u8],
+    align: usize,
+    offset: usize,
+}
+
+impl<'dt> DevTreeCatalogIter<'dt> {
+    /// Create an iterator over the trees of the catalog in `buf`, whose trees are `align` bytes
+    /// aligned. The alignment must be that the catalog was written with.
+    ///
+    /// # Safety
+    ///
+    /// Callers of this method the must guarantee the following:
+    ///
+    /// - The passed buffer is 32-bit aligned.
+    ///
+    /// The trees in the buffer will be interpreted as Flattened Device Trees, see
+    /// [`DevTree::new`].
+    pub unsafe fn new(buf: &'dt [u8], align: usize) -> Result<Self> {
+        check_align(align)?;
+        Ok(Self {
+            buf,
+            align,
+            offset: 0,
+        })
+    }
+}
+
+impl<'dt> FallibleIterator for DevTreeCatalogIter<'dt> {
+    type Item = DevTree<'dt>;
+    type Error = DevTreeError;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        if self.offset >= self.buf.len() || self.buf.len() - self.offset < DevTree::MIN_HEADER_SIZE
+        {
+            return Ok(None);
+        }
+        let rest = &self.buf[self.offset..];
+        // Safe as the catalog's buffer is 32-bit aligned, and so is every tree's offset.
+        let size = match unsafe { DevTree::read_totalsize(rest) } {
+            Ok(size) => size,
+            Err(DevTreeError::InvalidMagicNumber) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        if size < DevTree::MIN_HEADER_SIZE || size > rest.len() {
+            return Err(DevTreeError::ParseError);
+        }
+        let fdt = unsafe { DevTree::new(&rest[..size]) }?;
+        self.offset = align_up(self.offset + size, self.align);
+        Ok(Some(fdt))
+    }
+}
@@ -0,0 +1,149 @@
+use core::ops::Range;
+use core::str::from_utf8;
+
+use crate::prelude::*;
+
+use crate::base::iters::DevTreeIter;
+use crate::base::parse::{DevTreeParseIter, ParsedTok};
+use crate::base::DevTree;
+use crate::bindings::cells::{address_cells, size_cells};
+use crate::error::{DevTreeError, Result};
+use crate::modify::sorted::prop_name;
+use crate::modify::{MetadataValue, Serializer};
+
+/// Returns whether the property defines a node's phandle.
+pub(super) fn is_phandle(name: &str) -> bool {
+    name == "phandle" || name == "linux,phandle"
+}
+
+/// Returns whether the property's value is a list of phandles without specifiers, so each of
+/// its cells may need renumbering.
+pub(super) fn is_phandle_list(name: &str) -> bool {
+    is_phandle(name)
+        || name == "interrupt-parent"
+        || name == "msi-parent"
+        || (name.starts_with("pinctrl-") && name != "pinctrl-names")
+}
+
+/// Returns whether a node within `range` of the structure block of `fdt` defines `phandle`.
+fn defines_phandle(fdt: &DevTree, range: &Range<usize>, phandle: u32) -> Result<bool> {
+    let mut iter = DevTreeParseIter {
+        offset: range.start,
+        fdt,
+    };
+    while iter.offset < range.end {
+        if let Some(ParsedTok::Prop(prop)) = iter.next()? {
+            if is_phandle(from_utf8(prop_name(fdt, &prop)?)?)
+                && prop.prop_buf.read_be_u32(0)? == phandle
+            {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Returns the `#address-cells` and `#size-cells` of the parent of the node at `path` of
+/// `source`, which a bus the node is grafted to must have for the node's `reg` to be valid.
+///
+/// Returns [`DevTreeError::InvalidParameter`] unless the parent is the root node or a bus with
+/// an empty `ranges`, so the node's addresses are CPU addresses.
+pub(super) fn bus_cells(source: &DevTree, path: &str) -> Result<(u32, u32)> {
+    let offset = source
+        .node_offset_by_exact_path(path)?
+        .ok_or(DevTreeError::InvalidParameter("Node to graft not found"))?;
+    let node = DevTreeIter::from_offset(source, offset)
+        .next_node()?
+        .ok_or(DevTreeError::ParseError)?;
+    let parent = node.parent()?.ok_or(DevTreeError::InvalidParameter(
+        "The root node can't be grafted",
+    ))?;
+    if parent.parent()?.is_some() && parent.find_prop("ranges")?.is_none_or(|r| r.length() != 0) {
+        return Err(DevTreeError::InvalidParameter(
+            "Grafted node's bus doesn't map addresses 1:1",
+        ));
+    }
+    Ok((address_cells(&parent)? as u32, size_cells(&parent)? as u32))
+}
+
+/// Write the BeginNode token and properties of a `simple-bus` node named `name`, whose
+/// addresses are those of its parent.
+pub(super) fn serialize_bus_begin(
+    ser: &mut Serializer,
+    name: &str,
+    address_cells: u32,
+    size_cells: u32,
+) -> Result<()> {
+    ser.serialize_new_begin_node(name.as_bytes())?;
+    ser.serialize_new_prop("compatible", &MetadataValue::Str("simple-bus"))?;
+    ser.serialize_new_prop("#address-cells", &MetadataValue::U32(address_cells))?;
+    ser.serialize_new_prop("#size-cells", &MetadataValue::U32(size_cells))?;
+    ser.serialize_new_prop("ranges", &MetadataValue::Bytes(&[]))
+}
+
+/// Write a copy of the subtree at `path` of `source`.
+///
+/// Phandles defined within the subtree are renumbered by adding `base`, the largest phandle of
+/// the destination tree, so they can't collide with it. References to them from phandle list
+/// properties within the subtree are updated to match. If the serializer has a phandle style,
+/// each node's phandle is written in it.
+pub(super) fn serialize_graft(
+    ser: &mut Serializer,
+    source: &DevTree,
+    path: &str,
+    base: u32,
+) -> Result<()> {
+    let offset = source
+        .node_offset_by_exact_path(path)?
+        .ok_or(DevTreeError::InvalidParameter("Node to graft not found"))?;
+    let range = DevTreeIter::from_offset(source, offset)
+        .next_node()?
+        .ok_or(DevTreeError::ParseError)?
+        .byte_range()?;
+
+    let renumber = |phandle: u32| {
+        if !defines_phandle(source, &range, phandle)? {
+            return Ok(phandle);
+        }
+        let renumbered = phandle.checked_add(base).filter(|&p| p != u32::MAX);
+        renumbered.ok_or(DevTreeError::InvalidParameter(
+            "Out of phandles to renumber",
+        ))
+    };
+
+    let mut iter = DevTreeParseIter {
+        offset,
+        fdt: source,
+    };
+    // Whether the current node's phandle has been written in the serializer's phandle style.
+    let mut phandle_written = false;
+    while iter.offset < range.end {
+        match iter.next()?.ok_or(DevTreeError::ParseError)? {
+            ParsedTok::BeginNode(node) => {
+                ser.serialize_new_begin_node(node.name)?;
+                phandle_written = false;
+            }
+            ParsedTok::EndNode => ser.serialize_new_end_node()?,
+            ParsedTok::Prop(prop) => {
+                let name = from_utf8(prop_name(source, &prop)?)?;
+                match ser.phandle_style() {
+                    Some(_) if is_phandle(name) && phandle_written => continue,
+                    Some(style) if is_phandle(name) && prop.prop_buf.len() == 4 => {
+                        let phandle = renumber(prop.prop_buf.read_be_u32(0)?)?;
+                        ser.serialize_new_phandle(style, phandle)?;
+                        phandle_written = true;
+                        continue;
+                    }
+                    _ => (),
+                }
+                if is_phandle_list(name) {
+                    ser.serialize_new_cells_prop(name, prop.prop_buf, renumber)?;
+                } else {
+                    ser.serialize_new_prop(name, &MetadataValue::Bytes(prop.prop_buf))?;
+                }
+            }
+            ParsedTok::Nop => (),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,250 @@
+use core::mem::size_of;
+use core::str::from_utf8;
+
+use num_traits::FromPrimitive;
+
+use crate::base::DevTree;
+use crate::error::{DevTreeError, Result};
+use crate::modify::serializer::check_node_name;
+use crate::modify::strings::find_string;
+use crate::modify::{ModifyTokenResponse, ReplacementTok};
+use crate::priv_util::{SliceRead, SliceWrite};
+use crate::spec::{fdt_header, fdt_prop_header, FdtTok};
+
+#[cfg(doc)]
+use crate::modify::Serializer;
+
+/// A token of the device tree being modified, as passed to the [`modify_in_place`] callback.
+#[derive(Debug)]
+pub enum InPlaceTok<'a> {
+    /// The start of a node and its name (without its null terminator).
+    ///
+    /// To rename the node, write a name no longer than the original to the start of the buffer
+    /// and respond with [`ModifyTokenResponse::ModifySize`].
+    ///
+    /// Responding with [`ModifyTokenResponse::Drop`] drops the node along with all of its
+    /// properties and children.
+    BeginNode(&'a mut [u8]),
+    EndNode,
+    /// A property's name and value.
+    ///
+    /// The value may be edited in place. To shorten it, write the new value to the start of the
+    /// buffer and respond with [`ModifyTokenResponse::ModifySize`].
+    Prop(&'a str, &'a mut [u8]),
+    Nop,
+}
+
+/// Modify the device tree in `buf` in place, passing every token to `f` to decide how it should
+/// be written.
+///
+/// Unlike [`Serializer::modify`], no second buffer is needed. In exchange, the tree may not
+/// grow: tokens may be passed, dropped, or replaced with ones no larger than the original.
+/// Replacement property names must already be in the strings block. The structure block is
+/// compacted towards the front of `buf`, followed by the strings block.
+///
+/// Returns the new size of the device tree. If `f` responds with an edit the tree has no room
+/// for, [`DevTreeError::InvalidParameter`] is returned and `buf` is left partially modified.
+///
+/// # Safety
+///
+/// Callers of this method the must guarantee the following:
+///
+/// - The passed buffer is 32-bit aligned.
+/// - The passed buffer is exactly the length returned by [`DevTree::read_totalsize()`]
+pub unsafe fn modify_in_place<'r, F>(buf: &mut [u8], mut f: F) -> Result<usize>
+where
+    F: FnMut(InPlaceTok<'_>) -> ModifyTokenResponse<'r>,
+{
+    let (off_dt_struct, size_dt_struct, off_dt_strings, size_dt_strings, totalsize) = {
+        let fdt = DevTree::new(buf)?;
+        (
+            fdt.off_dt_struct(),
+            fdt.size_dt_struct() as usize,
+            fdt.off_dt_strings(),
+            fdt.size_dt_strings() as usize,
+            fdt.totalsize(),
+        )
+    };
+    let struct_end = off_dt_struct + size_dt_struct;
+    let strings_end = off_dt_strings + size_dt_strings;
+    if struct_end > buf.len() || strings_end > buf.len() {
+        return Err(DevTreeError::ParseError);
+    }
+
+    // Split the buffer so the strings block can be read while the structure block is rewritten.
+    let strings_follow = off_dt_strings >= struct_end;
+    let size = if strings_follow {
+        let (head, tail) = buf.split_at_mut(off_dt_strings);
+        let structs = &mut head[off_dt_struct..struct_end];
+        modify_struct_block(structs, &tail[..size_dt_strings], &mut f)?
+    } else if strings_end <= off_dt_struct {
+        let (head, tail) = buf.split_at_mut(off_dt_struct);
+        let structs = &mut tail[..size_dt_struct];
+        modify_struct_block(structs, &head[off_dt_strings..strings_end], &mut f)?
+    } else {
+        return Err(DevTreeError::ParseError);
+    };
+
+    buf.write_be_u32(offset_of!(fdt_header, size_dt_struct), size as u32)?;
+    if !strings_follow {
+        return Ok(totalsize);
+    }
+
+    let off_dt_strings_new = off_dt_struct + size;
+    buf.copy_within(off_dt_strings..strings_end, off_dt_strings_new);
+    let totalsize = off_dt_strings_new + size_dt_strings;
+    buf.write_be_u32(
+        offset_of!(fdt_header, off_dt_strings),
+        off_dt_strings_new as u32,
+    )?;
+    buf.write_be_u32(offset_of!(fdt_header, totalsize), totalsize as u32)?;
+    Ok(totalsize)
+}
+
+/// Rewrite the tokens of `structs` towards its start, returning the new size of the block.
+///
+/// No token written is larger than the one it was read from, so the write offset never passes
+/// the read offset.
+fn modify_struct_block<'r, F>(structs: &mut [u8], strings: &[u8], f: &mut F) -> Result<usize>
+where
+    F: FnMut(InPlaceTok<'_>) -> ModifyTokenResponse<'r>,
+{
+    // Number of unmatched BeginNode tokens seen within a dropped subtree.
+    let mut drop_depth = 0usize;
+    let mut r = 0;
+    let mut w = 0;
+
+    loop {
+        let tok = (&*structs).read_be_u32(r)?;
+        match FdtTok::from_u32(tok) {
+            Some(FdtTok::BeginNode) => {
+                let name_off = r + size_of::<u32>();
+                let name_len = (&*structs).read_bstring0(name_off)?.len();
+                let next = align(name_off + name_len + 1);
+                r = next;
+                if drop_depth > 0 {
+                    drop_depth += 1;
+                    continue;
+                }
+
+                let name = &mut structs[name_off..name_off + name_len];
+                let len = match f(InPlaceTok::BeginNode(name)) {
+                    ModifyTokenResponse::Pass => name_len,
+                    ModifyTokenResponse::Drop => {
+                        drop_depth = 1;
+                        continue;
+                    }
+                    ModifyTokenResponse::ModifySize(len) if len <= name_len => len,
+                    ModifyTokenResponse::Replace(ReplacementTok::BeginNode(name))
+                        if name.len() <= name_len =>
+                    {
+                        structs[name_off..name_off + name.len()].copy_from_slice(name.as_bytes());
+                        name.len()
+                    }
+                    ModifyTokenResponse::ModifySize(_)
+                    | ModifyTokenResponse::Replace(ReplacementTok::BeginNode(_)) => {
+                        return Err(no_room())
+                    }
+                    ModifyTokenResponse::Replace(_) => return Err(invalid_response()),
+                };
+                check_node_name(&structs[name_off..name_off + len])?;
+
+                structs.copy_within(name_off..name_off + len, w + size_of::<u32>());
+                structs.write_be_u32(w, FdtTok::BeginNode as u32)?;
+                structs[w + size_of::<u32>() + len] = 0;
+                w = zero_pad(structs, w + size_of::<u32>() + len + 1);
+            }
+            Some(FdtTok::Prop) => {
+                let len = (&*structs).read_be_u32(r + size_of::<u32>())? as usize;
+                let name_offset = (&*structs).read_be_u32(r + 2 * size_of::<u32>())? as usize;
+                let value_off = r + size_of::<u32>() + size_of::<fdt_prop_header>();
+                r = align(value_off + len);
+                if drop_depth > 0 {
+                    continue;
+                }
+
+                let name = from_utf8(strings.read_bstring0(name_offset)?)?;
+                let value = structs
+                    .get_mut(value_off..value_off + len)
+                    .ok_or(DevTreeError::ParseError)?;
+                let (name_offset, len) = match f(InPlaceTok::Prop(name, value)) {
+                    ModifyTokenResponse::Pass => (name_offset, len),
+                    ModifyTokenResponse::Drop => continue,
+                    ModifyTokenResponse::ModifySize(new_len) if new_len <= len => {
+                        (name_offset, new_len)
+                    }
+                    ModifyTokenResponse::Replace(ReplacementTok::Prop { name, value })
+                        if value.len() <= len =>
+                    {
+                        let name_offset = find_string(strings, name.as_bytes()).ok_or(
+                            DevTreeError::InvalidParameter(
+                                "Property name is not in the strings block",
+                            ),
+                        )?;
+                        structs[value_off..value_off + value.len()].copy_from_slice(value);
+                        (name_offset, value.len())
+                    }
+                    ModifyTokenResponse::ModifySize(_)
+                    | ModifyTokenResponse::Replace(ReplacementTok::Prop { .. }) => {
+                        return Err(no_room())
+                    }
+                    ModifyTokenResponse::Replace(_) => return Err(invalid_response()),
+                };
+
+                // The value is moved first, as the header may overlap the original.
+                let new_value_off = w + size_of::<u32>() + size_of::<fdt_prop_header>();
+                structs.copy_within(value_off..value_off + len, new_value_off);
+                structs.write_be_u32(w, FdtTok::Prop as u32)?;
+                structs.write_be_u32(w + size_of::<u32>(), len as u32)?;
+                structs.write_be_u32(w + 2 * size_of::<u32>(), name_offset as u32)?;
+                w = zero_pad(structs, new_value_off + len);
+            }
+            Some(FdtTok::EndNode) | Some(FdtTok::Nop) => {
+                r += size_of::<u32>();
+                if drop_depth > 0 {
+                    if tok == FdtTok::EndNode as u32 {
+                        drop_depth -= 1;
+                    }
+                    continue;
+                }
+                let parsed = if tok == FdtTok::EndNode as u32 {
+                    InPlaceTok::EndNode
+                } else {
+                    InPlaceTok::Nop
+                };
+                match f(parsed) {
+                    ModifyTokenResponse::Pass => {
+                        structs.write_be_u32(w, tok)?;
+                        w += size_of::<u32>();
+                    }
+                    ModifyTokenResponse::Drop => (),
+                    _ => return Err(invalid_response()),
+                }
+            }
+            Some(FdtTok::End) => {
+                structs.write_be_u32(w, tok)?;
+                return Ok(w + size_of::<u32>());
+            }
+            None => return Err(DevTreeError::ParseError),
+        }
+    }
+}
+
+fn align(pos: usize) -> usize {
+    (pos + size_of::<u32>() - 1) & !(size_of::<u32>() - 1)
+}
+
+/// Zero the bytes from `end` up to the next 32-bit boundary, returning it.
+fn zero_pad(structs: &mut [u8], end: usize) -> usize {
+    let aligned = align(end);
+    structs[end..aligned].iter_mut().for_each(|b| *b = 0);
+    aligned
+}
+
+fn no_room() -> DevTreeError {
+    DevTreeError::InvalidParameter("Edits in place may not grow the device tree")
+}
+
+fn invalid_response() -> DevTreeError {
+    DevTreeError::InvalidParameter("Response is not valid for the given token")
+}
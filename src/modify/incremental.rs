@@ -0,0 +1,387 @@
+use core::mem::size_of;
+
+use crate::base::iters::DevTreeIter;
+use crate::base::parse::{DevTreeParseIter, ParsedTok};
+use crate::base::DevTree;
+use crate::error::{DevTreeError, Result};
+use crate::modify::modifier::{Edit, EditKind};
+use crate::modify::strings::find_string;
+use crate::modify::{DevTreeModifier, MetadataNode, MetadataValue};
+use crate::prelude::*;
+use crate::priv_util::{SliceRead, SliceWrite};
+use crate::spec::{fdt_header, fdt_prop_header, FdtTok};
+
+/// Size of a Prop token up to its value.
+const PROP_HEADER_SIZE: usize = size_of::<u32>() + size_of::<fdt_prop_header>();
+
+fn align(pos: usize) -> usize {
+    (pos + size_of::<u32>() - 1) & !(size_of::<u32>() - 1)
+}
+
+/// Returns the device tree at the start of `buf`.
+///
+/// # Safety
+///
+/// `buf` must be 32-bit aligned.
+unsafe fn tree(buf: &[u8]) -> Result<DevTree<'_>> {
+    let size = DevTree::read_totalsize(buf)?;
+    DevTree::new(buf.get(..size).ok_or(DevTreeError::ParseError)?)
+}
+
+/// Where a node's tokens are within the structure block.
+struct NodeScan {
+    /// The offset and value length of the node's property of the requested name.
+    prop: Option<(usize, usize)>,
+    /// The offset just after the node's last property.
+    props_end: usize,
+    /// The offset of the node's EndNode token.
+    end: usize,
+}
+
+/// Scan the tokens of the node whose BeginNode token is at `node_off`.
+fn scan_node(fdt: &DevTree, node_off: usize, prop_name: Option<&[u8]>) -> Result<NodeScan> {
+    let mut iter = DevTreeParseIter {
+        offset: node_off,
+        fdt,
+    };
+    let mut scan = NodeScan {
+        prop: None,
+        props_end: 0,
+        end: 0,
+    };
+    let mut props_open = true;
+    let mut depth = 0usize;
+    loop {
+        let off = iter.offset;
+        match iter.next()? {
+            Some(ParsedTok::BeginNode(_)) => {
+                if depth == 1 && props_open {
+                    scan.props_end = off;
+                    props_open = false;
+                }
+                depth += 1;
+            }
+            Some(ParsedTok::Prop(prop)) if depth == 1 => {
+                let name = fdt
+                    .buf()
+                    .read_bstring0(fdt.off_dt_strings() + prop.name_offset)?;
+                if prop_name == Some(name) {
+                    scan.prop = Some((off, prop.prop_buf.len()));
+                }
+                scan.props_end = iter.offset;
+            }
+            Some(ParsedTok::EndNode) => {
+                depth -= 1;
+                if depth == 0 {
+                    if props_open {
+                        scan.props_end = off;
+                    }
+                    scan.end = off;
+                    return Ok(scan);
+                }
+            }
+            Some(_) => (),
+            None => return Err(DevTreeError::ParseError),
+        }
+    }
+}
+
+/// Returns whether `path` is `ancestor` or one of its descendants.
+fn is_within(path: &str, ancestor: &str) -> bool {
+    let mut components = path.split('/').filter(|c| !c.is_empty());
+    ancestor
+        .split('/')
+        .filter(|c| !c.is_empty())
+        .all(|c| components.next() == Some(c))
+}
+
+/// Returns the size of `node` once serialized, excluding any names added to the strings block.
+fn node_size(node: &MetadataNode) -> usize {
+    let props: usize = node
+        .props
+        .iter()
+        .map(|prop| PROP_HEADER_SIZE + align(prop.value.len()))
+        .sum();
+    size_of::<u32>() + align(node.name.len() + 1) + props + size_of::<u32>()
+}
+
+/// Returns the number of bytes adding `name` to the strings block takes.
+fn string_size(fdt: &DevTree, name: &str) -> usize {
+    match find_string(strings(fdt), name.as_bytes()) {
+        Some(_) => 0,
+        None => name.len() + 1,
+    }
+}
+
+fn strings<'dt>(fdt: &DevTree<'dt>) -> &'dt [u8] {
+    let start = fdt.off_dt_strings();
+    &fdt.buf()[start..start + fdt.size_dt_strings() as usize]
+}
+
+fn header(buf: &[u8], field: usize) -> Result<usize> {
+    Ok(buf.read_be_u32(field)? as usize)
+}
+
+fn set_header(buf: &mut [u8], field: usize, val: usize) -> Result<()> {
+    Ok(buf.write_be_u32(field, val as u32)?)
+}
+
+/// Replace the `old_len` bytes of the structure block at `at` with `new_len` bytes, moving
+/// everything after them (including the strings block) and updating the header.
+///
+/// The new bytes are left for the caller to fill in.
+fn splice(buf: &mut [u8], at: usize, old_len: usize, new_len: usize) -> Result<()> {
+    let totalsize = header(buf, offset_of!(fdt_header, totalsize))?;
+    let new_totalsize = totalsize + new_len - old_len;
+    if new_totalsize > buf.len() {
+        return Err(DevTreeError::OutputBufferTooSmall {
+            needed: new_totalsize,
+            available: buf.len(),
+        });
+    }
+    buf.copy_within(at + old_len..totalsize, at + new_len);
+
+    for field in [
+        offset_of!(fdt_header, size_dt_struct),
+        offset_of!(fdt_header, off_dt_strings),
+    ] {
+        let val = header(buf, field)?;
+        set_header(buf, field, val + new_len - old_len)?;
+    }
+    set_header(buf, offset_of!(fdt_header, totalsize), new_totalsize)
+}
+
+/// Returns the offset of `name` in the strings block, appending it to the block if it isn't
+/// already present.
+fn string_offset(buf: &mut [u8], name: &str) -> Result<usize> {
+    let off_dt_strings = header(buf, offset_of!(fdt_header, off_dt_strings))?;
+    let size_dt_strings = header(buf, offset_of!(fdt_header, size_dt_strings))?;
+    let strings_end = off_dt_strings + size_dt_strings;
+    if let Some(offset) = find_string(&buf[off_dt_strings..strings_end], name.as_bytes()) {
+        return Ok(offset);
+    }
+
+    // The strings block is the last block, so the name is appended to the device tree.
+    buf.write_slice(strings_end, name.as_bytes())?;
+    buf.write_slice(strings_end + name.len(), &[0])?;
+    set_header(
+        buf,
+        offset_of!(fdt_header, size_dt_strings),
+        size_dt_strings + name.len() + 1,
+    )?;
+    set_header(
+        buf,
+        offset_of!(fdt_header, totalsize),
+        strings_end + name.len() + 1,
+    )?;
+    Ok(size_dt_strings)
+}
+
+/// Write a Prop token at `off`, into space already spliced for it. Returns the end of the token.
+fn write_prop(
+    buf: &mut [u8],
+    off: usize,
+    name_offset: usize,
+    value: &MetadataValue,
+) -> Result<usize> {
+    buf.write_be_u32(off, FdtTok::Prop as u32)?;
+    write_value(buf, off, name_offset, value)
+}
+
+/// Write the length, name offset, and value of the Prop token at `off`. Returns the end of the
+/// token.
+fn write_value(
+    buf: &mut [u8],
+    off: usize,
+    name_offset: usize,
+    value: &MetadataValue,
+) -> Result<usize> {
+    buf.write_be_u32(off + size_of::<u32>(), value.len() as u32)?;
+    buf.write_be_u32(off + 2 * size_of::<u32>(), name_offset as u32)?;
+    let value_off = off + PROP_HEADER_SIZE;
+    value.write_to(&mut buf[value_off..])?;
+    let end = align(value_off + value.len());
+    buf[value_off + value.len()..end]
+        .iter_mut()
+        .for_each(|b| *b = 0);
+    Ok(end)
+}
+
+impl<'s, 'm> DevTreeModifier<'s, 'm> {
+    /// Apply the edits to the device tree at the start of `buf` (typically the output of an
+    /// earlier [`DevTreeModifier::apply`]), patching only the bytes they affect.
+    ///
+    /// Each edit is spliced into place, so a property set to a value of the same size costs
+    /// only the write of the value. Edits which grow the tree use the free space after it in
+    /// `buf`. If there isn't enough, or the tree's strings block isn't its last block, the tree
+    /// is serialized in full into `scratch` as [`DevTreeModifier::apply`] does, and copied back
+    /// into `buf`.
+    ///
+    /// A [size budget](DevTreeModifier::set_size_budget) is checked before anything is patched,
+    /// with a dry run in `scratch`, or in the free space after the tree if that's larger.
+    ///
+    /// Returns the new size of the device tree.
+    ///
+    /// # Safety
+    ///
+    /// Callers of this method the must guarantee the following:
+    ///
+    /// - The passed buffer is 32-bit aligned.
+    /// - The passed buffer starts with a device tree. It may be longer than the tree's
+    ///   `totalsize`.
+    pub unsafe fn apply_incremental(&self, buf: &mut [u8], scratch: &mut [u8]) -> Result<usize> {
+        if self.fits_in_place(&tree(buf)?, buf.len())? {
+            let (fdt, slack) = buf.split_at_mut(tree(buf)?.totalsize());
+            let dry_run = if slack.len() > scratch.len() {
+                slack
+            } else {
+                &mut *scratch
+            };
+            self.enforce_budget(&tree(fdt)?, dry_run)?;
+            for (i, (edit, _)) in self.edits().enumerate() {
+                self.patch(buf, i, edit)?;
+            }
+            return Ok(tree(buf)?.totalsize());
+        }
+
+        let size = self.apply(&tree(buf)?, scratch)?;
+        if size > buf.len() {
+            return Err(DevTreeError::OutputBufferTooSmall {
+                needed: size,
+                available: buf.len(),
+            });
+        }
+        buf[..size].copy_from_slice(&scratch[..size]);
+        Ok(size)
+    }
+
+    /// Returns whether the edits can be patched into `fdt` within `available` bytes.
+    fn fits_in_place(&self, fdt: &DevTree, available: usize) -> Result<bool> {
+        let struct_end = fdt.off_dt_struct() + fdt.size_dt_struct() as usize;
+        let strings_end = fdt.off_dt_strings() + fdt.size_dt_strings() as usize;
+        if fdt.off_dt_strings() < struct_end || strings_end != fdt.totalsize() {
+            return Ok(false);
+        }
+
+        // Rewriting phandles may grow any node.
+        if self.phandle_style.is_some() {
+            return Ok(false);
+        }
+
+        let mut growth = 0;
+        for (i, (edit, _)) in self.edits().enumerate() {
+            // Grafted subtrees and patch nodes are always serialized in full.
+            match edit.kind {
+                EditKind::Graft(..) | EditKind::GraftToBus(..) | EditKind::AddPatchNode(_) => {
+                    return Ok(false)
+                }
+                EditKind::SetProp(..)
+                | EditKind::DeleteProp(_)
+                | EditKind::DeleteNode
+                | EditKind::AddNode(_) => (),
+            }
+            let adds = matches!(edit.kind, EditKind::SetProp(..) | EditKind::AddNode(_));
+            let deleted = self.edits().any(|(other, _)| {
+                matches!(other.kind, EditKind::DeleteNode) && is_within(edit.path, other.path)
+            });
+            let node_off = match fdt.node_offset_by_exact_path(edit.path)? {
+                Some(off) if !(adds && deleted) => off,
+                _ if adds => {
+                    return Err(DevTreeError::InvalidParameter("Node to modify not found"))
+                }
+                _ => continue,
+            };
+
+            growth += match edit.kind {
+                EditKind::SetProp(name, value) if self.takes_effect(i) => {
+                    match scan_node(fdt, node_off, edit.prop_name())?.prop {
+                        Some((_, len)) => align(value.len()).saturating_sub(align(len)),
+                        None => PROP_HEADER_SIZE + align(value.len()) + string_size(fdt, name),
+                    }
+                }
+                EditKind::AddNode(node) => {
+                    let names: usize = node
+                        .props
+                        .iter()
+                        .map(|prop| string_size(fdt, prop.name))
+                        .sum();
+                    node_size(node) + names
+                }
+                EditKind::SetProp(..) | EditKind::DeleteProp(_) | EditKind::DeleteNode => 0,
+                EditKind::Graft(..) | EditKind::GraftToBus(..) | EditKind::AddPatchNode(_) => {
+                    return Ok(false)
+                }
+            };
+        }
+        Ok(fdt.totalsize() + growth <= available)
+    }
+
+    /// Splice the `i`th edit into the device tree in `buf`.
+    unsafe fn patch(&self, buf: &mut [u8], i: usize, edit: &Edit) -> Result<()> {
+        let (node_off, scan) = {
+            let fdt = tree(buf)?;
+            let node_off = match fdt.node_offset_by_exact_path(edit.path)? {
+                Some(off) => off,
+                None => return Ok(()),
+            };
+            (node_off, scan_node(&fdt, node_off, edit.prop_name())?)
+        };
+
+        match edit.kind {
+            EditKind::SetProp(name, value) if self.takes_effect(i) => match scan.prop {
+                Some((off, len)) => {
+                    let name_offset = (&*buf).read_be_u32(off + 2 * size_of::<u32>())? as usize;
+                    splice(buf, off + PROP_HEADER_SIZE, align(len), align(value.len()))?;
+                    write_value(buf, off, name_offset, &value)?;
+                }
+                None => {
+                    let name_offset = string_offset(buf, name)?;
+                    let len = PROP_HEADER_SIZE + align(value.len());
+                    splice(buf, scan.props_end, 0, len)?;
+                    write_prop(buf, scan.props_end, name_offset, &value)?;
+                }
+            },
+            EditKind::DeleteProp(_) => {
+                if let Some((off, len)) = scan.prop {
+                    splice(buf, off, PROP_HEADER_SIZE + align(len), 0)?;
+                }
+            }
+            EditKind::DeleteNode => {
+                let fdt = tree(buf)?;
+                let node = DevTreeIter::from_offset(&fdt, node_off)
+                    .next_node()?
+                    .ok_or(DevTreeError::ParseError)?;
+                let range = node.byte_range()?;
+                splice(buf, range.start, range.len(), 0)?;
+            }
+            EditKind::AddNode(node) => {
+                let mut off = scan.end;
+                // Add the names first, as they don't move the structure block.
+                for prop in node.props {
+                    string_offset(buf, prop.name)?;
+                }
+                splice(buf, off, 0, node_size(node))?;
+
+                buf.write_be_u32(off, FdtTok::BeginNode as u32)?;
+                off += size_of::<u32>();
+                buf.write_slice(off, node.name.as_bytes())?;
+                let name_end = off + node.name.len();
+                off = align(name_end + 1);
+                buf[name_end..off].iter_mut().for_each(|b| *b = 0);
+                for prop in node.props {
+                    let name_offset = string_offset(buf, prop.name)?;
+                    off = write_prop(buf, off, name_offset, &prop.value)?;
+                }
+                buf.write_be_u32(off, FdtTok::EndNode as u32)?;
+            }
+            // Overridden by a later edit of the same property.
+            EditKind::SetProp(..) => (),
+            EditKind::Graft(..) | EditKind::GraftToBus(..) | EditKind::AddPatchNode(_) => {
+                return Err(DevTreeError::InvalidParameter(
+                    "Edit can't be patched in place",
+                ))
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+use core::mem::size_of;
+use core::str::from_utf8;
+
+use crate::prelude::*;
+
+use crate::base::{DevTree, DevTreeNode};
+use crate::bindings::interrupts::InterruptMapIter;
+use crate::error::{DevTreeError, Result};
+use crate::modify::renumber::{cells, source_node};
+use crate::modify::{
+    ModifyContext, ModifyOptions, ModifyParsedTok, ModifyStage, ModifyTokenResponse, Serializer,
+};
+use crate::priv_util::{SliceRead, SliceWrite};
+
+/// The format of the interrupt specifiers of an interrupt controller: which of their cells holds
+/// the interrupt number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IrqCellFormat {
+    /// A `compatible` string of the controller.
+    pub compatible: &'static str,
+    /// The index of the cell which holds the interrupt number.
+    pub number_cell: usize,
+}
+
+impl IrqCellFormat {
+    #[must_use]
+    pub const fn new(compatible: &'static str, number_cell: usize) -> Self {
+        Self {
+            compatible,
+            number_cell,
+        }
+    }
+}
+
+/// The formats of common interrupt controllers.
+///
+/// The first cell of a GIC specifier is the interrupt type (SPI or PPI), and the second its
+/// number within that type.
+pub const DEFAULT_IRQ_FORMATS: &[IrqCellFormat] = &[
+    IrqCellFormat::new("arm,gic-400", 1),
+    IrqCellFormat::new("arm,cortex-a15-gic", 1),
+    IrqCellFormat::new("arm,cortex-a9-gic", 1),
+    IrqCellFormat::new("arm,gic-v3", 1),
+    IrqCellFormat::new("riscv,plic0", 0),
+    IrqCellFormat::new("sifive,plic-1.0.0", 0),
+    IrqCellFormat::new("riscv,aplic", 0),
+    IrqCellFormat::new("riscv,cpu-intc", 0),
+];
+
+/// An interrupt specifier found by an [`IrqRemapper`].
+pub struct IrqSpecifier<'s, 'a, 'dt> {
+    /// The interrupt controller the specifier is for.
+    pub controller: &'s DevTreeNode<'a, 'dt>,
+    /// The controller's format.
+    pub format: IrqCellFormat,
+    /// The specifier's cells, as they're stored in the tree.
+    cells: &'s [u8],
+}
+
+impl IrqSpecifier<'_, '_, '_> {
+    /// Returns the number of cells of the specifier, as given by the controller's
+    /// `#interrupt-cells`.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.cells.len() / size_of::<u32>()
+    }
+
+    /// Returns whether the specifier has no cells.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// Returns cell `index` of the specifier.
+    pub fn cell(&self, index: usize) -> Result<u32> {
+        Ok(self.cells.read_be_u32(index * size_of::<u32>())?)
+    }
+
+    /// Returns the (host) interrupt number.
+    pub fn number(&self) -> Result<u32> {
+        self.cell(self.format.number_cell)
+    }
+}
+
+/// Rewrites the interrupt numbers of a [`DevTree`], e.g. a guest's tree filtered from its host's,
+/// by passing each interrupt specifier to a remap function (host number to guest number).
+///
+/// Every specifier for a controller whose `compatible` has a registered [`IrqCellFormat`] is
+/// remapped, wherever it appears:
+///
+/// * `interrupts`, for the node's interrupt parent (given by `interrupt-parent` on the node or
+///   its nearest ancestor which has one).
+/// * `interrupts-extended`.
+/// * The parent specifiers of `interrupt-map` entries.
+///
+/// The remap function returns the new number, or `None` to leave the specifier unchanged. Other
+/// cells (e.g. trigger flags) are copied unchanged. Specifiers for controllers without a
+/// registered format, or interrupts of a node whose parent is an interrupt nexus (which
+/// `interrupt-map` translates), are left unchanged.
+///
+/// The remapper may be applied directly, or as a stage of a
+/// [`ModifyPipeline`](crate::modify::ModifyPipeline). It always reads the source tree's values.
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::prelude::*;
+/// use fdt_rs::base::*;
+/// use fdt_rs::modify::*;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let mut buf = vec![0u32; FDT.len() / 4];
+/// let out = unsafe {
+///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, FDT.len())
+/// };
+///
+/// // Move the PLIC's interrupts up by 32.
+/// let mut remapper = IrqRemapper::new(&devtree, DEFAULT_IRQ_FORMATS, |spec: &IrqSpecifier| {
+///     match spec.format.compatible {
+///         "riscv,plic0" => Some(spec.number().ok()? + 32),
+///         _ => None,
+///     }
+/// });
+/// let size = remapper.apply(out, &ModifyOptions::default()).unwrap();
+///
+/// let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+/// let uart = modified
+///     .props()
+///     .find(|p| Ok(p.name()? == "interrupts" && p.node().name()? == "uart@10000000"))
+///     .unwrap()
+///     .unwrap();
+/// assert_eq!(uart.u32(0).unwrap(), 10 + 32);
+/// ```
+pub struct IrqRemapper<'f, 'a, 'dt, F> {
+    fdt: &'a DevTree<'dt>,
+    formats: &'f [IrqCellFormat],
+    remap: F,
+    /// The node whose properties are being passed to [`ModifyStage::respond`].
+    node: Option<DevTreeNode<'a, 'dt>>,
+    /// The first error found while rewriting specifiers.
+    error: Option<DevTreeError>,
+}
+
+impl<'f, 'a, 'dt, F> IrqRemapper<'f, 'a, 'dt, F>
+where
+    F: FnMut(&IrqSpecifier<'_, 'a, 'dt>) -> Option<u32>,
+{
+    /// Create a remapper for `fdt`, which understands the specifiers of controllers with one of
+    /// `formats` (e.g. [`DEFAULT_IRQ_FORMATS`]) and passes them to `remap`.
+    pub fn new(fdt: &'a DevTree<'dt>, formats: &'f [IrqCellFormat], remap: F) -> Self {
+        Self {
+            fdt,
+            formats,
+            remap,
+            node: None,
+            error: None,
+        }
+    }
+
+    /// Serialize a copy of the tree with its interrupts remapped into `buf`, as
+    /// [`Serializer::modify_with_options`] does.
+    ///
+    /// Returns [`DevTreeError::ParseError`] if a reference is to a phandle which isn't defined,
+    /// or a specifier is shorter than its controller's `#interrupt-cells`.
+    pub fn apply(&mut self, buf: &mut [u8], options: &ModifyOptions) -> Result<usize> {
+        let fdt = self.fdt;
+        let size =
+            Serializer::modify_with_context(fdt, buf, options, |ctx, tok| self.respond(ctx, tok))?;
+        self.check()?;
+        Ok(size)
+    }
+
+    /// Returns the first error found while rewriting the specifiers of the tokens passed to
+    /// [`ModifyStage::respond`], and forgets it.
+    ///
+    /// Check this after serializing with the remapper as a stage of a pipeline.
+    pub fn check(&mut self) -> Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the node with the (source) `phandle`.
+    fn node_by_phandle(&self, phandle: u32) -> Result<DevTreeNode<'a, 'dt>> {
+        self.fdt
+            .node_by_phandle(phandle)?
+            .ok_or(DevTreeError::ParseError)
+    }
+
+    /// Returns the format of `controller`'s specifiers, if it's registered.
+    fn format(&self, controller: &DevTreeNode<'a, 'dt>) -> Result<Option<IrqCellFormat>> {
+        let compatible = match controller.find_prop("compatible")? {
+            Some(prop) => prop,
+            None => return Ok(None),
+        };
+        let mut strings = compatible.iter_str();
+        while let Some(s) = strings.next()? {
+            if let Some(format) = self.formats.iter().find(|f| f.compatible == s) {
+                return Ok(Some(*format));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Remap the specifier for `controller` at `offset` of `value`, writing it to `out`.
+    ///
+    /// Returns the length of the specifier.
+    fn remap(
+        &mut self,
+        controller: &DevTreeNode<'a, 'dt>,
+        value: &[u8],
+        offset: usize,
+        out: &mut [u8],
+    ) -> Result<usize> {
+        let len = cells(controller, "#interrupt-cells")? * size_of::<u32>();
+        let format = match self.format(controller)? {
+            Some(format) => format,
+            None => return Ok(len),
+        };
+        let spec = IrqSpecifier {
+            controller,
+            format,
+            cells: value
+                .get(offset..offset + len)
+                .ok_or(DevTreeError::ParseError)?,
+        };
+        if format.number_cell >= spec.len() {
+            return Err(DevTreeError::ParseError);
+        }
+        if let Some(number) = (self.remap)(&spec) {
+            out.write_be_u32(offset + format.number_cell * size_of::<u32>(), number)?;
+        }
+        Ok(len)
+    }
+
+    /// Remap the specifiers held by the property `name` to `out`, which holds a copy of its
+    /// `value`. Properties which don't hold specifiers are left as they are.
+    fn rewrite(&mut self, name: &str, value: &'dt [u8], out: &mut [u8]) -> Result<()> {
+        if !matches!(name, "interrupts" | "interrupts-extended" | "interrupt-map") {
+            return Ok(());
+        }
+        let node = self.node.clone().ok_or(DevTreeError::ParseError)?;
+        let mut i = 0;
+        match name {
+            "interrupts" => {
+                let parent = node.interrupt_parent()?.ok_or(DevTreeError::ParseError)?;
+                if parent.find_prop("interrupt-map")?.is_some() {
+                    return Ok(());
+                }
+                while i < value.len() {
+                    i += self.remap(&parent, value, i, out)?;
+                }
+            }
+            "interrupts-extended" => {
+                while i < value.len() {
+                    let controller = self.node_by_phandle(value.read_be_u32(i)?)?;
+                    i += size_of::<u32>();
+                    i += self.remap(&controller, value, i, out)?;
+                }
+            }
+            _ => {
+                let mut entries = InterruptMapIter::new(&node, value)?;
+                while let Some(entry) = entries.next()? {
+                    self.remap(&entry.parent, value, entry.specifier_offset, out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'dt, 'r, F> ModifyStage<'dt, 'r> for IrqRemapper<'_, 'a, 'dt, F>
+where
+    F: FnMut(&IrqSpecifier<'_, 'a, 'dt>) -> Option<u32>,
+{
+    fn respond(
+        &mut self,
+        _ctx: &ModifyContext<'_, 'dt>,
+        tok: ModifyParsedTok<'_, 'dt>,
+    ) -> ModifyTokenResponse<'r> {
+        let result = match tok {
+            ModifyParsedTok::BeginNode(node, _) => {
+                source_node(self.fdt, &node).map(|node| self.node = node)
+            }
+            ModifyParsedTok::Prop(prop, name, value_buf) => from_utf8(name)
+                .map_err(DevTreeError::from)
+                .and_then(|name| self.rewrite(name, prop.prop_buf, value_buf)),
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            self.error.get_or_insert(err);
+        }
+        ModifyTokenResponse::Pass
+    }
+}
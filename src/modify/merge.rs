@@ -0,0 +1,524 @@
+use core::array;
+use core::cell::Cell;
+use core::mem::size_of;
+use core::str::from_utf8;
+
+use crate::prelude::*;
+
+use crate::base::parse::{DevTreeParseIter, ParsedTok};
+use crate::base::DevTree;
+use crate::error::{DevTreeError, Result};
+use crate::infer::{infer_value_type, PropType};
+use crate::modify::provenance::{begin_node_len, prop_len};
+use crate::modify::serializer::{Insert, InsertPoint};
+use crate::modify::sorted::{contents, prop_name, root_offset, NodeContents};
+use crate::modify::{
+    MetadataValue, ModifyContext, ModifyOptions, ModifyParsedTok, ModifyTokenResponse, Origin,
+    ReplacementTok, Serializer, Trace, MAX_DEPTH,
+};
+
+/// The name of the property which marks a node of a delta tree for deletion, see
+/// [`Serializer::merge`].
+///
+/// The DTB format has no equivalent of the source format's `/delete-node/` directive, so a node
+/// is deleted by giving the delta's node at the same path a property of this name.
+pub const DELETE_NODE_MARKER: &str = "/delete-node/";
+
+/// How [`Serializer::merge_with`] merges a property which both trees' nodes have.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropMergePolicy {
+    /// The delta's value replaces the base's.
+    Replace,
+    /// The delta's strings which the base's value doesn't already have are appended to it, e.g.
+    /// to add a `compatible` string. Both values must be lists of strings.
+    AppendDedup,
+    /// The merge fails with [`DevTreeError::InvalidParameter`] unless both values are the same.
+    Error,
+}
+
+/// A table of [`PropMergePolicy`]s by property name, for [`Serializer::merge_with`].
+#[derive(Clone, Copy, Debug)]
+pub struct PropMergePolicies<'p> {
+    /// Each property name and its policy.
+    pub by_name: &'p [(&'p str, PropMergePolicy)],
+    /// The policy of properties which aren't in the table.
+    pub default: PropMergePolicy,
+}
+
+impl PropMergePolicies<'_> {
+    /// Returns the policy for the property `name`.
+    #[must_use]
+    pub fn policy(&self, name: &str) -> PropMergePolicy {
+        self.by_name
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map_or(self.default, |&(_, policy)| policy)
+    }
+}
+
+/// Returns the offset of the contents of the child `name` of the node whose contents are at
+/// `offset`, if it has one.
+fn child_contents(fdt: &DevTree, offset: usize, name: &[u8]) -> Result<Option<usize>> {
+    for child in NodeContents::children(fdt, offset) {
+        let (child_name, child_offset) = child?;
+        if child_name == name {
+            return contents(fdt, child_offset).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the name and value of the property `name` of the node whose contents are at
+/// `offset`, if it has one.
+fn find_prop<'dt>(
+    fdt: &DevTree<'dt>,
+    offset: usize,
+    name: &[u8],
+) -> Result<Option<(&'dt [u8], &'dt [u8])>> {
+    for prop in NodeContents::props(fdt, offset) {
+        let (prop_name, _, prop) = prop?;
+        if prop_name == name {
+            return Ok(Some((prop_name, prop.prop_buf)));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns whether the node whose contents are at `offset` is marked for deletion.
+fn is_deleted(fdt: &DevTree, offset: usize) -> Result<bool> {
+    Ok(find_prop(fdt, offset, DELETE_NODE_MARKER.as_bytes())?.is_some())
+}
+
+/// Append the strings of `extra` which aren't among the strings of `buf[..len]` to them.
+///
+/// Returns the length of the combined value. Strings which don't fit in `buf` aren't written.
+fn append_strings(buf: &mut [u8], len: usize, extra: &[u8]) -> Result<usize> {
+    let is_strings =
+        |v: &[u8]| matches!(infer_value_type(v), PropType::String | PropType::StringList);
+    if !is_strings(&buf[..len]) || !is_strings(extra) {
+        return Err(DevTreeError::InvalidParameter(
+            "Only lists of strings can be appended",
+        ));
+    }
+    let contains = |list: &[u8], s: &[u8]| list.split_inclusive(|&c| c == 0).any(|l| l == s);
+    let (mut new_len, mut pos) = (len, 0);
+    for s in extra.split_inclusive(|&c| c == 0) {
+        let duplicate = contains(&buf[..len], s) || contains(&extra[..pos], s);
+        pos += s.len();
+        if duplicate {
+            continue;
+        }
+        if let Some(dest) = buf.get_mut(new_len..new_len + s.len()) {
+            dest.copy_from_slice(s);
+        }
+        new_len += s.len();
+    }
+    Ok(new_len)
+}
+
+/// The state of a [`Serializer::merge`].
+struct Merge<'a, 'dt, 'd, 't, P> {
+    base: &'a DevTree<'dt>,
+    delta: &'a DevTree<'d>,
+    policy: P,
+    /// Where to record the origin of the output's tokens, and the delta's layer number.
+    trace: Option<(Trace<'t>, usize)>,
+    /// The offset of the contents of each open node of the base tree.
+    base_nodes: [Cell<usize>; MAX_DEPTH],
+    /// The offset of the contents of the delta's node at the path of each open node of the base
+    /// tree, if it has one.
+    delta_nodes: [Cell<Option<usize>>; MAX_DEPTH],
+    /// The first error found by the callback.
+    error: Cell<Option<DevTreeError>>,
+}
+
+impl<'a, 'dt, 'd, P> Merge<'a, 'dt, 'd, '_, P>
+where
+    P: Fn(&str) -> PropMergePolicy,
+{
+    fn respond(
+        &self,
+        ctx: &ModifyContext<'_, 'dt>,
+        tok: ModifyParsedTok<'_, 'dt>,
+    ) -> ModifyTokenResponse<'d> {
+        let len = match &tok {
+            ModifyParsedTok::BeginNode(node, _) => Some(begin_node_len(node.name)),
+            ModifyParsedTok::Prop(prop, ..) => Some(prop_len(prop.prop_buf.len())),
+            _ => None,
+        };
+        let result = self.try_respond(ctx, tok).and_then(|response| {
+            self.record(ctx, len, response)?;
+            Ok(response)
+        });
+        match result {
+            Ok(response) => response,
+            Err(err) => {
+                if self.error.get().is_none() {
+                    self.error.set(Some(err));
+                }
+                ModifyTokenResponse::Pass
+            }
+        }
+    }
+
+    fn try_respond(
+        &self,
+        ctx: &ModifyContext<'_, 'dt>,
+        tok: ModifyParsedTok<'_, 'dt>,
+    ) -> Result<ModifyTokenResponse<'d>> {
+        let depth = ctx.depth();
+        match tok {
+            ModifyParsedTok::BeginNode(node, _) => {
+                // A node's name borrows the base tree's buffer, just after its BeginNode token.
+                let name_off = node.name.as_ptr() as usize - self.base.buf().as_ptr() as usize;
+                let base = contents(self.base, name_off - size_of::<u32>())?;
+                let delta = match depth {
+                    1 => Some(contents(self.delta, root_offset(self.delta)?)?),
+                    _ => match self.delta_nodes[depth - 2].get() {
+                        Some(parent) => child_contents(self.delta, parent, node.name)?,
+                        None => None,
+                    },
+                };
+                if depth > 1 && delta.map_or(Ok(false), |d| is_deleted(self.delta, d))? {
+                    return Ok(ModifyTokenResponse::Drop);
+                }
+                self.base_nodes[depth - 1].set(base);
+                self.delta_nodes[depth - 1].set(delta);
+            }
+            ModifyParsedTok::Prop(prop, name, value_buf) => {
+                let delta = match self.delta_nodes[depth - 1].get() {
+                    Some(delta) => delta,
+                    None => return Ok(ModifyTokenResponse::Pass),
+                };
+                let (name, value) = match find_prop(self.delta, delta, name)? {
+                    Some(found) => found,
+                    None => return Ok(ModifyTokenResponse::Pass),
+                };
+                let name = from_utf8(name)?;
+                return match (self.policy)(name) {
+                    PropMergePolicy::Replace => {
+                        Ok(ModifyTokenResponse::Replace(ReplacementTok::Prop {
+                            name,
+                            value,
+                        }))
+                    }
+                    PropMergePolicy::AppendDedup => {
+                        let len = append_strings(value_buf, prop.prop_buf.len(), value)?;
+                        Ok(ModifyTokenResponse::ModifySize(len))
+                    }
+                    PropMergePolicy::Error if value != prop.prop_buf => Err(
+                        DevTreeError::InvalidParameter("Merged trees' properties conflict"),
+                    ),
+                    PropMergePolicy::Error => Ok(ModifyTokenResponse::Pass),
+                };
+            }
+            _ => (),
+        }
+        Ok(ModifyTokenResponse::Pass)
+    }
+
+    /// Record the origin of the token of `len` bytes at `ctx`, given the response to it.
+    fn record(
+        &self,
+        ctx: &ModifyContext<'_, 'dt>,
+        len: Option<usize>,
+        response: ModifyTokenResponse,
+    ) -> Result<()> {
+        let (trace, layer, len) = match (self.trace, len) {
+            (Some((trace, layer)), Some(len)) => (trace, layer, len),
+            _ => return Ok(()),
+        };
+        match response {
+            ModifyTokenResponse::Pass => trace.record(ctx, len, None),
+            ModifyTokenResponse::ModifySize(size) => {
+                trace.record(ctx, prop_len(size), Some(Origin::Layer(layer)))
+            }
+            ModifyTokenResponse::Replace(ReplacementTok::Prop { value, .. }) => {
+                trace.record(ctx, prop_len(value.len()), Some(Origin::Layer(layer)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<'dt, P> Insert<'dt> for Merge<'_, 'dt, '_, '_, P> {
+    fn insert(
+        &self,
+        at: InsertPoint,
+        ctx: &ModifyContext<'_, 'dt>,
+        ser: &mut Serializer<'_, 'dt>,
+    ) -> Result<()> {
+        let depth = ctx.depth();
+        let delta = match self.delta_nodes[depth - 1].get() {
+            Some(delta) => delta,
+            None => return Ok(()),
+        };
+        let base = self.base_nodes[depth - 1].get();
+        let start = ser.offset();
+        match at {
+            InsertPoint::Props => {
+                for prop in NodeContents::props(self.delta, delta) {
+                    let (name, _, prop) = prop?;
+                    if name != DELETE_NODE_MARKER.as_bytes()
+                        && find_prop(self.base, base, name)?.is_none()
+                    {
+                        ser.serialize_new_prop(
+                            from_utf8(name)?,
+                            &MetadataValue::Bytes(prop.prop_buf),
+                        )?;
+                    }
+                }
+            }
+            InsertPoint::Children => {
+                for child in NodeContents::children(self.delta, delta) {
+                    let (name, offset) = child?;
+                    if child_contents(self.base, base, name)?.is_none() {
+                        serialize_subtree(ser, self.delta, offset)?;
+                    }
+                }
+            }
+        }
+        match self.trace {
+            Some((trace, layer)) => trace.record_range(start, ser.offset(), Origin::Layer(layer)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Write a copy of the subtree of `fdt` whose BeginNode token is at `offset`, leaving out nodes
+/// marked for deletion.
+fn serialize_subtree(ser: &mut Serializer, fdt: &DevTree, offset: usize) -> Result<()> {
+    let mut iter = DevTreeParseIter { offset, fdt };
+    // Depth within the subtree, and within a subtree being left out.
+    let mut depth = 0usize;
+    let mut drop_depth = 0usize;
+    loop {
+        match iter.next()?.ok_or(DevTreeError::ParseError)? {
+            ParsedTok::BeginNode(node) => {
+                depth += 1;
+                if drop_depth > 0 || is_deleted(fdt, iter.offset)? {
+                    drop_depth += 1;
+                } else {
+                    ser.serialize_new_begin_node(node.name)?;
+                }
+            }
+            ParsedTok::EndNode => {
+                depth -= 1;
+                if drop_depth > 0 {
+                    drop_depth -= 1;
+                } else {
+                    ser.serialize_new_end_node()?;
+                }
+                if depth == 0 {
+                    return Ok(());
+                }
+            }
+            ParsedTok::Prop(prop) if drop_depth == 0 => {
+                let (name, value) = (prop_name(fdt, &prop)?, prop.prop_buf);
+                ser.serialize_new_prop(from_utf8(name)?, &MetadataValue::Bytes(value))?;
+            }
+            ParsedTok::Prop(_) | ParsedTok::Nop => (),
+        }
+    }
+}
+
+impl<'o, 'dt> Serializer<'o, 'dt> {
+    /// Serialize the result of merging `delta` into `base` into `buf`, as `dtc` merges a later
+    /// definition of a node into an earlier one:
+    ///
+    /// * Properties of `delta` replace those of the same name in `base`'s node at the same path,
+    ///   or are added after its other properties.
+    /// * Nodes of `delta` which aren't in `base` are added, with their subtrees, after the other
+    ///   children of their parent.
+    /// * Nodes of `delta` with a [`DELETE_NODE_MARKER`] property delete the node at the same path
+    ///   of `base` along with its subtree. The marker itself is never written.
+    ///
+    /// Node names must match exactly, including their unit addresses. Phandles aren't renumbered,
+    /// so the nodes `delta` adds must not reuse the phandles of `base`. The memory reservations
+    /// and header fields of `base` are kept, and those of `delta` ignored.
+    ///
+    /// Returns the size of the merged device tree, or [`DevTreeError::OutputBufferTooSmall`] if
+    /// it doesn't fit in `buf`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use fdt_rs::doctest::FDT;
+    /// use fdt_rs::prelude::*;
+    /// use fdt_rs::base::*;
+    /// use fdt_rs::modify::*;
+    ///
+    /// let base = unsafe { DevTree::new(FDT) }.unwrap();
+    ///
+    /// // A board variant with a different model and no flash.
+    /// let mut delta_buf = vec![0u32; 64];
+    /// let delta_out = unsafe {
+    ///     core::slice::from_raw_parts_mut(delta_buf.as_mut_ptr() as *mut u8, 256)
+    /// };
+    /// let mut builder = FdtBuilder::new(delta_out);
+    /// builder.begin_node("").unwrap();
+    /// builder.prop_str("model", "acme,variant-b").unwrap();
+    /// builder.begin_node("flash@20000000").unwrap();
+    /// builder.prop_bytes(DELETE_NODE_MARKER, &[]).unwrap();
+    /// builder.end_node().unwrap();
+    /// builder.end_node().unwrap();
+    /// let size = builder.finish().unwrap();
+    /// let delta = unsafe { DevTree::new(&delta_out[..size]) }.unwrap();
+    ///
+    /// let mut buf = vec![0u32; FDT.len() / 4];
+    /// let out = unsafe {
+    ///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, FDT.len())
+    /// };
+    /// let size = Serializer::merge(&base, &delta, out).unwrap();
+    ///
+    /// let merged = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    /// let model = merged.props().find(|p| Ok(p.name()? == "model")).unwrap().unwrap();
+    /// assert_eq!(model.str().unwrap(), "acme,variant-b");
+    /// assert!(merged.nodes().find(|n| Ok(n.name()? == "flash@20000000")).unwrap().is_none());
+    /// ```
+    pub fn merge(base: &DevTree<'dt>, delta: &DevTree, buf: &'o mut [u8]) -> Result<usize> {
+        Self::merge_with(base, delta, buf, |_| PropMergePolicy::Replace)
+    }
+
+    /// As [`Serializer::merge`], but merge each property which both trees' nodes have as
+    /// `policy` returns for its name, rather than always replacing the base's value.
+    ///
+    /// A [`PropMergePolicies`] table may be used as the policy.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use fdt_rs::doctest::FDT;
+    /// use fdt_rs::prelude::*;
+    /// use fdt_rs::base::*;
+    /// use fdt_rs::modify::*;
+    ///
+    /// let base = unsafe { DevTree::new(FDT) }.unwrap();
+    ///
+    /// let mut delta_buf = vec![0u32; 64];
+    /// let delta_out = unsafe {
+    ///     core::slice::from_raw_parts_mut(delta_buf.as_mut_ptr() as *mut u8, 256)
+    /// };
+    /// let mut builder = FdtBuilder::new(delta_out);
+    /// builder.begin_node("").unwrap();
+    /// builder.prop_str("compatible", "acme,board").unwrap();
+    /// builder.end_node().unwrap();
+    /// let size = builder.finish().unwrap();
+    /// let delta = unsafe { DevTree::new(&delta_out[..size]) }.unwrap();
+    ///
+    /// let policies = PropMergePolicies {
+    ///     by_name: &[("compatible", PropMergePolicy::AppendDedup)],
+    ///     default: PropMergePolicy::Replace,
+    /// };
+    /// let mut buf = vec![0u32; FDT.len() / 4 + 16];
+    /// let out = unsafe {
+    ///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4)
+    /// };
+    /// let size = Serializer::merge_with(&base, &delta, out, |name| policies.policy(name)).unwrap();
+    ///
+    /// let merged = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    /// let compatible = merged.props().find(|p| Ok(p.name()? == "compatible")).unwrap().unwrap();
+    /// assert_eq!(compatible.raw(), b"riscv-virtio\0acme,board\0");
+    /// ```
+    pub fn merge_with<P>(
+        base: &DevTree<'dt>,
+        delta: &DevTree,
+        buf: &'o mut [u8],
+        policy: P,
+    ) -> Result<usize>
+    where
+        P: Fn(&str) -> PropMergePolicy,
+    {
+        Self::merge_impl(base, delta, buf, policy, None)
+    }
+
+    /// As [`Serializer::merge_with`], but also record where each node and property of the
+    /// merged tree came from in `trace`, attributing those of `delta` to
+    /// [`Origin::Layer`]`(layer)`.
+    ///
+    /// To trace a chain of merges (e.g. a base tree and overlays applied in turn), pass each
+    /// merge the [`ProvenanceMap`](crate::modify::ProvenanceMap) of the last as
+    /// [`Trace::source`], and a new layer number.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use fdt_rs::doctest::FDT;
+    /// use fdt_rs::prelude::*;
+    /// use fdt_rs::base::*;
+    /// use fdt_rs::modify::*;
+    ///
+    /// let base = unsafe { DevTree::new(FDT) }.unwrap();
+    ///
+    /// let mut delta_buf = vec![0u32; 64];
+    /// let delta_out = unsafe {
+    ///     core::slice::from_raw_parts_mut(delta_buf.as_mut_ptr() as *mut u8, 256)
+    /// };
+    /// let mut builder = FdtBuilder::new(delta_out);
+    /// builder.begin_node("").unwrap();
+    /// builder.prop_str("model", "acme,variant-b").unwrap();
+    /// builder.end_node().unwrap();
+    /// let size = builder.finish().unwrap();
+    /// let delta = unsafe { DevTree::new(&delta_out[..size]) }.unwrap();
+    ///
+    /// let mut records = [ProvenanceRecord::default(); 8];
+    /// let provenance = ProvenanceMap::new(&mut records);
+    /// let trace = Trace { output: &provenance, source: None };
+    /// let mut buf = vec![0u32; FDT.len() / 4 + 16];
+    /// let out = unsafe {
+    ///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4)
+    /// };
+    /// let size =
+    ///     Serializer::merge_traced(&base, &delta, out, |_| PropMergePolicy::Replace, 1, trace)
+    ///         .unwrap();
+    ///
+    /// let merged = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    /// let model = merged.props().find(|p| Ok(p.name()? == "model")).unwrap().unwrap();
+    /// assert_eq!(provenance.origin_of_prop(&model), Origin::Layer(1));
+    /// let compatible = merged.props().find(|p| Ok(p.name()? == "compatible")).unwrap().unwrap();
+    /// assert_eq!(provenance.origin_of_prop(&compatible), Origin::Base);
+    /// ```
+    pub fn merge_traced<P>(
+        base: &DevTree<'dt>,
+        delta: &DevTree,
+        buf: &'o mut [u8],
+        policy: P,
+        layer: usize,
+        trace: Trace,
+    ) -> Result<usize>
+    where
+        P: Fn(&str) -> PropMergePolicy,
+    {
+        trace.output.clear();
+        Self::merge_impl(base, delta, buf, policy, Some((trace, layer)))
+    }
+
+    fn merge_impl<P>(
+        base: &DevTree<'dt>,
+        delta: &DevTree,
+        buf: &'o mut [u8],
+        policy: P,
+        trace: Option<(Trace, usize)>,
+    ) -> Result<usize>
+    where
+        P: Fn(&str) -> PropMergePolicy,
+    {
+        let merge = Merge {
+            base,
+            delta,
+            policy,
+            trace,
+            base_nodes: array::from_fn(|_| Cell::new(0)),
+            delta_nodes: array::from_fn(|_| Cell::new(None)),
+            error: Cell::new(None),
+        };
+        let size =
+            Self::modify_with_inserts(base, buf, &ModifyOptions::default(), &merge, |ctx, tok| {
+                merge.respond(ctx, tok)
+            })?;
+        match merge.error.get() {
+            Some(err) => Err(err),
+            None => Ok(size),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+#[cfg(doc)]
+use crate::modify::Serializer;
+
+use crate::priv_util::{SliceWrite, SliceWriteResult};
+
+/// The value of a [`MetadataProp`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetadataValue<'m> {
+    /// A string. The null terminator is added when it is serialized.
+    Str(&'m str),
+    /// A single big endian 32 bit cell.
+    U32(u32),
+    /// Two big endian 32 bit cells.
+    U64(u64),
+    /// Raw bytes, written as is.
+    Bytes(&'m [u8]),
+}
+
+impl MetadataValue<'_> {
+    /// Returns the length of the serialized value.
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            MetadataValue::Str(s) => s.len() + 1,
+            MetadataValue::U32(_) => 4,
+            MetadataValue::U64(_) => 8,
+            MetadataValue::Bytes(b) => b.len(),
+        }
+    }
+
+    /// Write the serialized value to the start of `buf`.
+    pub(crate) fn write_to(&self, buf: &mut [u8]) -> SliceWriteResult {
+        match *self {
+            MetadataValue::Str(s) => {
+                buf.write_slice(0, s.as_bytes())?;
+                buf.write_slice(s.len(), &[0])
+            }
+            MetadataValue::U32(val) => buf.write_be_u32(0, val),
+            MetadataValue::U64(val) => buf.write_be_u64(0, val),
+            MetadataValue::Bytes(b) => buf.write_slice(0, b),
+        }
+    }
+}
+
+/// A property of a [`MetadataNode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MetadataProp<'m> {
+    pub name: &'m str,
+    pub value: MetadataValue<'m>,
+}
+
+impl<'m> MetadataProp<'m> {
+    #[must_use]
+    pub const fn new(name: &'m str, value: MetadataValue<'m>) -> Self {
+        Self { name, value }
+    }
+}
+
+/// A node of firmware or board metadata (version strings, build IDs, boot counters, ...) to add
+/// to a device tree with [`Serializer::modify_with_metadata`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MetadataNode<'m> {
+    pub name: &'m str,
+    pub props: &'m [MetadataProp<'m>],
+}
+
+impl<'m> MetadataNode<'m> {
+    #[must_use]
+    pub const fn new(name: &'m str, props: &'m [MetadataProp<'m>]) -> Self {
+        Self { name, props }
+    }
+}
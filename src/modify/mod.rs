@@ -0,0 +1,147 @@
+//! Utilities to modify a [`DevTree`] by serializing a modified copy of it.
+//!
+//! # Overview
+//!
+//! A FDT is a packed binary format, so most edits can't be made in place. Instead, the
+//! [`Serializer`] walks the tokens of an existing [`DevTree`] and writes a new device tree into a
+//! caller provided buffer. Each token is handed to a callback first, which decides whether the token is
+//! written unchanged, dropped, replaced, or written with a new property value or node name.
+//!
+//! No allocator is required. The only memory used is the output buffer. To avoid even that,
+//! [`Serializer::modify_to_writer`] writes the device tree to a [`FdtWrite`] sink as it goes.
+//!
+//! To write a device tree from scratch rather than copying one, use [`FdtBuilder`].
+//!
+//! For the common case of setting properties and adding or deleting nodes by path,
+//! [`DevTreeModifier`] builds the callback for you. To combine several independent callbacks in
+//! one pass, use a [`ModifyPipeline`].
+//!
+//! When there's no room for a second buffer, [`modify_in_place`] rewrites a device tree within
+//! its own buffer instead, provided no token grows.
+//!
+//! To use the output with overlays, [`ModifyOptions::overlay`] adds the `__symbols__`,
+//! `__fixups__` and `__local_fixups__` nodes built from caller supplied label metadata.
+//!
+//! To combine a base tree with a small delta tree (e.g. a board variant), use
+//! [`Serializer::merge`]. To find out which layer or edit each node and property of a composed
+//! tree came from, trace the merges and edits with a [`ProvenanceMap`].
+//!
+//! To ship edits separately from a full device tree (e.g. as an OTA fixup), write them as a
+//! compact [`Patch`] with a [`PatchWriter`], and apply the patch to a compatible tree later.
+//!
+//! To fix the known quirks of vendor trees (e.g. legacy property names) in one normalization pass,
+//! register a handler per `compatible` string with a [`QuirkRegistry`].
+//!
+//! To store several trees (e.g. per-guest variants) back-to-back in one buffer, write them with a
+//! [`DevTreeCatalogWriter`] and enumerate them later with a [`DevTreeCatalogIter`].
+//!
+//! # Examples
+//!
+//! ## Removing a node
+//!
+//! Dropping a node's [`ModifyParsedTok::BeginNode`] removes the node and its entire subtree:
+//! ```
+//! # use fdt_rs::doctest::FDT;
+//! use fdt_rs::prelude::*;
+//! use fdt_rs::base::*;
+//! use fdt_rs::modify::*;
+//!
+//! let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+//!
+//! let mut buf = vec![0u32; FDT.len() / 4];
+//! let out = unsafe {
+//!     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, FDT.len())
+//! };
+//!
+//! let size = Serializer::modify(&devtree, out, |tok| match tok {
+//!     ModifyParsedTok::BeginNode(node, _) if node.name == b"cpus" => ModifyTokenResponse::Drop,
+//!     _ => ModifyTokenResponse::Pass,
+//! })
+//! .unwrap();
+//!
+//! // The cpus node and its five descendants are gone.
+//! let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+//! assert_eq!(modified.nodes().count().unwrap(), devtree.nodes().count().unwrap() - 6);
+//! ```
+#[cfg(doc)]
+use crate::base::DevTree;
+
+macro_rules! set_be32_field {
+    ( $f:ident, $s:ident , $buf:expr, $val:expr ) => {
+        $buf.write_be_u32(offset_of!($s, $f), $val as u32)
+    };
+}
+
+#[doc(hidden)]
+pub mod addr_remap;
+#[doc(hidden)]
+pub mod builder;
+#[doc(hidden)]
+pub mod catalog;
+mod graft;
+#[doc(hidden)]
+pub mod in_place;
+mod incremental;
+#[doc(hidden)]
+pub mod irq_remap;
+#[doc(hidden)]
+pub mod merge;
+#[doc(hidden)]
+pub mod metadata;
+#[doc(hidden)]
+pub mod modifier;
+#[doc(hidden)]
+pub mod overlay;
+#[doc(hidden)]
+pub mod patch;
+#[doc(hidden)]
+pub mod phandle_alloc;
+#[doc(hidden)]
+pub mod pipeline;
+#[doc(hidden)]
+pub mod prop_writer;
+#[doc(hidden)]
+pub mod provenance;
+#[doc(hidden)]
+pub mod quirks;
+#[doc(hidden)]
+pub mod renumber;
+#[doc(hidden)]
+pub mod serializer;
+pub(crate) mod sorted;
+mod strings;
+
+#[doc(inline)]
+pub use addr_remap::*;
+#[doc(inline)]
+pub use builder::*;
+#[doc(inline)]
+pub use catalog::*;
+#[doc(inline)]
+pub use in_place::*;
+#[doc(inline)]
+pub use irq_remap::*;
+#[doc(inline)]
+pub use merge::*;
+#[doc(inline)]
+pub use metadata::*;
+#[doc(inline)]
+pub use modifier::*;
+#[doc(inline)]
+pub use overlay::*;
+#[doc(inline)]
+pub use patch::*;
+#[doc(inline)]
+pub use phandle_alloc::*;
+#[doc(inline)]
+pub use pipeline::*;
+#[doc(inline)]
+pub use prop_writer::*;
+#[doc(inline)]
+pub use provenance::*;
+#[doc(inline)]
+pub use quirks::*;
+#[doc(inline)]
+pub use renumber::*;
+#[doc(inline)]
+pub use serializer::*;
@@ -0,0 +1,578 @@
+use core::cell::Cell;
+
+use crate::base::DevTree;
+use crate::error::{DevTreeError, Result};
+use crate::modify::graft::{bus_cells, serialize_bus_begin, serialize_graft};
+use crate::modify::patch::serialize_patch_node;
+use crate::modify::provenance::{begin_node_len, prop_len};
+use crate::modify::serializer::{Insert, InsertPoint};
+use crate::modify::{
+    MetadataNode, MetadataValue, ModifyContext, ModifyOptions, ModifyParsedTok,
+    ModifyTokenResponse, Origin, PatchRecordIter, PhandleStyle, Serializer, Trace,
+};
+use crate::scratch::ScratchArena;
+
+#[derive(Clone, Copy, Debug)]
+pub(super) enum EditKind<'m> {
+    SetProp(&'m str, MetadataValue<'m>),
+    DeleteProp(&'m str),
+    DeleteNode,
+    AddNode(&'m MetadataNode<'m>),
+    /// A node added by a [`Patch`](crate::modify::Patch), from the records beginning with its
+    /// `AddNode` record.
+    AddPatchNode(PatchRecordIter<'m>),
+    Graft(&'m DevTree<'m>, &'m str),
+    /// A graft to the bus at the edit's path.
+    GraftToBus(&'m DevTree<'m>, &'m str),
+}
+
+/// An edit to the node at `path`.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Edit<'m> {
+    pub(super) path: &'m str,
+    pub(super) kind: EditKind<'m>,
+}
+
+impl Edit<'_> {
+    /// Returns the name of the property this edit sets or deletes.
+    pub(super) fn prop_name(&self) -> Option<&[u8]> {
+        match self.kind {
+            EditKind::SetProp(name, _) | EditKind::DeleteProp(name) => Some(name.as_bytes()),
+            _ => None,
+        }
+    }
+
+    /// Returns whether both edits set or delete the same property.
+    fn same_prop(&self, other: &Edit) -> bool {
+        self.path == other.path
+            && self.prop_name().is_some()
+            && self.prop_name() == other.prop_name()
+    }
+}
+
+/// A list of path based edits to apply to a [`DevTree`] in a single [`Serializer`] pass.
+///
+/// Paths are absolute (e.g. `/chosen`) and node names must match exactly, including their unit
+/// addresses. The list is stored in a [`ScratchArena`], so no allocator is required.
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::prelude::*;
+/// use fdt_rs::base::*;
+/// use fdt_rs::modify::*;
+/// use fdt_rs::scratch::ScratchArena;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let mut buf = vec![0u32; FDT.len() / 2];
+/// let out = unsafe {
+///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4)
+/// };
+///
+/// let mut mem = [0u8; 512];
+/// let mut scratch = ScratchArena::new(&mut mem);
+/// let mut modifier = DevTreeModifier::new(&mut scratch, 4).unwrap();
+/// modifier
+///     .set_prop("/chosen", "bootargs", MetadataValue::Str("console=ttyS0"))
+///     .unwrap()
+///     .delete_node("/cpus")
+///     .unwrap();
+/// let size = modifier.apply(&devtree, out).unwrap();
+///
+/// let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+/// let bootargs = modified
+///     .props()
+///     .find(|p| Ok(p.name()? == "bootargs"))
+///     .unwrap()
+///     .unwrap();
+/// assert_eq!(bootargs.str().unwrap(), "console=ttyS0");
+/// ```
+#[derive(Debug)]
+pub struct DevTreeModifier<'s, 'm> {
+    edits: &'s mut [Option<Edit<'m>>],
+    /// Whether the node each edit targets was found by the last [`DevTreeModifier::apply`].
+    found: &'s [Cell<bool>],
+    /// The largest phandle of the tree the last [`DevTreeModifier::apply`] modified.
+    phandle_base: Cell<u32>,
+    pub(super) phandle_style: Option<PhandleStyle>,
+    /// The largest size of tree [`DevTreeModifier::apply`] may write.
+    size_budget: Option<usize>,
+    /// The number of edits passed to the serializer, while checking the size budget.
+    limit: Cell<usize>,
+    len: usize,
+}
+
+impl<'s, 'm> DevTreeModifier<'s, 'm> {
+    /// Create a modifier with room for `capacity` edits, allocated from `scratch`.
+    pub fn new(scratch: &mut ScratchArena<'s>, capacity: usize) -> Result<Self> {
+        let edits = scratch.alloc_slice(capacity, None)?;
+        let found = Cell::from_mut(scratch.alloc_slice(capacity, false)?).as_slice_of_cells();
+        Ok(Self {
+            edits,
+            found,
+            phandle_base: Cell::new(0),
+            phandle_style: None,
+            size_budget: None,
+            limit: Cell::new(usize::MAX),
+            len: 0,
+        })
+    }
+
+    /// Set the property `name` of the node at `path` to `value`.
+    ///
+    /// Any existing property of that name is rewritten in place. Otherwise the property is added
+    /// after the node's other properties. Where a property is both set and deleted, it is
+    /// deleted.
+    pub fn set_prop(
+        &mut self,
+        path: &'m str,
+        name: &'m str,
+        value: MetadataValue<'m>,
+    ) -> Result<&mut Self> {
+        self.push(path, EditKind::SetProp(name, value))
+    }
+
+    /// Delete the property `name` of the node at `path`, if it exists.
+    pub fn delete_prop(&mut self, path: &'m str, name: &'m str) -> Result<&mut Self> {
+        self.push(path, EditKind::DeleteProp(name))
+    }
+
+    /// Delete the node at `path` and its subtree, if it exists.
+    pub fn delete_node(&mut self, path: &'m str) -> Result<&mut Self> {
+        self.push(path, EditKind::DeleteNode)
+    }
+
+    /// Add `node` as the last child of the node at `parent`.
+    pub fn add_node(&mut self, parent: &'m str, node: &'m MetadataNode<'m>) -> Result<&mut Self> {
+        self.push(parent, EditKind::AddNode(node))
+    }
+
+    /// Add the node a [`Patch`](crate::modify::Patch) adds with the records `records` begins
+    /// with as the last child of the node at `parent`.
+    pub(super) fn add_patch_node(
+        &mut self,
+        parent: &'m str,
+        records: PatchRecordIter<'m>,
+    ) -> Result<&mut Self> {
+        self.push(parent, EditKind::AddPatchNode(records))
+    }
+
+    /// Add a copy of the node at `path` of `source`, and its subtree, as the last child of the
+    /// node at `parent`.
+    ///
+    /// Phandles defined within the copied subtree are renumbered above those of the tree being
+    /// modified, so they can't collide. References to them from properties which hold a plain
+    /// list of phandles (`interrupt-parent`, `msi-parent` and `pinctrl-<N>`) within the subtree
+    /// are renumbered to match. Other references are copied unchanged.
+    pub fn graft(
+        &mut self,
+        parent: &'m str,
+        source: &'m DevTree<'m>,
+        path: &'m str,
+    ) -> Result<&mut Self> {
+        self.push(parent, EditKind::Graft(source, path))
+    }
+
+    /// As [`DevTreeModifier::graft`], but add the copy as the last child of the `simple-bus`
+    /// node at `bus` (e.g. `/passthrough`).
+    ///
+    /// If there's no such bus, one is added as the last child of its parent. Its `ranges` is
+    /// empty, so its addresses are those of its parent, and its `#address-cells` and
+    /// `#size-cells` are those of the node's parent in `source`, so the node's `reg` is copied
+    /// unchanged. Every node grafted to the same new bus must have the same cells. An existing
+    /// bus is left as it is, so must already have them.
+    ///
+    /// Returns [`DevTreeError::InvalidParameter`] from [`DevTreeModifier::apply`] unless the
+    /// node's parent in `source` is the root node or a bus with an empty `ranges`, so the
+    /// node's addresses are CPU addresses.
+    pub fn graft_to_bus(
+        &mut self,
+        bus: &'m str,
+        source: &'m DevTree<'m>,
+        path: &'m str,
+    ) -> Result<&mut Self> {
+        self.push(bus, EditKind::GraftToBus(source, path))
+    }
+
+    /// Write every node's phandle properties in `style` (including those of grafted
+    /// subtrees), see [`ModifyOptions::phandle_style`].
+    pub fn set_phandle_style(&mut self, style: PhandleStyle) -> &mut Self {
+        self.phandle_style = Some(style);
+        self
+    }
+
+    /// Limit the size of the tree [`DevTreeModifier::apply`] (or
+    /// [`DevTreeModifier::apply_incremental`]) writes to `budget` bytes.
+    ///
+    /// The size is projected with a dry run before anything is written. If it's over budget,
+    /// `apply` fails with [`DevTreeError::SizeBudgetExceeded`], naming the first edit after
+    /// which the projected size is over budget. This costs a dry run per edit, so only when
+    /// the budget is exceeded.
+    pub fn set_size_budget(&mut self, budget: usize) -> &mut Self {
+        self.size_budget = Some(budget);
+        self
+    }
+
+    /// Serialize a copy of `fdt` with the edits applied into `buf`, as
+    /// [`Serializer::modify`] does.
+    ///
+    /// Returns [`DevTreeError::InvalidParameter`] if the node a property or child is to be added
+    /// to doesn't exist, or [`DevTreeError::SizeBudgetExceeded`] if the tree would be larger
+    /// than the [size budget](DevTreeModifier::set_size_budget).
+    pub fn apply<'dt>(&self, fdt: &DevTree<'dt>, buf: &mut [u8]) -> Result<usize> {
+        self.apply_impl(fdt, buf, None)
+    }
+
+    /// As [`DevTreeModifier::apply`], but also record where each node and property of the
+    /// output came from in `trace`, attributing those each edit adds or sets to
+    /// [`Origin::Edit`] with the edit's index.
+    pub fn apply_traced<'dt>(
+        &self,
+        fdt: &DevTree<'dt>,
+        buf: &mut [u8],
+        trace: Trace,
+    ) -> Result<usize> {
+        trace.output.clear();
+        self.apply_impl(fdt, buf, Some(trace))
+    }
+
+    fn apply_impl<'dt>(
+        &self,
+        fdt: &DevTree<'dt>,
+        buf: &mut [u8],
+        trace: Option<Trace>,
+    ) -> Result<usize> {
+        if self
+            .edits()
+            .any(|(edit, _)| matches!(edit.kind, EditKind::Graft(..) | EditKind::GraftToBus(..)))
+        {
+            self.phandle_base.set(fdt.max_phandle()?);
+        }
+        self.enforce_budget(fdt, buf)?;
+
+        self.reset_found();
+        let applied = Applied::new(self, trace);
+        let size =
+            Serializer::modify_with_inserts(fdt, buf, &self.options(), &applied, |ctx, tok| {
+                applied.respond(ctx, tok)
+            })?;
+        if let Some(err) = applied.error.get() {
+            return Err(err);
+        }
+
+        let missing = self.edits().any(|(edit, found)| {
+            !found.get()
+                && matches!(
+                    edit.kind,
+                    EditKind::SetProp(..)
+                        | EditKind::AddNode(_)
+                        | EditKind::AddPatchNode(_)
+                        | EditKind::Graft(..)
+                        | EditKind::GraftToBus(..)
+                )
+        });
+        if missing {
+            return Err(DevTreeError::InvalidParameter("Node to modify not found"));
+        }
+        Ok(size)
+    }
+
+    /// Forget which edits' nodes were found, before a run.
+    fn reset_found(&self) {
+        for found in self.found {
+            found.set(false);
+        }
+    }
+
+    fn options(&self) -> ModifyOptions<'_> {
+        ModifyOptions {
+            phandle_style: self.phandle_style,
+            ..ModifyOptions::default()
+        }
+    }
+
+    /// Returns the size of the tree the first `limit` edits would write, using `scratch` for a
+    /// dry run.
+    fn projected_size<'dt>(
+        &self,
+        fdt: &DevTree<'dt>,
+        scratch: &mut [u8],
+        limit: usize,
+    ) -> Result<usize> {
+        self.limit.set(limit);
+        self.reset_found();
+        let applied = Applied::new(self, None);
+        Serializer::dry_run_with_inserts(fdt, scratch, &self.options(), &applied, |ctx, tok| {
+            applied.respond(ctx, tok)
+        })
+    }
+
+    /// Check that the edits don't make the tree larger than the size budget (if any), using
+    /// `scratch` for dry runs.
+    pub(super) fn enforce_budget(&self, fdt: &DevTree, scratch: &mut [u8]) -> Result<()> {
+        match self.size_budget {
+            Some(budget) => {
+                let result = self.check_budget(fdt, scratch, budget);
+                self.limit.set(usize::MAX);
+                result
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Check that the edits don't make the tree larger than `budget`, using `scratch` for dry
+    /// runs.
+    fn check_budget(&self, fdt: &DevTree, scratch: &mut [u8], budget: usize) -> Result<()> {
+        let needed = self.projected_size(fdt, scratch, self.len)?;
+        if needed <= budget {
+            return Ok(());
+        }
+        for limit in 0..self.len {
+            if self.projected_size(fdt, scratch, limit)? > budget {
+                return Err(DevTreeError::SizeBudgetExceeded {
+                    edit: limit.checked_sub(1),
+                    needed,
+                    budget,
+                });
+            }
+        }
+        Err(DevTreeError::SizeBudgetExceeded {
+            edit: self.len.checked_sub(1),
+            needed,
+            budget,
+        })
+    }
+
+    fn push(&mut self, path: &'m str, kind: EditKind<'m>) -> Result<&mut Self> {
+        let slot = self
+            .edits
+            .get_mut(self.len)
+            .ok_or(DevTreeError::NotEnoughMemory)?;
+        *slot = Some(Edit { path, kind });
+        self.len += 1;
+        Ok(self)
+    }
+
+    pub(super) fn edits(&self) -> impl Iterator<Item = (&Edit<'m>, &Cell<bool>)> {
+        self.edits[..self.len.min(self.limit.get())]
+            .iter()
+            .flatten()
+            .zip(self.found)
+    }
+
+    /// Returns whether the `i`th edit, which sets or deletes a property, decides the
+    /// property's final state.
+    ///
+    /// Where a property is set more than once, the last value wins. Deleting a property takes
+    /// precedence over setting it.
+    pub(super) fn takes_effect(&self, i: usize) -> bool {
+        let edit = match self.edits().nth(i) {
+            Some((edit, _)) => edit,
+            None => return false,
+        };
+        !self.edits().enumerate().any(|(j, (other, _))| {
+            j != i
+                && other.same_prop(edit)
+                && (j > i || matches!(other.kind, EditKind::DeleteProp(_)))
+        })
+    }
+
+    /// Write a new `simple-bus` node at `bus` holding every node grafted to it.
+    fn serialize_new_bus(
+        &self,
+        ser: &mut Serializer,
+        bus: &str,
+        trace: Option<Trace>,
+    ) -> Result<()> {
+        let mut cells = None;
+        for (i, (edit, found)) in self.edits().enumerate() {
+            match edit.kind {
+                EditKind::GraftToBus(source, path) if edit.path == bus => {
+                    let graft_cells = bus_cells(source, path)?;
+                    if cells.is_none() {
+                        let (_, name) = split_path(bus);
+                        serialize_bus_begin(ser, name, graft_cells.0, graft_cells.1)?;
+                    } else if cells != Some(graft_cells) {
+                        return Err(DevTreeError::InvalidParameter(
+                            "Nodes grafted to a bus have different cells",
+                        ));
+                    }
+                    cells = Some(graft_cells);
+                    let start = ser.offset();
+                    serialize_graft(ser, source, path, self.phandle_base.get())?;
+                    record_range(trace, start, ser, i)?;
+                    found.set(true);
+                }
+                _ => (),
+            }
+        }
+        ser.serialize_new_end_node()
+    }
+
+    /// Returns the response to `tok`, and the index of the edit which decided it, if any.
+    fn respond<'dt>(
+        &self,
+        ctx: &ModifyContext<'_, 'dt>,
+        tok: ModifyParsedTok<'_, 'dt>,
+    ) -> (ModifyTokenResponse<'m>, Option<usize>) {
+        let response = match tok {
+            ModifyParsedTok::BeginNode(..)
+                if self
+                    .edits()
+                    .filter(|(edit, _)| ctx.is_at(edit.path))
+                    .any(|(edit, _)| matches!(edit.kind, EditKind::DeleteNode)) =>
+            {
+                ModifyTokenResponse::Drop
+            }
+            ModifyParsedTok::BeginNode(..) => {
+                // Nodes are grafted to an existing bus when it ends, rather than to a new one.
+                for (edit, found) in self.edits() {
+                    if matches!(edit.kind, EditKind::GraftToBus(..)) && ctx.is_at(edit.path) {
+                        found.set(true);
+                    }
+                }
+                ModifyTokenResponse::Pass
+            }
+            ModifyParsedTok::Prop(_, name, value_buf) => {
+                let mut response = (ModifyTokenResponse::Pass, None);
+                for (i, (edit, found)) in self.edits().enumerate() {
+                    if !ctx.is_at(edit.path) || edit.prop_name() != Some(name) {
+                        continue;
+                    }
+                    found.set(true);
+                    match edit.kind {
+                        EditKind::SetProp(_, value) if self.takes_effect(i) => {
+                            // A value which doesn't fit makes the serializer report the overflow.
+                            let _ = value.write_to(value_buf);
+                            response = (ModifyTokenResponse::ModifySize(value.len()), Some(i));
+                        }
+                        EditKind::DeleteProp(_) => response = (ModifyTokenResponse::Drop, None),
+                        _ => (),
+                    }
+                }
+                return response;
+            }
+            _ => ModifyTokenResponse::Pass,
+        };
+        (response, None)
+    }
+
+    /// Serialize the tokens the edits add to the node at `ctx` with `ser`, recording where they
+    /// came from in `trace`.
+    fn insert<'dt>(
+        &self,
+        at: InsertPoint,
+        ctx: &ModifyContext<'_, 'dt>,
+        ser: &mut Serializer<'_, 'dt>,
+        trace: Option<Trace>,
+    ) -> Result<()> {
+        for (i, (edit, found)) in self.edits().enumerate() {
+            let start = ser.offset();
+            if let (InsertPoint::Children, EditKind::GraftToBus(source, path)) = (at, edit.kind) {
+                let (parent, _) = split_path(edit.path);
+                if ctx.is_at(edit.path) {
+                    serialize_graft(ser, source, path, self.phandle_base.get())?;
+                } else if ctx.is_at(parent) && !found.get() {
+                    self.serialize_new_bus(ser, edit.path, trace)?;
+                }
+                record_range(trace, start, ser, i)?;
+                continue;
+            }
+            if !ctx.is_at(edit.path) {
+                continue;
+            }
+            match (at, edit.kind) {
+                (InsertPoint::Props, EditKind::SetProp(name, value)) if !found.get() => {
+                    if self.takes_effect(i) {
+                        ser.serialize_new_prop(name, &value)?;
+                    }
+                    found.set(true);
+                }
+                (InsertPoint::Children, EditKind::AddNode(node)) => {
+                    ser.serialize_metadata_node(node)?;
+                    found.set(true);
+                }
+                (InsertPoint::Children, EditKind::AddPatchNode(records)) => {
+                    serialize_patch_node(ser, records)?;
+                    found.set(true);
+                }
+                (InsertPoint::Children, EditKind::Graft(source, path)) => {
+                    serialize_graft(ser, source, path, self.phandle_base.get())?;
+                    found.set(true);
+                }
+                _ => (),
+            }
+            record_range(trace, start, ser, i)?;
+        }
+        Ok(())
+    }
+}
+
+/// Record that the tokens `ser` wrote since `start` came from the `i`th edit.
+fn record_range(trace: Option<Trace>, start: usize, ser: &Serializer, i: usize) -> Result<()> {
+    match trace {
+        Some(trace) => trace.record_range(start, ser.offset(), Origin::Edit(i)),
+        None => Ok(()),
+    }
+}
+
+/// The edits of a [`DevTreeModifier`] being applied, and where to record their provenance.
+struct Applied<'a, 's, 'm, 't> {
+    modifier: &'a DevTreeModifier<'s, 'm>,
+    trace: Option<Trace<'t>>,
+    /// The first error found while recording provenance.
+    error: Cell<Option<DevTreeError>>,
+}
+
+impl<'a, 's, 'm, 't> Applied<'a, 's, 'm, 't> {
+    fn new(modifier: &'a DevTreeModifier<'s, 'm>, trace: Option<Trace<'t>>) -> Self {
+        Self {
+            modifier,
+            trace,
+            error: Cell::new(None),
+        }
+    }
+
+    fn respond<'dt>(
+        &self,
+        ctx: &ModifyContext<'_, 'dt>,
+        tok: ModifyParsedTok<'_, 'dt>,
+    ) -> ModifyTokenResponse<'m> {
+        let len = match &tok {
+            ModifyParsedTok::BeginNode(node, _) => Some(begin_node_len(node.name)),
+            ModifyParsedTok::Prop(prop, ..) => Some(prop_len(prop.prop_buf.len())),
+            _ => None,
+        };
+        let (response, edit) = self.modifier.respond(ctx, tok);
+        let result = match (self.trace, len, response) {
+            (Some(trace), Some(len), ModifyTokenResponse::Pass) => trace.record(ctx, len, None),
+            (Some(trace), _, ModifyTokenResponse::ModifySize(size)) => {
+                trace.record(ctx, prop_len(size), edit.map(Origin::Edit))
+            }
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            self.error.set(self.error.get().or(Some(err)));
+        }
+        response
+    }
+}
+
+impl<'dt> Insert<'dt> for Applied<'_, '_, '_, '_> {
+    fn insert(
+        &self,
+        at: InsertPoint,
+        ctx: &ModifyContext<'_, 'dt>,
+        ser: &mut Serializer<'_, 'dt>,
+    ) -> Result<()> {
+        self.modifier.insert(at, ctx, ser, self.trace)
+    }
+}
+
+/// Split an absolute path into the path of its node's parent and its node's name.
+pub(super) fn split_path(path: &str) -> (&str, &str) {
+    path.trim_end_matches('/')
+        .rsplit_once('/')
+        .unwrap_or(("", path))
+}
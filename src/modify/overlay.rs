@@ -0,0 +1,194 @@
+#[cfg(doc)]
+use crate::modify::ModifyOptions;
+
+use crate::error::Result;
+use crate::modify::{MetadataValue, Serializer};
+
+/// A node label, written to `__symbols__` so that overlays applied to the tree can refer to the
+/// node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OverlaySymbol<'m> {
+    pub label: &'m str,
+    /// The full path of the labelled node.
+    pub path: &'m str,
+}
+
+impl<'m> OverlaySymbol<'m> {
+    #[must_use]
+    pub const fn new(label: &'m str, path: &'m str) -> Self {
+        Self { label, path }
+    }
+}
+
+/// A phandle cell which refers to a label the tree doesn't define, written to `__fixups__` so
+/// it's filled in when the tree is applied as an overlay.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OverlayFixup<'m> {
+    /// The label the cell refers to.
+    pub label: &'m str,
+    /// The full path of the node whose property holds the cell.
+    pub path: &'m str,
+    pub prop: &'m str,
+    /// The byte offset of the cell within the property's value.
+    pub offset: u32,
+}
+
+impl<'m> OverlayFixup<'m> {
+    #[must_use]
+    pub const fn new(label: &'m str, path: &'m str, prop: &'m str, offset: u32) -> Self {
+        Self {
+            label,
+            path,
+            prop,
+            offset,
+        }
+    }
+}
+
+/// A phandle cell which refers to a node within the tree, written to `__local_fixups__` so it's
+/// renumbered along with the node's phandle when the tree is applied as an overlay.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LocalFixup<'m> {
+    /// The full path of the node whose property holds the cell.
+    pub path: &'m str,
+    pub prop: &'m str,
+    /// The byte offset of the cell within the property's value.
+    pub offset: u32,
+}
+
+impl<'m> LocalFixup<'m> {
+    #[must_use]
+    pub const fn new(path: &'m str, prop: &'m str, offset: u32) -> Self {
+        Self { path, prop, offset }
+    }
+}
+
+/// Label and phandle metadata to emit as the `__symbols__`, `__fixups__` and `__local_fixups__`
+/// nodes of an overlay capable tree, see [`ModifyOptions::overlay`].
+///
+/// The nodes are added as the last children of the root node. Each is left out if it would be
+/// empty. Any such nodes already in the source tree are left alone, so drop them with the
+/// serializer's callback if they're being regenerated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OverlayMetadata<'m> {
+    pub symbols: &'m [OverlaySymbol<'m>],
+    pub fixups: &'m [OverlayFixup<'m>],
+    pub local_fixups: &'m [LocalFixup<'m>],
+}
+
+/// Returns `path` without any trailing separators, so the root node's path is empty.
+fn trim_path(path: &str) -> &str {
+    path.trim_end_matches('/')
+}
+
+/// Write `value` in decimal to the end of `buf`, returning the digits.
+fn decimal(mut value: u32, buf: &mut [u8; 10]) -> &[u8] {
+    let mut start = buf.len();
+    loop {
+        start -= 1;
+        buf[start] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            return &buf[start..];
+        }
+    }
+}
+
+impl<'o, 'dt> Serializer<'o, 'dt> {
+    /// Write the overlay nodes described by `overlay`.
+    pub(crate) fn serialize_overlay_metadata(&mut self, overlay: &OverlayMetadata) -> Result<()> {
+        if !overlay.symbols.is_empty() {
+            self.serialize_new_begin_node(b"__symbols__")?;
+            for symbol in overlay.symbols {
+                self.serialize_new_prop(symbol.label, &MetadataValue::Str(symbol.path))?;
+            }
+            self.serialize_new_end_node()?;
+        }
+        if !overlay.fixups.is_empty() {
+            self.serialize_new_begin_node(b"__fixups__")?;
+            self.serialize_fixups(overlay.fixups)?;
+            self.serialize_new_end_node()?;
+        }
+        if !overlay.local_fixups.is_empty() {
+            self.serialize_new_begin_node(b"__local_fixups__")?;
+            self.serialize_local_fixups(overlay.local_fixups, "")?;
+            self.serialize_new_end_node()?;
+        }
+        Ok(())
+    }
+
+    /// Write a property per label, listing each cell which refers to it as `path:prop:offset`.
+    fn serialize_fixups(&mut self, fixups: &[OverlayFixup]) -> Result<()> {
+        let mut digits = [0u8; 10];
+        for (i, fixup) in fixups.iter().enumerate() {
+            if fixups[..i].iter().any(|f| f.label == fixup.label) {
+                continue;
+            }
+            let refs = || fixups[i..].iter().filter(|f| f.label == fixup.label);
+            let len: usize = refs()
+                .map(|f| f.path.len() + f.prop.len() + decimal(f.offset, &mut digits).len() + 3)
+                .sum();
+            self.serialize_new_prop_header(fixup.label, len)?;
+            for f in refs() {
+                self.serialize_value(f.path.as_bytes())?;
+                self.serialize_value(b":")?;
+                self.serialize_value(f.prop.as_bytes())?;
+                self.serialize_value(b":")?;
+                self.serialize_value(decimal(f.offset, &mut digits))?;
+                self.serialize_value(&[0])?;
+            }
+            self.serialize_value_end()?;
+        }
+        Ok(())
+    }
+
+    /// Write the contents of the `__local_fixups__` node mirroring the node at `path`: a
+    /// property of cell offsets for each of its properties with fixups, then a node for each
+    /// child with fixups within its subtree.
+    fn serialize_local_fixups(&mut self, fixups: &[LocalFixup], path: &str) -> Result<()> {
+        let at_path = |f: &&LocalFixup| trim_path(f.path) == path;
+        for (i, fixup) in fixups.iter().enumerate().filter(|(_, f)| at_path(f)) {
+            if fixups[..i]
+                .iter()
+                .filter(at_path)
+                .any(|f| f.prop == fixup.prop)
+            {
+                continue;
+            }
+            let offsets = || {
+                fixups[i..]
+                    .iter()
+                    .filter(at_path)
+                    .filter(|f| f.prop == fixup.prop)
+            };
+            self.serialize_new_prop_header(fixup.prop, offsets().count() * 4)?;
+            for f in offsets() {
+                self.serialize_value(&f.offset.to_be_bytes())?;
+            }
+        }
+
+        // The end of the path of the child of `path` which holds the fixup (if any).
+        let child = |f: &LocalFixup<'_>| -> Option<usize> {
+            let rest = trim_path(f.path).strip_prefix(path)?.strip_prefix('/')?;
+            let name_len = rest.find('/').unwrap_or(rest.len());
+            Some(path.len() + 1 + name_len)
+        };
+        for (i, fixup) in fixups.iter().enumerate() {
+            let end = match child(fixup) {
+                Some(end) => end,
+                None => continue,
+            };
+            let child_path = &fixup.path[..end];
+            let seen = fixups[..i]
+                .iter()
+                .any(|f| child(f).is_some_and(|end| &f.path[..end] == child_path));
+            if seen {
+                continue;
+            }
+            self.serialize_new_begin_node(&child_path.as_bytes()[path.len() + 1..])?;
+            self.serialize_local_fixups(fixups, child_path)?;
+            self.serialize_new_end_node()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,585 @@
+use core::mem::size_of;
+use core::str::from_utf8;
+
+use crate::prelude::*;
+
+use crate::base::iters::DevTreeIter;
+use crate::base::parse::{DevTreeParseIter, ParsedTok};
+use crate::base::{DevTree, DevTreeNode, DevTreeProp};
+use crate::diff::DiffRecord;
+use crate::error::{DevTreeError, Result};
+use crate::modify::modifier::split_path;
+use crate::modify::{DevTreeModifier, MetadataValue, Serializer};
+use crate::priv_util::{SliceRead, SliceWrite};
+use crate::scratch::ScratchArena;
+
+// A patch is a header followed by its records. Integers are big endian u32s. Paths, names and
+// values are a length followed by that many bytes, unpadded. An absent old value has the length
+// 0xffffffff.
+//
+// Header: magic, version, record count.
+// SetProp: kind, path, name, old value, new value.
+// DeleteProp: kind, path, name, old value.
+// AddNode: kind, path.
+// DeleteNode: kind, path.
+const PATCH_MAGIC: u32 = 0x4644_5450;
+const PATCH_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 3 * size_of::<u32>();
+const ABSENT: u32 = u32::MAX;
+
+const SET_PROP: u32 = 1;
+const DELETE_PROP: u32 = 2;
+const ADD_NODE: u32 = 3;
+const DELETE_NODE: u32 = 4;
+
+/// A change made by a [`Patch`], to the node at an absolute path.
+///
+/// Node names must match path components exactly, including their unit addresses. Old values
+/// are those of the tree the patch applies to, so the patch can be checked against it before
+/// it's applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatchRecord<'p> {
+    /// Set the property `name` to `new`. `old` is its current value, or `None` if the node
+    /// doesn't have the property yet.
+    SetProp {
+        path: &'p str,
+        name: &'p str,
+        old: Option<&'p [u8]>,
+        new: &'p [u8],
+    },
+    /// Delete the property `name`, whose current value is `old`.
+    DeleteProp {
+        path: &'p str,
+        name: &'p str,
+        old: &'p [u8],
+    },
+    /// Add a node, as the last child of its parent.
+    ///
+    /// Its properties and its descendants are added by the [`PatchRecord::SetProp`] and
+    /// [`PatchRecord::AddNode`] records which immediately follow it, in tree order.
+    AddNode { path: &'p str },
+    /// Delete a node and its subtree.
+    DeleteNode { path: &'p str },
+}
+
+impl<'p> PatchRecord<'p> {
+    /// Returns the path of the node the record changes.
+    #[must_use]
+    pub fn path(&self) -> &'p str {
+        match *self {
+            PatchRecord::SetProp { path, .. }
+            | PatchRecord::DeleteProp { path, .. }
+            | PatchRecord::AddNode { path }
+            | PatchRecord::DeleteNode { path } => path,
+        }
+    }
+
+    /// Returns whether the record adds part of the subtree of a node added at `root`.
+    fn is_within(&self, root: &str) -> bool {
+        let path = match *self {
+            PatchRecord::SetProp {
+                path, old: None, ..
+            }
+            | PatchRecord::AddNode { path } => path,
+            _ => return false,
+        };
+        path.strip_prefix(root)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+/// Writes a [`Patch`] into a caller provided buffer.
+///
+/// Records are added by hand with [`PatchWriter::push`], or for every difference between two
+/// trees with [`PatchWriter::push_diff`]. The patch is complete once
+/// [`PatchWriter::finish`] writes its header.
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::prelude::*;
+/// use fdt_rs::base::*;
+/// use fdt_rs::modify::*;
+/// use fdt_rs::scratch::ScratchArena;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let mut mem = [0u8; 512];
+/// let mut scratch = ScratchArena::new(&mut mem);
+/// let mut modifier = DevTreeModifier::new(&mut scratch, 1).unwrap();
+/// modifier
+///     .set_prop("/chosen", "bootargs", MetadataValue::Str("console=ttyS0"))
+///     .unwrap();
+/// let mut buf = vec![0u32; FDT.len() / 2];
+/// let out = unsafe {
+///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4)
+/// };
+/// let size = modifier.apply(&devtree, out).unwrap();
+/// let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+///
+/// // Ship the difference as a patch, and apply it on the device.
+/// let mut blob = [0u8; 256];
+/// let mut path_buf = [0u8; 256];
+/// let mut writer = PatchWriter::new(&mut blob);
+/// writer.push_diff(&devtree, &modified, &mut path_buf).unwrap();
+/// let len = writer.finish().unwrap();
+///
+/// let patch = Patch::new(&blob[..len]).unwrap();
+/// let mut patched = vec![0u8; FDT.len() * 2];
+/// let mut mem = [0u8; 512];
+/// let size = patch
+///     .apply(&devtree, &mut patched, &mut ScratchArena::new(&mut mem))
+///     .unwrap();
+/// let patched = unsafe { DevTree::new(&patched[..size]) }.unwrap();
+/// assert!(patched.diff(&modified).next().unwrap().is_none());
+/// ```
+#[derive(Debug)]
+pub struct PatchWriter<'o> {
+    buf: &'o mut [u8],
+    offset: usize,
+    records: usize,
+}
+
+impl<'o> PatchWriter<'o> {
+    /// Create a writer which writes a patch into `buf`.
+    pub fn new(buf: &'o mut [u8]) -> Self {
+        Self {
+            buf,
+            offset: HEADER_SIZE,
+            records: 0,
+        }
+    }
+
+    /// Add `record` to the patch.
+    ///
+    /// Returns [`DevTreeError::OutputBufferTooSmall`] if it doesn't fit.
+    pub fn push(&mut self, record: &PatchRecord) -> Result<&mut Self> {
+        match *record {
+            PatchRecord::SetProp {
+                path,
+                name,
+                old,
+                new,
+            } => {
+                self.write_u32(SET_PROP)?;
+                self.write_bytes(path.as_bytes())?;
+                self.write_bytes(name.as_bytes())?;
+                match old {
+                    Some(old) => self.write_bytes(old)?,
+                    None => self.write_u32(ABSENT)?,
+                }
+                self.write_bytes(new)?;
+            }
+            PatchRecord::DeleteProp { path, name, old } => {
+                self.write_u32(DELETE_PROP)?;
+                self.write_bytes(path.as_bytes())?;
+                self.write_bytes(name.as_bytes())?;
+                self.write_bytes(old)?;
+            }
+            PatchRecord::AddNode { path } => {
+                self.write_u32(ADD_NODE)?;
+                self.write_bytes(path.as_bytes())?;
+            }
+            PatchRecord::DeleteNode { path } => {
+                self.write_u32(DELETE_NODE)?;
+                self.write_bytes(path.as_bytes())?;
+            }
+        }
+        self.records += 1;
+        Ok(self)
+    }
+
+    /// Add records which turn `old` into `new`, one for each difference
+    /// [`DevTree::diff`] finds. Nodes which `new` adds are added with their whole subtree.
+    ///
+    /// `path_buf` is used to build the path of each node changed, so must be large enough for
+    /// the longest (see [`DevTreeNode::path`]).
+    pub fn push_diff(
+        &mut self,
+        old: &DevTree,
+        new: &DevTree,
+        path_buf: &mut [u8],
+    ) -> Result<&mut Self> {
+        let mut diff = old.diff(new);
+        while let Some(record) = diff.next()? {
+            match record {
+                DiffRecord::NodeAdded(node) => self.push_subtree(&node, path_buf)?,
+                DiffRecord::NodeRemoved(node) => {
+                    let path = node.path(&mut ScratchArena::new(path_buf))?;
+                    self.push(&PatchRecord::DeleteNode { path })?;
+                }
+                DiffRecord::PropAdded(prop) => {
+                    self.push(&PatchRecord::SetProp {
+                        path: prop_path(&prop, path_buf)?,
+                        name: prop.name()?,
+                        old: None,
+                        new: prop.raw(),
+                    })?;
+                }
+                DiffRecord::PropRemoved(prop) => {
+                    self.push(&PatchRecord::DeleteProp {
+                        path: prop_path(&prop, path_buf)?,
+                        name: prop.name()?,
+                        old: prop.raw(),
+                    })?;
+                }
+                DiffRecord::PropChanged { old, new } => {
+                    self.push(&PatchRecord::SetProp {
+                        path: prop_path(&new, path_buf)?,
+                        name: new.name()?,
+                        old: Some(old.raw()),
+                        new: new.raw(),
+                    })?;
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// Write the patch's header, returning the size of the patch.
+    pub fn finish(self) -> Result<usize> {
+        self.buf.write_be_u32(0, PATCH_MAGIC)?;
+        self.buf.write_be_u32(4, PATCH_VERSION)?;
+        self.buf.write_be_u32(8, self.records as u32)?;
+        Ok(self.offset)
+    }
+
+    /// Add records which add `node` and its subtree.
+    fn push_subtree(&mut self, node: &DevTreeNode, path_buf: &mut [u8]) -> Result<()> {
+        let fdt = node.fdt();
+        let mut iter = DevTreeParseIter {
+            offset: node.byte_range()?.start,
+            fdt,
+        };
+        let mut depth = 0usize;
+        loop {
+            let offset = iter.offset;
+            match iter.next()? {
+                Some(ParsedTok::BeginNode(_)) => {
+                    depth += 1;
+                    let node = DevTreeIter::from_offset(fdt, offset)
+                        .next_node()?
+                        .ok_or(DevTreeError::ParseError)?;
+                    let path = node.path(&mut ScratchArena::new(path_buf))?;
+                    self.push(&PatchRecord::AddNode { path })?;
+                    let mut props = node.props();
+                    while let Some(prop) = props.next()? {
+                        self.push(&PatchRecord::SetProp {
+                            path,
+                            name: prop.name()?,
+                            old: None,
+                            new: prop.raw(),
+                        })?;
+                    }
+                }
+                Some(ParsedTok::EndNode) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                Some(_) => (),
+                None => return Err(DevTreeError::ParseError),
+            }
+        }
+    }
+
+    fn write_u32(&mut self, val: u32) -> Result<()> {
+        self.buf.write_be_u32(self.offset, val)?;
+        self.offset += size_of::<u32>();
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.write_u32(bytes.len() as u32)?;
+        self.buf.write_slice(self.offset, bytes)?;
+        self.offset += bytes.len();
+        Ok(())
+    }
+}
+
+/// A compact list of property level edits written by a [`PatchWriter`], which can be shipped
+/// separately from a full device tree (e.g. as an OTA fixup) and applied to a compatible tree
+/// later.
+///
+/// See [`PatchWriter`] for an example.
+#[derive(Clone, Copy, Debug)]
+pub struct Patch<'p> {
+    records: &'p [u8],
+    len: usize,
+}
+
+impl<'p> Patch<'p> {
+    /// Parse the patch in `blob`.
+    ///
+    /// Returns [`DevTreeError::ParseError`] if it isn't a patch, or any of its records is
+    /// malformed.
+    pub fn new(blob: &'p [u8]) -> Result<Self> {
+        if blob.read_be_u32(0)? != PATCH_MAGIC || blob.read_be_u32(4)? != PATCH_VERSION {
+            return Err(DevTreeError::ParseError);
+        }
+        let patch = Self {
+            records: &blob[HEADER_SIZE..],
+            len: blob.read_be_u32(8)? as usize,
+        };
+        let mut records = patch.records();
+        let mut count = 0;
+        while records.next()?.is_some() {
+            count += 1;
+        }
+        if count != patch.len {
+            return Err(DevTreeError::ParseError);
+        }
+        Ok(patch)
+    }
+
+    /// Returns the number of records.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the patch has no records.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns an iterator over the records, in order.
+    #[must_use]
+    pub fn records(&self) -> PatchRecordIter<'p> {
+        PatchRecordIter {
+            buf: self.records,
+            offset: 0,
+        }
+    }
+
+    /// Check that the patch applies to `fdt`: every node it changes exists (and every node it
+    /// adds doesn't), and every old value matches `fdt`'s.
+    ///
+    /// Returns [`DevTreeError::PatchMismatch`] with the index of the first record which
+    /// doesn't.
+    pub fn check(&self, fdt: &DevTree) -> Result<()> {
+        self.for_each_edit(|index, _, record| {
+            let node = node_at_exact_path(fdt, record.path())?;
+            let matches = match (record, &node) {
+                (PatchRecord::SetProp { name, old, .. }, Some(node)) => {
+                    node.find_prop(name)?.map(|prop| prop.raw()) == old
+                }
+                (PatchRecord::DeleteProp { name, old, .. }, Some(node)) => {
+                    node.find_prop(name)?.map(|prop| prop.raw()) == Some(old)
+                }
+                (PatchRecord::AddNode { path }, None) => {
+                    let (parent, _) = split_path(path);
+                    node_at_exact_path(fdt, if parent.is_empty() { "/" } else { parent })?.is_some()
+                }
+                (PatchRecord::DeleteNode { .. }, Some(_)) => true,
+                _ => false,
+            };
+            match matches {
+                true => Ok(()),
+                false => Err(DevTreeError::PatchMismatch { record: index }),
+            }
+        })
+    }
+
+    /// [Check](Patch::check) that the patch applies to `fdt`, then serialize a copy of `fdt`
+    /// with it applied into `buf`, as [`DevTreeModifier::apply`] does.
+    ///
+    /// A [`DevTreeModifier`] edit is allocated from `scratch` for each record, except those
+    /// which add the properties and descendants of an added node.
+    pub fn apply<'s>(
+        &self,
+        fdt: &DevTree,
+        buf: &mut [u8],
+        scratch: &mut ScratchArena<'s>,
+    ) -> Result<usize>
+    where
+        'p: 's,
+    {
+        self.check(fdt)?;
+        let mut edits = 0;
+        self.for_each_edit(|_, _, _| {
+            edits += 1;
+            Ok(())
+        })?;
+        let mut modifier = DevTreeModifier::new(scratch, edits)?;
+        self.for_each_edit(|_, at, record| {
+            match record {
+                PatchRecord::SetProp {
+                    path, name, new, ..
+                } => modifier.set_prop(path, name, MetadataValue::Bytes(new))?,
+                PatchRecord::DeleteProp { path, name, .. } => modifier.delete_prop(path, name)?,
+                PatchRecord::AddNode { path } => modifier.add_patch_node(split_path(path).0, at)?,
+                PatchRecord::DeleteNode { path } => modifier.delete_node(path)?,
+            };
+            Ok(())
+        })?;
+        modifier.apply(fdt, buf)
+    }
+
+    /// Call `f` with the index of each record which is applied as an edit of its own, the
+    /// records beginning with it, and the record itself.
+    ///
+    /// The records which add the properties and descendants of an added node are skipped, as
+    /// they're written along with it.
+    fn for_each_edit(
+        &self,
+        mut f: impl FnMut(usize, PatchRecordIter<'p>, PatchRecord<'p>) -> Result<()>,
+    ) -> Result<()> {
+        let mut records = self.records();
+        let mut added = None;
+        let mut index = 0;
+        loop {
+            let at = records;
+            let record = match records.next()? {
+                Some(record) => record,
+                None => return Ok(()),
+            };
+            index += 1;
+            if added.is_some_and(|root| record.is_within(root)) {
+                continue;
+            }
+            added = match record {
+                PatchRecord::AddNode { path } => Some(path),
+                _ => None,
+            };
+            f(index - 1, at, record)?;
+        }
+    }
+}
+
+/// Returns the path of the node `prop` belongs to, built in `path_buf`.
+fn prop_path<'s>(prop: &DevTreeProp, path_buf: &'s mut [u8]) -> Result<&'s str> {
+    prop.node().path(&mut ScratchArena::new(path_buf))
+}
+
+/// Returns the node of `fdt` at `path`, whose node names must match exactly.
+fn node_at_exact_path<'a, 'dt>(
+    fdt: &'a DevTree<'dt>,
+    path: &str,
+) -> Result<Option<DevTreeNode<'a, 'dt>>> {
+    match fdt.node_offset_by_exact_path(path)? {
+        Some(offset) => DevTreeIter::from_offset(fdt, offset).next_node(),
+        None => Ok(None),
+    }
+}
+
+/// Write the node added by the [`PatchRecord::AddNode`] record `records` begins with, along
+/// with the properties and descendants the records which follow it add.
+pub(super) fn serialize_patch_node(
+    ser: &mut Serializer,
+    mut records: PatchRecordIter,
+) -> Result<()> {
+    let root = match records.next()? {
+        Some(PatchRecord::AddNode { path }) => path,
+        _ => return Err(DevTreeError::ParseError),
+    };
+    let depth = |path: &str| path.split('/').filter(|c| !c.is_empty()).count();
+    ser.serialize_new_begin_node(split_path(root).1.as_bytes())?;
+    // The number of nodes open, starting with the added node.
+    let mut open = 1;
+    while let Some(record) = records.next()? {
+        if !record.is_within(root) {
+            break;
+        }
+        match record {
+            PatchRecord::SetProp { name, new, .. } => {
+                ser.serialize_new_prop(name, &MetadataValue::Bytes(new))?
+            }
+            PatchRecord::AddNode { path } => {
+                // Close the nodes which aren't ancestors of this one.
+                let ancestors = depth(path) - depth(root);
+                if ancestors > open {
+                    return Err(DevTreeError::ParseError);
+                }
+                while open > ancestors {
+                    ser.serialize_new_end_node()?;
+                    open -= 1;
+                }
+                ser.serialize_new_begin_node(split_path(path).1.as_bytes())?;
+                open += 1;
+            }
+            _ => (),
+        }
+    }
+    for _ in 0..open {
+        ser.serialize_new_end_node()?;
+    }
+    Ok(())
+}
+
+/// An iterator over the [`PatchRecord`]s of a [`Patch`].
+#[derive(Clone, Copy, Debug)]
+pub struct PatchRecordIter<'p> {
+    buf: &'p [u8],
+    offset: usize,
+}
+
+impl<'p> PatchRecordIter<'p> {
+    fn read_u32(&mut self) -> Result<u32> {
+        let val = self.buf.read_be_u32(self.offset)?;
+        self.offset += size_of::<u32>();
+        Ok(val)
+    }
+
+    fn read_bytes(&mut self) -> Result<&'p [u8]> {
+        let len = self.read_u32()? as usize;
+        let bytes = self
+            .buf
+            .get(self.offset..self.offset + len)
+            .ok_or(DevTreeError::ParseError)?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn read_str(&mut self) -> Result<&'p str> {
+        Ok(from_utf8(self.read_bytes()?)?)
+    }
+
+    fn read_path(&mut self) -> Result<&'p str> {
+        let path = self.read_str()?;
+        if !path.starts_with('/') {
+            return Err(DevTreeError::ParseError);
+        }
+        Ok(path)
+    }
+}
+
+impl<'p> FallibleIterator for PatchRecordIter<'p> {
+    type Error = DevTreeError;
+    type Item = PatchRecord<'p>;
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        if self.offset == self.buf.len() {
+            return Ok(None);
+        }
+        let record = match self.read_u32()? {
+            SET_PROP => PatchRecord::SetProp {
+                path: self.read_path()?,
+                name: self.read_str()?,
+                old: match self.buf.read_be_u32(self.offset)? {
+                    ABSENT => {
+                        self.offset += size_of::<u32>();
+                        None
+                    }
+                    _ => Some(self.read_bytes()?),
+                },
+                new: self.read_bytes()?,
+            },
+            DELETE_PROP => PatchRecord::DeleteProp {
+                path: self.read_path()?,
+                name: self.read_str()?,
+                old: self.read_bytes()?,
+            },
+            ADD_NODE => PatchRecord::AddNode {
+                path: self.read_path()?,
+            },
+            DELETE_NODE => PatchRecord::DeleteNode {
+                path: self.read_path()?,
+            },
+            _ => return Err(DevTreeError::ParseError),
+        };
+        Ok(Some(record))
+    }
+}
@@ -0,0 +1,98 @@
+use core::cell::Cell;
+
+use crate::base::DevTree;
+use crate::error::{DevTreeError, Result};
+use crate::spec::Phandle;
+
+/// Hands out phandles which no node of a [`DevTree`] uses, for nodes added to it which must be
+/// referenced (e.g. interrupt parents or clock providers).
+///
+/// Phandles are handed out in increasing order from just above the tree's
+/// [largest](DevTree::max_phandle). The allocator only needs a shared reference, so it may be
+/// used from within a [`Serializer`](crate::modify::Serializer) callback or while building a
+/// [`DevTreeModifier`](crate::modify::DevTreeModifier)'s edits.
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::prelude::*;
+/// use fdt_rs::base::*;
+/// use fdt_rs::modify::*;
+/// use fdt_rs::scratch::ScratchArena;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let phandles = PhandleAllocator::new(&devtree).unwrap();
+/// let phandle = phandles.alloc().unwrap();
+/// assert_eq!(phandle, devtree.max_phandle().unwrap() + 1);
+///
+/// // Add a clock provider, then refer to it.
+/// let props = [
+///     MetadataProp::new("compatible", MetadataValue::Str("fixed-clock")),
+///     MetadataProp::new("#clock-cells", MetadataValue::U32(0)),
+///     MetadataProp::new("phandle", MetadataValue::U32(phandle)),
+/// ];
+/// let clock = MetadataNode::new("clock", &props);
+/// let mut mem = [0u8; 512];
+/// let mut scratch = ScratchArena::new(&mut mem);
+/// let mut modifier = DevTreeModifier::new(&mut scratch, 2).unwrap();
+/// modifier
+///     .add_node("/", &clock)
+///     .unwrap()
+///     .set_prop("/uart@10000000", "clocks", MetadataValue::U32(phandle))
+///     .unwrap();
+///
+/// let mut buf = vec![0u32; FDT.len() / 2];
+/// let out = unsafe {
+///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4)
+/// };
+/// let size = modifier.apply(&devtree, out).unwrap();
+/// let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+/// assert_eq!(modified.max_phandle().unwrap(), phandle);
+/// ```
+#[derive(Debug)]
+pub struct PhandleAllocator {
+    /// The largest phandle handed out or in use.
+    max: Cell<Phandle>,
+}
+
+impl PhandleAllocator {
+    /// Create an allocator which hands out phandles which `fdt` doesn't use.
+    pub fn new(fdt: &DevTree) -> Result<Self> {
+        Ok(Self::above(fdt.max_phandle()?))
+    }
+
+    /// Create an allocator which hands out phandles above `max`.
+    #[must_use]
+    pub const fn above(max: Phandle) -> Self {
+        Self {
+            max: Cell::new(max),
+        }
+    }
+
+    /// Returns an unused phandle.
+    ///
+    /// Returns [`DevTreeError::InvalidParameter`] once every phandle has been handed out. The
+    /// value `0xffffffff` is never handed out, as the specification reserves it.
+    pub fn alloc(&self) -> Result<Phandle> {
+        let phandle = self
+            .max
+            .get()
+            .checked_add(1)
+            .filter(|&p| p != Phandle::MAX)
+            .ok_or(DevTreeError::InvalidParameter("Out of phandles"))?;
+        self.max.set(phandle);
+        Ok(phandle)
+    }
+
+    /// Mark `phandle` as in use (e.g. by a node from another tree), so it's never handed out.
+    pub fn reserve(&self, phandle: Phandle) {
+        self.max.set(self.max.get().max(phandle));
+    }
+
+    /// Returns the largest phandle handed out or in use.
+    #[must_use]
+    pub fn max(&self) -> Phandle {
+        self.max.get()
+    }
+}
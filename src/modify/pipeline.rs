@@ -0,0 +1,138 @@
+use crate::base::DevTree;
+use crate::error::Result;
+use crate::modify::{
+    ModifyContext, ModifyOptions, ModifyParsedTok, ModifyTokenResponse, Serializer,
+};
+
+/// A stage of a [`ModifyPipeline`].
+///
+/// This is implemented for any closure which could be passed to
+/// [`Serializer::modify_with_context`].
+pub trait ModifyStage<'dt, 'r> {
+    /// Decide how `tok` should be serialized, as a [`Serializer::modify_with_context`] callback
+    /// does.
+    fn respond(
+        &mut self,
+        ctx: &ModifyContext<'_, 'dt>,
+        tok: ModifyParsedTok<'_, 'dt>,
+    ) -> ModifyTokenResponse<'r>;
+}
+
+impl<'dt, 'r, F> ModifyStage<'dt, 'r> for F
+where
+    F: FnMut(&ModifyContext<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+{
+    fn respond(
+        &mut self,
+        ctx: &ModifyContext<'_, 'dt>,
+        tok: ModifyParsedTok<'_, 'dt>,
+    ) -> ModifyTokenResponse<'r> {
+        self(ctx, tok)
+    }
+}
+
+/// Applies several independent [`ModifyStage`]s to a [`DevTree`] in a single [`Serializer`]
+/// pass, rather than serializing once per stage.
+///
+/// Each token is passed to the stages in order:
+///
+/// * A stage responding with [`ModifyTokenResponse::Pass`] leaves the token to the next stage.
+/// * A stage responding with [`ModifyTokenResponse::Drop`] or [`ModifyTokenResponse::Replace`]
+///   decides the token. Later stages aren't called for it (or, for a dropped node, its subtree).
+/// * A stage responding with [`ModifyTokenResponse::ModifySize`] changes the token's value (or
+///   name) in the output buffer and leaves the token to the next stage. Later stages see the
+///   changed buffer, but the [`ParsedProp`](crate::base::parse::ParsedProp) or
+///   [`ParsedBeginNode`](crate::base::parse::ParsedBeginNode) of the source token. If a later
+///   stage changes the token too, its change wins.
+///
+/// The stages are held in a caller provided slice, so no allocator is required.
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::prelude::*;
+/// use fdt_rs::base::*;
+/// use fdt_rs::modify::*;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let mut buf = vec![0u32; FDT.len() / 4];
+/// let out = unsafe {
+///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, FDT.len())
+/// };
+///
+/// let mut prune_cpus = |ctx: &ModifyContext, tok: ModifyParsedTok| match tok {
+///     ModifyParsedTok::BeginNode(..) if ctx.is_at("/cpus/cpu-map") => ModifyTokenResponse::Drop,
+///     _ => ModifyTokenResponse::Pass,
+/// };
+/// let mut rename_model = |ctx: &ModifyContext, tok: ModifyParsedTok| match tok {
+///     ModifyParsedTok::Prop(prop, _, value)
+///         if ctx.is_at("/") && prop.prop_buf == b"riscv-virtio,qemu\0" =>
+///     {
+///         value[..5].copy_from_slice(b"acme\0");
+///         ModifyTokenResponse::ModifySize(5)
+///     }
+///     _ => ModifyTokenResponse::Pass,
+/// };
+/// let mut stages: [&mut dyn ModifyStage; 2] = [&mut prune_cpus, &mut rename_model];
+/// let size = ModifyPipeline::new(&mut stages)
+///     .apply(&devtree, out, &ModifyOptions::default())
+///     .unwrap();
+///
+/// let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+/// assert_eq!(modified.nodes().count().unwrap(), devtree.nodes().count().unwrap() - 3);
+/// ```
+pub struct ModifyPipeline<'p, 's, 'dt, 'r> {
+    stages: &'p mut [&'s mut dyn ModifyStage<'dt, 'r>],
+}
+
+impl<'p, 's, 'dt, 'r> ModifyPipeline<'p, 's, 'dt, 'r> {
+    /// Create a pipeline which passes each token through `stages`, in order.
+    pub fn new(stages: &'p mut [&'s mut dyn ModifyStage<'dt, 'r>]) -> Self {
+        Self { stages }
+    }
+
+    /// Serialize a copy of `fdt` into `buf` with every stage applied, as
+    /// [`Serializer::modify_with_context`] does.
+    pub fn apply(
+        &mut self,
+        fdt: &DevTree<'dt>,
+        buf: &mut [u8],
+        options: &ModifyOptions,
+    ) -> Result<usize> {
+        Serializer::modify_with_context(fdt, buf, options, |ctx, tok| self.respond(ctx, tok))
+    }
+
+    /// Pass `tok` through the stages, returning the combined response.
+    ///
+    /// This may be used as the callback of any of the [`Serializer`] functions which pass a
+    /// [`ModifyContext`].
+    pub fn respond(
+        &mut self,
+        ctx: &ModifyContext<'_, 'dt>,
+        mut tok: ModifyParsedTok<'_, 'dt>,
+    ) -> ModifyTokenResponse<'r> {
+        let mut response = ModifyTokenResponse::Pass;
+        for stage in self.stages.iter_mut() {
+            match stage.respond(ctx, reborrow(&mut tok)) {
+                ModifyTokenResponse::Pass => (),
+                ModifyTokenResponse::ModifySize(len) => {
+                    response = ModifyTokenResponse::ModifySize(len)
+                }
+                decided => return decided,
+            }
+        }
+        response
+    }
+}
+
+/// Returns a copy of `tok` which borrows its buffer, to pass to one stage.
+fn reborrow<'a, 'dt>(tok: &'a mut ModifyParsedTok<'_, 'dt>) -> ModifyParsedTok<'a, 'dt> {
+    match tok {
+        ModifyParsedTok::BeginNode(node, buf) => ModifyParsedTok::BeginNode(node.clone(), buf),
+        ModifyParsedTok::EndNode => ModifyParsedTok::EndNode,
+        ModifyParsedTok::Prop(prop, name, buf) => ModifyParsedTok::Prop(prop.clone(), name, buf),
+        ModifyParsedTok::Nop => ModifyParsedTok::Nop,
+        ModifyParsedTok::End(buf) => ModifyParsedTok::End(buf),
+    }
+}
@@ -0,0 +1,128 @@
+use core::mem::size_of;
+
+use crate::bindings::cells::write_cells;
+use crate::modify::{MetadataValue, ModifyTokenResponse};
+
+#[cfg(doc)]
+use crate::modify::{ModifyParsedTok, Serializer};
+
+/// Writes a new property value into the buffer of a [`ModifyParsedTok::Prop`], keeping track of
+/// its length.
+///
+/// Each `set_` method replaces the whole value. Once it's set, respond with
+/// [`PropWriter::response`].
+///
+/// A value which doesn't fit in the buffer isn't written, but its length is still recorded, so
+/// the [`Serializer`] reports that the output buffer is too small.
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::prelude::*;
+/// use fdt_rs::base::*;
+/// use fdt_rs::modify::*;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let mut buf = vec![0u32; FDT.len() / 4];
+/// let out = unsafe {
+///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, FDT.len())
+/// };
+///
+/// // Halve the size of memory.
+/// let size = Serializer::modify_with_context(&devtree, out, &ModifyOptions::default(), |ctx, tok| {
+///     match tok {
+///         ModifyParsedTok::Prop(_, b"reg", value) if ctx.is_at("/memory@80000000") => {
+///             let mut writer = PropWriter::new(value);
+///             writer.set_reg(0x8000_0000, 0x400_0000, 2, 2);
+///             writer.response()
+///         }
+///         _ => ModifyTokenResponse::Pass,
+///     }
+/// })
+/// .unwrap();
+///
+/// let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+/// let reg = modified
+///     .props()
+///     .find(|p| Ok(p.name()? == "reg" && p.length() == 16 && p.u32(1)? == 0x8000_0000))
+///     .unwrap()
+///     .unwrap();
+/// assert_eq!(reg.u32(3).unwrap(), 0x400_0000);
+/// ```
+#[derive(Debug)]
+pub struct PropWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> PropWriter<'b> {
+    /// Create a writer for the value buffer of a property. The value is initially empty.
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    /// Returns the length of the value written.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the value is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Set the value to a single cell.
+    pub fn set_u32(&mut self, value: u32) -> &mut Self {
+        self.set(&MetadataValue::U32(value))
+    }
+
+    /// Set the value to a 64 bit quantity of two cells.
+    pub fn set_u64(&mut self, value: u64) -> &mut Self {
+        self.set(&MetadataValue::U64(value))
+    }
+
+    /// Set the value to a string. The null terminator is added.
+    pub fn set_str(&mut self, value: &str) -> &mut Self {
+        self.set(&MetadataValue::Str(value))
+    }
+
+    /// Set the value to raw bytes.
+    pub fn set_bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.set(&MetadataValue::Bytes(value))
+    }
+
+    /// Set the value to a single `reg` entry of `address_cells` address cells and `size_cells`
+    /// size cells, as given by the parent node's `#address-cells` and `#size-cells`.
+    ///
+    /// Bits of `address` or `size` which don't fit in their cells are dropped.
+    pub fn set_reg(
+        &mut self,
+        address: u64,
+        size: u64,
+        address_cells: usize,
+        size_cells: usize,
+    ) -> &mut Self {
+        self.len = (address_cells + size_cells) * size_of::<u32>();
+        if self.len <= self.buf.len() {
+            write_cells(self.buf, 0, address, address_cells);
+            write_cells(self.buf, address_cells, size, size_cells);
+        }
+        self
+    }
+
+    /// Returns the response which writes the property with the new value.
+    #[must_use]
+    pub fn response(&self) -> ModifyTokenResponse<'static> {
+        ModifyTokenResponse::ModifySize(self.len)
+    }
+
+    fn set(&mut self, value: &MetadataValue) -> &mut Self {
+        self.len = value.len();
+        // A value which doesn't fit makes the serializer report the overflow.
+        let _ = value.write_to(self.buf);
+        self
+    }
+}
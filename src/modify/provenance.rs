@@ -0,0 +1,171 @@
+use core::cell::Cell;
+use core::mem::size_of;
+
+use crate::prelude::*;
+
+use crate::base::{DevTreeNode, DevTreeProp};
+use crate::error::{DevTreeError, Result};
+use crate::modify::ModifyContext;
+
+/// Where a node or property of a composed device tree came from, see [`ProvenanceMap`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Origin {
+    /// The tree the first merge or edit was applied to.
+    #[default]
+    Base,
+    /// The delta of the merge with the given layer number, see [`Serializer::merge_traced`].
+    ///
+    /// [`Serializer::merge_traced`]: crate::modify::Serializer::merge_traced
+    Layer(usize),
+    /// The edit of a [`DevTreeModifier`](crate::modify::DevTreeModifier) with the given index,
+    /// counting from 0 in the order the edits were added.
+    Edit(usize),
+}
+
+/// The origin of the tokens within a range of a device tree's structure block, as offsets from
+/// the start of the tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProvenanceRecord {
+    pub start: usize,
+    pub end: usize,
+    pub origin: Origin,
+}
+
+/// The origin of each node and property of a device tree written by a traced merge or edit, see
+/// [`Trace`].
+///
+/// A record is kept for each property written with a new value, for each node or property
+/// added (covering the node's whole subtree), and for each token copied from a source tree
+/// whose own origin wasn't [`Origin::Base`]. Tokens without a record came from the base tree.
+/// The map is cleared at the start of each traced merge or edit.
+///
+/// This answers "where did this property come from?" when debugging a tree composed from a
+/// base, layers merged onto it in turn, and programmatic edits.
+#[derive(Debug)]
+pub struct ProvenanceMap<'m> {
+    records: &'m [Cell<ProvenanceRecord>],
+    len: Cell<usize>,
+}
+
+impl<'m> ProvenanceMap<'m> {
+    /// Create a map which keeps up to `records.len()` records in `records`.
+    ///
+    /// A traced merge or edit fails with [`DevTreeError::NotEnoughMemory`] if there are more
+    /// to keep.
+    pub fn new(records: &'m mut [ProvenanceRecord]) -> Self {
+        Self {
+            records: Cell::from_mut(records).as_slice_of_cells(),
+            len: Cell::new(0),
+        }
+    }
+
+    /// Returns the number of records kept.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    /// Returns whether no records have been kept.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns an iterator over the records, in the order they were kept.
+    pub fn iter(&self) -> impl Iterator<Item = ProvenanceRecord> + '_ {
+        self.records[..self.len()].iter().map(Cell::get)
+    }
+
+    /// Returns the origin of the token at `offset` in the traced tree.
+    ///
+    /// Where records overlap (e.g. a property added to a node which was itself added), the
+    /// innermost decides.
+    #[must_use]
+    pub fn origin_of(&self, offset: usize) -> Origin {
+        self.iter()
+            .filter(|record| (record.start..record.end).contains(&offset))
+            .min_by_key(|record| record.end - record.start)
+            .map_or(Origin::Base, |record| record.origin)
+    }
+
+    /// Returns the origin of `node`, a node of the traced tree.
+    pub fn origin_of_node(&self, node: &DevTreeNode) -> Result<Origin> {
+        Ok(self.origin_of(node.byte_range()?.start))
+    }
+
+    /// Returns the origin of `prop`, a property of the traced tree.
+    #[must_use]
+    pub fn origin_of_prop(&self, prop: &DevTreeProp) -> Origin {
+        let value = prop.propbuf().as_ptr() as usize - prop.fdt().buf().as_ptr() as usize;
+        self.origin_of(value - PROP_HEADER_LEN)
+    }
+
+    pub(crate) fn clear(&self) {
+        self.len.set(0);
+    }
+
+    pub(crate) fn push(&self, record: ProvenanceRecord) -> Result<()> {
+        let slot = self
+            .records
+            .get(self.len())
+            .ok_or(DevTreeError::NotEnoughMemory)?;
+        slot.set(record);
+        self.len.set(self.len() + 1);
+        Ok(())
+    }
+}
+
+/// The length of a property's token and header, before its value.
+const PROP_HEADER_LEN: usize = 3 * size_of::<u32>();
+
+/// Returns the length of a `BeginNode` token for a node named `name`.
+pub(crate) fn begin_node_len(name: &[u8]) -> usize {
+    size_of::<u32>() + align(name.len() + 1)
+}
+
+/// Returns the length of a `Prop` token with a value of `len` bytes.
+pub(crate) fn prop_len(len: usize) -> usize {
+    PROP_HEADER_LEN + align(len)
+}
+
+fn align(len: usize) -> usize {
+    (len + size_of::<u32>() - 1) & !(size_of::<u32>() - 1)
+}
+
+/// Where a traced merge or edit records the origin of what it writes, see [`ProvenanceMap`].
+#[derive(Clone, Copy, Debug)]
+pub struct Trace<'p> {
+    /// Records the origin of each node and property of the output.
+    pub output: &'p ProvenanceMap<'p>,
+    /// The provenance of the source tree, if it was itself written by a traced merge or edit.
+    ///
+    /// Tokens copied from the source tree keep their origin. Without it they're from
+    /// [`Origin::Base`]. It mustn't be the same map as [`Trace::output`].
+    pub source: Option<&'p ProvenanceMap<'p>>,
+}
+
+impl Trace<'_> {
+    /// Record the origin of the token of `len` bytes the serializer writes for the token at
+    /// `ctx`, or if `origin` is `None`, copy the source tree's origin of it.
+    pub(crate) fn record(
+        &self,
+        ctx: &ModifyContext,
+        len: usize,
+        origin: Option<Origin>,
+    ) -> Result<()> {
+        let origin = match (origin, self.source) {
+            (Some(origin), _) => origin,
+            (None, Some(source)) => source.origin_of(ctx.source_offset()),
+            (None, None) => Origin::Base,
+        };
+        self.record_range(ctx.output_offset(), ctx.output_offset() + len, origin)
+    }
+
+    /// Record the origin of the tokens written between `start` and `end`.
+    pub(crate) fn record_range(&self, start: usize, end: usize, origin: Origin) -> Result<()> {
+        if origin == Origin::Base || start == end {
+            return Ok(());
+        }
+        self.output.push(ProvenanceRecord { start, end, origin })
+    }
+}
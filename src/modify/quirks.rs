@@ -0,0 +1,144 @@
+use crate::base::{DevTree, DevTreeNode};
+use crate::error::{DevTreeError, Result};
+use crate::modify::renumber::source_node;
+use crate::modify::{
+    ModifyContext, ModifyOptions, ModifyParsedTok, ModifyStage, ModifyTokenResponse, Serializer,
+};
+
+/// A quirk handler of a [`QuirkRegistry`].
+///
+/// It's passed the (source) node the quirk matched and each of the node's tokens: its
+/// `BeginNode` and then each of its properties. It responds as a [`Serializer::modify`] callback
+/// does, e.g. with [`ModifyTokenResponse::Replace`] to rename a legacy property.
+pub type QuirkHandler<'dt, 'r> =
+    fn(&DevTreeNode<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> Result<ModifyTokenResponse<'r>>;
+
+/// Normalizes a [`DevTree`] by running quirk handlers, registered by `compatible` string, on the
+/// nodes they match (e.g. to fix a vendor tree which uses legacy property names or wrong cell
+/// counts).
+///
+/// Each node runs at most one quirk: the one matching its most specific `compatible` string, as
+/// [`DevTreeNode::match_table`] finds it. Nodes without a quirk are copied unchanged.
+///
+/// The registry may be applied directly, or as a stage of a
+/// [`ModifyPipeline`](crate::modify::ModifyPipeline).
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::prelude::*;
+/// use fdt_rs::base::*;
+/// use fdt_rs::modify::*;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let mut buf = vec![0u32; FDT.len() / 4];
+/// let out = unsafe {
+///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, FDT.len())
+/// };
+///
+/// // This board's UARTs are clocked at 1.8432 MHz, whatever its tree says.
+/// fn uart_clock<'r>(
+///     _node: &DevTreeNode,
+///     tok: ModifyParsedTok,
+/// ) -> fdt_rs::error::Result<ModifyTokenResponse<'r>> {
+///     match tok {
+///         ModifyParsedTok::Prop(_, b"clock-frequency", value) => {
+///             Ok(PropWriter::new(value).set_u32(1_843_200).response())
+///         }
+///         _ => Ok(ModifyTokenResponse::Pass),
+///     }
+/// }
+///
+/// let quirks: &[(&str, QuirkHandler)] = &[("ns16550a", uart_clock)];
+/// let size = QuirkRegistry::new(&devtree, quirks)
+///     .apply(out, &ModifyOptions::default())
+///     .unwrap();
+///
+/// let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+/// let uart = modified.node_at_path("/uart@10000000").unwrap().unwrap();
+/// let clock = uart.props().find(|p| Ok(p.name()? == "clock-frequency")).unwrap().unwrap();
+/// assert_eq!(clock.u32(0).unwrap(), 1_843_200);
+/// ```
+pub struct QuirkRegistry<'q, 'a, 'dt, 'r> {
+    fdt: &'a DevTree<'dt>,
+    quirks: &'q [(&'q str, QuirkHandler<'dt, 'r>)],
+    /// The node whose tokens are being passed to [`ModifyStage::respond`], if it has a quirk.
+    node: Option<(DevTreeNode<'a, 'dt>, QuirkHandler<'dt, 'r>)>,
+    /// The first error returned by a handler.
+    error: Option<DevTreeError>,
+}
+
+impl<'q, 'a, 'dt, 'r> QuirkRegistry<'q, 'a, 'dt, 'r> {
+    /// Create a registry which runs the handlers of `quirks` on the nodes of `fdt` whose
+    /// `compatible` contains their string.
+    #[must_use]
+    pub fn new(fdt: &'a DevTree<'dt>, quirks: &'q [(&'q str, QuirkHandler<'dt, 'r>)]) -> Self {
+        Self {
+            fdt,
+            quirks,
+            node: None,
+            error: None,
+        }
+    }
+
+    /// Serialize a normalized copy of the tree into `buf`, as
+    /// [`Serializer::modify_with_options`] does.
+    ///
+    /// Returns the first error returned by a handler, if any.
+    pub fn apply(&mut self, buf: &mut [u8], options: &ModifyOptions) -> Result<usize> {
+        let fdt = self.fdt;
+        let size =
+            Serializer::modify_with_context(fdt, buf, options, |ctx, tok| self.respond(ctx, tok))?;
+        self.check()?;
+        Ok(size)
+    }
+
+    /// Returns the first error returned by a handler for the tokens passed to
+    /// [`ModifyStage::respond`], and forgets it.
+    ///
+    /// Check this after serializing with the registry as a stage of a pipeline.
+    pub fn check(&mut self) -> Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns `node`'s quirk, if one is registered for its `compatible`.
+    fn find(&self, node: &DevTreeNode<'a, 'dt>) -> Result<Option<QuirkHandler<'dt, 'r>>> {
+        Ok(node.match_table(self.quirks)?.map(|&(_, handler)| handler))
+    }
+}
+
+impl<'dt, 'r> ModifyStage<'dt, 'r> for QuirkRegistry<'_, '_, 'dt, 'r> {
+    fn respond(
+        &mut self,
+        _ctx: &ModifyContext<'_, 'dt>,
+        tok: ModifyParsedTok<'_, 'dt>,
+    ) -> ModifyTokenResponse<'r> {
+        if let ModifyParsedTok::BeginNode(ref node, _) = tok {
+            let found = source_node(self.fdt, node).and_then(|node| match node {
+                Some(node) => Ok(self.find(&node)?.map(|handler| (node, handler))),
+                None => Ok(None),
+            });
+            match found {
+                Ok(found) => self.node = found,
+                Err(err) => {
+                    self.node = None;
+                    self.error.get_or_insert(err);
+                }
+            }
+        }
+        // A node's properties precede its children, so they follow its BeginNode.
+        let result = match (&self.node, tok) {
+            (Some((node, handler)), tok @ ModifyParsedTok::BeginNode(..))
+            | (Some((node, handler)), tok @ ModifyParsedTok::Prop(..)) => handler(node, tok),
+            _ => Ok(ModifyTokenResponse::Pass),
+        };
+        result.unwrap_or_else(|err| {
+            self.error.get_or_insert(err);
+            ModifyTokenResponse::Pass
+        })
+    }
+}
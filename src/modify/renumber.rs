@@ -0,0 +1,253 @@
+use core::mem::size_of;
+use core::str::from_utf8;
+
+use crate::prelude::*;
+
+use crate::base::iters::DevTreeIter;
+use crate::base::parse::ParsedBeginNode;
+use crate::base::{DevTree, DevTreeNode};
+use crate::bindings::cells::specifier_cells_name;
+use crate::bindings::interrupts::InterruptMapIter;
+use crate::error::{DevTreeError, Result};
+use crate::modify::graft::{is_phandle, is_phandle_list};
+use crate::modify::{
+    ModifyContext, ModifyOptions, ModifyParsedTok, ModifyStage, ModifyTokenResponse, Serializer,
+};
+use crate::priv_util::{SliceRead, SliceWrite};
+use crate::scratch::ScratchArena;
+
+/// Properties whose values are a plain list of phandles, other than those a graft renumbers.
+const PHANDLE_LISTS: &[&str] = &[
+    "regmap",
+    "memory-region",
+    "next-level-cache",
+    "cpu-idle-states",
+    "operating-points-v2",
+    "remote-endpoint",
+];
+
+/// Renumbers the phandles of a [`DevTree`] into the dense range `1..=N`, in tree order, so the
+/// same tree always gets the same phandles (e.g. for reproducible builds).
+///
+/// Every reference to a renumbered phandle is rewritten to match:
+///
+/// * Properties holding a plain list of phandles (e.g. `interrupt-parent`, `pinctrl-<N>` and
+///   `regmap`).
+/// * Properties holding `<phandle specifier...>` entries whose specifiers are sized by a
+///   `#<foo>-cells` property of the provider (e.g. `clocks`, `resets` and `*-gpios`).
+/// * `interrupt-map`.
+///
+/// Other properties are copied unchanged, so references held by properties of other bindings
+/// aren't renumbered. The `__symbols__` node refers to nodes by path, so needs no changes.
+///
+/// The renumberer may be applied directly, or as a stage of a
+/// [`ModifyPipeline`](crate::modify::ModifyPipeline). It always reads the source tree's
+/// values, so should come before stages which change reference properties.
+///
+/// # Example
+///
+/// ```
+/// # use fdt_rs::doctest::FDT;
+/// use fdt_rs::prelude::*;
+/// use fdt_rs::base::*;
+/// use fdt_rs::modify::*;
+/// use fdt_rs::scratch::ScratchArena;
+///
+/// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+/// let mut buf = vec![0u32; FDT.len() / 4];
+/// let out = unsafe {
+///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, FDT.len())
+/// };
+///
+/// let mut mem = [0u8; 64];
+/// let mut renumber = PhandleRenumber::new(&devtree, &mut ScratchArena::new(&mut mem)).unwrap();
+/// let size = renumber.apply(out, &ModifyOptions::default()).unwrap();
+///
+/// let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+/// let phandle = modified
+///     .props()
+///     .find(|p| Ok(p.name()? == "phandle"))
+///     .unwrap()
+///     .unwrap();
+/// assert_eq!(phandle.u32(0).unwrap(), 1);
+/// ```
+pub struct PhandleRenumber<'s, 'a, 'dt> {
+    fdt: &'a DevTree<'dt>,
+    /// The phandle of each node which has one, in tree order. A node's new phandle is its index
+    /// plus one.
+    phandles: &'s [u32],
+    /// The node whose properties are being passed to [`ModifyStage::respond`].
+    node: Option<DevTreeNode<'a, 'dt>>,
+    /// The first error found while rewriting references.
+    error: Option<DevTreeError>,
+}
+
+impl<'s, 'a, 'dt> PhandleRenumber<'s, 'a, 'dt> {
+    /// Create a renumberer for `fdt`, recording its phandles in `scratch`. That takes four bytes
+    /// per node with a phandle.
+    ///
+    /// Returns [`DevTreeError::InvalidParameter`] if two nodes have the same phandle.
+    pub fn new(fdt: &'a DevTree<'dt>, scratch: &mut ScratchArena<'s>) -> Result<Self> {
+        let mut count = 0;
+        let mut nodes = fdt.nodes();
+        while let Some(node) = nodes.next()? {
+            if node_phandle(&node)?.is_some() {
+                count += 1;
+            }
+        }
+
+        let phandles = scratch.alloc_slice(count, 0)?;
+        let mut i = 0;
+        let mut nodes = fdt.nodes();
+        while let Some(node) = nodes.next()? {
+            if let Some(phandle) = node_phandle(&node)? {
+                if phandles[..i].contains(&phandle) {
+                    return Err(DevTreeError::InvalidParameter("Duplicate phandle"));
+                }
+                phandles[i] = phandle;
+                i += 1;
+            }
+        }
+        Ok(Self {
+            fdt,
+            phandles,
+            node: None,
+            error: None,
+        })
+    }
+
+    /// Returns the new number of `phandle`, or `None` if no node of the tree has it.
+    #[must_use]
+    pub fn renumbered(&self, phandle: u32) -> Option<u32> {
+        let index = self.phandles.iter().position(|&p| p == phandle)?;
+        Some(index as u32 + 1)
+    }
+
+    /// Serialize a copy of the tree with its phandles renumbered into `buf`, as
+    /// [`Serializer::modify_with_options`] does.
+    ///
+    /// Returns [`DevTreeError::ParseError`] if a reference is to a phandle which isn't defined,
+    /// or the size of its specifier can't be found.
+    pub fn apply(&mut self, buf: &mut [u8], options: &ModifyOptions) -> Result<usize> {
+        let fdt = self.fdt;
+        let size =
+            Serializer::modify_with_context(fdt, buf, options, |ctx, tok| self.respond(ctx, tok))?;
+        self.check()?;
+        Ok(size)
+    }
+
+    /// Returns the first error found while rewriting the references of the tokens passed to
+    /// [`ModifyStage::respond`], and forgets it.
+    ///
+    /// Check this after serializing with the renumberer as a stage of a pipeline. (A stage can't
+    /// return errors, so leaves the reference unchanged.)
+    pub fn check(&mut self) -> Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn renumbered_or_err(&self, phandle: u32) -> Result<u32> {
+        self.renumbered(phandle).ok_or(DevTreeError::ParseError)
+    }
+
+    /// Returns the node with the (source) `phandle`.
+    fn node_by_phandle(&self, phandle: u32) -> Result<DevTreeNode<'a, 'dt>> {
+        self.fdt
+            .node_by_phandle(phandle)?
+            .ok_or(DevTreeError::ParseError)
+    }
+
+    /// Rewrite the references held by the property `name` to `out`, which holds a copy of its
+    /// `value`. Properties which don't hold references are left as they are.
+    fn rewrite(&self, name: &str, value: &'dt [u8], out: &mut [u8]) -> Result<()> {
+        if is_phandle_list(name) || PHANDLE_LISTS.contains(&name) {
+            for i in (0..value.len()).step_by(size_of::<u32>()) {
+                match value.read_be_u32(i)? {
+                    // An empty entry.
+                    0 => (),
+                    phandle => out.write_be_u32(i, self.renumbered_or_err(phandle)?)?,
+                }
+            }
+        } else if let Some(cells_name) = specifier_cells_name(name) {
+            let mut i = 0;
+            while i < value.len() {
+                let phandle = value.read_be_u32(i)?;
+                i += size_of::<u32>();
+                if phandle == 0 {
+                    continue;
+                }
+                out.write_be_u32(i - size_of::<u32>(), self.renumbered_or_err(phandle)?)?;
+                i += cells(&self.node_by_phandle(phandle)?, cells_name)? * size_of::<u32>();
+            }
+        } else if name == "interrupt-map" {
+            let node = self.node.as_ref().ok_or(DevTreeError::ParseError)?;
+            let mut entries = InterruptMapIter::new(node, value)?;
+            while let Some(entry) = entries.next()? {
+                let phandle = value.read_be_u32(entry.phandle_offset)?;
+                out.write_be_u32(entry.phandle_offset, self.renumbered_or_err(phandle)?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'dt, 'r> ModifyStage<'dt, 'r> for PhandleRenumber<'_, '_, 'dt> {
+    fn respond(
+        &mut self,
+        _ctx: &ModifyContext<'_, 'dt>,
+        tok: ModifyParsedTok<'_, 'dt>,
+    ) -> ModifyTokenResponse<'r> {
+        let result = match tok {
+            ModifyParsedTok::BeginNode(node, _) => {
+                source_node(self.fdt, &node).map(|node| self.node = node)
+            }
+            ModifyParsedTok::Prop(prop, name, value_buf) => from_utf8(name)
+                .map_err(DevTreeError::from)
+                .and_then(|name| {
+                    if is_phandle(name) && prop.prop_buf.len() == size_of::<u32>() {
+                        let phandle = self.renumbered_or_err(prop.prop_buf.read_be_u32(0)?)?;
+                        value_buf.write_be_u32(0, phandle)?;
+                        Ok(())
+                    } else {
+                        self.rewrite(name, prop.prop_buf, value_buf)
+                    }
+                }),
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            self.error.get_or_insert(err);
+        }
+        ModifyTokenResponse::Pass
+    }
+}
+
+/// Returns the node of `fdt` which `node`, a token of its structure block, begins.
+pub(super) fn source_node<'a, 'dt>(
+    fdt: &'a DevTree<'dt>,
+    node: &ParsedBeginNode<'dt>,
+) -> Result<Option<DevTreeNode<'a, 'dt>>> {
+    // A node's name borrows the source tree's buffer, just after its BeginNode token.
+    let name_off = node.name.as_ptr() as usize - fdt.buf().as_ptr() as usize;
+    let offset = name_off - size_of::<u32>();
+    DevTreeIter::from_offset(fdt, offset).next_node()
+}
+
+/// Returns the phandle of `node`, if it has one.
+fn node_phandle(node: &DevTreeNode) -> Result<Option<u32>> {
+    for &name in &["phandle", "linux,phandle"] {
+        if let Some(prop) = node.find_prop(name)? {
+            return Ok(Some(prop.u32(0)?));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the number of cells given by the property `name` of `node`.
+pub(super) fn cells(node: &DevTreeNode, name: &str) -> Result<usize> {
+    match node.find_prop(name)? {
+        Some(prop) => Ok(prop.u32(0)? as usize),
+        None => Err(DevTreeError::ParseError),
+    }
+}
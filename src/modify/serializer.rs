@@ -0,0 +1,1693 @@
+use core::cell::Cell;
+use core::fmt;
+use core::mem::size_of;
+
+use crate::prelude::*;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use crate::base::parse::{DevTreeParseIter, ParsedBeginNode, ParsedProp, ParsedTok};
+use crate::base::DevTree;
+use crate::error::{DevTreeError, Result};
+use crate::modify::sorted::{prop_name, SortedParseIter};
+use crate::modify::strings::{self, StringTableBuilder};
+use crate::modify::{MetadataNode, MetadataValue, OverlayMetadata};
+use crate::priv_util::{SliceRead, SliceWrite};
+use crate::spec::{
+    fdt_header, fdt_prop_header, fdt_reserve_entry, FdtTok, FDT_LAST_COMP_VERSION, FDT_MAGIC,
+    FDT_VERSION, MAX_NODE_NAME_LEN,
+};
+
+/// A token of the device tree being modified, as passed to the [`Serializer::modify`] callback.
+#[derive(Debug)]
+pub enum ModifyParsedTok<'a, 'dt: 'a> {
+    /// The start of a node and the output buffer its name will be written into.
+    ///
+    /// The buffer is pre-filled with the original node name (without its null terminator) and
+    /// extends to the end of the output buffer. To rename the node, write the new name to the
+    /// start of the buffer and respond with [`ModifyTokenResponse::ModifySize`].
+    ///
+    /// Responding with [`ModifyTokenResponse::Drop`] drops the node along with all of its
+    /// properties and children.
+    BeginNode(ParsedBeginNode<'dt>, &'a mut [u8]),
+    EndNode,
+    /// A property, its name and the output buffer its value will be written into.
+    ///
+    /// The buffer is pre-filled with the original property value and extends to the end of the
+    /// output buffer. To change the value, write the new value to the start of the buffer and
+    /// respond with [`ModifyTokenResponse::ModifySize`]. A
+    /// [`PropWriter`](crate::modify::PropWriter) does both.
+    Prop(ParsedProp<'dt>, &'dt [u8], &'a mut [u8]),
+    Nop,
+    /// The end of the structure block, after the root node's `EndNode` and any `Nop` tokens,
+    /// and the output buffer the `End` token will be written into.
+    ///
+    /// Nothing is pre-filled, and the buffer extends to the end of the output buffer. To append
+    /// tokens before the `End` token (e.g. `Nop` tokens to leave room for in-place patching),
+    /// write them to the start of the buffer and respond with
+    /// [`ModifyTokenResponse::ModifySize`]. Their length must be a multiple of 4 bytes. Only
+    /// `Nop` tokens may follow the root node.
+    End(&'a mut [u8]),
+}
+
+/// A new token to write in place of the original, see [`ModifyTokenResponse::Replace`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplacementTok<'r> {
+    /// Replace the start of a node with one of the given name. The node's contents are kept.
+    BeginNode(&'r str),
+    /// Replace a property with one of the given name and value.
+    ///
+    /// If the name isn't already present in the source tree's strings block, it is appended to
+    /// the output's strings block.
+    Prop { name: &'r str, value: &'r [u8] },
+}
+
+/// The callback's decision on how a [`ModifyParsedTok`] should be serialized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModifyTokenResponse<'r> {
+    /// Write the token unmodified.
+    Pass,
+    /// Don't write the token.
+    ///
+    /// Dropping a [`ModifyParsedTok::BeginNode`] drops the node's entire subtree, up to and
+    /// including its matching `EndNode`. The callback isn't called for any of the dropped tokens.
+    Drop,
+    /// Write the property with a new value, or the node with a new name, of the given length.
+    ///
+    /// Only valid in response to a [`ModifyParsedTok::Prop`] or [`ModifyParsedTok::BeginNode`].
+    /// A length longer than the buffer passed with the token gives
+    /// [`DevTreeError::OutputBufferTooSmall`] rather than writing bytes the callback couldn't
+    /// have.
+    ModifySize(usize),
+    /// Write the given token instead of the original.
+    ///
+    /// The replacement must be of the same kind as the original token.
+    Replace(ReplacementTok<'r>),
+}
+
+/// The deepest nesting of nodes the [`Serializer`] accepts. The root node is at depth 1.
+///
+/// This is the same limit as Linux's `FDT_MAX_DEPTH`.
+pub const MAX_DEPTH: usize = 64;
+
+/// The most nodes and properties, together, a tree may have to be written with
+/// [`ModifyOptions::sorted`].
+///
+/// Sorting is done by re-parsing rather than in memory, so takes time quadratic in the size of
+/// the tree. Larger trees are refused rather than sorted slowly.
+pub const MAX_SORTED_ENTRIES: usize = 16384;
+
+/// Where a token passed to the [`Serializer::modify_with_context`] callback is in the source
+/// tree.
+///
+/// The token's node is the node a `BeginNode` or `EndNode` token starts or ends, or the node a
+/// property belongs to.
+#[derive(Clone, Copy, Debug)]
+pub struct ModifyContext<'c, 'dt> {
+    /// The names of the token's node and its ancestors, starting with the root.
+    nodes: &'c [&'dt [u8]],
+    /// The offsets of the token in the source tree and (if it's written) the output.
+    source: usize,
+    output: usize,
+}
+
+impl<'c, 'dt> ModifyContext<'c, 'dt> {
+    fn new(nodes: &'c [&'dt [u8]], source: usize, output: usize) -> Self {
+        Self {
+            nodes,
+            source,
+            output,
+        }
+    }
+
+    /// Returns the offset of the token in the source tree.
+    pub(crate) fn source_offset(&self) -> usize {
+        self.source
+    }
+
+    /// Returns the offset the token is written at in the output, if it's written.
+    pub(crate) fn output_offset(&self) -> usize {
+        self.output
+    }
+
+    /// Returns the depth of the token's node. The root node is at depth 1.
+    ///
+    /// `Nop` tokens outside of the root node, and the `End` token, are at depth 0.
+    #[must_use]
+    pub fn depth(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns the name of the token's node.
+    #[must_use]
+    pub fn node_name(&self) -> Option<&'dt [u8]> {
+        self.nodes.last().copied()
+    }
+
+    /// Returns the name of the parent of the token's node.
+    #[must_use]
+    pub fn parent_name(&self) -> Option<&'dt [u8]> {
+        self.nodes.iter().rev().nth(1).copied()
+    }
+
+    /// Returns the components of the path of the token's node, excluding the root.
+    pub fn path_components(&self) -> impl Iterator<Item = &'dt [u8]> + 'c {
+        self.nodes.iter().skip(1).copied()
+    }
+
+    /// Returns whether the token's node is at the given absolute path (e.g. `/chosen`).
+    ///
+    /// Node names must match exactly, including their unit addresses.
+    #[must_use]
+    pub fn is_at(&self, path: &str) -> bool {
+        let mut components = path.split('/').filter(|c| !c.is_empty());
+        !self.nodes.is_empty()
+            && self
+                .path_components()
+                .all(|name| components.next().map(str::as_bytes) == Some(name))
+            && components.next().is_none()
+    }
+}
+
+/// Options which control how [`Serializer::modify_with_options`] writes the output.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ModifyOptions<'m> {
+    /// A node to add as the last child of the named child of the root node, see
+    /// [`Serializer::modify_with_metadata`].
+    pub metadata: Option<(&'m str, &'m MetadataNode<'m>)>,
+    /// Rebuild the strings block with only the property names still in use, without duplicates.
+    ///
+    /// Otherwise the source tree's strings block is copied whole, including the names of any
+    /// dropped properties. Collection costs a pass over the structure block per string.
+    pub gc_strings: bool,
+    /// How `Nop` tokens are written.
+    pub nop_policy: NopPolicy,
+    /// Changes to the memory reservation block.
+    pub mem_reserve: MemReserveEdits<'m>,
+    /// Bytes of zeroed free space to leave after the strings block, as `dtc -p` does.
+    ///
+    /// The space is counted in the header's `totalsize`, so consumers of the tree may grow it in
+    /// place. It follows any [trailing data](ModifyOptions::trailing).
+    pub padding: usize,
+    /// Opaque data to write after the strings block, see [`TrailingData`].
+    pub trailing: TrailingData<'m>,
+    /// Rewrite the phandle properties of each node in this style, dropping any others.
+    ///
+    /// `None` writes phandle properties as they are.
+    pub phandle_style: Option<PhandleStyle>,
+    /// Header fields to set rather than derive from the source tree.
+    pub header: HeaderOverrides,
+    /// Record where each node and property of the source tree is written, see [`OffsetMap`].
+    pub offset_map: Option<&'m OffsetMap<'m>>,
+    /// Overlay nodes to add as the last children of the root node, see [`OverlayMetadata`].
+    pub overlay: Option<&'m OverlayMetadata<'m>>,
+    /// Write each node's properties sorted by name, then its children sorted by name (and so by
+    /// unit address), as `dtc -s` does. Names are compared bytewise.
+    ///
+    /// This gives the same output for trees with the same contents, so they may be diffed.
+    /// `Nop` tokens are dropped. Added nodes and properties (e.g. the
+    /// [metadata](ModifyOptions::metadata)) are still written last, and the memory reservation
+    /// block isn't sorted. Sorting is done by re-parsing rather than in memory, so takes time
+    /// quadratic in the size of the tree. Serialization returns
+    /// [`DevTreeError::InvalidParameter`] if the tree has more than [`MAX_SORTED_ENTRIES`]
+    /// nodes and properties.
+    pub sorted: bool,
+    /// Keep the source tree's `off_mem_rsvmap`, `off_dt_struct` and `off_dt_strings`, as
+    /// in-place patchers and signed image layouts expect.
+    ///
+    /// Where the memory reservation or structure block shrinks, the space up to the next block is
+    /// zeroed rather than the later blocks moved. Where either grows past the start of the next
+    /// block, [`DevTreeError::InvalidParameter`] is returned.
+    pub preserve_layout: bool,
+}
+
+/// An entry of the memory reservation block.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemReservation {
+    pub address: u64,
+    pub size: u64,
+}
+
+impl MemReservation {
+    #[must_use]
+    pub const fn new(address: u64, size: u64) -> Self {
+        Self { address, size }
+    }
+
+    /// Returns whether the reserved memory overlaps the `size` bytes at `address`.
+    ///
+    /// Empty ranges overlap nothing.
+    #[must_use]
+    pub fn overlaps(&self, address: u64, size: u64) -> bool {
+        self.size != 0
+            && size != 0
+            && address < self.address.saturating_add(self.size)
+            && self.address < address.saturating_add(size)
+    }
+}
+
+impl From<fdt_reserve_entry> for MemReservation {
+    fn from(entry: fdt_reserve_entry) -> Self {
+        Self::new(entry.address.into(), entry.size.into())
+    }
+}
+
+impl From<MemReservation> for fdt_reserve_entry {
+    fn from(entry: MemReservation) -> Self {
+        Self::new(entry.address, entry.size)
+    }
+}
+
+/// Changes the [`Serializer`] makes to the memory reservation block, see
+/// [`ModifyOptions::mem_reserve`].
+///
+/// Entries are matched by both their address and size.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemReserveEdits<'m> {
+    /// Entries of the source tree to leave out.
+    pub remove: &'m [MemReservation],
+    /// Entries of the source tree to rewrite, as pairs of the original entry and its
+    /// replacement.
+    pub replace: &'m [(MemReservation, MemReservation)],
+    /// Entries to add after those of the source tree.
+    pub append: &'m [MemReservation],
+}
+
+impl<'m> MemReserveEdits<'m> {
+    /// Returns edits which replace the source tree's entries, `original`, with `entries`.
+    ///
+    /// `original` is typically filled by [`MemReserveMap::copy_into`].
+    ///
+    /// [`MemReserveMap::copy_into`]: crate::bindings::MemReserveMap::copy_into
+    #[must_use]
+    pub fn rewrite(original: &'m [MemReservation], entries: &'m [MemReservation]) -> Self {
+        Self {
+            remove: original,
+            replace: &[],
+            append: entries,
+        }
+    }
+}
+
+/// How the [`Serializer`] writes `Nop` tokens.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NopPolicy {
+    /// Pass each `Nop` token to the callback as a [`ModifyParsedTok::Nop`], as with any other
+    /// token.
+    ///
+    /// Keeps the padding which in-place patchers rely on.
+    #[default]
+    Preserve,
+    /// Drop every `Nop` token without passing it to the callback, shrinking the output.
+    Strip,
+    /// As [`NopPolicy::Preserve`], but write each run of consecutive `Nop` tokens as a single
+    /// token.
+    Coalesce,
+}
+
+/// Opaque data after the strings block of a device tree, within its `totalsize`, see
+/// [`DevTree::trailing_data`].
+///
+/// Some toolchains keep vendor data there. The [`Serializer`] doesn't interpret it, but writes it
+/// straight after the strings block (and before any [padding](ModifyOptions::padding)).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrailingData<'m> {
+    /// Don't write any trailing data. The source tree's is dropped.
+    #[default]
+    Drop,
+    /// Copy the source tree's trailing data.
+    Preserve,
+    /// Write the given data.
+    Emit(&'m [u8]),
+}
+
+impl<'m> TrailingData<'m> {
+    /// Returns the data to write after the strings block of a copy of `fdt`.
+    fn data<'dt: 'm>(&self, fdt: &DevTree<'dt>) -> &'m [u8] {
+        match *self {
+            TrailingData::Drop => &[],
+            TrailingData::Preserve => fdt.trailing_data(),
+            TrailingData::Emit(data) => data,
+        }
+    }
+}
+
+/// Where a token of the source tree was written, as a pair of offsets from the start of each
+/// device tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OffsetMapping {
+    pub source: usize,
+    pub output: usize,
+}
+
+/// The offsets in the output of the nodes and properties of the source tree, filled in by
+/// serializing with [`ModifyOptions::offset_map`].
+///
+/// There is a mapping for each `BeginNode` and `Prop` token written, including those the
+/// callback modified or replaced, in the order of their source offsets (which with
+/// [`ModifyOptions::sorted`] isn't the order they were written). Dropped tokens, and tokens
+/// which aren't in the source tree, have none. The map is cleared at the start of each
+/// serialization.
+///
+/// This saves re-parsing the output to find where a token ended up, e.g. to patch a property's
+/// value later.
+pub struct OffsetMap<'m> {
+    mappings: &'m Cell<[OffsetMapping]>,
+    len: Cell<usize>,
+}
+
+impl fmt::Debug for OffsetMap<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<'m> OffsetMap<'m> {
+    /// Create a map which records up to `mappings.len()` mappings in `mappings`.
+    ///
+    /// Serialization fails with [`DevTreeError::NotEnoughMemory`] if there are more tokens to
+    /// map.
+    pub fn new(mappings: &'m mut [OffsetMapping]) -> Self {
+        Self {
+            mappings: Cell::from_mut(mappings),
+            len: Cell::new(0),
+        }
+    }
+
+    /// Returns the number of mappings recorded.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    /// Returns whether no mappings have been recorded.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns an iterator over the mappings, in the order of their source offsets.
+    pub fn iter(&self) -> impl Iterator<Item = OffsetMapping> + '_ {
+        self.cells().iter().map(Cell::get)
+    }
+
+    /// Returns the offset in the output of the token at `source` in the source tree, if it was
+    /// written.
+    #[must_use]
+    pub fn output_offset(&self, source: usize) -> Option<usize> {
+        let mappings = self.cells();
+        // The mappings are sorted once serialization finishes.
+        let i = mappings
+            .binary_search_by_key(&source, |mapping| mapping.get().source)
+            .ok()?;
+        Some(mappings[i].get().output)
+    }
+
+    fn clear(&self) {
+        self.len.set(0);
+    }
+
+    /// Returns the cells of the mappings recorded.
+    fn cells(&self) -> &'m [Cell<OffsetMapping>] {
+        &self.mappings.as_slice_of_cells()[..self.len()]
+    }
+
+    /// Sort the mappings by source offset, for tokens written out of source order.
+    fn sort(&self) {
+        // Unsafe okay. The map isn't `Sync` and the sort doesn't call back into it, so nothing
+        // else accesses the mappings while they're sorted.
+        let mappings = unsafe { &mut (&mut *self.mappings.as_ptr())[..self.len()] };
+        mappings.sort_unstable_by_key(|mapping| mapping.source);
+    }
+
+    fn push(&self, mapping: OffsetMapping) -> Result<()> {
+        let slot = self
+            .mappings
+            .as_slice_of_cells()
+            .get(self.len())
+            .ok_or(DevTreeError::NotEnoughMemory)?;
+        slot.set(mapping);
+        self.len.set(self.len() + 1);
+        Ok(())
+    }
+}
+
+/// Which properties hold a node's phandle when it is written.
+///
+/// The specification names the property `phandle`. Linux kernels before 2.6.37 only read
+/// `linux,phandle`, which `dtc` writes alongside `phandle` by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PhandleStyle {
+    /// Only `phandle`.
+    Phandle,
+    /// Only `linux,phandle`.
+    Linux,
+    /// Both `linux,phandle` and `phandle`, as `dtc -H both` writes.
+    Both,
+}
+
+impl PhandleStyle {
+    /// Returns the names of the properties to write, in order.
+    pub(crate) fn names(self) -> &'static [&'static str] {
+        match self {
+            PhandleStyle::Phandle => &["phandle"],
+            PhandleStyle::Linux => &["linux,phandle"],
+            PhandleStyle::Both => &["linux,phandle", "phandle"],
+        }
+    }
+}
+
+/// Returns whether the property holds a node's phandle.
+pub(crate) fn is_phandle_prop(name: &[u8]) -> bool {
+    name == b"phandle" || name == b"linux,phandle"
+}
+
+/// A sink for a serialized device tree, see [`Serializer::modify_to_writer`].
+///
+/// Enable the `std` feature to write to any [`std::io::Write`] through [`IoSink`].
+pub trait FdtWrite {
+    /// Write all of `data` to the sink.
+    ///
+    /// Implementations should return [`DevTreeError::SinkError`] if the sink fails.
+    fn write_all(&mut self, data: &[u8]) -> Result<()>;
+}
+
+/// Adapts a [`std::io::Write`] (a file, socket, ...) into a [`FdtWrite`].
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct IoSink<W>(pub W);
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> FdtWrite for IoSink<W> {
+    fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        self.0.write_all(data).map_err(|_| DevTreeError::SinkError)
+    }
+}
+
+/// Where the [`Serializer`] writes the device tree.
+enum Output<'o> {
+    /// Into the output buffer.
+    Buffer,
+    /// Nowhere. Only the size of the output is calculated, using the buffer as scratch space.
+    DryRun,
+    /// To a sink, using the buffer as scratch space.
+    Sink(&'o mut dyn FdtWrite),
+}
+
+/// The header fields of a serialized device tree which don't describe its layout.
+struct HeaderIds {
+    version: u32,
+    last_comp_version: u32,
+    boot_cpuid_phys: u32,
+}
+
+/// Header fields to write instead of those derived from the source tree, see
+/// [`ModifyOptions::header`].
+///
+/// The fields which give the sizes and offsets of the blocks are always computed from what's
+/// written, and so can't be overridden.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeaderOverrides {
+    /// The physical ID of the boot CPU. Copied from the source tree by default.
+    pub boot_cpuid_phys: Option<u32>,
+    /// The format version, 16 or 17.
+    ///
+    /// The output is always laid out as version 17 describes, which is compatible with version
+    /// 16. By default, a source tree's version 16 is kept, and any other version written as 17.
+    pub version: Option<u32>,
+    /// The oldest format version the output is compatible with, which can't be later than
+    /// `version`.
+    ///
+    /// Defaults to 16, the value the specification requires, whatever the source tree's is.
+    pub last_comp_version: Option<u32>,
+}
+
+impl HeaderOverrides {
+    /// Returns the header fields to write for a copy of `fdt`.
+    ///
+    /// Returns [`DevTreeError::InvalidParameter`] if the versions are invalid.
+    fn resolve(&self, fdt: &DevTree) -> Result<HeaderIds> {
+        let version = match self.version {
+            Some(version @ (16 | FDT_VERSION)) => version,
+            Some(_) => {
+                return Err(DevTreeError::InvalidParameter(
+                    "Only versions 16 and 17 can be written",
+                ))
+            }
+            None if fdt.version() == 16 => 16,
+            None => FDT_VERSION,
+        };
+        let last_comp_version = self.last_comp_version.unwrap_or(FDT_LAST_COMP_VERSION);
+        if last_comp_version > version {
+            return Err(DevTreeError::InvalidParameter(
+                "last_comp_version is later than version",
+            ));
+        }
+        Ok(HeaderIds {
+            version,
+            last_comp_version,
+            boot_cpuid_phys: self
+                .boot_cpuid_phys
+                .unwrap_or_else(|| fdt.boot_cpuid_phys()),
+        })
+    }
+}
+
+/// The offsets and sizes of the blocks of a serialized device tree.
+#[derive(PartialEq, Eq)]
+struct BlockLayout {
+    off_mem_rsvmap: usize,
+    off_dt_struct: usize,
+    size_dt_struct: usize,
+    /// Follows the structure block, unless the source tree's layout is preserved.
+    off_dt_strings: usize,
+    size_dt_strings: usize,
+    /// The length of the trailing data after the strings block.
+    trailing: usize,
+    padding: usize,
+}
+
+impl BlockLayout {
+    fn strings_end(&self) -> usize {
+        self.off_dt_strings + self.size_dt_strings
+    }
+
+    fn totalsize(&self) -> usize {
+        self.strings_end() + self.trailing + self.padding
+    }
+
+    fn write_header(&self, buf: &mut [u8], ids: &HeaderIds) -> Result<()> {
+        set_be32_field!(magic, fdt_header, buf, FDT_MAGIC)?;
+        set_be32_field!(totalsize, fdt_header, buf, self.totalsize())?;
+        set_be32_field!(off_dt_struct, fdt_header, buf, self.off_dt_struct)?;
+        set_be32_field!(off_dt_strings, fdt_header, buf, self.off_dt_strings)?;
+        set_be32_field!(off_mem_rsvmap, fdt_header, buf, self.off_mem_rsvmap)?;
+        set_be32_field!(version, fdt_header, buf, ids.version)?;
+        set_be32_field!(last_comp_version, fdt_header, buf, ids.last_comp_version)?;
+        set_be32_field!(boot_cpuid_phys, fdt_header, buf, ids.boot_cpuid_phys)?;
+        set_be32_field!(size_dt_strings, fdt_header, buf, self.size_dt_strings)?;
+        set_be32_field!(size_dt_struct, fdt_header, buf, self.size_dt_struct)?;
+        Ok(())
+    }
+}
+
+/// Where within a node an [`Insert`] is asked for new tokens.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum InsertPoint {
+    /// After the node's last property, before its first child.
+    Props,
+    /// After the node's last child, before its `EndNode`.
+    Children,
+}
+
+/// Adds tokens which aren't in the source tree while it's serialized.
+pub(crate) trait Insert<'dt> {
+    /// Serialize any new tokens for the node at `ctx` with `ser`.
+    fn insert(
+        &self,
+        at: InsertPoint,
+        ctx: &ModifyContext<'_, 'dt>,
+        ser: &mut Serializer<'_, 'dt>,
+    ) -> Result<()>;
+}
+
+impl<'dt> Insert<'dt> for () {
+    fn insert(
+        &self,
+        _: InsertPoint,
+        _: &ModifyContext<'_, 'dt>,
+        _: &mut Serializer<'_, 'dt>,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes a (possibly modified) copy of a [`DevTree`] into an output buffer.
+///
+/// The output is laid out as the header, the memory reservation block, the structure block, and
+/// the strings block.
+///
+/// Property names which aren't in the source tree's strings block are appended to the output's
+/// strings block.
+pub struct Serializer<'o, 'dt> {
+    buf: &'o mut [u8],
+    off: usize,
+    strings: StringTableBuilder<'dt>,
+    output: Output<'o>,
+    phandle_style: Option<PhandleStyle>,
+}
+
+impl<'o, 'dt> Serializer<'o, 'dt> {
+    /// Serialize a copy of `fdt` into `buf`, passing every token to `f` to decide how it should
+    /// be written.
+    ///
+    /// Returns the size of the serialized device tree, or
+    /// [`DevTreeError::OutputBufferTooSmall`] if it doesn't fit in `buf`.
+    ///
+    /// `buf` should be 32-bit aligned if the output is to be parsed with [`DevTree::new`].
+    pub fn modify<'r, F>(fdt: &DevTree<'dt>, buf: &'o mut [u8], f: F) -> Result<usize>
+    where
+        F: FnMut(ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        Self::modify_with_options(fdt, buf, &ModifyOptions::default(), f)
+    }
+
+    /// As [`Serializer::modify`], but also add `metadata` as the last child of the root's
+    /// `parent` child node (e.g. `"chosen"` or `"firmware"`).
+    ///
+    /// The parent node is created at the end of the root node if it doesn't exist (or if `f`
+    /// drops it).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use fdt_rs::doctest::FDT;
+    /// use fdt_rs::prelude::*;
+    /// use fdt_rs::base::*;
+    /// use fdt_rs::modify::*;
+    ///
+    /// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    /// let mut buf = vec![0u32; FDT.len() / 2];
+    /// let out = unsafe {
+    ///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4)
+    /// };
+    ///
+    /// let props = [
+    ///     MetadataProp::new("version", MetadataValue::Str("1.2.3")),
+    ///     MetadataProp::new("boot-count", MetadataValue::U32(42)),
+    /// ];
+    /// let size = Serializer::modify_with_metadata(
+    ///     &devtree,
+    ///     out,
+    ///     "firmware",
+    ///     &MetadataNode::new("acme,bootloader", &props),
+    ///     |_| ModifyTokenResponse::Pass,
+    /// )
+    /// .unwrap();
+    ///
+    /// let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    /// let node = modified
+    ///     .nodes()
+    ///     .find(|n| Ok(n.name()? == "acme,bootloader"))
+    ///     .unwrap()
+    ///     .unwrap();
+    /// assert_eq!(node.props().next().unwrap().unwrap().str().unwrap(), "1.2.3");
+    /// ```
+    pub fn modify_with_metadata<'r, F>(
+        fdt: &DevTree<'dt>,
+        buf: &'o mut [u8],
+        parent: &str,
+        metadata: &MetadataNode,
+        f: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        let options = ModifyOptions {
+            metadata: Some((parent, metadata)),
+            ..ModifyOptions::default()
+        };
+        Self::modify_with_options(fdt, buf, &options, f)
+    }
+
+    /// As [`Serializer::modify`], with the given [`ModifyOptions`].
+    pub fn modify_with_options<'r, F>(
+        fdt: &DevTree<'dt>,
+        buf: &'o mut [u8],
+        options: &ModifyOptions,
+        f: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        Self::serialize(fdt, buf, options, Output::Buffer, &(), without_context(f))
+    }
+
+    /// As [`Serializer::modify_with_options`], but also pass `f` the [`ModifyContext`] of each
+    /// token: where it is in the source tree.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use fdt_rs::doctest::FDT;
+    /// use fdt_rs::prelude::*;
+    /// use fdt_rs::base::*;
+    /// use fdt_rs::modify::*;
+    ///
+    /// let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    /// let mut buf = vec![0u32; FDT.len() / 4];
+    /// let out = unsafe {
+    ///     core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, FDT.len())
+    /// };
+    ///
+    /// // Drop the properties of `/cpus` itself, but not those of its children.
+    /// let size = Serializer::modify_with_context(&devtree, out, &ModifyOptions::default(), |ctx, tok| {
+    ///     match tok {
+    ///         ModifyParsedTok::Prop(..) if ctx.is_at("/cpus") => ModifyTokenResponse::Drop,
+    ///         _ => ModifyTokenResponse::Pass,
+    ///     }
+    /// })
+    /// .unwrap();
+    ///
+    /// let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    /// assert_eq!(modified.props().count().unwrap(), devtree.props().count().unwrap() - 3);
+    /// ```
+    pub fn modify_with_context<'r, F>(
+        fdt: &DevTree<'dt>,
+        buf: &'o mut [u8],
+        options: &ModifyOptions,
+        f: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(&ModifyContext<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        Self::serialize(fdt, buf, options, Output::Buffer, &(), f)
+    }
+
+    /// As [`Serializer::modify_with_context`], but also write the tokens `inserts` adds.
+    pub(crate) fn modify_with_inserts<'r, F>(
+        fdt: &DevTree<'dt>,
+        buf: &'o mut [u8],
+        options: &ModifyOptions,
+        inserts: &dyn Insert<'dt>,
+        f: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(&ModifyContext<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        Self::serialize(fdt, buf, options, Output::Buffer, inserts, f)
+    }
+
+    /// As [`Serializer::dry_run`], but with a context and the tokens `inserts` adds, see
+    /// [`Serializer::modify_with_inserts`].
+    pub(crate) fn dry_run_with_inserts<'r, F>(
+        fdt: &DevTree<'dt>,
+        scratch: &'o mut [u8],
+        options: &ModifyOptions,
+        inserts: &dyn Insert<'dt>,
+        f: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(&ModifyContext<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        Self::serialize(fdt, scratch, options, Output::DryRun, inserts, f)
+    }
+
+    /// Returns the size of the device tree [`Serializer::modify_with_options`] would write,
+    /// without writing it.
+    ///
+    /// `f` is called exactly as it would be by [`Serializer::modify_with_options`], except that
+    /// the buffers passed with [`ModifyParsedTok::Prop`] and [`ModifyParsedTok::BeginNode`] are
+    /// carved from `scratch`. `scratch` must be large enough for the largest property value or
+    /// node name in the tree or written by `f`, plus any property names appended to the strings
+    /// block.
+    ///
+    /// With [`ModifyOptions::gc_strings`] set, the size before the strings block is collected is
+    /// returned. That is an upper bound on the size of the output.
+    pub fn dry_run<'r, F>(
+        fdt: &DevTree<'dt>,
+        scratch: &'o mut [u8],
+        options: &ModifyOptions,
+        f: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        Self::serialize(
+            fdt,
+            scratch,
+            options,
+            Output::DryRun,
+            &(),
+            without_context(f),
+        )
+    }
+
+    /// As [`Serializer::modify_with_options`], but write the device tree to `sink` as it is
+    /// serialized rather than into one contiguous buffer.
+    ///
+    /// The header comes first and depends on the size of the rest of the tree, so the tree is
+    /// serialized twice: once as a [`Serializer::dry_run`] and once to the sink. `f` is called
+    /// for every token in both passes and must respond the same way each time. `scratch` must be
+    /// as large as [`Serializer::dry_run`] requires.
+    ///
+    /// Returns the size of the device tree written. [`ModifyOptions::gc_strings`] isn't
+    /// supported, since collection rewrites the structure block after it has been written.
+    pub fn modify_to_writer<'r, F>(
+        fdt: &DevTree<'dt>,
+        scratch: &'o mut [u8],
+        options: &ModifyOptions,
+        sink: &'o mut dyn FdtWrite,
+        f: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        let mut f = without_context(f);
+        if options.gc_strings {
+            return Err(DevTreeError::InvalidParameter(
+                "Strings can't be collected while streaming",
+            ));
+        }
+
+        let layout = Serializer::new(fdt, &mut *scratch, Output::DryRun)?.serialize_blocks(
+            fdt,
+            options,
+            &(),
+            &mut f,
+        )?;
+        let mut header = [0u8; size_of::<fdt_header>()];
+        layout.write_header(&mut header, &options.header.resolve(fdt)?)?;
+        sink.write_all(&header)?;
+
+        let mut ser = Self::new(fdt, scratch, Output::Sink(sink))?;
+        if ser.serialize_blocks(fdt, options, &(), &mut f)? != layout {
+            return Err(DevTreeError::InvalidParameter(
+                "Callback responded differently to the second pass",
+            ));
+        }
+        if let Output::Sink(sink) = ser.output {
+            ser.strings.write_to(ser.buf, sink)?;
+            sink.write_all(options.trailing.data(fdt))?;
+            let zeros = [0u8; 64];
+            let mut padding = layout.padding;
+            while padding > 0 {
+                let len = padding.min(zeros.len());
+                sink.write_all(&zeros[..len])?;
+                padding -= len;
+            }
+        }
+        Ok(layout.totalsize())
+    }
+
+    /// As [`Serializer::modify_with_options`], but serialize into a [`Vec`] which grows to fit
+    /// the device tree, so the size of the output needn't be known in advance.
+    ///
+    /// The buffer starts at the source tree's size. Each time it turns out to be too small, it
+    /// grows (at least doubling) and the tree is serialized again from the start, so `f` may be
+    /// called more than once for each token and must respond the same way each time.
+    ///
+    /// The allocation isn't guaranteed to be 32-bit aligned, so copy the output to an aligned
+    /// buffer to parse it with [`DevTree::new`].
+    #[cfg(feature = "alloc")]
+    pub fn modify_to_vec<'r, F>(
+        fdt: &DevTree<'dt>,
+        options: &ModifyOptions,
+        mut f: F,
+    ) -> Result<Vec<u8>>
+    where
+        F: FnMut(ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        let mut buf = vec![0u8; fdt.totalsize()];
+        loop {
+            match Serializer::modify_with_options(fdt, &mut buf, options, &mut f) {
+                Ok(size) => {
+                    buf.truncate(size);
+                    return Ok(buf);
+                }
+                Err(DevTreeError::OutputBufferTooSmall { needed, available }) => {
+                    buf.resize(needed.max(available * 2), 0);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn new(fdt: &DevTree<'dt>, buf: &'o mut [u8], output: Output<'o>) -> Result<Self> {
+        let strings = StringTableBuilder::new(fdt, buf)?;
+        Ok(Self {
+            buf,
+            off: size_of::<fdt_header>(),
+            strings,
+            output,
+            phandle_style: None,
+        })
+    }
+
+    fn serialize<'r, F>(
+        fdt: &DevTree<'dt>,
+        buf: &'o mut [u8],
+        options: &ModifyOptions,
+        output: Output<'o>,
+        inserts: &dyn Insert<'dt>,
+        f: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(&ModifyContext<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        let mut ser = Self::new(fdt, buf, output)?;
+        let mut layout = ser.serialize_blocks(fdt, options, inserts, f)?;
+        if let Output::DryRun = ser.output {
+            return Ok(layout.totalsize());
+        }
+
+        let buf = ser.buf;
+        let off_dt_strings = layout.off_dt_strings;
+        ser.strings.finish(buf, off_dt_strings)?;
+        if options.gc_strings {
+            layout.size_dt_strings = strings::collect_garbage(
+                buf,
+                layout.off_dt_struct,
+                off_dt_strings,
+                layout.size_dt_strings,
+            )?;
+        }
+        let available = buf.len();
+        let (trailing, padding) = buf
+            .get_mut(layout.strings_end()..layout.totalsize())
+            .ok_or(DevTreeError::OutputBufferTooSmall {
+                needed: layout.totalsize(),
+                available,
+            })?
+            .split_at_mut(layout.trailing);
+        trailing.copy_from_slice(options.trailing.data(fdt));
+        padding.iter_mut().for_each(|b| *b = 0);
+        layout.write_header(buf, &options.header.resolve(fdt)?)?;
+        Ok(layout.totalsize())
+    }
+
+    /// Create a serializer for a device tree which isn't copied from another (see
+    /// [`FdtBuilder`](crate::modify::FdtBuilder)). Space is left for the header, which is
+    /// written by [`Serializer::finish_new`].
+    pub(crate) fn new_empty(buf: &'o mut [u8]) -> Self {
+        Self {
+            strings: StringTableBuilder::empty(buf),
+            buf,
+            off: size_of::<fdt_header>(),
+            output: Output::Buffer,
+            phandle_style: None,
+        }
+    }
+
+    /// Returns the style new phandle properties are written in, if one was chosen.
+    pub(crate) fn phandle_style(&self) -> Option<PhandleStyle> {
+        self.phandle_style
+    }
+
+    /// Write a node's phandle properties in `style`.
+    pub(crate) fn serialize_new_phandle(
+        &mut self,
+        style: PhandleStyle,
+        phandle: u32,
+    ) -> Result<()> {
+        for name in style.names() {
+            self.serialize_new_prop(name, &MetadataValue::U32(phandle))?;
+        }
+        Ok(())
+    }
+
+    /// Overwrite the cell already written at `off` with `val`.
+    pub(crate) fn rewrite_u32(&mut self, off: usize, val: u32) -> Result<()> {
+        self.out().write_be_u32(off, val)?;
+        Ok(())
+    }
+
+    /// Returns the offset the next token will be written at.
+    pub(crate) fn offset(&self) -> usize {
+        self.off
+    }
+
+    /// Write the End token, strings block and header of a device tree started with
+    /// [`Serializer::new_empty`].
+    ///
+    /// Returns the size of the device tree.
+    pub(crate) fn finish_new(
+        mut self,
+        off_mem_rsvmap: usize,
+        off_dt_struct: usize,
+        boot_cpuid_phys: u32,
+    ) -> Result<usize> {
+        self.serialize_u32(FdtTok::End as u32)?;
+        let layout = BlockLayout {
+            off_mem_rsvmap,
+            off_dt_struct,
+            size_dt_struct: self.off - off_dt_struct,
+            off_dt_strings: self.off,
+            size_dt_strings: self.strings.size(self.buf),
+            trailing: 0,
+            padding: 0,
+        };
+        self.strings.finish(self.buf, layout.off_dt_strings)?;
+        let ids = HeaderIds {
+            version: FDT_VERSION,
+            last_comp_version: FDT_LAST_COMP_VERSION,
+            boot_cpuid_phys,
+        };
+        layout.write_header(self.buf, &ids)?;
+        Ok(layout.totalsize())
+    }
+
+    /// Serialize everything up to the strings block, after the space left for the header.
+    ///
+    /// Returns the layout of the device tree.
+    fn serialize_blocks<'r, F>(
+        &mut self,
+        fdt: &DevTree<'dt>,
+        options: &ModifyOptions,
+        inserts: &dyn Insert<'dt>,
+        f: F,
+    ) -> Result<BlockLayout>
+    where
+        F: FnMut(&ModifyContext<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        self.phandle_style = options.phandle_style;
+        if let Some(map) = options.offset_map {
+            map.clear();
+        }
+        self.serialize_align(size_of::<u64>())?;
+        if options.preserve_layout {
+            self.serialize_zeros_to(fdt.off_mem_rsvmap())?;
+        }
+        let off_mem_rsvmap = self.off;
+        self.serialize_memory_reservation_block(fdt, &options.mem_reserve)?;
+
+        if options.preserve_layout {
+            self.serialize_zeros_to(fdt.off_dt_struct())?;
+        }
+        let off_dt_struct = self.off;
+        self.serialize_struct_block(fdt, options, inserts, f)?;
+        let size_dt_struct = self.off - off_dt_struct;
+        if let (Some(map), true) = (options.offset_map, options.sorted) {
+            map.sort();
+        }
+
+        if options.preserve_layout {
+            self.serialize_zeros_to(fdt.off_dt_strings())?;
+        }
+        Ok(BlockLayout {
+            off_mem_rsvmap,
+            off_dt_struct,
+            size_dt_struct,
+            off_dt_strings: self.off,
+            size_dt_strings: self.strings.size(self.buf),
+            trailing: options.trailing.data(fdt).len(),
+            padding: options.padding,
+        })
+    }
+
+    /// The part of the output buffer not yet claimed by appended property names.
+    fn out(&mut self) -> &mut [u8] {
+        &mut self.buf[..self.strings.tail()]
+    }
+
+    /// Returns the offset of the buffer for a property value or node name which will be written
+    /// at `off`.
+    ///
+    /// Unless writing into the output buffer, the start of the scratch buffer is used instead.
+    fn field_off(&self, off: usize) -> usize {
+        match self.output {
+            Output::Buffer => off,
+            Output::DryRun | Output::Sink(_) => 0,
+        }
+    }
+
+    /// Returns the buffer for a property value or node name which will be written at `off`.
+    fn field_buf(&mut self, off: usize) -> Result<&mut [u8]> {
+        let off = self.field_off(off);
+        if off > self.strings.tail() {
+            return Err(self.too_small(off));
+        }
+        Ok(&mut self.out()[off..])
+    }
+
+    /// Write `data` to the start of the buffer for a field at `off`, see
+    /// [`Serializer::field_buf`].
+    fn write_field(&mut self, off: usize, data: &[u8]) -> Result<()> {
+        if self.field_buf(off)?.write_slice(0, data).is_err() {
+            return Err(self.too_small(self.field_off(off) + data.len()));
+        }
+        Ok(())
+    }
+
+    /// Returns the error for a write which needed `needed` bytes of [`Serializer::out`].
+    fn too_small(&self, needed: usize) -> DevTreeError {
+        DevTreeError::OutputBufferTooSmall {
+            needed: needed + self.buf.len() - self.strings.tail(),
+            available: self.buf.len(),
+        }
+    }
+
+    fn serialize_u32(&mut self, val: u32) -> Result<()> {
+        self.serialize_slice(&val.to_be_bytes())
+    }
+
+    fn serialize_u64(&mut self, val: u64) -> Result<()> {
+        self.serialize_slice(&val.to_be_bytes())
+    }
+
+    fn serialize_slice(&mut self, data: &[u8]) -> Result<()> {
+        let off = self.off;
+        match &mut self.output {
+            Output::Buffer => {
+                if self.out().write_slice(off, data).is_err() {
+                    return Err(self.too_small(off + data.len()));
+                }
+            }
+            Output::DryRun => (),
+            Output::Sink(sink) => sink.write_all(data)?,
+        }
+        self.off += data.len();
+        Ok(())
+    }
+
+    /// Serialize the first `len` bytes of the buffer for the current field, see
+    /// [`Serializer::field_buf`].
+    ///
+    /// They're already in place unless the output is going to a sink, in which case the field
+    /// is at the start of the scratch buffer.
+    fn serialize_field(&mut self, len: usize) -> Result<()> {
+        if let Output::Sink(sink) = &mut self.output {
+            sink.write_all(&self.buf[..len])?;
+        }
+        self.off += len;
+        Ok(())
+    }
+
+    /// Zero pad the output up to the next multiple of `align`.
+    fn serialize_align(&mut self, align: usize) -> Result<()> {
+        while !self.off.is_multiple_of(align) {
+            self.serialize_slice(&[0])?;
+        }
+        Ok(())
+    }
+
+    /// Write zeros up to `off`, where the source tree's next block starts.
+    fn serialize_zeros_to(&mut self, off: usize) -> Result<()> {
+        if self.off > off {
+            return Err(DevTreeError::InvalidParameter(
+                "Block outgrew the source tree's layout",
+            ));
+        }
+        while self.off < off {
+            self.serialize_slice(&[0])?;
+        }
+        Ok(())
+    }
+
+    fn serialize_memory_reservation_block(
+        &mut self,
+        fdt: &DevTree,
+        edits: &MemReserveEdits,
+    ) -> Result<()> {
+        for entry in fdt.reserved_entries() {
+            let mut entry = MemReservation::from(entry);
+            if edits.remove.contains(&entry) {
+                continue;
+            }
+            if let Some(&(_, new)) = edits.replace.iter().find(|(old, _)| *old == entry) {
+                entry = new;
+            }
+            self.serialize_mem_reservation(entry)?;
+        }
+        for &entry in edits.append {
+            self.serialize_mem_reservation(entry)?;
+        }
+        self.serialize_mem_reservation_end()
+    }
+
+    pub(crate) fn serialize_mem_reservation_end(&mut self) -> Result<()> {
+        // The block is terminated by an empty entry.
+        self.serialize_slice(&[0; size_of::<fdt_reserve_entry>()])
+    }
+
+    pub(crate) fn serialize_mem_reservation(&mut self, entry: MemReservation) -> Result<()> {
+        self.serialize_u64(entry.address)?;
+        self.serialize_u64(entry.size)
+    }
+
+    fn serialize_begin_node<'r, F>(
+        &mut self,
+        node: ParsedBeginNode<'dt>,
+        ctx: &ModifyContext<'_, 'dt>,
+        drop_depth: &mut usize,
+        f: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(&ModifyContext<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        let name_off = self.off + size_of::<u32>();
+
+        // Pre-fill the name so the callback may pass or edit it in place.
+        self.write_field(name_off, node.name)?;
+        let name_buf = self.field_buf(name_off)?;
+        let available = name_buf.len();
+
+        let len = match f(ctx, ModifyParsedTok::BeginNode(node.clone(), name_buf)) {
+            ModifyTokenResponse::Pass => node.name.len(),
+            ModifyTokenResponse::Drop => {
+                *drop_depth = 1;
+                return Ok(());
+            }
+            ModifyTokenResponse::ModifySize(len) if len <= available => len,
+            ModifyTokenResponse::ModifySize(len) => {
+                return Err(self.too_small(self.field_off(name_off) + len))
+            }
+            ModifyTokenResponse::Replace(ReplacementTok::BeginNode(name)) => {
+                self.write_field(name_off, name.as_bytes())?;
+                name.len()
+            }
+            ModifyTokenResponse::Replace(_) => return Err(Self::invalid_response()),
+        };
+
+        check_node_name(&self.field_buf(name_off)?[..len])?;
+        self.serialize_u32(FdtTok::BeginNode as u32)?;
+        // The name has already been written by the pre-fill, the callback, or the replacement.
+        self.serialize_field(len)?;
+        self.serialize_slice(&[0])?;
+        self.serialize_align(size_of::<u32>())
+    }
+
+    /// Write the BeginNode token of a node which isn't in the source tree.
+    pub(crate) fn serialize_new_begin_node(&mut self, name: &[u8]) -> Result<()> {
+        check_node_name(name)?;
+        self.serialize_u32(FdtTok::BeginNode as u32)?;
+        self.serialize_slice(name)?;
+        self.serialize_slice(&[0])?;
+        self.serialize_align(size_of::<u32>())
+    }
+
+    /// Write a node which isn't in the source tree, along with its properties.
+    pub(crate) fn serialize_metadata_node(&mut self, node: &MetadataNode) -> Result<()> {
+        self.serialize_new_begin_node(node.name.as_bytes())?;
+        for prop in node.props {
+            self.serialize_new_prop(prop.name, &prop.value)?;
+        }
+        self.serialize_new_end_node()
+    }
+
+    pub(crate) fn serialize_new_end_node(&mut self) -> Result<()> {
+        self.serialize_u32(FdtTok::EndNode as u32)
+    }
+
+    /// Write a property which isn't in the source tree, whose value is a list of cells. Each
+    /// cell of `value` is passed through `map` first.
+    pub(crate) fn serialize_new_cells_prop(
+        &mut self,
+        name: &str,
+        value: &[u8],
+        mut map: impl FnMut(u32) -> Result<u32>,
+    ) -> Result<()> {
+        if !value.len().is_multiple_of(size_of::<u32>()) {
+            return Err(DevTreeError::ParseError);
+        }
+        self.serialize_new_prop_header(name, value.len())?;
+        for i in 0..value.len() / size_of::<u32>() {
+            self.serialize_u32(map(value.read_be_u32(i * size_of::<u32>())?)?)?;
+        }
+        Ok(())
+    }
+
+    /// Write a property which isn't in the source tree.
+    pub(crate) fn serialize_new_prop(&mut self, name: &str, value: &MetadataValue) -> Result<()> {
+        self.serialize_new_prop_header(name, value.len())?;
+        match *value {
+            MetadataValue::Str(s) => {
+                self.serialize_slice(s.as_bytes())?;
+                self.serialize_slice(&[0])?;
+            }
+            MetadataValue::U32(val) => self.serialize_u32(val)?,
+            MetadataValue::U64(val) => self.serialize_u64(val)?,
+            MetadataValue::Bytes(b) => self.serialize_slice(b)?,
+        }
+        self.serialize_align(size_of::<u32>())
+    }
+
+    /// Write the Prop token and header of a property which isn't in the source tree, whose value
+    /// of `len` bytes is written next with [`Serializer::serialize_value`].
+    pub(crate) fn serialize_new_prop_header(&mut self, name: &str, len: usize) -> Result<()> {
+        let name_offset = self.string_offset(name.as_bytes())?;
+        self.serialize_u32(FdtTok::Prop as u32)?;
+        self.serialize_u32(len as u32)?;
+        self.serialize_u32(name_offset as u32)
+    }
+
+    /// Write part of the value of a property started by [`Serializer::serialize_new_prop_header`].
+    ///
+    /// Once the whole value is written, the token must be padded with
+    /// [`Serializer::serialize_value_end`].
+    pub(crate) fn serialize_value(&mut self, data: &[u8]) -> Result<()> {
+        self.serialize_slice(data)
+    }
+
+    pub(crate) fn serialize_value_end(&mut self) -> Result<()> {
+        self.serialize_align(size_of::<u32>())
+    }
+
+    fn serialize_struct_block<'r, F>(
+        &mut self,
+        fdt: &DevTree<'dt>,
+        options: &ModifyOptions,
+        inserts: &dyn Insert<'dt>,
+        mut f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&ModifyContext<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        let mut metadata = options.metadata;
+        // The end of the last Nop token written, to find runs of them.
+        let mut nop_end = None;
+        // Number of unmatched BeginNode tokens seen within a dropped subtree.
+        let mut drop_depth = 0usize;
+        // Depth of the current node in the source tree. The root node is at depth 1.
+        let mut depth = 0usize;
+        // The names of the open nodes, see ModifyContext.
+        let mut path: [&'dt [u8]; MAX_DEPTH] = [&[]; MAX_DEPTH];
+        // Whether the current node at depth 2 is the metadata's parent.
+        let mut in_parent = false;
+        // Whether the current node's properties may still be followed by inserted ones.
+        let mut props_open = false;
+        // Whether the current node's phandle has been written in the chosen style.
+        let mut phandle_written = false;
+        // The offset of the source tree's End token.
+        let mut end = fdt.off_dt_struct();
+
+        let mut tokens = if options.sorted {
+            SourceTokens::Sorted(SortedParseIter::new(fdt))
+        } else {
+            SourceTokens::InOrder(fdt.parse_iter())
+        };
+        while let Some((source, tok)) = tokens.next()? {
+            let output = self.off;
+            if drop_depth > 0 {
+                match tok {
+                    ParsedTok::BeginNode(_) => drop_depth += 1,
+                    ParsedTok::EndNode => drop_depth -= 1,
+                    _ => (),
+                }
+                continue;
+            }
+
+            match tok {
+                ParsedTok::BeginNode(node) => {
+                    if props_open {
+                        let ctx = ModifyContext::new(&path[..depth], source, self.off);
+                        inserts.insert(InsertPoint::Props, &ctx, self)?;
+                        props_open = false;
+                    }
+                    if depth == MAX_DEPTH {
+                        return Err(DevTreeError::InvalidParameter(
+                            "Device tree is nested too deeply",
+                        ));
+                    }
+                    path[depth] = node.name;
+                    depth += 1;
+                    let is_parent = depth == 2
+                        && metadata.is_some_and(|(parent, _)| parent.as_bytes() == node.name);
+                    let ctx = ModifyContext::new(&path[..depth], source, self.off);
+                    self.serialize_begin_node(node, &ctx, &mut drop_depth, &mut f)?;
+                    phandle_written = false;
+                    if drop_depth > 0 {
+                        depth -= 1;
+                    } else {
+                        if let Some(map) = options.offset_map {
+                            map.push(OffsetMapping { source, output })?;
+                        }
+                        props_open = true;
+                        if depth == 2 {
+                            in_parent = is_parent;
+                        }
+                    }
+                }
+                ParsedTok::Prop(prop) => {
+                    let ctx = ModifyContext::new(&path[..depth], source, self.off);
+                    let name = prop_name(fdt, &prop)?;
+                    match options.phandle_style {
+                        Some(_) if phandle_written => {
+                            if !is_phandle_prop(name) {
+                                self.serialize_prop(prop, name, &ctx, &mut f)?;
+                            }
+                        }
+                        Some(style) if is_phandle_prop(name) => {
+                            phandle_written =
+                                self.serialize_phandle_prop(prop, name, style, &ctx, &mut f)?;
+                        }
+                        _ => self.serialize_prop(prop, name, &ctx, &mut f)?,
+                    }
+                    if let (Some(map), true) = (options.offset_map, self.off != output) {
+                        map.push(OffsetMapping { source, output })?;
+                    }
+                }
+                ParsedTok::EndNode => {
+                    let ctx = ModifyContext::new(&path[..depth], source, self.off);
+                    if props_open {
+                        inserts.insert(InsertPoint::Props, &ctx, self)?;
+                        props_open = false;
+                    }
+                    match (depth, metadata) {
+                        (2, Some((_, node))) if in_parent => {
+                            self.serialize_metadata_node(node)?;
+                            metadata = None;
+                        }
+                        (1, Some((parent, node))) => {
+                            self.serialize_new_begin_node(parent.as_bytes())?;
+                            self.serialize_metadata_node(node)?;
+                            self.serialize_u32(FdtTok::EndNode as u32)?;
+                            metadata = None;
+                        }
+                        _ => (),
+                    }
+                    inserts.insert(InsertPoint::Children, &ctx, self)?;
+                    if let (1, Some(overlay)) = (depth, options.overlay) {
+                        self.serialize_overlay_metadata(overlay)?;
+                    }
+                    self.serialize_end_node(&ctx, &mut f)?;
+                    depth = depth.checked_sub(1).ok_or(DevTreeError::ParseError)?;
+                    end = source + size_of::<u32>();
+                }
+                ParsedTok::Nop if options.nop_policy == NopPolicy::Strip => {
+                    end = source + size_of::<u32>();
+                }
+                ParsedTok::Nop => {
+                    end = source + size_of::<u32>();
+                    match f(
+                        &ModifyContext::new(&path[..depth], source, self.off),
+                        ModifyParsedTok::Nop,
+                    ) {
+                        ModifyTokenResponse::Pass
+                            if options.nop_policy == NopPolicy::Coalesce
+                                && nop_end == Some(self.off) => {}
+                        ModifyTokenResponse::Pass => {
+                            self.serialize_u32(FdtTok::Nop as u32)?;
+                            nop_end = Some(self.off);
+                        }
+                        ModifyTokenResponse::Drop => (),
+                        _ => return Err(Self::invalid_response()),
+                    }
+                }
+            }
+        }
+
+        let ctx = ModifyContext::new(&[], end, self.off);
+        self.serialize_end(&ctx, &mut f)
+    }
+
+    fn serialize_end<'r, F>(&mut self, ctx: &ModifyContext<'_, 'dt>, f: &mut F) -> Result<()>
+    where
+        F: FnMut(&ModifyContext<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        let off = self.off;
+        let buf = self.field_buf(off)?;
+        let available = buf.len();
+        let len = match f(ctx, ModifyParsedTok::End(buf)) {
+            ModifyTokenResponse::Pass => 0,
+            ModifyTokenResponse::ModifySize(len) if len > available => {
+                return Err(self.too_small(self.field_off(off) + len))
+            }
+            ModifyTokenResponse::ModifySize(len) if len.is_multiple_of(size_of::<u32>()) => len,
+            ModifyTokenResponse::ModifySize(_) => {
+                return Err(DevTreeError::InvalidParameter(
+                    "Appended tokens must be a multiple of 4 bytes",
+                ))
+            }
+            _ => return Err(Self::invalid_response()),
+        };
+        // The appended tokens have already been written by the callback.
+        self.serialize_field(len)?;
+        self.serialize_u32(FdtTok::End as u32)
+    }
+
+    fn serialize_end_node<'r, F>(&mut self, ctx: &ModifyContext<'_, 'dt>, f: &mut F) -> Result<()>
+    where
+        F: FnMut(&ModifyContext<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        match f(ctx, ModifyParsedTok::EndNode) {
+            ModifyTokenResponse::Pass => self.serialize_u32(FdtTok::EndNode as u32),
+            ModifyTokenResponse::Drop => Ok(()),
+            _ => Err(Self::invalid_response()),
+        }
+    }
+
+    fn serialize_prop<'r, F>(
+        &mut self,
+        prop: ParsedProp<'dt>,
+        name: &'dt [u8],
+        ctx: &ModifyContext<'_, 'dt>,
+        f: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(&ModifyContext<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        match self.prop_response(prop, name, ctx, f)? {
+            Some((name_offset, len)) => self.serialize_prop_tok(name_offset, len),
+            None => Ok(()),
+        }
+    }
+
+    /// As [`Serializer::serialize_prop`], but for a `phandle` or `linux,phandle` property, which
+    /// is written in `style` unless the callback renames it.
+    ///
+    /// Returns whether the node's phandle was written.
+    fn serialize_phandle_prop<'r, F>(
+        &mut self,
+        prop: ParsedProp<'dt>,
+        name: &'dt [u8],
+        style: PhandleStyle,
+        ctx: &ModifyContext<'_, 'dt>,
+        f: &mut F,
+    ) -> Result<bool>
+    where
+        F: FnMut(&ModifyContext<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        let value_off = self.off + size_of::<u32>() + size_of::<fdt_prop_header>();
+        let source_name_offset = prop.name_offset;
+        match self.prop_response(prop, name, ctx, f)? {
+            Some((name_offset, len)) if name_offset == source_name_offset && len == 4 => {
+                let phandle = (&*self.field_buf(value_off)?).read_be_u32(0)?;
+                self.serialize_new_phandle(style, phandle)?;
+                Ok(true)
+            }
+            Some((name_offset, len)) => {
+                self.serialize_prop_tok(name_offset, len)?;
+                Ok(false)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Pass a property to the callback, leaving its value (if it's kept) in the buffer for the
+    /// field at the current offset, see [`Serializer::field_buf`].
+    ///
+    /// Returns the name offset and length of the property to write, or `None` if it is dropped.
+    fn prop_response<'r, F>(
+        &mut self,
+        prop: ParsedProp<'dt>,
+        name: &'dt [u8],
+        ctx: &ModifyContext<'_, 'dt>,
+        f: &mut F,
+    ) -> Result<Option<(usize, usize)>>
+    where
+        F: FnMut(&ModifyContext<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+    {
+        let value_off = self.off + size_of::<u32>() + size_of::<fdt_prop_header>();
+
+        // Pre-fill the value so the callback may pass or edit it in place.
+        self.write_field(value_off, prop.prop_buf)?;
+        let value_buf = self.field_buf(value_off)?;
+        let available = value_buf.len();
+
+        let (name_offset, len) = match f(ctx, ModifyParsedTok::Prop(prop.clone(), name, value_buf))
+        {
+            ModifyTokenResponse::Pass => (prop.name_offset, prop.prop_buf.len()),
+            ModifyTokenResponse::Drop => return Ok(None),
+            ModifyTokenResponse::ModifySize(len) if len <= available => (prop.name_offset, len),
+            ModifyTokenResponse::ModifySize(len) => {
+                return Err(self.too_small(self.field_off(value_off) + len))
+            }
+            ModifyTokenResponse::Replace(ReplacementTok::Prop { name, value }) => {
+                let name_offset = self.string_offset(name.as_bytes())?;
+                self.write_field(value_off, value)?;
+                (name_offset, value.len())
+            }
+            ModifyTokenResponse::Replace(_) => return Err(Self::invalid_response()),
+        };
+        Ok(Some((name_offset, len)))
+    }
+
+    /// Write a Prop token whose value is already in the buffer for the field at the current
+    /// offset.
+    fn serialize_prop_tok(&mut self, name_offset: usize, len: usize) -> Result<()> {
+        self.serialize_u32(FdtTok::Prop as u32)?;
+        self.serialize_u32(len as u32)?;
+        self.serialize_u32(name_offset as u32)?;
+        // The value has already been written by the pre-fill, the callback, or the replacement.
+        self.serialize_field(len)?;
+        self.serialize_align(size_of::<u32>())
+    }
+
+    /// Returns the offset of `name` within the output's strings block, appending it if it isn't
+    /// already present.
+    fn string_offset(&mut self, name: &[u8]) -> Result<usize> {
+        // Unless writing into the output buffer, the scratch buffer is only used for the current
+        // token.
+        let used = match self.output {
+            Output::Buffer => self.off,
+            Output::DryRun | Output::Sink(_) => 0,
+        };
+        self.strings.offset_of(self.buf, used, name)
+    }
+
+    /// Returns whether a property named `name` has been written since `start`, where the
+    /// properties of the node being written start.
+    pub(crate) fn has_prop_since(&self, start: usize, name: &str) -> Result<bool> {
+        let name_offset = match self.strings.find(self.buf, name.as_bytes()) {
+            Some(name_offset) => name_offset,
+            None => return Ok(false),
+        };
+        let buf: &[u8] = self.buf;
+        let mut off = start;
+        while off < self.off {
+            if buf.read_be_u32(off)? != FdtTok::Prop as u32 {
+                return Err(DevTreeError::ParseError);
+            }
+            let len = buf.read_be_u32(off + size_of::<u32>())? as usize;
+            if buf.read_be_u32(off + 2 * size_of::<u32>())? as usize == name_offset {
+                return Ok(true);
+            }
+            off += size_of::<u32>() + size_of::<fdt_prop_header>() + len;
+            off = (off + size_of::<u32>() - 1) & !(size_of::<u32>() - 1);
+        }
+        Ok(false)
+    }
+
+    fn invalid_response() -> DevTreeError {
+        DevTreeError::InvalidParameter("Response is not valid for the given token")
+    }
+}
+
+/// The tokens of the source tree, with their offsets, in the order they're written.
+// Only one is created per serialization, so the size of the sorted iterator's stack is fine.
+#[allow(clippy::large_enum_variant)]
+enum SourceTokens<'a, 'dt> {
+    InOrder(DevTreeParseIter<'a, 'dt>),
+    Sorted(SortedParseIter<'a, 'dt>),
+}
+
+impl<'dt> SourceTokens<'_, 'dt> {
+    fn next(&mut self) -> Result<Option<(usize, ParsedTok<'dt>)>> {
+        match self {
+            SourceTokens::InOrder(iter) => {
+                let offset = iter.offset;
+                Ok(iter.next()?.map(|tok| (offset, tok)))
+            }
+            SourceTokens::Sorted(iter) => iter.next(),
+        }
+    }
+}
+
+/// Adapt a callback which doesn't take a [`ModifyContext`] to one which does.
+fn without_context<'r, 'dt, F>(
+    mut f: F,
+) -> impl FnMut(&ModifyContext<'_, 'dt>, ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>
+where
+    F: FnMut(ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'r>,
+{
+    move |_, tok| f(tok)
+}
+
+pub(crate) fn check_node_name(name: &[u8]) -> Result<()> {
+    if name.len() >= MAX_NODE_NAME_LEN || name.contains(&0) {
+        return Err(DevTreeError::InvalidParameter("Invalid node name"));
+    }
+    Ok(())
+}
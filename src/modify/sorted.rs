@@ -0,0 +1,260 @@
+use core::mem::size_of;
+
+use crate::prelude::*;
+
+use crate::base::parse::{DevTreeParseIter, ParsedProp, ParsedTok};
+use crate::base::DevTree;
+use crate::error::{DevTreeError, Result};
+use crate::modify::{MAX_DEPTH, MAX_SORTED_ENTRIES};
+use crate::priv_util::SliceRead;
+
+/// Returns the smallest item of `items` greater than `after`, ordered by the key `key`.
+///
+/// Keys must be unique. Repeatedly taking the next item visits the items in sorted order
+/// without sorting them in memory.
+pub(crate) fn next_sorted<T, K: Ord>(
+    items: impl Iterator<Item = Result<T>>,
+    key: impl Fn(&T) -> K,
+    after: Option<&K>,
+) -> Result<Option<T>> {
+    let mut next: Option<(K, T)> = None;
+    for item in items {
+        let item = item?;
+        let k = key(&item);
+        if after.is_some_and(|after| k <= *after) {
+            continue;
+        }
+        if next.as_ref().is_none_or(|(next_key, _)| k < *next_key) {
+            next = Some((k, item));
+        }
+    }
+    Ok(next.map(|(_, item)| item))
+}
+
+/// The properties and children of a node, parsed from just after its BeginNode token.
+pub(crate) struct NodeContents<'a, 'dt> {
+    iter: DevTreeParseIter<'a, 'dt>,
+    /// Depth within the node's subtree. The node's children are at depth 1.
+    depth: usize,
+}
+
+/// A token of a node's contents, with its offset.
+pub(crate) enum Item<'dt> {
+    Prop(usize, ParsedProp<'dt>),
+    Child(usize, &'dt [u8]),
+}
+
+impl<'a, 'dt> NodeContents<'a, 'dt> {
+    pub(crate) fn new(fdt: &'a DevTree<'dt>, offset: usize) -> Self {
+        Self {
+            iter: DevTreeParseIter { offset, fdt },
+            depth: 0,
+        }
+    }
+
+    /// Returns the properties of the node, with their names and offsets.
+    pub(crate) fn props(
+        fdt: &'a DevTree<'dt>,
+        offset: usize,
+    ) -> impl Iterator<Item = Result<(&'dt [u8], usize, ParsedProp<'dt>)>> + 'a {
+        Self::new(fdt, offset).filter_map(move |item| match item {
+            Ok(Item::Prop(off, prop)) => Some(prop_name(fdt, &prop).map(|name| (name, off, prop))),
+            Ok(Item::Child(..)) => None,
+            Err(err) => Some(Err(err)),
+        })
+    }
+
+    /// Returns the children of the node, with their names and offsets.
+    pub(crate) fn children(
+        fdt: &'a DevTree<'dt>,
+        offset: usize,
+    ) -> impl Iterator<Item = Result<(&'dt [u8], usize)>> + 'a {
+        Self::new(fdt, offset).filter_map(|item| match item {
+            Ok(Item::Child(off, name)) => Some(Ok((name, off))),
+            Ok(Item::Prop(..)) => None,
+            Err(err) => Some(Err(err)),
+        })
+    }
+}
+
+impl<'dt> Iterator for NodeContents<'_, 'dt> {
+    type Item = Result<Item<'dt>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let offset = self.iter.offset;
+            let tok = match self.iter.next() {
+                Ok(Some(tok)) => tok,
+                Ok(None) => return Some(Err(DevTreeError::ParseError)),
+                Err(err) => return Some(Err(err)),
+            };
+            match tok {
+                ParsedTok::BeginNode(node) => {
+                    self.depth += 1;
+                    if self.depth == 1 {
+                        return Some(Ok(Item::Child(offset, node.name)));
+                    }
+                }
+                ParsedTok::EndNode if self.depth == 0 => return None,
+                ParsedTok::EndNode => self.depth -= 1,
+                ParsedTok::Prop(prop) if self.depth == 0 => {
+                    return Some(Ok(Item::Prop(offset, prop)))
+                }
+                ParsedTok::Prop(_) | ParsedTok::Nop => (),
+            }
+        }
+    }
+}
+
+/// Returns the offset of the contents of the node whose BeginNode token is at `offset`.
+pub(crate) fn contents(fdt: &DevTree, offset: usize) -> Result<usize> {
+    let mut iter = DevTreeParseIter { offset, fdt };
+    match iter.next()? {
+        Some(ParsedTok::BeginNode(_)) => Ok(iter.offset),
+        _ => Err(DevTreeError::ParseError),
+    }
+}
+
+/// Returns the offset of the BeginNode token of the root node of `fdt`.
+pub(crate) fn root_offset(fdt: &DevTree) -> Result<usize> {
+    let mut iter = fdt.parse_iter();
+    loop {
+        let offset = iter.offset;
+        match iter.next()? {
+            Some(ParsedTok::BeginNode(_)) => return Ok(offset),
+            Some(ParsedTok::Nop) => (),
+            _ => return Err(DevTreeError::ParseError),
+        }
+    }
+}
+
+/// Returns the name of a property of `fdt`.
+pub(crate) fn prop_name<'dt>(fdt: &DevTree<'dt>, prop: &ParsedProp) -> Result<&'dt [u8]> {
+    Ok(fdt
+        .buf()
+        .read_bstring0(fdt.off_dt_strings() + prop.name_offset)?)
+}
+
+/// A node whose tokens are being parsed by a [`SortedParseIter`].
+#[derive(Clone, Copy, Default)]
+struct Frame<'dt> {
+    /// The offset of the node's contents, just after its BeginNode token.
+    offset: usize,
+    /// Whether all of the node's properties have been parsed.
+    props_done: bool,
+    /// The name and offset of the last property (or, once they're done, child) parsed.
+    last: Option<(&'dt [u8], usize)>,
+}
+
+/// Parses the tokens of a tree, with their offsets, in sorted order: each node's properties
+/// sorted by name, then its children sorted by name, depth first. Names are compared bytewise,
+/// as `dtc -s` does, and those which are equal are left in tree order.
+///
+/// Nop tokens are skipped. Sorting is done by re-parsing rather than in memory: taking each
+/// property or child of a node rescans all of the node's contents, including the subtrees of its
+/// children. Parsing a node so takes time proportional to the size of its subtree times its
+/// number of properties and children, which for the whole tree is quadratic in its size. Trees
+/// of more than [`MAX_SORTED_ENTRIES`] nodes and properties are refused with
+/// [`DevTreeError::InvalidParameter`].
+pub(crate) struct SortedParseIter<'a, 'dt> {
+    fdt: &'a DevTree<'dt>,
+    started: bool,
+    stack: [Frame<'dt>; MAX_DEPTH],
+    depth: usize,
+}
+
+impl<'a, 'dt> SortedParseIter<'a, 'dt> {
+    pub(crate) fn new(fdt: &'a DevTree<'dt>) -> Self {
+        Self {
+            fdt,
+            started: false,
+            stack: [Frame::default(); MAX_DEPTH],
+            depth: 0,
+        }
+    }
+
+    /// Parse the BeginNode token at `offset`, and start parsing the node's contents.
+    fn begin_node(&mut self, offset: usize) -> Result<Option<(usize, ParsedTok<'dt>)>> {
+        if self.depth == MAX_DEPTH {
+            return Err(DevTreeError::InvalidParameter(
+                "Device tree is nested too deeply",
+            ));
+        }
+        let mut iter = DevTreeParseIter {
+            offset,
+            fdt: self.fdt,
+        };
+        let tok = iter.next()?.ok_or(DevTreeError::ParseError)?;
+        self.stack[self.depth] = Frame {
+            offset: iter.offset,
+            ..Frame::default()
+        };
+        self.depth += 1;
+        Ok(Some((offset, tok)))
+    }
+}
+
+impl<'dt> FallibleIterator for SortedParseIter<'_, 'dt> {
+    type Error = DevTreeError;
+    type Item = (usize, ParsedTok<'dt>);
+
+    fn next(&mut self) -> Result<Option<Self::Item>> {
+        let fdt = self.fdt;
+        if !self.started {
+            self.started = true;
+            let mut entries = 0usize;
+            let mut iter = fdt.parse_iter();
+            while let Some(tok) = iter.next()? {
+                if let ParsedTok::BeginNode(_) | ParsedTok::Prop(_) = tok {
+                    entries += 1;
+                }
+            }
+            if entries > MAX_SORTED_ENTRIES {
+                return Err(DevTreeError::InvalidParameter(
+                    "Device tree is too large to sort",
+                ));
+            }
+
+            let mut iter = fdt.parse_iter();
+            loop {
+                let offset = iter.offset;
+                match iter.next()? {
+                    Some(ParsedTok::BeginNode(_)) => return self.begin_node(offset),
+                    Some(ParsedTok::Nop) => (),
+                    _ => return Ok(None),
+                }
+            }
+        }
+        let frame = match self.depth.checked_sub(1) {
+            Some(top) => &mut self.stack[top],
+            None => return Ok(None),
+        };
+
+        if !frame.props_done {
+            let props = NodeContents::props(fdt, frame.offset);
+            if let Some((name, off, prop)) =
+                next_sorted(props, |&(name, off, _)| (name, off), frame.last.as_ref())?
+            {
+                frame.last = Some((name, off));
+                return Ok(Some((off, ParsedTok::Prop(prop))));
+            }
+            frame.props_done = true;
+            frame.last = None;
+        }
+
+        let children = NodeContents::children(fdt, frame.offset);
+        if let Some(child) = next_sorted(children, |&child| child, frame.last.as_ref())? {
+            frame.last = Some(child);
+            return self.begin_node(child.1);
+        }
+
+        // Find the node's EndNode token.
+        let mut contents = NodeContents::new(fdt, frame.offset);
+        while contents.next().transpose()?.is_some() {}
+        self.depth -= 1;
+        Ok(Some((
+            contents.iter.offset - size_of::<u32>(),
+            ParsedTok::EndNode,
+        )))
+    }
+}
@@ -0,0 +1,213 @@
+use core::mem::size_of;
+
+use num_traits::FromPrimitive;
+
+use crate::base::DevTree;
+use crate::error::{DevTreeError, Result};
+use crate::modify::FdtWrite;
+use crate::priv_util::{SliceRead, SliceWrite};
+use crate::spec::FdtTok;
+
+/// Builds the strings block of a serialized device tree: the source tree's strings block
+/// followed by any property names which weren't in it.
+///
+/// The structure block is written (and its size learned) before the strings block, so appended
+/// names are stored at the end of the output buffer until then. They're stored in reverse so
+/// the region can grow downwards without moving the names already in it. The byte at offset `i`
+/// of the appended names is at `buf[buf.len() - 1 - i]`.
+pub(crate) struct StringTableBuilder<'dt> {
+    original: &'dt [u8],
+    /// Start of the appended names at the end of the output buffer.
+    tail: usize,
+}
+
+impl<'dt> StringTableBuilder<'dt> {
+    pub(crate) fn new(fdt: &DevTree<'dt>, buf: &[u8]) -> Result<Self> {
+        let start = fdt.off_dt_strings();
+        let original = fdt
+            .buf()
+            .get(start..start + fdt.size_dt_strings() as usize)
+            .ok_or(DevTreeError::ParseError)?;
+        Ok(Self {
+            original,
+            tail: buf.len(),
+        })
+    }
+
+    /// Create a builder for a strings block which starts out empty.
+    pub(crate) fn empty(buf: &[u8]) -> Self {
+        Self {
+            original: &[],
+            tail: buf.len(),
+        }
+    }
+
+    /// Returns the offset in the output buffer at which the appended names start. Nothing else
+    /// may be written at or after it.
+    pub(crate) fn tail(&self) -> usize {
+        self.tail
+    }
+
+    /// Returns the offset of `name` within the strings block, appending it if it isn't already
+    /// present.
+    ///
+    /// `used` is the end of the data already written to `buf`, which appended names may not
+    /// overwrite.
+    pub(crate) fn offset_of(&mut self, buf: &mut [u8], used: usize, name: &[u8]) -> Result<usize> {
+        if let Some(off) = self.find(buf, name) {
+            return Ok(off);
+        }
+
+        let len = buf.len() - self.tail;
+        let new_tail = self
+            .tail
+            .checked_sub(name.len() + 1)
+            .filter(|&tail| tail >= used)
+            .ok_or(DevTreeError::OutputBufferTooSmall {
+                needed: used + len + name.len() + 1,
+                available: buf.len(),
+            })?;
+        buf[new_tail] = 0;
+        for (i, &c) in name.iter().rev().enumerate() {
+            buf[new_tail + 1 + i] = c;
+        }
+        self.tail = new_tail;
+        Ok(self.original.len() + len)
+    }
+
+    /// Returns the offset of `name` within the strings block, if it's present.
+    pub(crate) fn find(&self, buf: &[u8], name: &[u8]) -> Option<usize> {
+        if let Some(off) = find_string(self.original, name) {
+            return Some(off);
+        }
+
+        let appended = &buf[self.tail..];
+        let len = appended.len();
+        let at = |i: usize| appended[len - 1 - i];
+        (0..len)
+            .find(|&start| {
+                let end = start + name.len();
+                end < len
+                    && at(end) == 0
+                    && name.iter().enumerate().all(|(i, &c)| at(start + i) == c)
+            })
+            .map(|start| self.original.len() + start)
+    }
+
+    /// Returns the size of the combined strings block.
+    pub(crate) fn size(&self, buf: &[u8]) -> usize {
+        self.original.len() + buf.len() - self.tail
+    }
+
+    /// Write the combined strings block to `sink`.
+    pub(crate) fn write_to(self, buf: &mut [u8], sink: &mut dyn FdtWrite) -> Result<()> {
+        sink.write_all(self.original)?;
+        buf[self.tail..].reverse();
+        sink.write_all(&buf[self.tail..])
+    }
+
+    /// Write the combined strings block to `buf` at `off`.
+    ///
+    /// Returns its size, the `size_dt_strings` header field.
+    pub(crate) fn finish(self, buf: &mut [u8], off: usize) -> Result<usize> {
+        let size = self.size(buf);
+        if buf[..self.tail].write_slice(off, self.original).is_err() {
+            return Err(DevTreeError::OutputBufferTooSmall {
+                needed: off + size,
+                available: buf.len(),
+            });
+        }
+        let off = off + self.original.len();
+
+        buf[self.tail..].reverse();
+        buf.copy_within(self.tail.., off);
+        Ok(size)
+    }
+}
+
+/// Returns the offset of a null terminated copy of `name` within `strings`.
+///
+/// As with libfdt, the name may be the suffix of a longer string.
+pub(crate) fn find_string(strings: &[u8], name: &[u8]) -> Option<usize> {
+    let len = name.len() + 1;
+    strings
+        .windows(len)
+        .position(|s| s[len - 1] == 0 && &s[..len - 1] == name)
+}
+
+/// Returns the offset of the `nameoff` field of the first property at or after `pos` in a
+/// serialized structure block, along with the offset to continue from.
+fn next_prop(buf: &[u8], mut pos: usize) -> Result<Option<(usize, usize)>> {
+    let align = |pos: usize| (pos + size_of::<u32>() - 1) & !(size_of::<u32>() - 1);
+    loop {
+        let tok = buf.read_be_u32(pos)?;
+        pos += size_of::<u32>();
+        match FdtTok::from_u32(tok) {
+            Some(FdtTok::BeginNode) => {
+                let name = buf.read_bstring0(pos)?;
+                pos = align(pos + name.len() + 1);
+            }
+            Some(FdtTok::Prop) => {
+                let len = buf.read_be_u32(pos)? as usize;
+                let nameoff = pos + size_of::<u32>();
+                return Ok(Some((nameoff, align(nameoff + size_of::<u32>() + len))));
+            }
+            Some(FdtTok::EndNode) | Some(FdtTok::Nop) => (),
+            Some(FdtTok::End) => return Ok(None),
+            None => return Err(DevTreeError::ParseError),
+        }
+    }
+}
+
+/// Rebuild the serialized strings block at `off_dt_strings` with only the names referenced by
+/// the structure block at `off_dt_struct`, dropping duplicates, and rewrite the properties'
+/// name offsets to match.
+///
+/// Returns the new size of the strings block.
+pub(crate) fn collect_garbage(
+    buf: &mut [u8],
+    off_dt_struct: usize,
+    off_dt_strings: usize,
+    size_dt_strings: usize,
+) -> Result<usize> {
+    // Kept names are compacted towards the start of the block as it is read. Properties which
+    // have been rewritten refer to offsets below `w`, which is never past the entry being read,
+    // so they can't be mistaken for a reference to it.
+    let mut r = 0;
+    let mut w = 0;
+    while r < size_dt_strings {
+        let strings = &buf[off_dt_strings..off_dt_strings + size_dt_strings];
+        let len = strings.read_bstring0(r)?.len();
+
+        // Where the entry ends up, once it's known to be referenced.
+        let mut dest = None;
+        let mut pos = off_dt_struct;
+        while let Some((field, next)) = next_prop(buf, pos)? {
+            pos = next;
+            let nameoff = (&*buf).read_be_u32(field)? as usize;
+            if nameoff < r || nameoff > r + len {
+                continue;
+            }
+
+            let dest = match dest {
+                Some(dest) => dest,
+                None => {
+                    // Reuse an earlier copy of the name (or a longer name it is the suffix of)
+                    // if there is one.
+                    let strings = &mut buf[off_dt_strings..off_dt_strings + size_dt_strings];
+                    let found = find_string(&strings[..w], &strings[r..r + len]);
+                    let d = found.unwrap_or_else(|| {
+                        strings.copy_within(r..=r + len, w);
+                        w += len + 1;
+                        w - len - 1
+                    });
+                    dest = Some(d);
+                    d
+                }
+            };
+            buf.write_be_u32(field, (nameoff - r + dest) as u32)?;
+        }
+        r += len + 1;
+    }
+    Ok(w)
+}
@@ -0,0 +1,95 @@
+//! Helpers which compare node names and `device_type` values the way other device tree
+//! implementations do.
+//!
+//! Names are never case folded, but how much of a name must match depends on what is being
+//! compared:
+//!
+//! * [`node_name_eq`] compares whole names, including their unit addresses, byte for byte.
+//! * [`node_name_matches`] follows libfdt's path lookup rules. A pattern without a unit address
+//!   (e.g. `uart`) matches any node of that base name (e.g. `uart@10000000`).
+//! * [`device_type_matches`] follows Open Firmware, where `device_type` values compare without
+//!   regard to case.
+//! * [`stringlist_contains`] follows libfdt, where each string of a list (e.g. `compatible`)
+//!   must match whole.
+//!
+//! # Example
+//!
+//! ```
+//! use fdt_rs::name::*;
+//!
+//! assert!(node_name_matches(b"uart@10000000", "uart"));
+//! assert!(!node_name_matches(b"uart@10000000", "uart@20000000"));
+//! assert_eq!(unit_address(b"uart@10000000"), Some(&b"10000000"[..]));
+//! assert!(device_type_matches(b"Memory\0", "memory"));
+//! assert!(stringlist_contains(b"sifive,test1\0syscon\0", "syscon"));
+//! assert!(!stringlist_contains(b"sifive,test1\0syscon\0", "sifive,test"));
+//! ```
+
+/// Returns the node name with any trailing NUL removed.
+fn trim_nul(name: &[u8]) -> &[u8] {
+    name.strip_suffix(&[0]).unwrap_or(name)
+}
+
+/// Returns the last component of a node name.
+///
+/// Trees older than version 16 store the full path of each node as its name. For any other
+/// name this returns the name unchanged.
+#[must_use]
+pub fn last_component(name: &[u8]) -> &[u8] {
+    let name = trim_nul(name);
+    match name.iter().rposition(|&c| c == b'/') {
+        Some(i) => &name[i + 1..],
+        None => name,
+    }
+}
+
+/// Returns the base name of a node name, the part before any `@`.
+#[must_use]
+pub fn base_name(name: &[u8]) -> &[u8] {
+    let name = last_component(name);
+    name.split(|&c| c == b'@').next().unwrap_or(name)
+}
+
+/// Returns the unit address of a node name, the part after the first `@` (if any).
+#[must_use]
+pub fn unit_address(name: &[u8]) -> Option<&[u8]> {
+    let name = last_component(name);
+    name.iter().position(|&c| c == b'@').map(|i| &name[i + 1..])
+}
+
+/// Returns whether two node names are identical, including their unit addresses.
+#[must_use]
+pub fn node_name_eq(name: &[u8], other: &[u8]) -> bool {
+    last_component(name) == last_component(other)
+}
+
+/// Returns whether the node name matches `pattern`, as libfdt matches path components.
+///
+/// A pattern which includes a unit address must match the name exactly. Otherwise the pattern
+/// matches either the whole name or its base name.
+#[must_use]
+pub fn node_name_matches(name: &[u8], pattern: &str) -> bool {
+    let name = last_component(name);
+    name == pattern.as_bytes() || (!pattern.contains('@') && base_name(name) == pattern.as_bytes())
+}
+
+/// Returns whether a `device_type` property value is `device_type`.
+///
+/// As with Open Firmware, the comparison ignores ASCII case. The value may include its
+/// terminating NUL.
+#[must_use]
+pub fn device_type_matches(value: &[u8], device_type: &str) -> bool {
+    trim_nul(value).eq_ignore_ascii_case(device_type.as_bytes())
+}
+
+/// Returns whether a string list property value (e.g. `compatible`) contains `string`.
+///
+/// As with libfdt's `fdt_stringlist_contains`, `string` must match one of the NUL separated
+/// strings whole, not just a prefix of the list.
+#[must_use]
+pub fn stringlist_contains(value: &[u8], string: &str) -> bool {
+    !value.is_empty()
+        && trim_nul(value)
+            .split(|&c| c == 0)
+            .any(|s| s == string.as_bytes())
+}
@@ -0,0 +1,262 @@
+//! Checks for overlapping address ranges between the resources a device tree describes, a
+//! frequent source of board bring-up bugs.
+//!
+//! [`DevTree::check_overlaps`] hands each [`Overlap`] found to a callback. It finds:
+//!
+//! * Sibling devices on the same bus whose `reg` ranges overlap
+//!   ([`OverlapKind::Siblings`]).
+//! * Devices whose `reg` ranges overlap RAM described by a `memory` node
+//!   ([`OverlapKind::Memory`]).
+//! * Entries of the memory reservation block, and statically placed children of
+//!   `/reserved-memory`, which aren't entirely within RAM
+//!   ([`OverlapKind::ReservationOutsideMemory`]).
+//!
+//! Nodes whose `status` marks them as unavailable are ignored, as alternative descriptions of
+//! the same hardware are commonly left disabled at the same address.
+//!
+//! # Example
+//!
+//! ```
+//! # use fdt_rs::doctest::FDT;
+//! use fdt_rs::base::*;
+//! use fdt_rs::overlap::*;
+//! use fdt_rs::scratch::ScratchArena;
+//!
+//! let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+//! let found = devtree
+//!     .check_overlaps(|overlap| {
+//!         let mut buf = [0u8; 256];
+//!         let path = overlap.node.path(&mut ScratchArena::new(&mut buf))?;
+//!         println!("{:?} {} {:#x?}", overlap.kind, path, overlap.range);
+//!         Ok(())
+//!     })
+//!     .unwrap();
+//! assert_eq!(found, 0);
+//! ```
+use crate::prelude::*;
+
+use crate::base::{DevTree, DevTreeNode};
+use crate::bindings::memory::{for_each_reg, is_memory, RESERVED_MEMORY_PATH};
+use crate::bindings::MemoryRange;
+use crate::error::{DevTreeError, Result};
+
+/// The kind of an [`Overlap`] found by [`DevTree::check_overlaps`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OverlapKind {
+    /// A `reg` range of [`Overlap::node`] overlaps one of [`Overlap::other`], a later sibling.
+    /// Both ranges are bus addresses of their parent.
+    Siblings,
+    /// A `reg` range of the device [`Overlap::node`] overlaps one of the `memory` node
+    /// [`Overlap::other`]. Both ranges are CPU addresses.
+    Memory,
+    /// A reservation isn't entirely within RAM. [`Overlap::node`] is the child of
+    /// `/reserved-memory` with the reservation as a `reg` range, or the root node for an entry
+    /// of the memory reservation block. [`Overlap::other`] is `None`.
+    ReservationOutsideMemory,
+}
+
+/// Overlapping ranges found by [`DevTree::check_overlaps`].
+#[derive(Clone)]
+pub struct Overlap<'a, 'dt: 'a> {
+    pub kind: OverlapKind,
+    /// The node at fault.
+    pub node: DevTreeNode<'a, 'dt>,
+    /// The range of [`Overlap::node`] at fault.
+    pub range: MemoryRange,
+    /// The node whose range [`Overlap::range`] overlaps (if any).
+    pub other: Option<DevTreeNode<'a, 'dt>>,
+    /// The range of [`Overlap::other`] which [`Overlap::range`] overlaps, or an empty range if
+    /// there's no other node.
+    pub other_range: MemoryRange,
+}
+
+/// Translate `range`, a bus address of the parent of `node`, to a CPU address through the
+/// `ranges` properties of its ancestors.
+///
+/// Returns `None` if a bus on the way has no `ranges` property, or if no `ranges` entry of a bus
+/// holds the start of the range.
+fn cpu_range(node: &DevTreeNode, range: MemoryRange) -> Result<Option<MemoryRange>> {
+    let mut address = range.address;
+    let mut bus = node.parent()?.ok_or(DevTreeError::ParseError)?;
+    while let Some(parent) = bus.parent()? {
+        let mut entries = match bus.ranges()? {
+            Some(entries) => entries,
+            None => return Ok(None),
+        };
+        let mut identity = true;
+        let mut mapped = None;
+        while let Some(entry) = entries.next()? {
+            identity = false;
+            let (child, size) = (entry.child()?, entry.size()?);
+            if address >= child && address - child < size {
+                mapped = Some(entry.parent()?.wrapping_add(address - child));
+                break;
+            }
+        }
+        address = match (identity, mapped) {
+            (true, _) => address,
+            (false, Some(mapped)) => mapped,
+            (false, None) => return Ok(None),
+        };
+        bus = parent;
+    }
+    Ok(Some(MemoryRange::new(address, range.size)))
+}
+
+impl<'dt> DevTree<'dt> {
+    /// Check the tree for the overlapping ranges described in the
+    /// [module documentation](crate::overlap), calling `f` with each overlap found. Returns the
+    /// number found.
+    ///
+    /// Ranges of devices behind buses are translated to CPU addresses before comparing them with
+    /// RAM. Ranges which can't be translated (e.g. on a bus without a `ranges` property) are
+    /// skipped. Addresses wider than 64 bits (e.g. PCI addresses) are truncated as with
+    /// [`RegEntry::address`](crate::bindings::RegEntry::address), so overlaps behind such buses
+    /// may be missed.
+    ///
+    /// Errors returned by `f` stop the check and are returned. Each node is compared against
+    /// every other, re-parsing the tree to find their parents, so the check takes time cubic in
+    /// the size of the tree.
+    pub fn check_overlaps<'a, F>(&'a self, mut f: F) -> Result<usize>
+    where
+        F: FnMut(&Overlap<'a, 'dt>) -> Result<()>,
+    {
+        let mut found = 0;
+        let mut report = |kind: OverlapKind,
+                          node: &DevTreeNode<'a, 'dt>,
+                          range: MemoryRange,
+                          other: Option<DevTreeNode<'a, 'dt>>,
+                          other_range: MemoryRange| {
+            found += 1;
+            f(&Overlap {
+                kind,
+                node: node.clone(),
+                range,
+                other,
+                other_range,
+            })
+        };
+        let reserved_memory = self.node_at_path(RESERVED_MEMORY_PATH)?;
+
+        let mut nodes = self.nodes();
+        while let Some(node) = nodes.next()? {
+            if node.find_prop("reg")?.is_none() || !node.is_enabled()? {
+                continue;
+            }
+            let parent = node.parent()?;
+
+            // Later siblings.
+            let mut others = nodes.clone();
+            while let Some(other) = others.next()? {
+                if other.find_prop("reg")?.is_none()
+                    || !other.is_enabled()?
+                    || other.parent()? != parent
+                {
+                    continue;
+                }
+                for_each_reg(&node, |range| {
+                    for_each_reg(&other, |other_range| match range.overlaps(&other_range) {
+                        true => report(
+                            OverlapKind::Siblings,
+                            &node,
+                            range,
+                            Some(other.clone()),
+                            other_range,
+                        ),
+                        false => Ok(()),
+                    })
+                })?;
+            }
+
+            if is_memory(&node)? {
+                continue;
+            }
+            let is_reservation = reserved_memory.is_some() && parent == reserved_memory;
+            for_each_reg(&node, |range| {
+                let range = match cpu_range(&node, range)? {
+                    Some(range) => range,
+                    None => return Ok(()),
+                };
+                if is_reservation {
+                    if !self.within_memory(range)? {
+                        report(
+                            OverlapKind::ReservationOutsideMemory,
+                            &node,
+                            range,
+                            None,
+                            MemoryRange::default(),
+                        )?;
+                    }
+                    return Ok(());
+                }
+                self.for_each_memory_range(|memory, memory_range| {
+                    match range.overlaps(&memory_range) {
+                        true => report(
+                            OverlapKind::Memory,
+                            &node,
+                            range,
+                            Some(memory.clone()),
+                            memory_range,
+                        ),
+                        false => Ok(()),
+                    }
+                })
+            })?;
+        }
+
+        if let Some(root) = self.root()? {
+            let mut entries = self.mem_reserve_entries();
+            while let Some(entry) = entries.next()? {
+                let range = MemoryRange::new(entry.address()?, entry.size()?);
+                if !self.within_memory(range)? {
+                    report(
+                        OverlapKind::ReservationOutsideMemory,
+                        &root,
+                        range,
+                        None,
+                        MemoryRange::default(),
+                    )?;
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Call `f` with each available `memory` node and each of its ranges, as CPU addresses.
+    fn for_each_memory_range<'a>(
+        &'a self,
+        mut f: impl FnMut(&DevTreeNode<'a, 'dt>, MemoryRange) -> Result<()>,
+    ) -> Result<()> {
+        let mut nodes = self.nodes();
+        while let Some(node) = nodes.next()? {
+            if is_memory(&node)? && node.is_enabled()? {
+                for_each_reg(&node, |range| match cpu_range(&node, range)? {
+                    Some(range) => f(&node, range),
+                    None => Ok(()),
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether every byte of `range` is within a range of a `memory` node.
+    fn within_memory(&self, range: MemoryRange) -> Result<bool> {
+        // Advance through the memory ranges holding the next byte, which may be listed in any
+        // order, until none does or the whole range is covered.
+        let mut next = range.address;
+        while next < range.end() {
+            let mut advanced = false;
+            self.for_each_memory_range(|_, memory| {
+                if memory.address <= next && next < memory.end() {
+                    next = memory.end();
+                    advanced = true;
+                }
+                Ok(())
+            })?;
+            if !advanced {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
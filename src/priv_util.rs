@@ -11,7 +11,6 @@ pub(crate) type SliceReadResult<T> = Result<T, SliceReadError>;
 
 pub(crate) trait SliceRead<'a> {
     unsafe fn unsafe_read_be_u32(&self, pos: usize) -> SliceReadResult<u32>;
-    unsafe fn unsafe_read_be_u64(&self, pos: usize) -> SliceReadResult<u64>;
     fn read_be_u32(&self, pos: usize) -> SliceReadResult<u32>;
     fn read_be_u64(&self, pos: usize) -> SliceReadResult<u64>;
     fn read_bstring0(&self, pos: usize) -> SliceReadResult<&'a [u8]>;
@@ -49,10 +48,6 @@ impl<'a> SliceRead<'a> for &'a [u8] {
         unchecked_be_read!(self, u32, pos)
     }
 
-    unsafe fn unsafe_read_be_u64(&self, pos: usize) -> SliceReadResult<u64> {
-        unchecked_be_read!(self, u64, pos)
-    }
-
     fn read_be_u32(&self, pos: usize) -> SliceReadResult<u32> {
         be_read!(self, u32, pos)
     }
@@ -83,3 +78,38 @@ impl<'a> SliceRead<'a> for &'a [u8] {
         Err(SliceReadError::UnexpectedEndOfInput)
     }
 }
+
+#[derive(Debug, Copy, Clone)]
+pub enum SliceWriteError {
+    /// The write needed a buffer of the first size, but the buffer was of the second size.
+    UnexpectedEndOfOutput(usize, usize),
+}
+
+pub(crate) type SliceWriteResult = Result<(), SliceWriteError>;
+
+pub(crate) trait SliceWrite {
+    fn write_be_u32(&mut self, pos: usize, val: u32) -> SliceWriteResult;
+    fn write_be_u64(&mut self, pos: usize, val: u64) -> SliceWriteResult;
+    fn write_slice(&mut self, pos: usize, data: &[u8]) -> SliceWriteResult;
+}
+
+impl SliceWrite for [u8] {
+    fn write_be_u32(&mut self, pos: usize, val: u32) -> SliceWriteResult {
+        self.write_slice(pos, &val.to_be_bytes())
+    }
+
+    fn write_be_u64(&mut self, pos: usize, val: u64) -> SliceWriteResult {
+        self.write_slice(pos, &val.to_be_bytes())
+    }
+
+    fn write_slice(&mut self, pos: usize, data: &[u8]) -> SliceWriteResult {
+        let len = self.len();
+        self.get_mut(pos..pos + data.len())
+            .ok_or(SliceWriteError::UnexpectedEndOfOutput(
+                pos + data.len(),
+                len,
+            ))?
+            .copy_from_slice(data);
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+//! Caller provided scratch memory for helpers which need working space.
+//!
+//! Helpers which need memory beyond the device tree itself (for example to build a node's path)
+//! take a [`ScratchArena`] rather than allocating. The caller decides where the memory comes
+//! from, whether a static buffer, the stack, or a heap allocation, and how much of it to give.
+//!
+//! An arena hands out disjoint allocations for as long as the underlying buffer lives. There is
+//! no way to free them individually; create a new arena over the buffer once the allocations
+//! are no longer needed.
+//!
+//! # Example
+//!
+//! ```
+//! # use fdt_rs::doctest::FDT;
+//! use fdt_rs::prelude::*;
+//! use fdt_rs::base::*;
+//! use fdt_rs::scratch::ScratchArena;
+//!
+//! let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+//! let node = devtree
+//!     .nodes()
+//!     .find(|n| Ok(n.name()? == "core0"))
+//!     .unwrap()
+//!     .unwrap();
+//!
+//! let mut buf = [0u8; 256];
+//! let mut scratch = ScratchArena::new(&mut buf);
+//! assert_eq!(node.path(&mut scratch).unwrap(), "/cpus/cpu-map/cluster0/core0");
+//! ```
+use core::mem::{align_of, size_of, take};
+use core::ptr;
+use core::slice;
+
+use crate::error::{DevTreeError, Result};
+
+/// A bump allocator over a caller provided buffer.
+#[derive(Debug)]
+pub struct ScratchArena<'s> {
+    rest: &'s mut [u8],
+}
+
+impl<'s> ScratchArena<'s> {
+    #[must_use]
+    pub fn new(buf: &'s mut [u8]) -> Self {
+        Self { rest: buf }
+    }
+
+    /// Returns the number of bytes not yet allocated.
+    ///
+    /// Allocations of types with an alignment greater than one may not be able to use all of
+    /// them.
+    #[must_use]
+    pub fn remaining(&self) -> usize {
+        self.rest.len()
+    }
+
+    /// Allocate `len` zeroed bytes.
+    pub fn alloc_bytes(&mut self, len: usize) -> Result<&'s mut [u8]> {
+        self.alloc_slice(len, 0)
+    }
+
+    /// Allocate a slice of `len` copies of `init`.
+    ///
+    /// Returns [`DevTreeError::NotEnoughMemory`] if the arena doesn't have room for it.
+    pub fn alloc_slice<T: Copy>(&mut self, len: usize, init: T) -> Result<&'s mut [T]> {
+        let rest = take(&mut self.rest);
+        let pad = rest.as_ptr().align_offset(align_of::<T>());
+        let size = len
+            .checked_mul(size_of::<T>())
+            .and_then(|size| size.checked_add(pad))
+            .filter(|&size| size <= rest.len());
+        let size = match size {
+            Some(size) => size,
+            None => {
+                self.rest = rest;
+                return Err(DevTreeError::NotEnoughMemory);
+            }
+        };
+
+        let (block, rest) = rest.split_at_mut(size);
+        self.rest = rest;
+        let ptr = block[pad..].as_mut_ptr() as *mut T;
+        // Unsafe okay.
+        // - The block is aligned for T (by `pad`) and large enough for `len` of them.
+        // - The block was split from the buffer, so nothing else can borrow it for 's.
+        // - Every element is initialized before the slice is created. T is Copy, so the bytes
+        //   being overwritten don't need to be dropped.
+        unsafe {
+            for i in 0..len {
+                ptr::write(ptr.add(i), init);
+            }
+            Ok(slice::from_raw_parts_mut(ptr, len))
+        }
+    }
+}
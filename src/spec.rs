@@ -1,11 +1,23 @@
 //! Definitions of structs and enums from the device tree specification.
-use endian_type::types::{u32_be, u64_be};
+// num-derive 0.3 expands FromPrimitive into an anonymous const.
+#![allow(non_local_definitions)]
+use core::mem::size_of;
+use core::ptr::{read_unaligned, write_unaligned};
+
 use num_derive::FromPrimitive;
 
+use crate::error::{DevTreeError, Result};
+
+pub use endian_type::types::{u32_be, u64_be};
+
 /// Magic number used to denote the beginning of a device tree (as a native machine number).
 pub const FDT_MAGIC: u32 = 0xd00d_feed;
 /// Maximum length of a device tree node name (including null byte)
 pub const MAX_NODE_NAME_LEN: usize = 31;
+/// Version of the device tree format written by this crate.
+pub const FDT_VERSION: u32 = 17;
+/// Oldest version of the device tree format which a tree of [`FDT_VERSION`] is compatible with.
+pub const FDT_LAST_COMP_VERSION: u32 = 16;
 
 /// Definition of the parsed phandle as a native machine number
 pub type Phandle = u32;
@@ -21,6 +33,10 @@ pub enum FdtTok {
 }
 
 /// The `fdt_header` (Flattened Device Tree Header) as described by the specification
+///
+/// The layout matches the header at the start of a device tree, so it may be exchanged directly
+/// (e.g. over memory shared between a hypervisor and firmware).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(C)]
 pub struct fdt_header {
     pub magic: u32_be,
@@ -44,6 +60,10 @@ pub struct fdt_prop_header {
     pub nameoff: u32_be,
 }
 
+/// The `fdt_reserve_entry` (Memory Reservation Block entry) as described by the specification
+///
+/// As with [`fdt_header`], the layout matches the entries of a device tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(C)]
 pub struct fdt_reserve_entry {
     /// Starting address of the reserved memory region
@@ -51,3 +71,63 @@ pub struct fdt_reserve_entry {
     /// Size of the reserved memory region
     pub size: u64_be,
 }
+
+const_assert_eq!(size_of::<fdt_header>(), 40);
+const_assert_eq!(size_of::<fdt_reserve_entry>(), 16);
+
+impl fdt_header {
+    /// The size of the header in bytes.
+    pub const SIZE: usize = size_of::<Self>();
+
+    /// Read a header from the start of `buf`, which needn't be aligned.
+    ///
+    /// Returns [`DevTreeError::ParseError`] if `buf` is too short. The fields aren't checked.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        let buf = buf.get(..Self::SIZE).ok_or(DevTreeError::ParseError)?;
+        // Unsafe okay, the header fits within the buffer and read_unaligned doesn't require
+        // alignment.
+        unsafe { Ok(read_unaligned(buf.as_ptr().cast())) }
+    }
+
+    /// Returns the header's bytes, as laid out in a device tree.
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        // Unsafe okay, the header fits within the array and write_unaligned doesn't require
+        // alignment.
+        unsafe { write_unaligned(bytes.as_mut_ptr().cast(), *self) };
+        bytes
+    }
+}
+
+impl fdt_reserve_entry {
+    /// The size of an entry in bytes.
+    pub const SIZE: usize = size_of::<Self>();
+
+    /// Create an entry reserving the `size` bytes at `address`.
+    #[must_use]
+    pub fn new(address: u64, size: u64) -> Self {
+        Self {
+            address: address.into(),
+            size: size.into(),
+        }
+    }
+
+    /// Read an entry from the start of `buf`, which needn't be aligned.
+    ///
+    /// Returns [`DevTreeError::ParseError`] if `buf` is too short.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        let buf = buf.get(..Self::SIZE).ok_or(DevTreeError::ParseError)?;
+        // Unsafe okay, as for fdt_header::from_bytes.
+        unsafe { Ok(read_unaligned(buf.as_ptr().cast())) }
+    }
+
+    /// Returns the entry's bytes, as laid out in a device tree.
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        // Unsafe okay, as for fdt_header::to_bytes.
+        unsafe { write_unaligned(bytes.as_mut_ptr().cast(), *self) };
+        bytes
+    }
+}
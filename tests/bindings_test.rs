@@ -0,0 +1,1055 @@
+extern crate fdt_rs;
+
+use fdt_rs::base::{DevTree, DevTreeNode};
+use fdt_rs::bindings::{
+    Coreboot, DmaRange, DmaWindow, MemoryAllocator, MemoryRange, Optee, OpteeMethod, PciAddress,
+    PciRange, PciReg, PciSpace,
+};
+use fdt_rs::compliance::{ComplianceSummary, Rule, Severity};
+use fdt_rs::error::DevTreeError;
+use fdt_rs::modify::{
+    DevTreeModifier, FdtBuilder, MemReservation, MemReserveEdits, MetadataNode, MetadataProp,
+    MetadataValue, ModifyOptions, ModifyTokenResponse, PropCell, Serializer,
+};
+use fdt_rs::overlap::OverlapKind;
+use fdt_rs::prelude::*;
+use fdt_rs::scratch::ScratchArena;
+
+#[repr(align(4))]
+struct _Wrapper<T>(T);
+pub const FDT: &[u8] = &_Wrapper(*include_bytes!("../tests/bindings.dtb")).0;
+
+fn find_node<'a, 'dt>(fdt: &'a DevTree<'dt>, name: &str) -> DevTreeNode<'a, 'dt> {
+    fdt.nodes()
+        .find(|n| Ok(n.name()? == name))
+        .unwrap()
+        .expect("Node not found.")
+}
+
+#[test]
+fn pinctrl_states() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let serial = find_node(&fdt, "serial@2000");
+
+    let mut states = serial.pinctrl_states();
+    let default = states.next().unwrap().unwrap();
+    assert_eq!(default.id(), 0);
+    assert_eq!(default.name(), Some("default"));
+    assert_eq!(default.num_configs(), 2);
+    let configs: Vec<&str> = default
+        .configs()
+        .map(|n| n.name())
+        .iterator()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(configs, ["uart0-default", "uart0-cts"]);
+
+    let sleep = states.next().unwrap().unwrap();
+    assert_eq!(sleep.id(), 1);
+    assert_eq!(sleep.name(), Some("sleep"));
+    let config = sleep.configs().next().unwrap().unwrap();
+    assert_eq!(config.name().unwrap(), "uart0-sleep");
+    assert!(config
+        .props()
+        .any(|p| Ok(p.name()? == "bias-pull-down"))
+        .unwrap());
+
+    assert!(states.next().unwrap().is_none());
+}
+
+#[test]
+fn pinctrl_state_by_name() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let serial = find_node(&fdt, "serial@2000");
+
+    assert_eq!(serial.pinctrl_state("sleep").unwrap().unwrap().id(), 1);
+    assert!(serial.pinctrl_state("idle").unwrap().is_none());
+
+    let pinctrl = find_node(&fdt, "pinctrl@1000");
+    assert!(pinctrl.pinctrl_states().next().unwrap().is_none());
+}
+
+#[test]
+fn power_domains() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let gpu = find_node(&fdt, "gpu@5000");
+
+    let mut domains = gpu.power_domains().unwrap();
+    let core = domains.next().unwrap().unwrap();
+    assert_eq!(core.provider().name().unwrap(), "power-controller@3000");
+    assert_eq!(core.num_cells(), 1);
+    assert_eq!(core.cell(0).unwrap(), 3);
+
+    let always_on = domains.next().unwrap().unwrap();
+    assert_eq!(
+        always_on.provider().name().unwrap(),
+        "power-controller@3100"
+    );
+    assert_eq!(always_on.num_cells(), 0);
+    assert!(domains.next().unwrap().is_none());
+
+    let named = gpu.power_domain("always-on").unwrap().unwrap();
+    assert_eq!(named.phandle(), always_on.phandle());
+    assert!(gpu.power_domain("mem").unwrap().is_none());
+
+    let serial = find_node(&fdt, "serial@2000");
+    assert!(serial.power_domains().unwrap().next().unwrap().is_none());
+}
+
+#[test]
+fn resets() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let gpu = find_node(&fdt, "gpu@5000");
+
+    let cells: Vec<(u32, u32)> = gpu
+        .resets()
+        .unwrap()
+        .map(|r| {
+            assert_eq!(r.provider().name()?, "reset-controller@4000");
+            Ok((r.cell(0)?, r.cell(1)?))
+        })
+        .iterator()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(cells, [(7, 1), (8, 0)]);
+
+    let bus = gpu.reset("bus").unwrap().unwrap();
+    assert_eq!(bus.cell(0).unwrap(), 8);
+    bus.cell(2).expect_err("Expected failure.");
+}
+
+#[test]
+fn provider_refs() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let gpu = find_node(&fdt, "gpu@5000");
+
+    let specifiers = |prop: &str, cells: &'static str| -> Vec<(String, Vec<u32>)> {
+        gpu.provider_refs(prop, cells)
+            .unwrap()
+            .map(|r| {
+                let cells = (0..r.num_cells())
+                    .map(|i| r.cell(i))
+                    .collect::<Result<_, _>>()?;
+                Ok((r.provider().name()?.to_string(), cells))
+            })
+            .iterator()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    };
+    assert_eq!(
+        specifiers("power-domains", "#power-domain-cells"),
+        [
+            ("power-controller@3000".to_string(), vec![3]),
+            ("power-controller@3100".to_string(), vec![]),
+        ]
+    );
+    assert_eq!(
+        specifiers("resets", "#reset-cells"),
+        [
+            ("reset-controller@4000".to_string(), vec![7, 1]),
+            ("reset-controller@4000".to_string(), vec![8, 0]),
+        ]
+    );
+    assert!(specifiers("clocks", "#clock-cells").is_empty());
+
+    let bus = gpu
+        .provider_ref_by_name("resets", "#reset-cells", "reset-names", "bus")
+        .unwrap()
+        .unwrap();
+    assert_eq!(bus.cell(0).unwrap(), 8);
+    assert!(gpu
+        .provider_ref_by_name("resets", "#reset-cells", "clock-names", "bus")
+        .unwrap()
+        .is_none());
+
+    // The providers don't size clock specifiers.
+    let mut wrong = gpu.provider_refs("resets", "#clock-cells").unwrap();
+    assert_eq!(wrong.next().err(), Some(DevTreeError::ParseError));
+}
+
+#[test]
+fn provider_ref_placeholders() {
+    let cells =
+        |cells: &[u32]| -> Vec<PropCell> { cells.iter().map(|&c| PropCell::U32(c)).collect() };
+    let mut buf = [0u32; 256];
+    let out = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 1024) };
+    let mut builder = FdtBuilder::new(out);
+    builder.begin_node("").unwrap();
+    builder.begin_node("clock-controller").unwrap();
+    builder.prop_u32("#clock-cells", 1).unwrap();
+    builder.prop_u32("phandle", 1).unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node("huge-controller").unwrap();
+    builder.prop_u32("#clock-cells", u32::MAX).unwrap();
+    builder.prop_u32("phandle", 2).unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node("device").unwrap();
+    builder
+        .prop_cells("clocks", &cells(&[1, 5, 0, 1, 6]))
+        .unwrap();
+    builder
+        .prop_bytes("clock-names", b"first\0missing\0second\0")
+        .unwrap();
+    builder.prop_cells("huge-clocks", &cells(&[2, 0])).unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let fdt = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    let device = find_node(&fdt, "device");
+
+    // The placeholder is skipped, but still has a name.
+    let clocks: Vec<u32> = device
+        .provider_refs("clocks", "#clock-cells")
+        .unwrap()
+        .map(|r| r.cell(0))
+        .iterator()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(clocks, [5, 6]);
+    let by_name = |name| {
+        device
+            .provider_ref_by_name("clocks", "#clock-cells", "clock-names", name)
+            .unwrap()
+            .map(|r| r.cell(0).unwrap())
+    };
+    assert_eq!(by_name("first"), Some(5));
+    assert_eq!(by_name("missing"), None);
+    assert_eq!(by_name("second"), Some(6));
+
+    // A specifier size too large for the address space is an error.
+    let mut huge = device.provider_refs("huge-clocks", "#clock-cells").unwrap();
+    assert_eq!(huge.next().err(), Some(DevTreeError::ParseError));
+}
+
+#[test]
+fn status() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    assert!(find_node(&fdt, "memory@7f000000").is_enabled().unwrap());
+    assert!(!find_node(&fdt, "memory@90000000").is_enabled().unwrap());
+
+    let names = |nodes: &mut dyn FallibleIterator<Item = DevTreeNode, Error = DevTreeError>| {
+        let mut names = Vec::new();
+        while let Some(node) = nodes.next().unwrap() {
+            names.push(node.name().unwrap().to_string());
+        }
+        names
+    };
+    let enabled = names(&mut fdt.enabled_nodes());
+    let mut all = names(&mut fdt.nodes());
+    all.retain(|name| name != "memory@90000000");
+    assert_eq!(enabled, all);
+}
+
+#[test]
+fn aliases() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    assert!(fdt.alias("serial0").unwrap().is_none());
+    assert!(fdt.resolve_path("serial0").unwrap().is_none());
+    assert!(fdt.stdout().unwrap().is_none());
+
+    let alias_props = [
+        MetadataProp::new("serial0", MetadataValue::Str("/serial@2000")),
+        MetadataProp::new("pinctrl", MetadataValue::Str("/pinctrl@1000")),
+        MetadataProp::new("relative", MetadataValue::Str("serial0")),
+    ];
+    let aliases = MetadataNode::new("aliases", &alias_props);
+    let chosen_props = [MetadataProp::new(
+        "stdout-path",
+        MetadataValue::Str("serial0:115200n8"),
+    )];
+    let chosen = MetadataNode::new("chosen", &chosen_props);
+    let mut mem = [0u8; 256];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 2).unwrap();
+    modifier
+        .add_node("/", &aliases)
+        .unwrap()
+        .add_node("/", &chosen)
+        .unwrap();
+    let mut buf = vec![0u32; FDT.len() / 4 + 64];
+    let out =
+        unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4) };
+    let size = modifier.apply(&fdt, out).unwrap();
+    let fdt = unsafe { DevTree::new(&out[..size]) }.unwrap();
+
+    assert_eq!(fdt.alias("serial0").unwrap(), Some("/serial@2000"));
+    let serial = fdt.resolve_path("serial0").unwrap().unwrap();
+    assert_eq!(serial.name().unwrap(), "serial@2000");
+    let cts = fdt.resolve_path("pinctrl/uart0-cts").unwrap().unwrap();
+    assert_eq!(cts.name().unwrap(), "uart0-cts");
+    assert!(fdt.resolve_path("pinctrl/missing").unwrap().is_none());
+    assert!(fdt.resolve_path("serial1").unwrap().is_none());
+    assert_eq!(
+        fdt.resolve_path("/serial@2000")
+            .unwrap()
+            .unwrap()
+            .name()
+            .unwrap(),
+        "serial@2000"
+    );
+    assert!(matches!(
+        fdt.resolve_path("relative"),
+        Err(DevTreeError::InvalidParameter(_))
+    ));
+
+    let (stdout, options) = fdt.stdout().unwrap().unwrap();
+    assert_eq!(stdout.name().unwrap(), "serial@2000");
+    assert_eq!(options, Some("115200n8"));
+}
+
+#[test]
+fn dma_coherence() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    assert!(find_node(&fdt, "dma-controller@10000000")
+        .is_dma_coherent()
+        .unwrap());
+    assert!(!find_node(&fdt, "device@10100000")
+        .is_dma_coherent()
+        .unwrap());
+    assert!(find_node(&fdt, "device@10200000")
+        .is_dma_coherent()
+        .unwrap());
+    assert!(!find_node(&fdt, "serial@2000").is_dma_coherent().unwrap());
+}
+
+#[test]
+fn dma_ranges() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let bus = find_node(&fdt, "dma-bus@10000000");
+    let mut ranges = bus.dma_ranges().unwrap().unwrap();
+    assert_eq!(
+        ranges.next().unwrap(),
+        Some(DmaRange {
+            child_addr: 0,
+            parent_addr: 0x8000_0000,
+            size: 0x4000_0000,
+        })
+    );
+    assert_eq!(ranges.next().unwrap(), None);
+
+    let identity = find_node(&fdt, "identity-bus@10200000");
+    assert_eq!(identity.dma_ranges().unwrap().unwrap().count().unwrap(), 0);
+    assert!(find_node(&fdt, "serial@2000")
+        .dma_ranges()
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn dma_window() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let window = |name| find_node(&fdt, name).dma_window().unwrap().unwrap();
+
+    assert_eq!(
+        window("device@10100000"),
+        DmaWindow {
+            start: 0x9000_0000,
+            end: 0x9fff_ffff,
+        }
+    );
+    assert_eq!(
+        window("device@10200000"),
+        DmaWindow {
+            start: 0x8000_0000,
+            end: 0xbfff_ffff,
+        }
+    );
+    assert_eq!(
+        window("serial@2000"),
+        DmaWindow {
+            start: 0,
+            end: u64::MAX,
+        }
+    );
+}
+
+#[test]
+fn reg() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut reg = find_node(&fdt, "serial@2000").reg().unwrap().unwrap();
+    let entry = reg.next().unwrap().unwrap();
+    assert_eq!(entry.address().unwrap(), 0x2000);
+    assert_eq!(entry.size().unwrap(), 0x100);
+    assert_eq!(entry.raw(), &[0, 0, 0x20, 0, 0, 0, 1, 0]);
+    assert!(reg.next().unwrap().is_none());
+
+    // Sized by the parent's #address-cells and #size-cells.
+    let reg: Vec<_> = find_node(&fdt, "timer@0,1000")
+        .reg()
+        .unwrap()
+        .unwrap()
+        .iterator()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (entry.address().unwrap(), entry.size().unwrap())
+        })
+        .collect();
+    assert_eq!(reg, [(0x1000, 0x100), (0x1_0000_2000, 0x40)]);
+
+    assert!(find_node(&fdt, "dma-bus@10000000").reg().unwrap().is_none());
+}
+
+#[test]
+fn reg_pairs() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let pairs = |name: &str| -> Vec<(u64, u64)> {
+        find_node(&fdt, name)
+            .reg_pairs()
+            .unwrap()
+            .unwrap()
+            .iterator()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    };
+    assert_eq!(pairs("serial@2000"), [(0x2000, 0x100)]);
+    // Sized by the parent's cells, not the root's.
+    assert_eq!(
+        pairs("timer@0,1000"),
+        [(0x1000, 0x100), (0x1_0000_2000, 0x40)]
+    );
+    assert!(find_node(&fdt, "dma-bus@10000000")
+        .reg_pairs()
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn pci() {
+    let cells =
+        |cells: &[u32]| -> Vec<PropCell> { cells.iter().map(|&c| PropCell::U32(c)).collect() };
+    let mut buf = [0u32; 256];
+    let out = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 1024) };
+    let mut builder = FdtBuilder::new(out);
+    builder.begin_node("").unwrap();
+    builder.prop_u32("#address-cells", 2).unwrap();
+    builder.prop_u32("#size-cells", 2).unwrap();
+    builder.begin_node("pcie@30000000").unwrap();
+    builder.prop_u32("#address-cells", 3).unwrap();
+    builder.prop_u32("#size-cells", 2).unwrap();
+    // 64 KB of I/O, and 4 GB of prefetchable 64-bit memory.
+    let io = [0x0100_0000, 0, 0, 0, 0x3000_0000, 0, 0x1_0000];
+    let memory = [0x4300_0000, 1, 0, 1, 0, 1, 0];
+    let ranges = cells(&[io, memory].concat());
+    builder.prop_cells("ranges", &ranges).unwrap();
+    builder.begin_node("ethernet@2,3").unwrap();
+    // The config space header of 01:02.3, and its BAR 0.
+    let reg = cells(&[0x0001_1300, 0, 0, 0, 0, 0x8201_1310, 0, 0, 0, 0x1000]);
+    builder.prop_cells("reg", &reg).unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let fdt = unsafe { DevTree::new(&out[..size]) }.unwrap();
+
+    let bridge = find_node(&fdt, "pcie@30000000");
+    let ranges: Vec<PciRange> = bridge
+        .pci_ranges()
+        .unwrap()
+        .unwrap()
+        .iterator()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        ranges,
+        [
+            PciRange {
+                child: PciAddress::new(0x0100_0000, 0),
+                parent: 0x3000_0000,
+                size: 0x1_0000,
+            },
+            PciRange {
+                child: PciAddress::new(0x4300_0000, 0x1_0000_0000),
+                parent: 0x1_0000_0000,
+                size: 0x1_0000_0000,
+            },
+        ]
+    );
+    assert_eq!(ranges[0].child.space(), PciSpace::Io);
+    assert_eq!(ranges[1].child.space(), PciSpace::Memory64);
+    assert!(ranges[1].child.is_prefetchable());
+    assert!(ranges[1].child.is_relocatable());
+
+    let reg: Vec<PciReg> = find_node(&fdt, "ethernet@2,3")
+        .pci_reg()
+        .unwrap()
+        .unwrap()
+        .iterator()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(reg.len(), 2);
+    let (config, bar) = (reg[0].address, reg[1].address);
+    assert_eq!(config.space(), PciSpace::Config);
+    assert_eq!(
+        (config.bus(), config.device(), config.function()),
+        (1, 2, 3)
+    );
+    assert_eq!(bar.space(), PciSpace::Memory32);
+    assert!(!bar.is_relocatable());
+    assert_eq!((bar.bus(), bar.device(), bar.function()), (1, 2, 3));
+    assert_eq!(bar.register(), 0x10);
+    assert_eq!(reg[1].size, 0x1000);
+
+    // Neither has PCI addresses.
+    assert_eq!(bridge.pci_reg().err(), Some(DevTreeError::ParseError));
+    let root = fdt.root().unwrap().unwrap();
+    assert_eq!(root.pci_ranges().err(), Some(DevTreeError::ParseError));
+}
+
+#[test]
+fn interrupt_map() {
+    let cells =
+        |cells: &[u32]| -> Vec<PropCell> { cells.iter().map(|&c| PropCell::U32(c)).collect() };
+    let mut buf = [0u32; 512];
+    let out = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 2048) };
+    let mut builder = FdtBuilder::new(out);
+    builder.begin_node("").unwrap();
+    builder.prop_u32("#address-cells", 2).unwrap();
+    builder.prop_u32("#size-cells", 2).unwrap();
+    builder.begin_node("interrupt-controller@0").unwrap();
+    builder.prop_bytes("interrupt-controller", &[]).unwrap();
+    builder.prop_u32("#interrupt-cells", 1).unwrap();
+    builder.prop_u32("#address-cells", 0).unwrap();
+    builder.prop_u32("phandle", 1).unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node("serial@1000").unwrap();
+    builder.prop_u32("interrupt-parent", 1).unwrap();
+    builder.prop_u32("interrupts", 5).unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node("pcie@30000000").unwrap();
+    builder.prop_u32("#address-cells", 3).unwrap();
+    builder.prop_u32("#size-cells", 2).unwrap();
+    builder.prop_u32("#interrupt-cells", 1).unwrap();
+    builder
+        .prop_cells("interrupt-map-mask", &cells(&[0x1800, 0, 0, 7]))
+        .unwrap();
+    // Pin INTA-INTD of slots 0 and 1, swizzled onto interrupts 0x20-0x23.
+    let mut map = Vec::new();
+    for slot in 0..2 {
+        for pin in 1..=4 {
+            map.extend(&[slot << 11, 0, 0, pin, 1, 0x20 + (slot + pin - 1) % 4]);
+        }
+    }
+    builder.prop_cells("interrupt-map", &cells(&map)).unwrap();
+    for &(name, reg, pin) in &[
+        ("wifi@0,0", 0, 1),
+        ("ethernet@1,0", 0x800, 2),
+        ("nvme@3,0", 0x1800, 1),
+    ] {
+        builder.begin_node(name).unwrap();
+        builder
+            .prop_cells("reg", &cells(&[reg, 0, 0, 0, 0]))
+            .unwrap();
+        builder.prop_u32("interrupts", pin).unwrap();
+        builder.end_node().unwrap();
+    }
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let fdt = unsafe { DevTree::new(&out[..size]) }.unwrap();
+
+    let resolve = |name: &str| {
+        let node = find_node(&fdt, name);
+        let irq = node.interrupts().unwrap().unwrap().next().unwrap().unwrap();
+        irq.resolve().map(|irq| {
+            assert_eq!(irq.controller().name().unwrap(), "interrupt-controller@0");
+            assert_eq!(irq.num_cells(), 1);
+            irq.cell(0).unwrap()
+        })
+    };
+    // Interrupts for a controller are already resolved.
+    assert_eq!(resolve("serial@1000"), Ok(5));
+    assert_eq!(resolve("wifi@0,0"), Ok(0x20));
+    assert_eq!(resolve("ethernet@1,0"), Ok(0x22));
+    // No entry for slot 3.
+    assert_eq!(resolve("nvme@3,0"), Err(DevTreeError::ParseError));
+}
+
+#[test]
+fn interrupt_parent_cycle() {
+    let mut buf = [0u32; 128];
+    let out = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 512) };
+    let mut builder = FdtBuilder::new(out);
+    builder.begin_node("").unwrap();
+    // A node which is its own interrupt parent, but no interrupt controller.
+    builder.begin_node("serial@1000").unwrap();
+    builder.prop_u32("phandle", 1).unwrap();
+    builder.prop_u32("interrupt-parent", 1).unwrap();
+    builder.prop_u32("interrupts", 5).unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let fdt = unsafe { DevTree::new(&out[..size]) }.unwrap();
+
+    let serial = find_node(&fdt, "serial@1000");
+    assert!(matches!(
+        serial.interrupt_parent(),
+        Err(DevTreeError::ParseError)
+    ));
+    assert!(matches!(serial.interrupts(), Err(DevTreeError::ParseError)));
+}
+
+#[test]
+fn ranges() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let ranges: Vec<_> = find_node(&fdt, "soc@20000000")
+        .ranges()
+        .unwrap()
+        .unwrap()
+        .iterator()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (
+                entry.child().unwrap(),
+                entry.parent().unwrap(),
+                entry.size().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        ranges,
+        [
+            (0, 0x2000_0000, 0x10_0000),
+            (0x1_0000_0000, 0x3000_0000, 0x1_0000)
+        ]
+    );
+
+    let identity = find_node(&fdt, "dma-bus@10000000");
+    assert_eq!(identity.ranges().unwrap().unwrap().count().unwrap(), 0);
+}
+
+#[test]
+fn wide_cells() {
+    let cells =
+        |cells: &[u32]| -> Vec<PropCell> { cells.iter().map(|&c| PropCell::U32(c)).collect() };
+    let mut buf = [0u32; 256];
+    let out = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 1024) };
+    let mut builder = FdtBuilder::new(out);
+    builder.begin_node("").unwrap();
+    builder.prop_u32("#address-cells", 2).unwrap();
+    builder.prop_u32("#size-cells", 1).unwrap();
+    builder.begin_node("syscon@1000").unwrap();
+    builder.prop_u32("#address-cells", 3).unwrap();
+    builder.prop_u32("#size-cells", 1).unwrap();
+    builder
+        .prop_cells("ranges", &cells(&[0x2, 0, 0x100, 0, 0x1000, 0x10]))
+        .unwrap();
+    builder.begin_node("regs@2,0,100").unwrap();
+    builder
+        .prop_cells("reg", &cells(&[0x2, 0, 0x100, 0x10]))
+        .unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node("wider").unwrap();
+    builder.prop_u32("#address-cells", 5).unwrap();
+    builder.prop_u32("#size-cells", 0).unwrap();
+    builder.begin_node("dev").unwrap();
+    builder.prop_cells("reg", &cells(&[1, 2, 3, 4, 5])).unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let fdt = unsafe { DevTree::new(&out[..size]) }.unwrap();
+
+    let reg = find_node(&fdt, "regs@2,0,100")
+        .reg()
+        .unwrap()
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(reg.wide_address().unwrap(), 0x2_0000_0000_0000_0100);
+    assert_eq!(reg.wide_size().unwrap(), 0x10);
+    // The u64 reader drops the most significant cell.
+    assert_eq!(reg.address().unwrap(), 0x100);
+
+    let range = find_node(&fdt, "syscon@1000")
+        .ranges()
+        .unwrap()
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(range.wide_child().unwrap(), 0x2_0000_0000_0000_0100);
+    assert_eq!(range.wide_parent().unwrap(), 0x1000);
+    assert_eq!(range.wide_size().unwrap(), 0x10);
+
+    let reg = find_node(&fdt, "dev")
+        .reg()
+        .unwrap()
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(reg.wide_address(), Err(DevTreeError::ParseError));
+}
+
+#[test]
+fn mem_reserve_entries() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let entries: Vec<_> = fdt
+        .mem_reserve_entries()
+        .iterator()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (entry.address().unwrap(), entry.size().unwrap())
+        })
+        .collect();
+    assert_eq!(entries, [(0x1000_0000, 0x4000), (0x7ff0_0000, 0x10_0000)]);
+}
+
+#[test]
+fn firmware_nodes() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+
+    assert_eq!(
+        fdt.optee().unwrap(),
+        Some(Optee {
+            method: OpteeMethod::Hvc
+        })
+    );
+    assert_eq!(
+        fdt.coreboot().unwrap(),
+        Some(Coreboot {
+            tables: MemoryRange::new(0x7fff_0000, 0x1000),
+            cbmem: Some(MemoryRange::new(0x7ff0_0000, 0x10_0000)),
+        })
+    );
+
+    let options = fdt.uboot_options().unwrap().unwrap();
+    assert_eq!(options.node().name().unwrap(), "u-boot");
+    assert_eq!(options.bootcmd().unwrap(), Some("run distro_bootcmd"));
+    assert_eq!(options.bootdelay_sec().unwrap(), Some(3));
+    assert_eq!(options.bootscr_address().unwrap(), Some(0x1_8000_0000));
+    assert_eq!(options.bootscr_ram_offset().unwrap(), None);
+    assert!(!options.silent_console().unwrap());
+}
+
+#[test]
+fn write_firmware_nodes() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 8).unwrap();
+    modifier
+        .delete_node("/firmware/optee")
+        .unwrap()
+        .add_optee(OpteeMethod::Smc)
+        .unwrap()
+        .set_uboot_bootcmd("bootm")
+        .unwrap()
+        .set_uboot_bootdelay_sec(0)
+        .unwrap()
+        .set_uboot_silent_console(true)
+        .unwrap();
+
+    #[repr(align(4))]
+    struct OutBuf([u8; 8192]);
+    let mut out = OutBuf([0; 8192]);
+    let size = modifier.apply(&fdt, &mut out.0).unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+
+    assert_eq!(modified.optee().unwrap().unwrap().method, OpteeMethod::Smc);
+    assert_eq!(modified.coreboot().unwrap(), fdt.coreboot().unwrap());
+    let options = modified.uboot_options().unwrap().unwrap();
+    assert_eq!(options.bootcmd().unwrap(), Some("bootm"));
+    assert_eq!(options.bootdelay_sec().unwrap(), Some(0));
+    assert_eq!(options.bootscr_address().unwrap(), Some(0x1_8000_0000));
+    assert!(options.silent_console().unwrap());
+}
+
+#[test]
+fn mem_reserve_map() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let map = fdt.mem_reserve_map().unwrap();
+    assert_eq!(map.len(), 2);
+    assert!(!map.is_empty());
+    assert_eq!(
+        map.get(1).unwrap().reservation().unwrap(),
+        MemReservation::new(0x7ff0_0000, 0x10_0000)
+    );
+    assert!(map.get(2).is_none());
+    assert_eq!(map.iter().count().unwrap(), 2);
+
+    assert!(map.overlaps(0x1000_3fff, 1).unwrap());
+    assert!(!map.overlaps(0x1000_4000, 0x1000).unwrap());
+    assert!(!map.overlaps(0x0fff_0000, 0x1_0000).unwrap());
+    assert!(!map.overlaps(0x1000_0000, 0).unwrap());
+    let overlapping = map.overlapping(0x7000_0000, 0x1000_0000).unwrap().unwrap();
+    assert_eq!(overlapping.address().unwrap(), 0x7ff0_0000);
+
+    let mut original = [MemReservation::default(); 2];
+    assert!(map.copy_into(&mut original[..1]).is_err());
+    let original = map.copy_into(&mut original).unwrap();
+    let mut entries = [MemReservation::default(); 3];
+    entries[..2].copy_from_slice(original);
+    entries[0].size = 0x8000;
+    entries[2] = MemReservation::new(0x8000_0000, 0x20_0000);
+
+    let options = ModifyOptions {
+        mem_reserve: MemReserveEdits::rewrite(original, &entries),
+        ..ModifyOptions::default()
+    };
+    let mut buf = vec![0u32; FDT.len() / 4 + 16];
+    let out =
+        unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4) };
+    let size = Serializer::modify_with_options(&fdt, out, &options, |_| ModifyTokenResponse::Pass)
+        .unwrap();
+    let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    let mut rewritten = [MemReservation::default(); 3];
+    let map = modified.mem_reserve_map().unwrap();
+    assert_eq!(map.copy_into(&mut rewritten).unwrap(), entries);
+}
+
+#[test]
+fn memory_regions() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let regions: Vec<(u64, u64)> = fdt
+        .memory_regions()
+        .iterator()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    // The disabled memory node is skipped.
+    assert_eq!(
+        regions,
+        [(0x1000_0000, 0x1000_0000), (0x7f00_0000, 0x100_0000)]
+    );
+}
+
+#[test]
+fn free_memory() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = [MemoryRange::default(); 4];
+    assert_eq!(
+        fdt.memory_ranges(&mut out).unwrap(),
+        [
+            MemoryRange::new(0x1000_0000, 0x1000_0000),
+            MemoryRange::new(0x7f00_0000, 0x100_0000),
+        ]
+    );
+
+    // The framebuffer splits the first range in two.
+    assert_eq!(
+        fdt.free_memory(&mut out).unwrap(),
+        [
+            MemoryRange::new(0x1000_4000, 0x7ff_c000),
+            MemoryRange::new(0x1880_0000, 0x780_0000),
+            MemoryRange::new(0x7f00_0000, 0xf0_0000),
+        ]
+    );
+    assert!(matches!(
+        fdt.free_memory(&mut out[..2]),
+        Err(DevTreeError::NotEnoughMemory)
+    ));
+
+    let range = MemoryRange::new(0x1000, 0x1000);
+    assert_eq!(range.end(), 0x2000);
+    assert!(range.overlaps(&MemoryRange::new(0x1fff, 0x10)));
+    assert!(!range.overlaps(&MemoryRange::new(0x2000, 0x10)));
+    assert!(!range.overlaps(&MemoryRange::new(0x1800, 0)));
+}
+
+#[test]
+fn memory_allocator() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut free = [MemoryRange::default(); 4];
+    let mut reserved = [MemReservation::default(); 2];
+    let mut allocator = MemoryAllocator::new(&fdt, &mut free, &mut reserved).unwrap();
+
+    assert!(matches!(
+        allocator.allocate(0x1000, 3, None),
+        Err(DevTreeError::InvalidParameter(_))
+    ));
+    assert!(matches!(
+        allocator.allocate(0, 0x1000, None),
+        Err(DevTreeError::InvalidParameter(_))
+    ));
+    // Larger than any free range, or below all of them.
+    assert!(allocator.allocate(0x800_0000, 0x1000, None).is_err());
+    assert!(allocator
+        .allocate(0x1000, 0x1000, Some(0x1000_4fff))
+        .is_err());
+
+    assert_eq!(
+        allocator.allocate(0x1000, 0x1_0000, None).unwrap(),
+        0x1001_0000
+    );
+    assert_eq!(
+        allocator.free(),
+        [
+            MemoryRange::new(0x1000_4000, 0xc000),
+            MemoryRange::new(0x1001_1000, 0x7fe_f000),
+            MemoryRange::new(0x1880_0000, 0x780_0000),
+            MemoryRange::new(0x7f00_0000, 0xf0_0000),
+        ]
+    );
+    // Splitting another range needs a fifth element.
+    assert!(matches!(
+        allocator.allocate(0x1000, 0x1_0000, None),
+        Err(DevTreeError::NotEnoughMemory)
+    ));
+    assert_eq!(
+        allocator.allocate(0xc000, 0x4000, None).unwrap(),
+        0x1000_4000
+    );
+    assert_eq!(
+        allocator.reservations(),
+        [
+            MemReservation::new(0x1001_0000, 0x1000),
+            MemReservation::new(0x1000_4000, 0xc000),
+        ]
+    );
+    assert!(matches!(
+        allocator.allocate(0x1000, 0x1000, None),
+        Err(DevTreeError::NotEnoughMemory)
+    ));
+
+    let options = ModifyOptions {
+        mem_reserve: allocator.mem_reserve_edits(),
+        ..ModifyOptions::default()
+    };
+    let mut buf = vec![0u32; FDT.len() / 4 + 16];
+    let out =
+        unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4) };
+    let size = Serializer::modify_with_options(&fdt, out, &options, |_| ModifyTokenResponse::Pass)
+        .unwrap();
+    let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    let mut out = [MemoryRange::default(); 4];
+    assert_eq!(modified.free_memory(&mut out).unwrap(), allocator.free());
+}
+
+#[test]
+fn compliance() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut violations = Vec::new();
+    let summary = fdt
+        .check_compliance(|violation| {
+            let mut buf = [0u8; 256];
+            let path = violation.node.path(&mut ScratchArena::new(&mut buf))?;
+            violations.push((violation.rule.id(), path.to_string(), violation.prop));
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(
+        violations,
+        [
+            ("root-properties", "/".to_string(), Some("model")),
+            ("unit-address", "/firmware/coreboot".to_string(), None),
+            ("unit-address", "/template/device@0".to_string(), None),
+            ("cpus-node", "/".to_string(), None),
+        ]
+    );
+    assert_eq!(
+        summary,
+        ComplianceSummary {
+            errors: 2,
+            warnings: 2
+        }
+    );
+    assert!(!summary.is_compliant());
+    assert_eq!(Rule::UnitAddress.severity(), Severity::Warning);
+    assert_eq!(Rule::CpusNode.section(), "3.7");
+
+    // Errors from the callback stop the check.
+    let err = fdt
+        .check_compliance(|_| Err(DevTreeError::InvalidParameter("stop")))
+        .unwrap_err();
+    assert!(matches!(err, DevTreeError::InvalidParameter("stop")));
+}
+
+/// Returns the kind, node path, range and other node path of each overlap found in `fdt`.
+fn overlaps(fdt: &DevTree) -> Vec<(OverlapKind, String, MemoryRange, Option<String>)> {
+    let mut overlaps = Vec::new();
+    fdt.check_overlaps(|overlap| {
+        let mut buf = [0u8; 256];
+        let path = overlap
+            .node
+            .path(&mut ScratchArena::new(&mut buf))?
+            .to_string();
+        let other = match &overlap.other {
+            Some(other) => Some(other.path(&mut ScratchArena::new(&mut buf))?.to_string()),
+            None => None,
+        };
+        overlaps.push((overlap.kind, path, overlap.range, other));
+        Ok(())
+    })
+    .unwrap();
+    overlaps
+}
+
+#[test]
+fn region_overlaps() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let memory = || Some("/memory@10000000".to_string());
+    let mut expected = vec![
+        (
+            OverlapKind::Memory,
+            "/dma-bus@10000000/dma-controller@10000000".to_string(),
+            MemoryRange::new(0x1000_0000, 0x1000),
+            memory(),
+        ),
+        (
+            OverlapKind::Memory,
+            "/dma-bus@10000000/nested-bus@10100000/device@10100000".to_string(),
+            MemoryRange::new(0x1010_0000, 0x1000),
+            memory(),
+        ),
+        (
+            OverlapKind::Memory,
+            "/dma-bus@10000000/identity-bus@10200000/device@10200000".to_string(),
+            MemoryRange::new(0x1020_0000, 0x1000),
+            memory(),
+        ),
+    ];
+    assert_eq!(overlaps(&fdt), expected);
+
+    // Add a device overlapping serial@2000, and a reservation straddling the end of RAM.
+    let reg = [0, 0, 0x20, 0x80, 0, 0, 0x01, 0];
+    let props = [MetadataProp::new("reg", MetadataValue::Bytes(&reg))];
+    let serial = MetadataNode::new("serial@2080", &props);
+    let mut mem = [0u8; 256];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 1).unwrap();
+    modifier.add_node("/", &serial).unwrap();
+    let mut buf = vec![0u32; FDT.len() / 4 + 16];
+    let out =
+        unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4) };
+    let size = modifier.apply(&fdt, out).unwrap();
+    let added = unsafe { DevTree::new(&out[..size]) }.unwrap();
+
+    let options = ModifyOptions {
+        mem_reserve: MemReserveEdits {
+            append: &[MemReservation::new(0x7fff_f000, 0x2000)],
+            ..MemReserveEdits::default()
+        },
+        ..ModifyOptions::default()
+    };
+    let mut buf = vec![0u32; FDT.len() / 4 + 32];
+    let out =
+        unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4) };
+    let size =
+        Serializer::modify_with_options(&added, out, &options, |_| ModifyTokenResponse::Pass)
+            .unwrap();
+    let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    expected.insert(
+        0,
+        (
+            OverlapKind::Siblings,
+            "/serial@2000".to_string(),
+            MemoryRange::new(0x2000, 0x100),
+            Some("/serial@2080".to_string()),
+        ),
+    );
+    expected.push((
+        OverlapKind::ReservationOutsideMemory,
+        "/".to_string(),
+        MemoryRange::new(0x7fff_f000, 0x2000),
+        None,
+    ));
+    assert_eq!(overlaps(&modified), expected);
+
+    // Errors from the callback stop the check.
+    let err = fdt
+        .check_overlaps(|_| Err(DevTreeError::InvalidParameter("stop")))
+        .unwrap_err();
+    assert!(matches!(err, DevTreeError::InvalidParameter("stop")));
+}
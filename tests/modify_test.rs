@@ -0,0 +1,3417 @@
+extern crate fdt_rs;
+
+use std::convert::TryInto;
+
+use fdt_rs::base::parse::ParsedTok;
+use fdt_rs::base::{DevTree, DevTreeNode, DevTreeProp};
+use fdt_rs::diff::DiffRecord;
+use fdt_rs::error::DevTreeError;
+use fdt_rs::index::DevTreeIndex;
+use fdt_rs::modify::{
+    modify_in_place, AddressRemapper, AddressWindow, DevTreeCatalogIter, DevTreeCatalogWriter,
+    DevTreeModifier, FdtBuilder, FdtWrite, HeaderOverrides, InPlaceTok, IrqCellFormat, IrqRemapper,
+    IrqSpecifier, LocalFixup, MemReservation, MemReserveEdits, MetadataNode, MetadataProp,
+    MetadataValue, ModifyContext, ModifyOptions, ModifyParsedTok, ModifyPipeline, ModifyStage,
+    ModifyTokenResponse, NopPolicy, OffsetMap, OffsetMapping, Origin, OverlayFixup,
+    OverlayMetadata, OverlaySymbol, Patch, PatchRecord, PatchWriter, PhandleAllocator,
+    PhandleRenumber, PhandleStyle, PropCell, PropMergePolicies, PropMergePolicy, PropWriter,
+    ProvenanceMap, ProvenanceRecord, QuirkHandler, QuirkRegistry, ReplacementTok, Serializer,
+    Trace, TrailingData, DEFAULT_IRQ_FORMATS, DELETE_NODE_MARKER, MAX_SORTED_ENTRIES,
+};
+use fdt_rs::prelude::*;
+use fdt_rs::scratch::ScratchArena;
+
+#[repr(align(4))]
+struct _Wrapper<T>(T);
+pub const FDT: &[u8] = &_Wrapper(*include_bytes!("../tests/riscv64-virt.dtb")).0;
+const BINDINGS_FDT: &[u8] = &_Wrapper(*include_bytes!("../tests/bindings.dtb")).0;
+
+/// A 32-bit aligned output buffer large enough for any of the trees we serialize.
+#[repr(align(8))]
+struct OutBuf([u8; 8192]);
+
+impl OutBuf {
+    fn new() -> Self {
+        Self([0; 8192])
+    }
+}
+
+fn node_names<'dt>(fdt: &DevTree<'dt>) -> Vec<&'dt str> {
+    let mut names = Vec::new();
+    let mut iter = fdt.nodes();
+    while let Some(node) = iter.next().unwrap() {
+        names.push(node.name().unwrap());
+    }
+    names
+}
+
+#[test]
+fn passthrough_is_identical() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let size = Serializer::modify(&fdt, &mut out.0, |_| ModifyTokenResponse::Pass).unwrap();
+
+    // The source tree uses the same block layout as the serializer. (Only its padding bytes,
+    // which the serializer zeroes, differ.)
+    assert_eq!(size, FDT.len());
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(node_names(&modified), node_names(&fdt));
+
+    // Serializing the output again reproduces it exactly.
+    let mut out2 = OutBuf::new();
+    let size2 = Serializer::modify(&modified, &mut out2.0, |_| ModifyTokenResponse::Pass).unwrap();
+    assert_eq!(&out2.0[..size2], &out.0[..size]);
+}
+
+#[test]
+fn drop_begin_node_drops_subtree() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let size = Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::BeginNode(node, _) if node.name == b"cpus" => ModifyTokenResponse::Drop,
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    let expected: Vec<&str> = node_names(&fdt)
+        .into_iter()
+        .filter(|name| {
+            ![
+                "cpus",
+                "cpu-map",
+                "cluster0",
+                "core0",
+                "cpu@0",
+                "interrupt-controller",
+            ]
+            .contains(name)
+        })
+        .collect();
+    assert_eq!(node_names(&modified), expected);
+
+    // An index can only be built over a balanced tree.
+    let layout = DevTreeIndex::get_layout(&modified).unwrap();
+    let mut vec = vec![0u8; layout.size() + layout.align()];
+    let index = DevTreeIndex::new(modified, vec.as_mut_slice()).unwrap();
+    assert_eq!(index.root().children().count(), 17);
+}
+
+#[test]
+fn drop_callback_not_called_within_dropped_subtree() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let mut saw_cpu = false;
+    Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::BeginNode(node, _) if node.name == b"cpus" => ModifyTokenResponse::Drop,
+        ModifyParsedTok::BeginNode(node, _) => {
+            saw_cpu |= node.name == b"cpu@0";
+            ModifyTokenResponse::Pass
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+    assert!(!saw_cpu);
+}
+
+#[test]
+fn modify_prop_value() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let size = Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::Prop(prop, _, buf) if prop.prop_buf == b"riscv-virtio,qemu\0" => {
+            let model = b"a much longer model name\0";
+            buf[..model.len()].copy_from_slice(model);
+            ModifyTokenResponse::ModifySize(model.len())
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    let model = modified
+        .props()
+        .find(|p| Ok(p.name()? == "model"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(model.str().unwrap(), "a much longer model name");
+    assert_eq!(node_names(&modified), node_names(&fdt));
+}
+
+#[test]
+fn modify_into_small_buffer_fails() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let err = Serializer::modify(&fdt, &mut out.0[..FDT.len() - 1], |_| {
+        ModifyTokenResponse::Pass
+    })
+    .expect_err("Expected failure.");
+    assert_eq!(
+        err,
+        DevTreeError::OutputBufferTooSmall {
+            needed: FDT.len(),
+            available: FDT.len() - 1
+        }
+    );
+}
+
+#[test]
+fn modify_size_past_buffer_fails() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let available = out.0.len();
+    let oversize = |tok: ModifyParsedTok| match tok {
+        ModifyParsedTok::Prop(_, b"model", buf) => ModifyTokenResponse::ModifySize(buf.len() + 1),
+        _ => ModifyTokenResponse::Pass,
+    };
+    let err = Serializer::modify(&fdt, &mut out.0, oversize).unwrap_err();
+    assert!(matches!(
+        err,
+        DevTreeError::OutputBufferTooSmall { needed, .. } if needed > available
+    ));
+
+    let err = Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::BeginNode(node, buf) if node.name == b"chosen" => {
+            ModifyTokenResponse::ModifySize(buf.len() + 1)
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap_err();
+    assert!(matches!(err, DevTreeError::OutputBufferTooSmall { .. }));
+
+    // The buffers of a dry run are carved from the scratch buffer, which bounds them instead.
+    let mut scratch = [0u8; 1024];
+    let err =
+        Serializer::dry_run(&fdt, &mut scratch, &ModifyOptions::default(), oversize).unwrap_err();
+    assert!(matches!(err, DevTreeError::OutputBufferTooSmall { .. }));
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn modify_to_vec() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let model = [b'x'; 4096];
+    let mut calls = 0;
+    let vec = Serializer::modify_to_vec(&fdt, &ModifyOptions::default(), |tok| match tok {
+        ModifyParsedTok::Prop(_, b"model", _) => {
+            calls += 1;
+            ModifyTokenResponse::Replace(ReplacementTok::Prop {
+                name: "model",
+                value: &model,
+            })
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+    // The first attempt didn't fit in a buffer of the source tree's size.
+    assert!(calls > 1);
+
+    let mut out = OutBuf::new();
+    let size = Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::Prop(_, b"model", _) => {
+            ModifyTokenResponse::Replace(ReplacementTok::Prop {
+                name: "model",
+                value: &model,
+            })
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+    assert_eq!(vec, out.0[..size]);
+}
+
+#[test]
+fn replace_node_name() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let size = Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::BeginNode(node, _) if node.name == b"poweroff" => {
+            ModifyTokenResponse::Replace(ReplacementTok::BeginNode("shutdown-controller"))
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    let expected: Vec<&str> = node_names(&fdt)
+        .into_iter()
+        .map(|name| match name {
+            "poweroff" => "shutdown-controller",
+            name => name,
+        })
+        .collect();
+    assert_eq!(node_names(&modified), expected);
+
+    // The node's properties are kept.
+    let node = modified
+        .nodes()
+        .find(|n| Ok(n.name()? == "shutdown-controller"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(node.props().count().unwrap(), 4);
+}
+
+#[test]
+fn rename_node_in_place() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let size = Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::BeginNode(node, buf) if node.name == b"memory@80000000" => {
+            let name = b"memory@40000000000";
+            buf[..name.len()].copy_from_slice(name);
+            ModifyTokenResponse::ModifySize(name.len())
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    let expected: Vec<&str> = node_names(&fdt)
+        .into_iter()
+        .map(|name| match name {
+            "memory@80000000" => "memory@40000000000",
+            name => name,
+        })
+        .collect();
+    assert_eq!(node_names(&modified), expected);
+
+    let node = modified
+        .nodes()
+        .find(|n| Ok(n.name()? == "memory@40000000000"))
+        .unwrap()
+        .unwrap();
+    let device_type = node.props().next().unwrap().unwrap();
+    assert_eq!(device_type.str().unwrap(), "memory");
+}
+
+#[test]
+fn rename_node_with_null_fails() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::BeginNode(node, buf) if node.name == b"cpus" => {
+            buf[1] = 0;
+            ModifyTokenResponse::ModifySize(4)
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .expect_err("Expected failure.");
+}
+
+#[test]
+fn replace_prop() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let size = Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::Prop(prop, _, _) if prop.prop_buf == b"riscv-virtio,qemu\0" => {
+            ModifyTokenResponse::Replace(ReplacementTok::Prop {
+                name: "bootargs",
+                value: b"console=ttyS0\0",
+            })
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    let root = modified.root().unwrap().unwrap();
+    let mut props = root.props();
+    let mut names = Vec::new();
+    while let Some(prop) = props.next().unwrap() {
+        names.push(prop.name().unwrap());
+        if prop.name().unwrap() == "bootargs" {
+            assert_eq!(prop.str().unwrap(), "console=ttyS0");
+        }
+    }
+    assert_eq!(
+        names,
+        ["#address-cells", "#size-cells", "compatible", "bootargs"]
+    );
+}
+
+#[test]
+fn replace_prop_with_new_name() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let size = Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::Prop(prop, _, _) if prop.prop_buf == b"riscv-virtio,qemu\0" => {
+            ModifyTokenResponse::Replace(ReplacementTok::Prop {
+                name: "board-name",
+                value: b"qemu\0",
+            })
+        }
+        ModifyParsedTok::Prop(prop, _, _) if prop.prop_buf == b"riscv-virtio\0" => {
+            ModifyTokenResponse::Replace(ReplacementTok::Prop {
+                name: "board-name",
+                value: b"virt\0",
+            })
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+
+    // The new name is only appended once.
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(
+        modified.size_dt_strings(),
+        fdt.size_dt_strings() + b"board-name\0".len() as u32
+    );
+
+    let root = modified.root().unwrap().unwrap();
+    let mut props = root.props();
+    let mut values = Vec::new();
+    while let Some(prop) = props.next().unwrap() {
+        values.push((prop.name().unwrap(), prop.str().ok()));
+    }
+    assert_eq!(
+        values[2..],
+        [("board-name", Some("virt")), ("board-name", Some("qemu"))]
+    );
+    assert_eq!(node_names(&modified), node_names(&fdt));
+}
+
+#[test]
+fn replace_prop_with_suffix_of_existing_name() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let size = Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::Prop(prop, _, _) if prop.prop_buf == b"riscv-virtio,qemu\0" => {
+            ModifyTokenResponse::Replace(ReplacementTok::Prop {
+                name: "cells",
+                value: &[],
+            })
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(modified.size_dt_strings(), fdt.size_dt_strings());
+    assert!(modified
+        .props()
+        .find(|p| Ok(p.name()? == "cells"))
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn replace_prop_with_new_name_into_small_buffer_fails() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    Serializer::modify(&fdt, &mut out.0[..FDT.len() + 4], |tok| match tok {
+        ModifyParsedTok::Prop(prop, _, _) if prop.prop_buf == b"riscv-virtio,qemu\0" => {
+            ModifyTokenResponse::Replace(ReplacementTok::Prop {
+                name: "not-a-property-name",
+                value: prop.prop_buf,
+            })
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .expect_err("Expected failure.");
+}
+
+#[test]
+fn replace_with_mismatched_token_fails() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::BeginNode(..) => ModifyTokenResponse::Replace(ReplacementTok::Prop {
+            name: "model",
+            value: &[],
+        }),
+        _ => ModifyTokenResponse::Pass,
+    })
+    .expect_err("Expected failure.");
+}
+
+const METADATA_PROPS: [MetadataProp; 4] = [
+    MetadataProp::new("version", MetadataValue::Str("1.2.3")),
+    MetadataProp::new("boot-count", MetadataValue::U32(42)),
+    MetadataProp::new("build-time", MetadataValue::U64(0x1_0000_0002)),
+    MetadataProp::new("build-id", MetadataValue::Bytes(&[0xde, 0xad, 0xbe])),
+];
+
+fn check_metadata_node(fdt: &DevTree, parent: &str) {
+    let layout = DevTreeIndex::get_layout(fdt).unwrap();
+    let mut vec = vec![0u8; layout.size() + layout.align()];
+    let index = DevTreeIndex::new(*fdt, vec.as_mut_slice()).unwrap();
+    let parent = index
+        .root()
+        .children()
+        .find(|n| n.name().unwrap() == parent)
+        .unwrap();
+    let node = parent.children().last().unwrap();
+    assert_eq!(node.name().unwrap(), "acme,bootloader");
+
+    let props: Vec<_> = node.props().collect();
+    assert_eq!(props.len(), 4);
+    assert_eq!(props[0].name().unwrap(), "version");
+    assert_eq!(props[0].str().unwrap(), "1.2.3");
+    assert_eq!(props[1].name().unwrap(), "boot-count");
+    assert_eq!(props[1].u32(0).unwrap(), 42);
+    assert_eq!(props[2].name().unwrap(), "build-time");
+    assert_eq!(props[2].u64(0).unwrap(), 0x1_0000_0002);
+    assert_eq!(props[3].name().unwrap(), "build-id");
+    assert_eq!(props[3].raw(), &[0xde, 0xad, 0xbe]);
+}
+
+#[test]
+fn metadata_into_existing_node() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let size = Serializer::modify_with_metadata(
+        &fdt,
+        &mut out.0,
+        "chosen",
+        &MetadataNode::new("acme,bootloader", &METADATA_PROPS),
+        |_| ModifyTokenResponse::Pass,
+    )
+    .unwrap();
+
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(node_names(&modified).len(), node_names(&fdt).len() + 1);
+    check_metadata_node(&modified, "chosen");
+}
+
+#[test]
+fn metadata_into_new_node() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let size = Serializer::modify_with_metadata(
+        &fdt,
+        &mut out.0,
+        "firmware",
+        &MetadataNode::new("acme,bootloader", &METADATA_PROPS),
+        |_| ModifyTokenResponse::Pass,
+    )
+    .unwrap();
+
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    let mut expected = node_names(&fdt);
+    expected.extend(["firmware", "acme,bootloader"]);
+    assert_eq!(node_names(&modified), expected);
+    check_metadata_node(&modified, "firmware");
+}
+
+fn prop_names_and_values<'dt>(fdt: &DevTree<'dt>) -> Vec<(&'dt str, &'dt [u8])> {
+    let mut props = Vec::new();
+    let mut iter = fdt.props();
+    while let Some(prop) = iter.next().unwrap() {
+        props.push((prop.name().unwrap(), prop.raw()));
+    }
+    props
+}
+
+fn strings_block<'dt>(fdt: &DevTree<'dt>) -> &'dt [u8] {
+    let start = fdt.off_dt_strings();
+    &fdt.buf()[start..start + fdt.size_dt_strings() as usize]
+}
+
+#[test]
+fn modify_prop_names() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut props = fdt.props();
+    let mut count = 0;
+    let mut out = OutBuf::new();
+    Serializer::modify(&fdt, &mut out.0, |tok| {
+        if let ModifyParsedTok::Prop(_, name, _) = tok {
+            let prop = props.next().unwrap().unwrap();
+            assert_eq!(name, prop.name().unwrap().as_bytes());
+            count += 1;
+        }
+        ModifyTokenResponse::Pass
+    })
+    .unwrap();
+    assert_eq!(count, fdt.props().count().unwrap());
+}
+
+fn drop_cpus_and_rename_model<'dt>(tok: ModifyParsedTok<'_, 'dt>) -> ModifyTokenResponse<'dt> {
+    match tok {
+        ModifyParsedTok::BeginNode(node, _) if node.name == b"cpus" => ModifyTokenResponse::Drop,
+        ModifyParsedTok::Prop(prop, _, _) if prop.prop_buf == b"riscv-virtio,qemu\0" => {
+            ModifyTokenResponse::Replace(ReplacementTok::Prop {
+                name: "board-name",
+                value: prop.prop_buf,
+            })
+        }
+        _ => ModifyTokenResponse::Pass,
+    }
+}
+
+#[test]
+fn gc_strings_drops_unreferenced_names() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let options = ModifyOptions {
+        gc_strings: true,
+        ..ModifyOptions::default()
+    };
+    let mut expected_out = OutBuf::new();
+    let size = Serializer::modify(&fdt, &mut expected_out.0, drop_cpus_and_rename_model).unwrap();
+    let expected = unsafe { DevTree::new(&expected_out.0[..size]) }.unwrap();
+
+    let mut out = OutBuf::new();
+    let size =
+        Serializer::modify_with_options(&fdt, &mut out.0, &options, drop_cpus_and_rename_model)
+            .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+
+    assert_eq!(node_names(&modified), node_names(&expected));
+    let props = prop_names_and_values(&modified);
+    assert_eq!(props, prop_names_and_values(&expected));
+
+    // Every name left in the strings block is in use, and is only there once.
+    let strings = strings_block(&modified);
+    assert!(strings.len() < strings_block(&expected).len());
+    let entries: Vec<&[u8]> = strings[..strings.len() - 1].split(|&c| c == 0).collect();
+    for (i, entry) in entries.iter().enumerate() {
+        assert!(props
+            .iter()
+            .any(|(name, _)| entry.ends_with(name.as_bytes())));
+        assert!(!entries[..i].contains(entry));
+    }
+    assert!(entries.contains(&&b"board-name"[..]));
+    assert!(!entries.contains(&&b"model"[..]));
+    assert!(!entries.contains(&&b"timebase-frequency"[..]));
+}
+
+#[test]
+fn gc_strings_of_unmodified_tree() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let options = ModifyOptions {
+        gc_strings: true,
+        ..ModifyOptions::default()
+    };
+    let mut out = OutBuf::new();
+    let size =
+        Serializer::modify_with_options(&fdt, &mut out.0, &options, |_| ModifyTokenResponse::Pass)
+            .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(
+        prop_names_and_values(&modified),
+        prop_names_and_values(&fdt)
+    );
+    assert!(modified.size_dt_strings() <= fdt.size_dt_strings());
+}
+
+/// Returns a copy of the test tree with its "model" property overwritten by Nop tokens, as
+/// libfdt's `fdt_nop_property` does.
+fn fdt_with_nops() -> OutBuf {
+    let mut buf = OutBuf::new();
+    buf.0[..FDT.len()].copy_from_slice(FDT);
+    let value = b"riscv-virtio,qemu\0";
+    let value_off = FDT.windows(value.len()).position(|w| w == value).unwrap();
+    // The token, length, and name offset precede the value, which is padded to 4 bytes.
+    let start = value_off - 12;
+    let end = value_off + value.len().div_ceil(4) * 4;
+    for off in (start..end).step_by(4) {
+        buf.0[off..off + 4].copy_from_slice(&4u32.to_be_bytes());
+    }
+    buf
+}
+
+fn count_nops(fdt: &DevTree) -> usize {
+    let mut iter = fdt.parse_iter();
+    let mut count = 0;
+    while let Some(tok) = iter.next().unwrap() {
+        if let ParsedTok::Nop = tok {
+            count += 1;
+        }
+    }
+    count
+}
+
+fn serialize_nops(policy: NopPolicy) -> (usize, usize) {
+    let src = fdt_with_nops();
+    let fdt = unsafe { DevTree::new(&src.0[..FDT.len()]) }.unwrap();
+    let options = ModifyOptions {
+        nop_policy: policy,
+        ..ModifyOptions::default()
+    };
+    let mut out = OutBuf::new();
+    let mut callbacks = 0;
+    let size = Serializer::modify_with_options(&fdt, &mut out.0, &options, |tok| {
+        if let ModifyParsedTok::Nop = tok {
+            callbacks += 1;
+        }
+        ModifyTokenResponse::Pass
+    })
+    .unwrap();
+
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(node_names(&modified), node_names(&fdt));
+    let dropped = 4 * (8 - count_nops(&modified));
+    assert_eq!(size, FDT.len() - dropped);
+    assert_eq!(
+        modified.size_dt_struct() as usize,
+        fdt.size_dt_struct() as usize - dropped
+    );
+    assert_eq!(
+        modified.off_dt_strings(),
+        modified.off_dt_struct() + modified.size_dt_struct() as usize
+    );
+    (count_nops(&modified), callbacks)
+}
+
+#[test]
+fn nop_policy() {
+    assert_eq!(serialize_nops(NopPolicy::Preserve), (8, 8));
+    assert_eq!(serialize_nops(NopPolicy::Strip), (0, 0));
+    assert_eq!(serialize_nops(NopPolicy::Coalesce), (1, 8));
+}
+
+#[test]
+fn append_before_end() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    const NOPS: [u8; 8] = [0, 0, 0, 4, 0, 0, 0, 4];
+    let mut ends = 0;
+    let mut append_nops = |ctx: &ModifyContext, tok: ModifyParsedTok| match tok {
+        ModifyParsedTok::End(buf) => {
+            ends += 1;
+            assert_eq!(ctx.depth(), 0);
+            buf[..NOPS.len()].copy_from_slice(&NOPS);
+            ModifyTokenResponse::ModifySize(NOPS.len())
+        }
+        _ => ModifyTokenResponse::Pass,
+    };
+    let mut out = OutBuf::new();
+    let size = Serializer::modify_with_context(
+        &fdt,
+        &mut out.0,
+        &ModifyOptions::default(),
+        &mut append_nops,
+    )
+    .unwrap();
+    assert_eq!(ends, 1);
+    assert_eq!(size, FDT.len() + NOPS.len());
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(count_nops(&modified), 2);
+    assert_eq!(canonical(&modified), canonical_copy(&fdt));
+    let end = modified.off_dt_struct() + modified.size_dt_struct() as usize;
+    assert_eq!(&out.0[end - 12..end], &[0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 9]);
+
+    // Written to a sink, the appended tokens come from the scratch buffer.
+    let mut scratch = [0u8; 1024];
+    let mut sink = VecSink(Vec::new());
+    let append_nops = |tok: ModifyParsedTok| match tok {
+        ModifyParsedTok::End(buf) => {
+            buf[..NOPS.len()].copy_from_slice(&NOPS);
+            ModifyTokenResponse::ModifySize(NOPS.len())
+        }
+        _ => ModifyTokenResponse::Pass,
+    };
+    let options = ModifyOptions::default();
+    Serializer::modify_to_writer(&fdt, &mut scratch, &options, &mut sink, append_nops).unwrap();
+    assert_eq!(sink.0, &out.0[..size]);
+
+    // Only whole tokens may be appended, and only by changing the size.
+    let err = Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::End(_) => ModifyTokenResponse::ModifySize(2),
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap_err();
+    assert!(matches!(err, DevTreeError::InvalidParameter(_)));
+    let err = Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::End(_) => ModifyTokenResponse::Drop,
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap_err();
+    assert!(matches!(err, DevTreeError::InvalidParameter(_)));
+}
+
+#[test]
+fn dry_run_matches_modify() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let metadata_options = ModifyOptions {
+        metadata: Some((
+            "firmware",
+            &MetadataNode::new("acme,bootloader", &METADATA_PROPS),
+        )),
+        ..ModifyOptions::default()
+    };
+    let lengthen_model = |tok: ModifyParsedTok| match tok {
+        ModifyParsedTok::Prop(prop, _, buf) if prop.prop_buf == b"riscv-virtio,qemu\0" => {
+            let model = b"a much longer model name\0";
+            buf[..model.len()].copy_from_slice(model);
+            ModifyTokenResponse::ModifySize(model.len())
+        }
+        _ => ModifyTokenResponse::Pass,
+    };
+
+    let mut scratch = [0u8; 1024];
+    let mut out = OutBuf::new();
+    for options in &[ModifyOptions::default(), metadata_options] {
+        let expected =
+            Serializer::modify_with_options(&fdt, &mut out.0, options, drop_cpus_and_rename_model)
+                .unwrap();
+        let size =
+            Serializer::dry_run(&fdt, &mut scratch, options, drop_cpus_and_rename_model).unwrap();
+        assert_eq!(size, expected);
+
+        let expected =
+            Serializer::modify_with_options(&fdt, &mut out.0, options, lengthen_model).unwrap();
+        let size = Serializer::dry_run(&fdt, &mut scratch, options, lengthen_model).unwrap();
+        assert_eq!(size, expected);
+    }
+}
+
+struct VecSink(Vec<u8>);
+
+impl FdtWrite for VecSink {
+    fn write_all(&mut self, data: &[u8]) -> fdt_rs::error::Result<()> {
+        self.0.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+#[test]
+fn modify_to_writer_matches_modify() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let metadata_options = ModifyOptions {
+        metadata: Some((
+            "firmware",
+            &MetadataNode::new("acme,bootloader", &METADATA_PROPS),
+        )),
+        ..ModifyOptions::default()
+    };
+    let padded_options = ModifyOptions {
+        padding: 100,
+        ..ModifyOptions::default()
+    };
+
+    let mut scratch = [0u8; 1024];
+    let mut out = OutBuf::new();
+    for options in &[ModifyOptions::default(), metadata_options, padded_options] {
+        let expected =
+            Serializer::modify_with_options(&fdt, &mut out.0, options, drop_cpus_and_rename_model)
+                .unwrap();
+        let mut sink = VecSink(Vec::new());
+        let size = Serializer::modify_to_writer(
+            &fdt,
+            &mut scratch,
+            options,
+            &mut sink,
+            drop_cpus_and_rename_model,
+        )
+        .unwrap();
+        assert_eq!(size, expected);
+        assert_eq!(sink.0, &out.0[..expected]);
+    }
+
+    let options = ModifyOptions {
+        gc_strings: true,
+        ..ModifyOptions::default()
+    };
+    Serializer::modify_to_writer(
+        &fdt,
+        &mut scratch,
+        &options,
+        &mut VecSink(Vec::new()),
+        |_| ModifyTokenResponse::Pass,
+    )
+    .expect_err("Expected failure.");
+}
+
+#[test]
+fn trailing_data() {
+    const VENDOR: &[u8] = b"VNDR\x01\x02\x03\x04";
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    assert!(fdt.trailing_data().is_empty());
+
+    // Emit vendor data, then check a copy preserves it, before any padding.
+    let mut src = OutBuf::new();
+    let options = ModifyOptions {
+        trailing: TrailingData::Emit(VENDOR),
+        ..ModifyOptions::default()
+    };
+    let size =
+        Serializer::modify_with_options(&fdt, &mut src.0, &options, |_| ModifyTokenResponse::Pass)
+            .unwrap();
+    assert_eq!(size, FDT.len() + VENDOR.len());
+    let src = unsafe { DevTree::new(&src.0[..size]) }.unwrap();
+    assert_eq!(src.totalsize(), size);
+    assert_eq!(src.trailing_data(), VENDOR);
+
+    let mut out = OutBuf::new();
+    let options = ModifyOptions {
+        trailing: TrailingData::Preserve,
+        padding: 16,
+        ..ModifyOptions::default()
+    };
+    let size =
+        Serializer::modify_with_options(&src, &mut out.0, &options, drop_cpus_and_rename_model)
+            .unwrap();
+    let copy = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(&copy.trailing_data()[..VENDOR.len()], VENDOR);
+    assert_eq!(copy.trailing_data()[VENDOR.len()..], [0; 16]);
+
+    let mut scratch = [0u8; 1024];
+    let mut sink = VecSink(Vec::new());
+    let written = Serializer::modify_to_writer(
+        &src,
+        &mut scratch,
+        &options,
+        &mut sink,
+        drop_cpus_and_rename_model,
+    )
+    .unwrap();
+    assert_eq!(written, size);
+    assert_eq!(sink.0, &out.0[..size]);
+
+    // By default, it's dropped.
+    let size = Serializer::modify(&src, &mut out.0, |_| ModifyTokenResponse::Pass).unwrap();
+    assert_eq!(size, FDT.len());
+    let copy = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert!(copy.trailing_data().is_empty());
+}
+
+#[test]
+fn dry_run_with_small_scratch_fails() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut scratch = [0u8; 8];
+    Serializer::dry_run(&fdt, &mut scratch, &ModifyOptions::default(), |_| {
+        ModifyTokenResponse::Pass
+    })
+    .expect_err("Expected failure.");
+}
+
+/// Copy the test tree into an output buffer, to be modified in place.
+fn fdt_copy() -> OutBuf {
+    let mut out = OutBuf::new();
+    out.0[..FDT.len()].copy_from_slice(FDT);
+    out
+}
+
+#[test]
+fn modify_in_place_matches_modify() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    // Modifying in place leaves the header's versions as they are.
+    let options = ModifyOptions {
+        header: HeaderOverrides {
+            last_comp_version: Some(fdt.last_comp_version()),
+            ..HeaderOverrides::default()
+        },
+        ..ModifyOptions::default()
+    };
+    let mut expected = OutBuf::new();
+    let expected_size =
+        Serializer::modify_with_options(&fdt, &mut expected.0, &options, |tok| match tok {
+            ModifyParsedTok::BeginNode(node, _) if node.name == b"cpus" => {
+                ModifyTokenResponse::Drop
+            }
+            ModifyParsedTok::BeginNode(node, _) if node.name == b"memory@80000000" => {
+                ModifyTokenResponse::Replace(ReplacementTok::BeginNode("mem@80000000"))
+            }
+            ModifyParsedTok::Prop(prop, _, buf) if prop.prop_buf == b"riscv-virtio,qemu\0" => {
+                buf[..5].copy_from_slice(b"acme\0");
+                ModifyTokenResponse::ModifySize(5)
+            }
+            _ => ModifyTokenResponse::Pass,
+        })
+        .unwrap();
+
+    let mut out = fdt_copy();
+    let size = unsafe {
+        modify_in_place(&mut out.0[..FDT.len()], |tok| match tok {
+            InPlaceTok::BeginNode(name) if name == b"cpus" => ModifyTokenResponse::Drop,
+            InPlaceTok::BeginNode(name) if name == b"memory@80000000" => {
+                ModifyTokenResponse::Replace(ReplacementTok::BeginNode("mem@80000000"))
+            }
+            InPlaceTok::Prop("model", value) => {
+                value[..5].copy_from_slice(b"acme\0");
+                ModifyTokenResponse::ModifySize(5)
+            }
+            _ => ModifyTokenResponse::Pass,
+        })
+    }
+    .unwrap();
+
+    assert!(size < FDT.len());
+    assert_eq!(&out.0[..size], &expected.0[..expected_size]);
+}
+
+#[test]
+fn modify_in_place_cannot_grow() {
+    let mut out = fdt_copy();
+    let err = unsafe {
+        modify_in_place(&mut out.0[..FDT.len()], |tok| match tok {
+            InPlaceTok::Prop("model", value) => ModifyTokenResponse::ModifySize(value.len() + 1),
+            _ => ModifyTokenResponse::Pass,
+        })
+    }
+    .expect_err("Expected failure.");
+    assert!(matches!(err, DevTreeError::InvalidParameter(_)));
+
+    // Names can't be appended to the strings block either.
+    let mut out = fdt_copy();
+    unsafe {
+        modify_in_place(&mut out.0[..FDT.len()], |tok| match tok {
+            InPlaceTok::Prop("model", _) => ModifyTokenResponse::Replace(ReplacementTok::Prop {
+                name: "board-name",
+                value: b"acme\0",
+            }),
+            _ => ModifyTokenResponse::Pass,
+        })
+    }
+    .expect_err("Expected failure.");
+}
+
+fn context_path(ctx: &ModifyContext) -> String {
+    let mut path = String::new();
+    for name in ctx.path_components() {
+        path.push('/');
+        path.push_str(core::str::from_utf8(name).unwrap());
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    path
+}
+
+#[test]
+fn modify_with_context_tracks_path() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut expected = Vec::new();
+    let mut buf = [0u8; 256];
+    let mut iter = fdt.nodes();
+    while let Some(node) = iter.next().unwrap() {
+        let path = node.path(&mut ScratchArena::new(&mut buf)).unwrap();
+        expected.push(path.to_string());
+    }
+
+    let mut paths = Vec::new();
+    let mut open = 0;
+    let mut out = OutBuf::new();
+    Serializer::modify_with_context(&fdt, &mut out.0, &ModifyOptions::default(), |ctx, tok| {
+        match tok {
+            ModifyParsedTok::BeginNode(node, _) => {
+                open += 1;
+                assert_eq!(ctx.depth(), open);
+                assert_eq!(ctx.node_name(), Some(node.name));
+                let path = context_path(ctx);
+                assert!(ctx.is_at(&path));
+                paths.push(path);
+            }
+            ModifyParsedTok::EndNode => {
+                assert_eq!(ctx.depth(), open);
+                open -= 1;
+            }
+            ModifyParsedTok::Prop(..) if ctx.is_at("/cpus/cpu-map/cluster0/core0") => {
+                assert_eq!(ctx.parent_name(), Some(&b"cluster0"[..]));
+            }
+            _ => (),
+        }
+        ModifyTokenResponse::Pass
+    })
+    .unwrap();
+    assert_eq!(paths, expected);
+    assert_eq!(open, 0);
+}
+
+#[test]
+fn modify_with_context_drop_by_path() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let size =
+        Serializer::modify_with_context(&fdt, &mut out.0, &ModifyOptions::default(), |ctx, tok| {
+            match tok {
+                ModifyParsedTok::BeginNode(..) if ctx.is_at("/cpus/cpu-map") => {
+                    ModifyTokenResponse::Drop
+                }
+                _ => ModifyTokenResponse::Pass,
+            }
+        })
+        .unwrap();
+
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    let expected: Vec<&str> = node_names(&fdt)
+        .into_iter()
+        .filter(|name| !["cpu-map", "cluster0", "core0"].contains(name))
+        .collect();
+    assert_eq!(node_names(&modified), expected);
+}
+
+fn node_props<'dt>(fdt: &DevTree<'dt>, node: &str) -> Vec<(&'dt str, &'dt [u8])> {
+    let node = fdt
+        .nodes()
+        .find(|n| Ok(n.name()? == node))
+        .unwrap()
+        .unwrap();
+    let mut props = Vec::new();
+    let mut iter = node.props();
+    while let Some(prop) = iter.next().unwrap() {
+        props.push((prop.name().unwrap(), prop.propbuf()));
+    }
+    props
+}
+
+#[test]
+fn modifier_path_edits() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let props = [MetadataProp::new("count", MetadataValue::U32(3))];
+    let node = MetadataNode::new("acme,boot", &props);
+
+    let mut modifier = DevTreeModifier::new(&mut scratch, 8).unwrap();
+    modifier
+        .set_prop("/chosen", "bootargs", MetadataValue::Str("quiet"))
+        .unwrap()
+        .set_prop(
+            "/chosen",
+            "linux,initrd-start",
+            MetadataValue::U32(0x8400_0000),
+        )
+        .unwrap()
+        .delete_prop("/chosen", "stdout-path")
+        .unwrap()
+        .delete_node("/cpus/cpu-map")
+        .unwrap()
+        .add_node("/chosen", &node)
+        .unwrap();
+    let mut out = OutBuf::new();
+    let size = modifier.apply(&fdt, &mut out.0).unwrap();
+
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(
+        node_props(&modified, "chosen"),
+        [
+            ("bootargs", &b"quiet\0"[..]),
+            ("linux,initrd-start", &[0x84, 0, 0, 0][..]),
+        ]
+    );
+    assert_eq!(
+        node_props(&modified, "acme,boot"),
+        [("count", &[0, 0, 0, 3][..])]
+    );
+    let expected: Vec<&str> = node_names(&fdt)
+        .into_iter()
+        .filter(|name| !["cpu-map", "cluster0", "core0"].contains(name))
+        .flat_map(|name| {
+            let added = if name == "chosen" {
+                Some("acme,boot")
+            } else {
+                None
+            };
+            core::iter::once(name).chain(added)
+        })
+        .collect();
+    assert_eq!(node_names(&modified), expected);
+
+    // Applying the same edits again gives the same tree.
+    let mut again = OutBuf::new();
+    assert_eq!(modifier.apply(&fdt, &mut again.0).unwrap(), size);
+    assert_eq!(out.0[..size], again.0[..size]);
+}
+
+#[test]
+fn modifier_errors() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 1).unwrap();
+    modifier
+        .set_prop("/missing", "status", MetadataValue::Str("okay"))
+        .unwrap();
+    assert_eq!(
+        modifier.delete_node("/cpus").unwrap_err(),
+        DevTreeError::NotEnoughMemory
+    );
+    let mut out = OutBuf::new();
+    assert!(matches!(
+        modifier.apply(&fdt, &mut out.0),
+        Err(DevTreeError::InvalidParameter(_))
+    ));
+}
+
+#[test]
+fn modifier_size_budget() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let bootargs = [b'x'; 2048];
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 3).unwrap();
+    modifier
+        .delete_node("/cpus")
+        .unwrap()
+        .set_prop("/chosen", "bootargs", MetadataValue::Bytes(&bootargs))
+        .unwrap()
+        .delete_prop("/chosen", "stdout-path")
+        .unwrap();
+    let mut out = OutBuf::new();
+    let size = modifier.apply(&fdt, &mut out.0).unwrap();
+    let expected = out.0[..size].to_vec();
+
+    // The budget is checked before anything is written, and names the edit which broke it.
+    modifier.set_size_budget(FDT.len());
+    assert_eq!(
+        modifier.apply(&fdt, &mut out.0).unwrap_err(),
+        DevTreeError::SizeBudgetExceeded {
+            edit: Some(1),
+            needed: size,
+            budget: FDT.len(),
+        }
+    );
+
+    modifier.set_size_budget(size);
+    assert_eq!(modifier.apply(&fdt, &mut out.0).unwrap(), size);
+    assert_eq!(out.0[..size], expected[..]);
+
+    // A tree already over budget isn't blamed on an edit.
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 1).unwrap();
+    modifier.delete_prop("/chosen", "bootargs").unwrap();
+    modifier.set_size_budget(FDT.len() / 2);
+    assert!(matches!(
+        modifier.apply(&fdt, &mut out.0),
+        Err(DevTreeError::SizeBudgetExceeded { edit: None, .. })
+    ));
+}
+
+#[test]
+fn modifier_size_budget_new_prop() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let blob = [b'x'; 2048];
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 2).unwrap();
+    modifier
+        .set_prop("/chosen", "test,blob", MetadataValue::Bytes(&blob))
+        .unwrap()
+        .set_prop("/chosen", "test,flag", MetadataValue::U32(1))
+        .unwrap();
+    let mut out = OutBuf::new();
+    let size = modifier.apply(&fdt, &mut out.0).unwrap();
+
+    // Neither the last apply nor the budget's own dry runs hide the properties the edits add.
+    modifier.set_size_budget(FDT.len());
+    assert_eq!(
+        modifier.apply(&fdt, &mut out.0).unwrap_err(),
+        DevTreeError::SizeBudgetExceeded {
+            edit: Some(0),
+            needed: size,
+            budget: FDT.len(),
+        }
+    );
+    assert_eq!(
+        modifier.apply(&fdt, &mut out.0).unwrap_err(),
+        DevTreeError::SizeBudgetExceeded {
+            edit: Some(0),
+            needed: size,
+            budget: FDT.len(),
+        }
+    );
+    modifier.set_size_budget(size);
+    assert_eq!(modifier.apply(&fdt, &mut out.0).unwrap(), size);
+}
+
+fn reservations(fdt: &DevTree) -> Vec<MemReservation> {
+    fdt.reserved_entries()
+        .map(|e| MemReservation::new(e.address.into(), e.size.into()))
+        .collect()
+}
+
+#[test]
+fn edit_mem_reservations() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let source = reservations(&fdt);
+    let hyp = MemReservation::new(0x8000_0000, 0x20_0000);
+    let shm = MemReservation::new(0x8800_0000, 0x1000);
+    let appended = [hyp, shm];
+    let options = ModifyOptions {
+        mem_reserve: MemReserveEdits {
+            append: &appended,
+            ..MemReserveEdits::default()
+        },
+        ..ModifyOptions::default()
+    };
+    let mut out = OutBuf::new();
+    let size =
+        Serializer::modify_with_options(&fdt, &mut out.0, &options, |_| ModifyTokenResponse::Pass)
+            .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    let mut expected = source.clone();
+    expected.extend_from_slice(&appended);
+    assert_eq!(reservations(&modified), expected);
+    assert_eq!(node_names(&modified), node_names(&fdt));
+
+    let bigger_shm = MemReservation::new(0x8800_0000, 0x2000);
+    let replaced = [(shm, bigger_shm)];
+    let options = ModifyOptions {
+        mem_reserve: MemReserveEdits {
+            remove: &[hyp],
+            replace: &replaced,
+            append: &[],
+        },
+        ..ModifyOptions::default()
+    };
+    let mut again = OutBuf::new();
+    let size = Serializer::modify_with_options(&modified, &mut again.0, &options, |_| {
+        ModifyTokenResponse::Pass
+    })
+    .unwrap();
+    let modified = unsafe { DevTree::new(&again.0[..size]) }.unwrap();
+    let mut expected = source;
+    expected.push(bigger_shm);
+    assert_eq!(reservations(&modified), expected);
+}
+
+/// Serialize `modifier`'s edits of the test tree, returning the output and its size.
+fn modified_fdt(modifier: &DevTreeModifier) -> (OutBuf, usize) {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let size = modifier.apply(&fdt, &mut out.0).unwrap();
+    (out, size)
+}
+
+#[test]
+fn apply_incremental_same_size() {
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut first = DevTreeModifier::new(&mut scratch, 1).unwrap();
+    first
+        .set_prop("/chosen", "bootargs", MetadataValue::Str("quiet"))
+        .unwrap();
+    let (mut out, size) = modified_fdt(&first);
+
+    let mut second = DevTreeModifier::new(&mut scratch, 2).unwrap();
+    second
+        .set_prop("/chosen", "bootargs", MetadataValue::Str("loud"))
+        .unwrap()
+        .set_prop("/poweroff", "value", MetadataValue::U32(0x6666))
+        .unwrap();
+    let mut expected = OutBuf::new();
+    let expected_size = {
+        let prev = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+        second.apply(&prev, &mut expected.0).unwrap()
+    };
+
+    // No scratch space is needed to patch values of the same size.
+    let patched = unsafe { second.apply_incremental(&mut out.0[..size], &mut []) }.unwrap();
+    assert_eq!(patched, expected_size);
+    assert_eq!(out.0[..patched], expected.0[..expected_size]);
+}
+
+#[test]
+fn apply_incremental_grows_into_slack() {
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let props = [MetadataProp::new("acme,count", MetadataValue::U64(7))];
+    let node = MetadataNode::new("acme,boot", &props);
+    let mut edits = DevTreeModifier::new(&mut scratch, 8).unwrap();
+    edits
+        .set_prop("/chosen", "bootargs", MetadataValue::Str("console=ttyS0"))
+        .unwrap()
+        .set_prop("/chosen", "acme,slot", MetadataValue::Str("b"))
+        .unwrap()
+        .delete_prop("/chosen", "stdout-path")
+        .unwrap()
+        .delete_node("/cpus/cpu-map")
+        .unwrap()
+        .add_node("/chosen", &node)
+        .unwrap()
+        .set_prop("/cpus/cpu@0", "status", MetadataValue::Str("okay"))
+        .unwrap();
+    let (expected, expected_size) = modified_fdt(&edits);
+    let expected = unsafe { DevTree::new(&expected.0[..expected_size]) }.unwrap();
+
+    let mut out = OutBuf::new();
+    out.0[..FDT.len()].copy_from_slice(FDT);
+    let size = unsafe { edits.apply_incremental(&mut out.0, &mut []) }.unwrap();
+    assert_eq!(size, expected_size);
+    let patched = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(node_names(&patched), node_names(&expected));
+    assert_eq!(
+        prop_names_and_values(&patched),
+        prop_names_and_values(&expected)
+    );
+}
+
+#[test]
+fn apply_incremental_falls_back_without_slack() {
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut edits = DevTreeModifier::new(&mut scratch, 2).unwrap();
+    edits
+        .set_prop("/chosen", "bootargs", MetadataValue::Str("console=ttyS0"))
+        .unwrap()
+        .delete_prop("/chosen", "stdout-path")
+        .unwrap();
+    let (expected, expected_size) = modified_fdt(&edits);
+    assert!(expected_size <= FDT.len());
+
+    // Growth is checked before deletions free any space, so this must be fully re-serialized.
+    let mut out = OutBuf::new();
+    out.0[..FDT.len()].copy_from_slice(FDT);
+    assert!(unsafe { edits.apply_incremental(&mut out.0[..FDT.len()], &mut []) }.is_err());
+
+    let mut work = OutBuf::new();
+    let size = unsafe { edits.apply_incremental(&mut out.0[..FDT.len()], &mut work.0) }.unwrap();
+    assert_eq!(out.0[..size], expected.0[..expected_size]);
+}
+
+#[test]
+fn apply_incremental_size_budget() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut edits = DevTreeModifier::new(&mut scratch, 1).unwrap();
+    edits
+        .set_prop("/chosen", "bootargs", MetadataValue::Str("console=ttyS0"))
+        .unwrap()
+        .set_size_budget(FDT.len());
+    let mut work = OutBuf::new();
+    let err = edits.apply(&fdt, &mut work.0).unwrap_err();
+    assert!(matches!(err, DevTreeError::SizeBudgetExceeded { .. }));
+
+    // The budget is enforced even though the edit could be patched into the slack, and nothing
+    // is patched.
+    let mut out = OutBuf::new();
+    out.0[..FDT.len()].copy_from_slice(FDT);
+    assert_eq!(
+        unsafe { edits.apply_incremental(&mut out.0, &mut []) }.unwrap_err(),
+        err
+    );
+    assert_eq!(&out.0[..FDT.len()], FDT);
+
+    edits.set_size_budget(FDT.len() + 16);
+    let (expected, expected_size) = modified_fdt(&edits);
+    let expected = unsafe { DevTree::new(&expected.0[..expected_size]) }.unwrap();
+    let size = unsafe { edits.apply_incremental(&mut out.0, &mut []) }.unwrap();
+    assert_eq!(size, expected_size);
+    let patched = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(
+        prop_names_and_values(&patched),
+        prop_names_and_values(&expected)
+    );
+}
+
+#[test]
+fn padding() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let options = ModifyOptions {
+        padding: 1000,
+        ..ModifyOptions::default()
+    };
+    let mut out = OutBuf::new();
+    out.0.iter_mut().for_each(|b| *b = 0xff);
+    let size =
+        Serializer::modify_with_options(&fdt, &mut out.0, &options, |_| ModifyTokenResponse::Pass)
+            .unwrap();
+    assert_eq!(size, FDT.len() + 1000);
+
+    let padded = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(padded.totalsize(), size);
+    let strings_end = padded.off_dt_strings() + padded.size_dt_strings() as usize;
+    assert_eq!(strings_end, FDT.len());
+    assert!(out.0[strings_end..size].iter().all(|&b| b == 0));
+    assert_eq!(node_names(&padded), node_names(&fdt));
+
+    let mut scratch = [0u8; 1024];
+    let dry_size =
+        Serializer::dry_run(&fdt, &mut scratch, &options, |_| ModifyTokenResponse::Pass).unwrap();
+    assert_eq!(dry_size, size);
+
+    let err = Serializer::modify_with_options(&fdt, &mut out.0[..size - 1], &options, |_| {
+        ModifyTokenResponse::Pass
+    })
+    .unwrap_err();
+    assert!(matches!(err, DevTreeError::OutputBufferTooSmall { .. }));
+}
+
+#[test]
+fn preserve_layout() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let options = ModifyOptions {
+        preserve_layout: true,
+        ..ModifyOptions::default()
+    };
+    let drop_flash = |tok: ModifyParsedTok| match tok {
+        ModifyParsedTok::BeginNode(node, _) if node.name == b"flash@20000000" => {
+            ModifyTokenResponse::Drop
+        }
+        _ => ModifyTokenResponse::Pass,
+    };
+    let mut expected = OutBuf::new();
+    let expected_size = Serializer::modify_with_options(
+        &fdt,
+        &mut expected.0,
+        &ModifyOptions::default(),
+        drop_flash,
+    )
+    .unwrap();
+    let expected = unsafe { DevTree::new(&expected.0[..expected_size]) }.unwrap();
+
+    let mut out = OutBuf::new();
+    out.0.iter_mut().for_each(|b| *b = 0xff);
+    let size = Serializer::modify_with_options(&fdt, &mut out.0, &options, drop_flash).unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(modified.off_mem_rsvmap(), fdt.off_mem_rsvmap());
+    assert_eq!(modified.off_dt_struct(), fdt.off_dt_struct());
+    assert_eq!(modified.off_dt_strings(), fdt.off_dt_strings());
+    assert!(modified.size_dt_struct() < fdt.size_dt_struct());
+    let struct_end = modified.off_dt_struct() + modified.size_dt_struct() as usize;
+    assert!(out.0[struct_end..modified.off_dt_strings()]
+        .iter()
+        .all(|&b| b == 0));
+    assert_eq!(canonical(&modified), canonical(&expected));
+
+    let mut scratch = [0u8; 1024];
+    let dry_size = Serializer::dry_run(&fdt, &mut scratch, &options, drop_flash).unwrap();
+    assert_eq!(dry_size, size);
+
+    // Growing the structure block would move the strings block.
+    let err = Serializer::modify_with_options(&fdt, &mut out.0, &options, |tok| match tok {
+        ModifyParsedTok::Prop(_, b"model", _) => {
+            ModifyTokenResponse::Replace(ReplacementTok::Prop {
+                name: "model",
+                value: &[0; 64],
+            })
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap_err();
+    assert!(matches!(err, DevTreeError::InvalidParameter(_)));
+}
+
+#[test]
+fn modifier_graft() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let source = unsafe { DevTree::new(BINDINGS_FDT) }.unwrap();
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 1).unwrap();
+    modifier.graft("/soc", &source, "/template").unwrap();
+    let mut out = OutBuf::new();
+    let size = modifier.apply(&fdt, &mut out.0).unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+
+    let names = node_names(&modified);
+    let soc_end = node_names(&fdt).len();
+    assert_eq!(names[..soc_end], node_names(&fdt)[..]);
+    assert_eq!(
+        names[soc_end..],
+        ["template", "interrupt-controller", "device@0"]
+    );
+
+    // The template's phandles are renumbered above those of the destination, whose largest is
+    // 4. The reference to a node outside the template is left alone.
+    let cells = |node: &str, prop: &str| {
+        // The grafted nodes are last, after any of the same name in the destination.
+        let node = modified
+            .nodes()
+            .filter(|n| Ok(n.name()? == node))
+            .last()
+            .unwrap()
+            .unwrap();
+        let mut props = node.props();
+        let prop = props.find(|p| Ok(p.name()? == prop)).unwrap().unwrap();
+        prop.u32(0).unwrap()
+    };
+    assert_eq!(cells("interrupt-controller", "phandle"), 7 + 4);
+    assert_eq!(cells("device@0", "interrupt-parent"), 7 + 4);
+    assert_eq!(cells("device@0", "interrupts"), 5);
+    assert_eq!(cells("device@0", "pinctrl-0"), 1);
+
+    let mut modifier = DevTreeModifier::new(&mut scratch, 1).unwrap();
+    modifier.graft("/soc", &source, "/missing").unwrap();
+    assert!(modifier.apply(&fdt, &mut out.0).is_err());
+    let mut modifier = DevTreeModifier::new(&mut scratch, 1).unwrap();
+    modifier.graft("/missing", &source, "/template").unwrap();
+    assert!(modifier.apply(&fdt, &mut out.0).is_err());
+}
+
+#[test]
+fn patch_round_trip() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let source = unsafe { DevTree::new(BINDINGS_FDT) }.unwrap();
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 5).unwrap();
+    modifier
+        .set_prop("/", "model", MetadataValue::Str("patched"))
+        .unwrap()
+        .set_prop(
+            "/chosen",
+            "linux,initrd-start",
+            MetadataValue::U64(0x8800_0000),
+        )
+        .unwrap()
+        .delete_prop("/", "compatible")
+        .unwrap()
+        .delete_node("/cpus")
+        .unwrap()
+        .graft("/soc", &source, "/template")
+        .unwrap();
+    let mut out = OutBuf::new();
+    let size = modifier.apply(&fdt, &mut out.0).unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+
+    let mut blob = [0u8; 1024];
+    let mut path_buf = [0u8; 256];
+    let mut writer = PatchWriter::new(&mut blob);
+    writer.push_diff(&fdt, &modified, &mut path_buf).unwrap();
+    let len = writer.finish().unwrap();
+    let patch = Patch::new(&blob[..len]).unwrap();
+
+    let records: Vec<PatchRecord> = patch
+        .records()
+        .iterator()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(records.len(), patch.len());
+    assert!(records.contains(&PatchRecord::SetProp {
+        path: "/",
+        name: "model",
+        old: Some(b"riscv-virtio,qemu\0"),
+        new: b"patched\0",
+    }));
+    assert!(records.contains(&PatchRecord::SetProp {
+        path: "/chosen",
+        name: "linux,initrd-start",
+        old: None,
+        new: &[0, 0, 0, 0, 0x88, 0, 0, 0],
+    }));
+    assert!(records.contains(&PatchRecord::DeleteNode { path: "/cpus" }));
+    let added: Vec<&str> = records
+        .iter()
+        .filter(|r| matches!(r, PatchRecord::AddNode { .. }))
+        .map(PatchRecord::path)
+        .collect();
+    assert_eq!(
+        added,
+        [
+            "/soc/template",
+            "/soc/template/interrupt-controller",
+            "/soc/template/device@0"
+        ]
+    );
+
+    let mut patched = OutBuf::new();
+    let mut mem = [0u8; 512];
+    let size = patch
+        .apply(&fdt, &mut patched.0, &mut ScratchArena::new(&mut mem))
+        .unwrap();
+    let patched = unsafe { DevTree::new(&patched.0[..size]) }.unwrap();
+    assert!(patched.diff(&modified).next().unwrap().is_none());
+    assert_eq!(node_names(&patched), node_names(&modified));
+
+    // The patched tree no longer has the old values.
+    assert!(matches!(
+        patch.check(&modified),
+        Err(DevTreeError::PatchMismatch { record: 0 })
+    ));
+
+    let mut small = [0u8; 64];
+    let mut writer = PatchWriter::new(&mut small);
+    assert!(matches!(
+        writer.push_diff(&fdt, &modified, &mut path_buf),
+        Err(DevTreeError::OutputBufferTooSmall { .. })
+    ));
+    assert!(matches!(
+        Patch::new(&blob[..len - 1]),
+        Err(DevTreeError::ParseError)
+    ));
+    assert!(matches!(Patch::new(FDT), Err(DevTreeError::ParseError)));
+}
+
+#[test]
+fn modifier_graft_to_bus() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut guest_out = OutBuf::new();
+    let mut builder = FdtBuilder::new(&mut guest_out.0);
+    builder.begin_node("").unwrap();
+    builder.prop_u32("#address-cells", 2).unwrap();
+    builder.prop_u32("#size-cells", 2).unwrap();
+    builder.begin_simple_bus("soc", 2, 2).unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let guest = unsafe { DevTree::new(&guest_out.0[..size]) }.unwrap();
+
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 3).unwrap();
+    modifier
+        .graft_to_bus("/passthrough", &fdt, "/uart@10000000")
+        .unwrap()
+        .graft_to_bus("/soc", &fdt, "/soc/clint@2000000")
+        .unwrap()
+        .graft_to_bus("/passthrough", &fdt, "/rtc@101000")
+        .unwrap();
+    let mut out = OutBuf::new();
+    let size = modifier.apply(&guest, &mut out.0).unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+
+    // The existing bus is kept, and the new one holds both of the nodes grafted to it.
+    assert_eq!(
+        node_names(&modified),
+        [
+            "",
+            "soc",
+            "clint@2000000",
+            "passthrough",
+            "uart@10000000",
+            "rtc@101000"
+        ]
+    );
+    let bus_props = [
+        ("compatible", &b"simple-bus\0"[..]),
+        ("#address-cells", &[0, 0, 0, 2][..]),
+        ("#size-cells", &[0, 0, 0, 2][..]),
+        ("ranges", &[][..]),
+    ];
+    assert_eq!(node_props(&guest, "soc"), bus_props);
+    assert_eq!(node_props(&modified, "passthrough"), bus_props);
+    for node in &["clint@2000000", "uart@10000000", "rtc@101000"] {
+        assert_eq!(node_props(&modified, node), node_props(&fdt, node));
+    }
+
+    // A node whose addresses aren't CPU addresses.
+    let mut modifier = DevTreeModifier::new(&mut scratch, 1).unwrap();
+    modifier
+        .graft_to_bus("/passthrough", &fdt, "/cpus/cpu@0")
+        .unwrap();
+    assert!(matches!(
+        modifier.apply(&guest, &mut out.0),
+        Err(DevTreeError::InvalidParameter(_))
+    ));
+    let mut modifier = DevTreeModifier::new(&mut scratch, 1).unwrap();
+    modifier
+        .graft_to_bus("/missing/passthrough", &fdt, "/uart@10000000")
+        .unwrap();
+    assert!(modifier.apply(&guest, &mut out.0).is_err());
+}
+
+#[test]
+fn modifier_graft_to_bus_incremental() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 1).unwrap();
+    modifier
+        .graft_to_bus("/passthrough", &fdt, "/uart@10000000")
+        .unwrap();
+    let (expected, expected_size) = modified_fdt(&modifier);
+
+    // Grafts are serialized in full, even with slack to patch in place.
+    let mut out = OutBuf::new();
+    out.0[..FDT.len()].copy_from_slice(FDT);
+    let mut work = OutBuf::new();
+    let size = unsafe { modifier.apply_incremental(&mut out.0, &mut work.0) }.unwrap();
+    assert_eq!(out.0[..size], expected.0[..expected_size]);
+    let patched = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert!(patched
+        .node_at_path("/passthrough/uart@10000000")
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn overlay_metadata() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let overlay = OverlayMetadata {
+        symbols: &[
+            OverlaySymbol::new("uart0", "/soc/uart@10000000"),
+            OverlaySymbol::new("plic", "/soc/plic@c000000"),
+        ],
+        fixups: &[
+            OverlayFixup::new("gpio", "/soc/uart@10000000", "gpios", 0),
+            OverlayFixup::new("clk", "/soc/uart@10000000", "clocks", 4),
+            OverlayFixup::new("gpio", "/soc/plic@c000000", "gpios", 12),
+        ],
+        local_fixups: &[
+            LocalFixup::new("/soc/uart@10000000/", "interrupt-parent", 0),
+            LocalFixup::new("/", "phandles", 0),
+            LocalFixup::new("/soc/plic@c000000", "interrupts-extended", 0),
+            LocalFixup::new("/soc/plic@c000000", "interrupts-extended", 8),
+        ],
+    };
+    let options = ModifyOptions {
+        overlay: Some(&overlay),
+        ..ModifyOptions::default()
+    };
+    let mut out = OutBuf::new();
+    let size =
+        Serializer::modify_with_options(&fdt, &mut out.0, &options, |_| ModifyTokenResponse::Pass)
+            .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+
+    let names = node_names(&modified);
+    let end = node_names(&fdt).len();
+    assert_eq!(names[..end], node_names(&fdt)[..]);
+    assert_eq!(
+        names[end..],
+        [
+            "__symbols__",
+            "__fixups__",
+            "__local_fixups__",
+            "soc",
+            "uart@10000000",
+            "plic@c000000"
+        ]
+    );
+
+    // The overlay nodes are last, after any of the same name in the source tree.
+    let props = |node: &str| {
+        let node = modified
+            .nodes()
+            .filter(|n| Ok(n.name()? == node))
+            .last()
+            .unwrap()
+            .unwrap();
+        let mut props = Vec::new();
+        let mut iter = node.props();
+        while let Some(prop) = iter.next().unwrap() {
+            props.push((prop.name().unwrap(), prop.propbuf()));
+        }
+        props
+    };
+    assert_eq!(
+        props("__symbols__"),
+        [
+            ("uart0", &b"/soc/uart@10000000\0"[..]),
+            ("plic", b"/soc/plic@c000000\0"),
+        ]
+    );
+    assert_eq!(
+        props("__fixups__"),
+        [
+            (
+                "gpio",
+                &b"/soc/uart@10000000:gpios:0\0/soc/plic@c000000:gpios:12\0"[..]
+            ),
+            ("clk", b"/soc/uart@10000000:clocks:4\0"),
+        ]
+    );
+    assert_eq!(props("__local_fixups__"), [("phandles", &[0u8; 4][..])]);
+    assert_eq!(props("soc"), []);
+    assert_eq!(
+        props("uart@10000000"),
+        [("interrupt-parent", &[0u8; 4][..])]
+    );
+    assert_eq!(
+        props("plic@c000000"),
+        [("interrupts-extended", &[0, 0, 0, 0, 0, 0, 0, 8][..])]
+    );
+
+    let mut scratch = [0u8; 1024];
+    let dry_size =
+        Serializer::dry_run(&fdt, &mut scratch, &options, |_| ModifyTokenResponse::Pass).unwrap();
+    assert_eq!(dry_size, size);
+}
+
+#[test]
+fn builder() {
+    let mut out = OutBuf::new();
+    let mut builder = FdtBuilder::new(&mut out.0);
+    builder.set_boot_cpuid_phys(3);
+    builder.add_reservation(0x8000_0000, 0x1000).unwrap();
+    builder.begin_node("").unwrap();
+    builder.prop_u32("#address-cells", 1).unwrap();
+    builder.prop_str("compatible", "vmm,virt").unwrap();
+    builder.begin_node("uart@1000").unwrap();
+    builder.prop_str("compatible", "ns16550a").unwrap();
+    builder
+        .prop_bytes("reg", &[0, 0, 0x10, 0, 0, 0, 1, 0])
+        .unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node("chosen").unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+
+    let fdt = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(fdt.totalsize(), size);
+    assert_eq!(fdt.version(), 17);
+    assert_eq!(fdt.last_comp_version(), 16);
+    assert_eq!(fdt.boot_cpuid_phys(), 3);
+    assert_eq!(
+        reservations(&fdt),
+        [MemReservation::new(0x8000_0000, 0x1000)]
+    );
+    assert_eq!(node_names(&fdt), ["", "uart@1000", "chosen"]);
+    assert_eq!(
+        prop_names_and_values(&fdt),
+        [
+            ("#address-cells", &[0, 0, 0, 1][..]),
+            ("compatible", b"vmm,virt\0"),
+            ("compatible", b"ns16550a\0"),
+            ("reg", &[0, 0, 0x10, 0, 0, 0, 1, 0]),
+        ]
+    );
+    // Each name is stored once.
+    assert_eq!(strings_block(&fdt), b"#address-cells\0compatible\0reg\0");
+}
+
+#[test]
+fn builder_errors() {
+    let mut out = OutBuf::new();
+    let mut builder = FdtBuilder::new(&mut out.0);
+    assert!(builder.prop_u32("a", 1).is_err());
+    assert!(builder.end_node().is_err());
+    assert!(builder.begin_node("root").is_err());
+    builder.begin_node("").unwrap();
+    assert!(builder.add_reservation(0, 1).is_err());
+    builder.begin_node("child").unwrap();
+    builder.end_node().unwrap();
+    assert!(builder.prop_u32("late", 1).is_err());
+    assert!(matches!(
+        builder.finish(),
+        Err(DevTreeError::InvalidParameter(_))
+    ));
+
+    let mut builder = FdtBuilder::new(&mut out.0);
+    builder.begin_node("").unwrap();
+    builder.end_node().unwrap();
+    assert!(builder.begin_node("").is_err());
+    builder.finish().unwrap();
+
+    let mut small = [0u8; 64];
+    let mut builder = FdtBuilder::new(&mut small);
+    builder.begin_node("").unwrap();
+    let err = builder
+        .prop_str("compatible", "too long to fit")
+        .unwrap_err();
+    assert!(matches!(err, DevTreeError::OutputBufferTooSmall { .. }));
+}
+
+#[test]
+fn builder_validation() {
+    let mut out = OutBuf::new();
+    let mut builder = FdtBuilder::new(&mut out.0);
+    builder.set_validation(true);
+    builder.begin_node("").unwrap();
+    builder.prop_u32("#address-cells", 1).unwrap();
+    assert!(builder.prop_u32("bad name", 1).is_err());
+    assert!(builder.prop_u32("#address-cells", 2).is_err());
+    assert!(builder.begin_node("uart@").is_err());
+    assert!(builder.begin_node("0uart").is_err());
+    builder.begin_node("uart@1000").unwrap();
+    // A property of the same name as one of its parent's is fine.
+    builder.prop_u32("#address-cells", 1).unwrap();
+    builder.prop_phandle(1).unwrap();
+    assert!(builder.prop_phandle(2).is_err());
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let fdt = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(node_names(&fdt), ["", "uart@1000"]);
+
+    // Without validation, names are only checked for what the format can't hold.
+    let mut builder = FdtBuilder::new(&mut out.0);
+    builder.begin_node("").unwrap();
+    builder.prop_u32("bad name", 1).unwrap();
+    builder.prop_u32("bad name", 2).unwrap();
+    builder.begin_node("0uart").unwrap();
+}
+
+fn phandle_props<'dt>(fdt: &DevTree<'dt>) -> Vec<(&'dt str, u32)> {
+    prop_names_and_values(fdt)
+        .into_iter()
+        .filter(|(name, _)| *name == "phandle" || *name == "linux,phandle")
+        .map(|(name, value)| (name, u32::from_be_bytes(value.try_into().unwrap())))
+        .collect()
+}
+
+#[test]
+fn phandle_style() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let serialize = |fdt: &DevTree, out: &mut OutBuf, style| {
+        let options = ModifyOptions {
+            phandle_style: Some(style),
+            ..ModifyOptions::default()
+        };
+        let size = Serializer::modify_with_options(fdt, &mut out.0, &options, |_| {
+            ModifyTokenResponse::Pass
+        })
+        .unwrap();
+        let mut scratch = [0u8; 1024];
+        let dry_size =
+            Serializer::dry_run(fdt, &mut scratch, &options, |_| ModifyTokenResponse::Pass)
+                .unwrap();
+        assert_eq!(dry_size, size);
+        size
+    };
+
+    let mut both = OutBuf::new();
+    let size = serialize(&fdt, &mut both, PhandleStyle::Both);
+    let both = unsafe { DevTree::new(&both.0[..size]) }.unwrap();
+    assert_eq!(
+        phandle_props(&both),
+        [
+            ("linux,phandle", 4),
+            ("phandle", 4),
+            ("linux,phandle", 1),
+            ("phandle", 1),
+            ("linux,phandle", 2),
+            ("phandle", 2),
+            ("linux,phandle", 3),
+            ("phandle", 3),
+        ]
+    );
+
+    let mut linux = OutBuf::new();
+    let size = serialize(&both, &mut linux, PhandleStyle::Linux);
+    let linux = unsafe { DevTree::new(&linux.0[..size]) }.unwrap();
+    assert_eq!(
+        phandle_props(&linux),
+        [
+            ("linux,phandle", 4),
+            ("linux,phandle", 1),
+            ("linux,phandle", 2),
+            ("linux,phandle", 3)
+        ]
+    );
+
+    // Normalizing back to `phandle` restores the original properties.
+    let mut phandle = OutBuf::new();
+    let size = serialize(&linux, &mut phandle, PhandleStyle::Phandle);
+    let phandle = unsafe { DevTree::new(&phandle.0[..size]) }.unwrap();
+    assert_eq!(prop_names_and_values(&phandle), prop_names_and_values(&fdt));
+
+    // A phandle the callback renames is left alone. (Other cells of 4 are renamed too.)
+    let options = ModifyOptions {
+        phandle_style: Some(PhandleStyle::Both),
+        ..ModifyOptions::default()
+    };
+    let mut out = OutBuf::new();
+    let size = Serializer::modify_with_options(&fdt, &mut out.0, &options, |tok| match tok {
+        ModifyParsedTok::Prop(prop, _, _) if prop.prop_buf == [0, 0, 0, 4] => {
+            ModifyTokenResponse::Replace(ReplacementTok::Prop {
+                name: "old-phandle",
+                value: &[0, 0, 0, 4],
+            })
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(phandle_props(&modified).len(), 6);
+    assert!(prop_names_and_values(&modified)
+        .iter()
+        .any(|&(name, _)| name == "old-phandle"));
+}
+
+#[test]
+fn phandle_style_of_new_phandles() {
+    let mut out = OutBuf::new();
+    let mut builder = FdtBuilder::new(&mut out.0);
+    builder.set_phandle_style(PhandleStyle::Both);
+    builder.begin_node("").unwrap();
+    builder.prop_phandle(1).unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let built = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(
+        phandle_props(&built),
+        [("linux,phandle", 1), ("phandle", 1)]
+    );
+
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let source = unsafe { DevTree::new(BINDINGS_FDT) }.unwrap();
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 1).unwrap();
+    modifier
+        .graft("/soc", &source, "/template")
+        .unwrap()
+        .set_phandle_style(PhandleStyle::Linux);
+    let size = modifier.apply(&fdt, &mut out.0).unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(
+        phandle_props(&modified),
+        [
+            ("linux,phandle", 4),
+            ("linux,phandle", 1),
+            ("linux,phandle", 2),
+            ("linux,phandle", 3),
+            ("linux,phandle", 7 + 4),
+        ]
+    );
+}
+
+#[test]
+fn header_overrides() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let serialize = |header| -> Result<(u32, u32, u32), DevTreeError> {
+        let options = ModifyOptions {
+            header,
+            ..ModifyOptions::default()
+        };
+        let mut out = OutBuf::new();
+        let size = Serializer::modify_with_options(&fdt, &mut out.0, &options, |_| {
+            ModifyTokenResponse::Pass
+        })?;
+        let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+        Ok((
+            modified.version(),
+            modified.last_comp_version(),
+            modified.boot_cpuid_phys(),
+        ))
+    };
+
+    let source = (fdt.version(), 16, fdt.boot_cpuid_phys());
+    assert_eq!(serialize(HeaderOverrides::default()).unwrap(), source);
+    assert_eq!(
+        serialize(HeaderOverrides {
+            boot_cpuid_phys: Some(2),
+            version: Some(16),
+            ..HeaderOverrides::default()
+        })
+        .unwrap(),
+        (16, 16, 2)
+    );
+    assert_eq!(
+        serialize(HeaderOverrides {
+            version: Some(17),
+            last_comp_version: Some(17),
+            ..HeaderOverrides::default()
+        })
+        .unwrap(),
+        (17, 17, source.2)
+    );
+    assert!(serialize(HeaderOverrides {
+        version: Some(3),
+        ..HeaderOverrides::default()
+    })
+    .is_err());
+    assert!(serialize(HeaderOverrides {
+        version: Some(16),
+        last_comp_version: Some(17),
+        ..HeaderOverrides::default()
+    })
+    .is_err());
+
+    // The versions aren't copied from a source tree which claims a newer layout, or
+    // compatibility with an older one.
+    let mut source = OutBuf::new();
+    source.0[..FDT.len()].copy_from_slice(FDT);
+    source.0[20..24].copy_from_slice(&18u32.to_be_bytes());
+    source.0[24..28].copy_from_slice(&2u32.to_be_bytes());
+    let fdt = unsafe { DevTree::new(&source.0[..FDT.len()]) }.unwrap();
+    let options = ModifyOptions {
+        gc_strings: true,
+        ..ModifyOptions::default()
+    };
+    let mut out = OutBuf::new();
+    let size = Serializer::modify_with_options(&fdt, &mut out.0, &options, |tok| match tok {
+        ModifyParsedTok::Prop(_, b"model", _) => ModifyTokenResponse::Drop,
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!((modified.version(), modified.last_comp_version()), (17, 16));
+    assert_eq!(modified.totalsize(), size);
+    assert!(modified.size_dt_strings() < fdt.size_dt_strings());
+    assert_eq!(
+        modified.off_dt_strings() + modified.size_dt_strings() as usize,
+        size
+    );
+}
+
+#[test]
+fn builder_labels() {
+    let mut out = OutBuf::new();
+    let mut mem = [0u8; 256];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut builder = FdtBuilder::new(&mut out.0);
+    builder.reserve_labels(&mut scratch, 2, 2).unwrap();
+    builder.begin_node("").unwrap();
+    builder.begin_node("uart@1000").unwrap();
+    // The interrupt controller is referred to before it's labelled.
+    builder
+        .prop_cells(
+            "interrupts-extended",
+            &[PropCell::Ref("intc"), PropCell::U32(5)],
+        )
+        .unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node("clock").unwrap();
+    builder.prop_phandle(7).unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node("interrupt-controller").unwrap();
+    assert_eq!(builder.label("intc").unwrap(), 8);
+    assert!(builder.label("intc").is_err());
+    builder
+        .prop_cells("interrupt-parent", &[PropCell::Ref("intc")])
+        .unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+
+    let fdt = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(
+        prop_names_and_values(&fdt),
+        [
+            ("interrupts-extended", &[0, 0, 0, 8, 0, 0, 0, 5][..]),
+            ("phandle", &[0, 0, 0, 7]),
+            ("phandle", &[0, 0, 0, 8]),
+            ("interrupt-parent", &[0, 0, 0, 8]),
+        ]
+    );
+
+    // References to undefined labels, and more labels or references than there's room for,
+    // are errors.
+    let mut builder = FdtBuilder::new(&mut out.0);
+    builder.begin_node("").unwrap();
+    assert!(matches!(
+        builder.label("root"),
+        Err(DevTreeError::NotEnoughMemory)
+    ));
+    assert!(matches!(
+        builder.prop_cells("a", &[PropCell::Ref("root")]),
+        Err(DevTreeError::NotEnoughMemory)
+    ));
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut builder = FdtBuilder::new(&mut out.0);
+    builder.reserve_labels(&mut scratch, 1, 1).unwrap();
+    builder.begin_node("").unwrap();
+    builder
+        .prop_cells("a", &[PropCell::Ref("missing")])
+        .unwrap();
+    builder.end_node().unwrap();
+    assert!(matches!(
+        builder.finish(),
+        Err(DevTreeError::InvalidParameter(_))
+    ));
+}
+
+#[test]
+fn offset_map() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut mappings = [OffsetMapping::default(); 256];
+    let map = OffsetMap::new(&mut mappings);
+    let options = ModifyOptions {
+        offset_map: Some(&map),
+        ..ModifyOptions::default()
+    };
+    let mut out = OutBuf::new();
+    let size = Serializer::modify_with_options(&fdt, &mut out.0, &options, |tok| {
+        drop_cpus_and_rename_model(tok)
+    })
+    .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(
+        map.len(),
+        modified.nodes().count().unwrap() + modified.props().count().unwrap()
+    );
+
+    // Each mapped token was written unchanged, other than the renamed model.
+    let tok_at = |fdt: &DevTree, offset| {
+        let mut iter = fdt.parse_iter();
+        iter.offset = offset;
+        match iter.next().unwrap().unwrap() {
+            ParsedTok::BeginNode(node) => node.name.to_vec(),
+            ParsedTok::Prop(prop) => prop.prop_buf.to_vec(),
+            _ => panic!("Mapped a token which isn't a node or property"),
+        }
+    };
+    for mapping in map.iter() {
+        assert_eq!(
+            tok_at(&fdt, mapping.source),
+            tok_at(&modified, mapping.output)
+        );
+        assert_eq!(map.output_offset(mapping.source), Some(mapping.output));
+    }
+
+    // Tokens within the dropped subtree aren't mapped.
+    let mut iter = fdt.parse_iter();
+    let cpus = loop {
+        let offset = iter.offset;
+        if let ParsedTok::BeginNode(node) = iter.next().unwrap().unwrap() {
+            if node.name == b"cpus" {
+                break offset;
+            }
+        }
+    };
+    assert_eq!(map.output_offset(cpus), None);
+
+    let mut mappings = [OffsetMapping::default(); 4];
+    let map = OffsetMap::new(&mut mappings);
+    let options = ModifyOptions {
+        offset_map: Some(&map),
+        ..ModifyOptions::default()
+    };
+    let err =
+        Serializer::modify_with_options(&fdt, &mut out.0, &options, |_| ModifyTokenResponse::Pass)
+            .unwrap_err();
+    assert!(matches!(err, DevTreeError::NotEnoughMemory));
+}
+
+#[test]
+fn pipeline() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let model = |ctx: &ModifyContext, prop: &[u8]| ctx.is_at("/") && prop == b"riscv-virtio,qemu\0";
+
+    let mut prune_calls = 0;
+    let mut prune_cpus = |ctx: &ModifyContext, tok: ModifyParsedTok| {
+        prune_calls += 1;
+        match tok {
+            ModifyParsedTok::BeginNode(..) if ctx.is_at("/cpus") => ModifyTokenResponse::Drop,
+            _ => ModifyTokenResponse::Pass,
+        }
+    };
+    let mut rename_model = |ctx: &ModifyContext, tok: ModifyParsedTok| match tok {
+        ModifyParsedTok::Prop(prop, _, value) if model(ctx, prop.prop_buf) => {
+            value[..5].copy_from_slice(b"acme\0");
+            ModifyTokenResponse::ModifySize(5)
+        }
+        _ => ModifyTokenResponse::Pass,
+    };
+    // Sees the model as renamed by the previous stage, and renames it again.
+    let mut suffix_model = |ctx: &ModifyContext, tok: ModifyParsedTok| match tok {
+        ModifyParsedTok::Prop(prop, _, value) if model(ctx, prop.prop_buf) => {
+            assert_eq!(&value[..5], b"acme\0");
+            value[4..9].copy_from_slice(b",vm1\0");
+            ModifyTokenResponse::ModifySize(9)
+        }
+        ModifyParsedTok::BeginNode(..) => {
+            assert!(!ctx.is_at("/cpus"), "Called for a dropped node");
+            ModifyTokenResponse::Pass
+        }
+        _ => ModifyTokenResponse::Pass,
+    };
+    let mut stages: [&mut dyn ModifyStage; 3] =
+        [&mut prune_cpus, &mut rename_model, &mut suffix_model];
+    let mut out = OutBuf::new();
+    let size = ModifyPipeline::new(&mut stages)
+        .apply(&fdt, &mut out.0, &ModifyOptions::default())
+        .unwrap();
+
+    // The same as applying each stage in its own pass.
+    let mut expected = OutBuf::new();
+    let expected_size = Serializer::modify(&fdt, &mut expected.0, |tok| match tok {
+        ModifyParsedTok::BeginNode(node, _) if node.name == b"cpus" => ModifyTokenResponse::Drop,
+        ModifyParsedTok::Prop(prop, _, _) if prop.prop_buf == b"riscv-virtio,qemu\0" => {
+            ModifyTokenResponse::Replace(ReplacementTok::Prop {
+                name: "model",
+                value: b"acme,vm1\0",
+            })
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+    assert_eq!(&out.0[..size], &expected.0[..expected_size]);
+
+    // Every token written, including the End token, and the start of the dropped node, was
+    // passed to the first stage.
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    let tokens = modified.nodes().count().unwrap() * 2 + modified.props().count().unwrap() + 1;
+    assert_eq!(prune_calls, tokens + 1);
+}
+
+/// Returns the cells of the property `name` of each node which has it, in tree order.
+fn cells_of(fdt: &DevTree, name: &str) -> Vec<Vec<u32>> {
+    let mut values = Vec::new();
+    let mut iter = fdt.props();
+    while let Some(prop) = iter.next().unwrap() {
+        if prop.name().unwrap() == name {
+            values.push(
+                (0..prop.length() / 4)
+                    .map(|i| prop.u32(i).unwrap())
+                    .collect(),
+            );
+        }
+    }
+    values
+}
+
+#[test]
+fn renumber_phandles() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut mem = [0u8; 64];
+    let mut renumber = PhandleRenumber::new(&fdt, &mut ScratchArena::new(&mut mem)).unwrap();
+    // The phandles are defined in the order 4, 1, 2, 3.
+    assert_eq!(renumber.renumbered(4), Some(1));
+    assert_eq!(renumber.renumbered(3), Some(4));
+    assert_eq!(renumber.renumbered(5), None);
+
+    let mut out = OutBuf::new();
+    let size = renumber
+        .apply(&mut out.0, &ModifyOptions::default())
+        .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(cells_of(&modified, "phandle"), [[1], [2], [3], [4]]);
+    assert_eq!(cells_of(&modified, "regmap"), [[1], [1]]);
+    assert!(cells_of(&modified, "interrupt-parent")
+        .iter()
+        .all(|parent| parent == &[4]));
+    assert_eq!(
+        cells_of(&modified, "interrupts-extended"),
+        [[3, 11, 3, 9], [3, 3, 3, 7]]
+    );
+    // Each entry of the map is 3 address cells, 1 interrupt cell, the parent and its 1 cell.
+    let map = &cells_of(&modified, "interrupt-map")[0];
+    let source_map = &cells_of(&fdt, "interrupt-map")[0];
+    for (i, (cell, source)) in map.iter().zip(source_map).enumerate() {
+        assert_eq!(*cell, if i % 6 == 4 { 4 } else { *source });
+    }
+
+    // Renumbering is deterministic, so renumbering again changes nothing.
+    let mut mem = [0u8; 64];
+    let mut renumber = PhandleRenumber::new(&modified, &mut ScratchArena::new(&mut mem)).unwrap();
+    let mut out2 = OutBuf::new();
+    let size2 = renumber
+        .apply(&mut out2.0, &ModifyOptions::default())
+        .unwrap();
+    assert_eq!(&out2.0[..size2], &out.0[..size]);
+
+    // As a stage of a pipeline.
+    let mut mem = [0u8; 64];
+    let mut renumber = PhandleRenumber::new(&fdt, &mut ScratchArena::new(&mut mem)).unwrap();
+    let mut stages: [&mut dyn ModifyStage; 1] = [&mut renumber];
+    let mut out3 = OutBuf::new();
+    let size3 = ModifyPipeline::new(&mut stages)
+        .apply(&fdt, &mut out3.0, &ModifyOptions::default())
+        .unwrap();
+    assert_eq!(&out3.0[..size3], &out.0[..size]);
+    assert!(renumber.check().is_ok());
+}
+
+#[test]
+fn renumber_phandles_errors() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let renumber = |path, name, phandle| {
+        let mut mem = [0u8; 256];
+        let mut scratch = ScratchArena::new(&mut mem);
+        let mut modifier = DevTreeModifier::new(&mut scratch, 1).unwrap();
+        modifier
+            .set_prop(path, name, MetadataValue::U32(phandle))
+            .unwrap();
+        let mut edited = OutBuf::new();
+        let size = modifier.apply(&fdt, &mut edited.0).unwrap();
+        let edited = unsafe { DevTree::new(&edited.0[..size]) }.unwrap();
+
+        let mut mem = [0u8; 64];
+        let mut out = OutBuf::new();
+        PhandleRenumber::new(&edited, &mut ScratchArena::new(&mut mem))
+            .and_then(|mut renumber| renumber.apply(&mut out.0, &ModifyOptions::default()))
+            .unwrap_err()
+    };
+    assert_eq!(
+        renumber("/cpus/cpu@0", "phandle", 4),
+        DevTreeError::InvalidParameter("Duplicate phandle")
+    );
+    assert_eq!(renumber("/poweroff", "regmap", 9), DevTreeError::ParseError);
+    assert_eq!(
+        renumber("/soc/clint@2000000", "interrupts-extended", 4),
+        DevTreeError::ParseError
+    );
+}
+
+#[test]
+fn phandle_allocator() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    assert_eq!(fdt.max_phandle().unwrap(), 4);
+    let phandles = PhandleAllocator::new(&fdt).unwrap();
+    assert_eq!(phandles.alloc().unwrap(), 5);
+    phandles.reserve(3);
+    assert_eq!(phandles.alloc().unwrap(), 6);
+    phandles.reserve(10);
+    assert_eq!(phandles.max(), 10);
+
+    // Allocated from within a modify pass, replacing a node's phandle.
+    let mut out = OutBuf::new();
+    let mut value = [0u8; 4];
+    let size = Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::Prop(_, b"phandle", buf) if buf.starts_with(&[0, 0, 0, 4]) => {
+            value = phandles.alloc().unwrap().to_be_bytes();
+            buf[..4].copy_from_slice(&value);
+            ModifyTokenResponse::ModifySize(4)
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+    assert_eq!(value, [0, 0, 0, 11]);
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(modified.max_phandle().unwrap(), 11);
+
+    let phandles = PhandleAllocator::above(0xffff_fffd);
+    assert_eq!(phandles.alloc().unwrap(), 0xffff_fffe);
+    assert_eq!(
+        phandles.alloc().unwrap_err(),
+        DevTreeError::InvalidParameter("Out of phandles")
+    );
+}
+
+/// Build a tree with an interrupt nexus which inherits its #address-cells (1) from the root,
+/// whose map routes to a PLIC with phandle 7.
+fn inherited_nexus_fdt(out: &mut [u8]) -> usize {
+    let cells =
+        |cells: &[u32]| -> Vec<PropCell> { cells.iter().map(|&c| PropCell::U32(c)).collect() };
+    let mut builder = FdtBuilder::new(out);
+    builder.begin_node("").unwrap();
+    builder.prop_u32("#address-cells", 1).unwrap();
+    builder.prop_u32("#size-cells", 1).unwrap();
+    builder.begin_node("interrupt-controller@0").unwrap();
+    builder.prop_str("compatible", "riscv,plic0").unwrap();
+    builder.prop_bytes("interrupt-controller", &[]).unwrap();
+    builder.prop_u32("#interrupt-cells", 1).unwrap();
+    builder.prop_u32("#address-cells", 0).unwrap();
+    builder.prop_u32("phandle", 7).unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node("bridge").unwrap();
+    builder.prop_u32("#interrupt-cells", 1).unwrap();
+    builder
+        .prop_cells("interrupt-map", &cells(&[0, 1, 7, 5, 0, 2, 7, 6]))
+        .unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    builder.finish().unwrap()
+}
+
+#[test]
+fn irq_remap() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut plic_specs = 0;
+    let mut remapper = IrqRemapper::new(&fdt, DEFAULT_IRQ_FORMATS, |spec: &IrqSpecifier| {
+        assert_eq!(spec.len(), 1);
+        match spec.format.compatible {
+            "riscv,plic0" => {
+                plic_specs += 1;
+                Some(spec.number().unwrap() + 32)
+            }
+            _ => None,
+        }
+    });
+    let mut out = OutBuf::new();
+    let size = remapper
+        .apply(&mut out.0, &ModifyOptions::default())
+        .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+
+    // The uart, rtc and virtio devices' interrupts, and the 16 entries of the PCI map.
+    assert_eq!(plic_specs, 10 + 16);
+    let interrupts = cells_of(&modified, "interrupts");
+    let source_interrupts = cells_of(&fdt, "interrupts");
+    assert_eq!(interrupts.len(), source_interrupts.len());
+    for (irq, source) in interrupts.iter().zip(&source_interrupts) {
+        assert_eq!(irq[0], source[0] + 32);
+    }
+    // Only the parent's specifier of each map entry is remapped.
+    let map = &cells_of(&modified, "interrupt-map")[0];
+    let source_map = &cells_of(&fdt, "interrupt-map")[0];
+    for (i, (cell, source)) in map.iter().zip(source_map).enumerate() {
+        assert_eq!(*cell, if i % 6 == 5 { source + 32 } else { *source });
+    }
+    // The CPU-local interrupts aren't remapped.
+    assert_eq!(
+        cells_of(&modified, "interrupts-extended"),
+        cells_of(&fdt, "interrupts-extended")
+    );
+
+    // Those are, given another remap function.
+    let mut remapper =
+        IrqRemapper::new(&fdt, DEFAULT_IRQ_FORMATS, |spec: &IrqSpecifier| match spec
+            .format
+            .compatible
+        {
+            "riscv,cpu-intc" => Some(spec.number().unwrap() + 100),
+            _ => None,
+        });
+    let size = remapper
+        .apply(&mut out.0, &ModifyOptions::default())
+        .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(
+        cells_of(&modified, "interrupts-extended"),
+        [[2, 111, 2, 109], [2, 103, 2, 107]]
+    );
+    assert_eq!(
+        cells_of(&modified, "interrupts"),
+        cells_of(&fdt, "interrupts")
+    );
+
+    // Unregistered controllers' specifiers are left unchanged.
+    let mut remapper = IrqRemapper::new(&fdt, &[], |_: &IrqSpecifier| -> Option<u32> {
+        panic!("No controller is registered")
+    });
+    let size = remapper
+        .apply(&mut out.0, &ModifyOptions::default())
+        .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(canonical(&modified), canonical_copy(&fdt));
+
+    // A format whose number cell is beyond the controller's specifiers.
+    let formats = [IrqCellFormat::new("riscv,plic0", 1)];
+    let mut remapper = IrqRemapper::new(&fdt, &formats, |_: &IrqSpecifier| Some(0));
+    let err = remapper
+        .apply(&mut out.0, &ModifyOptions::default())
+        .unwrap_err();
+    assert_eq!(err, DevTreeError::ParseError);
+}
+
+#[test]
+fn renumber_inherited_address_cells() {
+    let mut source = OutBuf::new();
+    let size = inherited_nexus_fdt(&mut source.0);
+    let fdt = unsafe { DevTree::new(&source.0[..size]) }.unwrap();
+    let mut mem = [0u8; 64];
+    let mut renumber = PhandleRenumber::new(&fdt, &mut ScratchArena::new(&mut mem)).unwrap();
+    let mut out = OutBuf::new();
+    let size = renumber
+        .apply(&mut out.0, &ModifyOptions::default())
+        .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(
+        cells_of(&modified, "interrupt-map"),
+        [[0, 1, 1, 5, 0, 2, 1, 6]]
+    );
+}
+
+#[test]
+fn irq_remap_inherited_address_cells() {
+    let mut source = OutBuf::new();
+    let size = inherited_nexus_fdt(&mut source.0);
+    let fdt = unsafe { DevTree::new(&source.0[..size]) }.unwrap();
+    let mut remapper = IrqRemapper::new(&fdt, DEFAULT_IRQ_FORMATS, |spec: &IrqSpecifier| {
+        Some(spec.number().unwrap() + 32)
+    });
+    let mut out = OutBuf::new();
+    let size = remapper
+        .apply(&mut out.0, &ModifyOptions::default())
+        .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(
+        cells_of(&modified, "interrupt-map"),
+        [[0, 1, 7, 37, 0, 2, 7, 38]]
+    );
+}
+
+#[test]
+fn addr_remap() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let windows = [
+        // The uart, and the virtio devices after it, which aren't selected.
+        AddressWindow::new(0x1000_0000, 0x2_1000_0000, 0x1_0000),
+        // The PCI controller's registers, and its memory window.
+        AddressWindow::new(0x3000_0000, 0x5000_0000, 0x1000_0000),
+        AddressWindow::new(0x4000_0000, 0x8000_0000, 0x4000_0000),
+    ];
+    let paths = ["/uart@10000000", "/soc/pci@30000000"];
+    let mut remapper = AddressRemapper::new(&fdt, &windows, &paths);
+    let mut out = OutBuf::new();
+    let size = remapper
+        .apply(&mut out.0, &ModifyOptions::default())
+        .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+
+    let mut reg = cells_of(&fdt, "reg");
+    reg[2] = vec![0x2, 0x1000_0000, 0, 0x100];
+    reg[14] = vec![0, 0x5000_0000, 0, 0x1000_0000];
+    assert_eq!(cells_of(&modified, "reg"), reg);
+    // Only the parent address of the memory window is rewritten. The I/O window isn't within
+    // any window, and the child addresses (with PCI's phys.hi) are left as they are.
+    let mut ranges = cells_of(&fdt, "ranges");
+    assert_eq!(ranges[1][7..11], [0x200_0000, 0, 0x4000_0000, 0]);
+    ranges[1][11] = 0x8000_0000;
+    assert_eq!(cells_of(&modified, "ranges"), ranges);
+
+    // A region which crosses the end of its window.
+    let windows = [AddressWindow::new(0x1000_0000, 0x2000_0000, 0x80)];
+    let mut remapper = AddressRemapper::new(&fdt, &windows, &paths);
+    let err = remapper
+        .apply(&mut out.0, &ModifyOptions::default())
+        .unwrap_err();
+    assert!(matches!(err, DevTreeError::InvalidParameter(_)));
+
+    // A guest address which doesn't fit in a single cell.
+    let windows = [AddressWindow::new(0, 0x1_0000_0000, 1)];
+    let mut remapper = AddressRemapper::new(&fdt, &windows, &["/cpus/cpu@0"]);
+    let err = remapper
+        .apply(&mut out.0, &ModifyOptions::default())
+        .unwrap_err();
+    assert!(matches!(err, DevTreeError::InvalidParameter(_)));
+}
+
+#[test]
+fn merge() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut delta_out = OutBuf::new();
+    let mut builder = FdtBuilder::new(&mut delta_out.0);
+    builder.begin_node("").unwrap();
+    builder.prop_str("model", "acme,variant-b").unwrap();
+    builder.prop_u32("acme,board-rev", 2).unwrap();
+    builder.begin_node("chosen").unwrap();
+    builder.prop_str("bootargs", "console=ttyS0").unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node("flash@20000000").unwrap();
+    builder.prop_bytes(DELETE_NODE_MARKER, &[]).unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node("soc").unwrap();
+    builder.begin_node("pci@30000000").unwrap();
+    builder.prop_str("status", "disabled").unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node("gpio@40000").unwrap();
+    builder.prop_str("compatible", "acme,gpio").unwrap();
+    // A node marked for deletion within a new subtree isn't written.
+    builder.begin_node("unused").unwrap();
+    builder.prop_bytes(DELETE_NODE_MARKER, &[]).unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    // Nor is one for a node the base tree doesn't have.
+    builder.begin_node("missing@0").unwrap();
+    builder.prop_bytes(DELETE_NODE_MARKER, &[]).unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let delta = unsafe { DevTree::new(&delta_out.0[..size]) }.unwrap();
+
+    let mut out = OutBuf::new();
+    let size = Serializer::merge(&fdt, &delta, &mut out.0).unwrap();
+    let merged = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+
+    // The same edits, made by path.
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let gpio_props = [MetadataProp::new(
+        "compatible",
+        MetadataValue::Str("acme,gpio"),
+    )];
+    let gpio = MetadataNode::new("gpio@40000", &gpio_props);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 8).unwrap();
+    modifier
+        .set_prop("/", "model", MetadataValue::Str("acme,variant-b"))
+        .unwrap()
+        .set_prop("/", "acme,board-rev", MetadataValue::U32(2))
+        .unwrap()
+        .set_prop("/chosen", "bootargs", MetadataValue::Str("console=ttyS0"))
+        .unwrap()
+        .delete_node("/flash@20000000")
+        .unwrap()
+        .set_prop(
+            "/soc/pci@30000000",
+            "status",
+            MetadataValue::Str("disabled"),
+        )
+        .unwrap()
+        .add_node("/soc", &gpio)
+        .unwrap();
+    let mut expected_out = OutBuf::new();
+    let size = modifier.apply(&fdt, &mut expected_out.0).unwrap();
+    let expected = unsafe { DevTree::new(&expected_out.0[..size]) }.unwrap();
+    assert_eq!(canonical(&merged), canonical(&expected));
+    // Replaced properties keep their place, and new ones and new nodes come last.
+    assert_eq!(node_names(&merged), node_names(&expected));
+    assert_eq!(node_props(&merged, ""), node_props(&expected, ""));
+
+    // Merging a delta with only a root node changes nothing.
+    let mut delta_out = OutBuf::new();
+    let mut builder = FdtBuilder::new(&mut delta_out.0);
+    builder.begin_node("").unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let delta = unsafe { DevTree::new(&delta_out.0[..size]) }.unwrap();
+    let size = Serializer::merge(&fdt, &delta, &mut out.0).unwrap();
+    let merged = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(canonical(&merged), canonical_copy(&fdt));
+
+    let err = Serializer::merge(&fdt, &delta, &mut out.0[..FDT.len() / 2]).unwrap_err();
+    assert!(matches!(err, DevTreeError::OutputBufferTooSmall { .. }));
+}
+
+#[test]
+fn merge_policies() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut delta_out = OutBuf::new();
+    let mut builder = FdtBuilder::new(&mut delta_out.0);
+    builder.begin_node("").unwrap();
+    builder
+        .prop_bytes("compatible", b"acme,board\0riscv-virtio\0acme,board\0")
+        .unwrap();
+    builder.prop_str("model", "acme").unwrap();
+    builder.prop_u32("#address-cells", 2).unwrap();
+    builder.begin_node("chosen").unwrap();
+    builder.prop_str("stdout-path", "/uart@10000000").unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let delta = unsafe { DevTree::new(&delta_out.0[..size]) }.unwrap();
+
+    let merge = |policies: PropMergePolicies| {
+        let mut out = OutBuf::new();
+        let size = Serializer::merge_with(&fdt, &delta, &mut out.0, |name| policies.policy(name))?;
+        let merged = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+        Ok::<_, DevTreeError>((
+            node_props(&merged, "")
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_vec()))
+                .collect::<Vec<_>>(),
+            canonical(&merged),
+        ))
+    };
+    let root_prop = |props: &[(String, Vec<u8>)], name: &str| {
+        props.iter().find(|(n, _)| n == name).unwrap().1.clone()
+    };
+
+    let (props, _) = merge(PropMergePolicies {
+        by_name: &[("compatible", PropMergePolicy::AppendDedup)],
+        default: PropMergePolicy::Replace,
+    })
+    .unwrap();
+    assert_eq!(
+        root_prop(&props, "compatible"),
+        b"riscv-virtio\0acme,board\0"
+    );
+    assert_eq!(root_prop(&props, "model"), b"acme\0");
+
+    // Only differing values conflict.
+    let err = merge(PropMergePolicies {
+        by_name: &[],
+        default: PropMergePolicy::Error,
+    })
+    .unwrap_err();
+    assert!(matches!(err, DevTreeError::InvalidParameter(_)));
+    let (_, merged) = merge(PropMergePolicies {
+        by_name: &[
+            ("compatible", PropMergePolicy::Replace),
+            ("model", PropMergePolicy::Replace),
+        ],
+        default: PropMergePolicy::Error,
+    })
+    .unwrap();
+    let mut out = OutBuf::new();
+    let size = Serializer::merge(&fdt, &delta, &mut out.0).unwrap();
+    assert_eq!(
+        merged,
+        canonical(&unsafe { DevTree::new(&out.0[..size]) }.unwrap())
+    );
+
+    // Only lists of strings can be appended.
+    let err = merge(PropMergePolicies {
+        by_name: &[],
+        default: PropMergePolicy::AppendDedup,
+    })
+    .unwrap_err();
+    assert!(matches!(err, DevTreeError::InvalidParameter(_)));
+}
+
+#[test]
+fn merge_provenance() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let build_delta = |out: &mut OutBuf, layer: u32| {
+        let mut builder = FdtBuilder::new(&mut out.0);
+        builder.begin_node("").unwrap();
+        if layer == 1 {
+            builder.prop_str("model", "acme").unwrap();
+        }
+        builder.begin_node("acme,dev@0").unwrap();
+        builder
+            .prop_str("status", if layer == 1 { "okay" } else { "disabled" })
+            .unwrap();
+        if layer == 2 {
+            builder.prop_u32("acme,rev", 2).unwrap();
+        }
+        builder.end_node().unwrap();
+        builder.end_node().unwrap();
+        builder.finish().unwrap()
+    };
+    let (mut delta1, mut delta2) = (OutBuf::new(), OutBuf::new());
+    let size = build_delta(&mut delta1, 1);
+    let delta1 = unsafe { DevTree::new(&delta1.0[..size]) }.unwrap();
+    let size = build_delta(&mut delta2, 2);
+    let delta2 = unsafe { DevTree::new(&delta2.0[..size]) }.unwrap();
+
+    // Merge two layers onto the base tree, then edit the result.
+    let mut records = [[ProvenanceRecord::default(); 16]; 3];
+    let [r1, r2, r3] = &mut records;
+    let (map1, map2, map3) = (
+        ProvenanceMap::new(r1),
+        ProvenanceMap::new(r2),
+        ProvenanceMap::new(r3),
+    );
+    let replace = |_: &str| PropMergePolicy::Replace;
+    let mut out1 = OutBuf::new();
+    let trace = Trace {
+        output: &map1,
+        source: None,
+    };
+    let size = Serializer::merge_traced(&fdt, &delta1, &mut out1.0, replace, 1, trace).unwrap();
+    let merged1 = unsafe { DevTree::new(&out1.0[..size]) }.unwrap();
+    let mut out2 = OutBuf::new();
+    let trace = Trace {
+        output: &map2,
+        source: Some(&map1),
+    };
+    let size = Serializer::merge_traced(&merged1, &delta2, &mut out2.0, replace, 2, trace).unwrap();
+    let merged2 = unsafe { DevTree::new(&out2.0[..size]) }.unwrap();
+
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let props = [MetadataProp::new("count", MetadataValue::U32(3))];
+    let node = MetadataNode::new("acme,boot", &props);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 4).unwrap();
+    modifier
+        .set_prop("/chosen", "bootargs", MetadataValue::Str("quiet"))
+        .unwrap()
+        .add_node("/chosen", &node)
+        .unwrap();
+    let mut out3 = OutBuf::new();
+    let trace = Trace {
+        output: &map3,
+        source: Some(&map2),
+    };
+    let size = modifier.apply_traced(&merged2, &mut out3.0, trace).unwrap();
+    let composed = unsafe { DevTree::new(&out3.0[..size]) }.unwrap();
+
+    let node_origin = |name: &str| {
+        let node = composed
+            .nodes()
+            .find(|n| Ok(n.name()? == name))
+            .unwrap()
+            .unwrap();
+        map3.origin_of_node(&node).unwrap()
+    };
+    let prop_origin = |node: &str, name: &str| {
+        let prop = composed
+            .props()
+            .find(|p| Ok(p.node().name()? == node && p.name()? == name))
+            .unwrap()
+            .unwrap();
+        map3.origin_of_prop(&prop)
+    };
+    assert_eq!(prop_origin("", "compatible"), Origin::Base);
+    assert_eq!(prop_origin("", "model"), Origin::Layer(1));
+    assert_eq!(node_origin("acme,dev@0"), Origin::Layer(1));
+    assert_eq!(prop_origin("acme,dev@0", "status"), Origin::Layer(2));
+    assert_eq!(prop_origin("acme,dev@0", "acme,rev"), Origin::Layer(2));
+    assert_eq!(node_origin("chosen"), Origin::Base);
+    assert_eq!(prop_origin("chosen", "bootargs"), Origin::Edit(0));
+    assert_eq!(prop_origin("chosen", "stdout-path"), Origin::Base);
+    assert_eq!(node_origin("acme,boot"), Origin::Edit(1));
+    assert_eq!(prop_origin("acme,boot", "count"), Origin::Edit(1));
+
+    // Untraced merges give the same tree.
+    let mut out = OutBuf::new();
+    let size = Serializer::merge(&fdt, &delta1, &mut out.0).unwrap();
+    assert_eq!(&out.0[..size], merged1.buf());
+
+    // A map without room for every record fails the merge.
+    let mut records = [ProvenanceRecord::default(); 1];
+    let small = ProvenanceMap::new(&mut records);
+    let trace = Trace {
+        output: &small,
+        source: Some(&map1),
+    };
+    let err =
+        Serializer::merge_traced(&merged1, &delta2, &mut out.0, replace, 2, trace).unwrap_err();
+    assert!(matches!(err, DevTreeError::NotEnoughMemory));
+}
+
+/// Returns a line describing each difference between `old` and `new`.
+fn diff_lines(old: &DevTree, new: &DevTree) -> Vec<String> {
+    let prop_desc =
+        |prop: &DevTreeProp| format!("{} {}", prop.node().name().unwrap(), prop.name().unwrap());
+    let mut lines = Vec::new();
+    let mut diff = old.diff(new);
+    while let Some(record) = diff.next().unwrap() {
+        lines.push(match record {
+            DiffRecord::NodeAdded(node) => format!("+node {}", node.name().unwrap()),
+            DiffRecord::NodeRemoved(node) => format!("-node {}", node.name().unwrap()),
+            DiffRecord::PropAdded(prop) => format!("+prop {}", prop_desc(&prop)),
+            DiffRecord::PropRemoved(prop) => format!("-prop {}", prop_desc(&prop)),
+            DiffRecord::PropChanged { old, new } => {
+                assert_ne!(old.propbuf(), new.propbuf());
+                format!("~prop {}", prop_desc(&new))
+            }
+        });
+    }
+    lines
+}
+
+#[test]
+fn diff() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    assert!(diff_lines(&fdt, &fdt).is_empty());
+
+    // The order of nodes and properties isn't compared.
+    let options = ModifyOptions {
+        sorted: true,
+        ..ModifyOptions::default()
+    };
+    let mut out = OutBuf::new();
+    let size =
+        Serializer::modify_with_options(&fdt, &mut out.0, &options, |_| ModifyTokenResponse::Pass)
+            .unwrap();
+    let sorted = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert!(diff_lines(&fdt, &sorted).is_empty());
+
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let node = MetadataNode::new("acme,boot", &[]);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 8).unwrap();
+    modifier
+        .set_prop("/chosen", "bootargs", MetadataValue::Str("quiet"))
+        .unwrap()
+        .set_prop("/chosen", "linux,initrd-start", MetadataValue::U32(0))
+        .unwrap()
+        .set_prop(
+            "/chosen",
+            "stdout-path",
+            MetadataValue::Str("/uart@10000000"),
+        )
+        .unwrap()
+        .delete_prop("/cpus", "timebase-frequency")
+        .unwrap()
+        .delete_node("/cpus/cpu-map")
+        .unwrap()
+        .add_node("/soc", &node)
+        .unwrap();
+    let size = modifier.apply(&fdt, &mut out.0).unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+
+    // Differences come in sorted order, depth first.
+    assert_eq!(
+        diff_lines(&fdt, &modified),
+        [
+            "~prop chosen bootargs",
+            "+prop chosen linux,initrd-start",
+            "-prop cpus timebase-frequency",
+            "-node cpu-map",
+            "+node acme,boot",
+        ]
+    );
+    assert_eq!(
+        diff_lines(&modified, &fdt),
+        [
+            "~prop chosen bootargs",
+            "-prop chosen linux,initrd-start",
+            "+prop cpus timebase-frequency",
+            "+node cpu-map",
+            "-node acme,boot",
+        ]
+    );
+}
+
+fn canonical(fdt: &DevTree) -> String {
+    let mut dump = String::new();
+    fdt.write_canonical(&mut dump).unwrap();
+    dump
+}
+
+/// Returns the canonical dump of an unmodified copy of `fdt`, whose header versions are those
+/// the serializer writes.
+fn canonical_copy(fdt: &DevTree) -> String {
+    let mut out = OutBuf::new();
+    let size = Serializer::modify(fdt, &mut out.0, |_| ModifyTokenResponse::Pass).unwrap();
+    canonical(&unsafe { DevTree::new(&out.0[..size]) }.unwrap())
+}
+
+#[test]
+fn canonical_dump_ignores_layout() {
+    // The same tree, built with its properties, children and reservations in a different order.
+    let build = |reversed: bool| {
+        let mut out = OutBuf::new();
+        let mut builder = FdtBuilder::new(&mut out.0);
+        let mut reservations = [(0x8000_0000, 0x1000), (0x1000, 0x10)];
+        if reversed {
+            reservations.reverse();
+        }
+        for &(address, size) in &reservations {
+            builder.add_reservation(address, size).unwrap();
+        }
+        builder.begin_node("").unwrap();
+        let mut props = [("model", "vmm"), ("compatible", "vmm,virt")];
+        let mut children = ["uart@1000", "chosen"];
+        if reversed {
+            props.reverse();
+            children.reverse();
+        }
+        for &(name, value) in &props {
+            builder.prop_str(name, value).unwrap();
+        }
+        for &child in &children {
+            builder.begin_node(child).unwrap();
+            builder.prop_u32("value", 1).unwrap();
+            builder.end_node().unwrap();
+        }
+        builder.end_node().unwrap();
+        let size = builder.finish().unwrap();
+        (out, size)
+    };
+    let (out, size) = build(false);
+    let (reversed, reversed_size) = build(true);
+    assert_ne!(&out.0[..size], &reversed.0[..reversed_size]);
+    let fdt = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    let reversed = unsafe { DevTree::new(&reversed.0[..reversed_size]) }.unwrap();
+    assert_eq!(canonical(&fdt), canonical(&reversed));
+    assert!(canonical(&fdt).contains(
+        "memreserve 0x0000000000001000 0x0000000000000010\n\
+         memreserve 0x0000000080000000 0x0000000000001000\n\
+         node /\n\
+         prop / compatible string \"vmm,virt\"\n\
+         prop / model string \"vmm\"\n\
+         node /chosen\n"
+    ));
+
+    // Nops and the layout of the strings block don't appear either.
+    let nops = fdt_with_nops();
+    let nops = unsafe { DevTree::new(&nops.0[..FDT.len()]) }.unwrap();
+    let model = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let options = ModifyOptions {
+        gc_strings: true,
+        header: HeaderOverrides {
+            last_comp_version: Some(nops.last_comp_version()),
+            ..HeaderOverrides::default()
+        },
+        ..ModifyOptions::default()
+    };
+    let size = Serializer::modify_with_options(&model, &mut out.0, &options, |tok| match tok {
+        ModifyParsedTok::Prop(prop, _, _) if prop.prop_buf == b"riscv-virtio,qemu\0" => {
+            ModifyTokenResponse::Drop
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+    let without_model = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_ne!(nops.size_dt_strings(), without_model.size_dt_strings());
+    assert_eq!(canonical(&nops), canonical(&without_model));
+}
+
+#[test]
+fn prop_writer() {
+    let mut buf = [0xffu8; 16];
+    let mut writer = PropWriter::new(&mut buf);
+    assert!(writer.is_empty());
+    writer.set_str("okay");
+    assert_eq!(writer.len(), 5);
+    writer.set_reg(0x1_2345_6789, 0x1000, 2, 1);
+    assert_eq!(writer.response(), ModifyTokenResponse::ModifySize(12));
+    writer.set_reg(0x10, 0x20, 3, 0).set_u64(7);
+    assert_eq!(writer.len(), 8);
+    assert_eq!(&buf[..12], &[0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0x10]);
+
+    let mut buf = [0u8; 16];
+    PropWriter::new(&mut buf).set_reg(0x1_2345_6789, 0x1000, 3, 1);
+    assert_eq!(
+        &buf,
+        &[0, 0, 0, 0, 0, 0, 0, 1, 0x23, 0x45, 0x67, 0x89, 0, 0, 0x10, 0]
+    );
+
+    // Values which don't fit are reported by the serializer.
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let long = "x".repeat(FDT.len());
+    let err = Serializer::modify(&fdt, &mut out.0[..FDT.len() + 64], |tok| match tok {
+        ModifyParsedTok::Prop(prop, _, value) if prop.prop_buf == b"riscv-virtio,qemu\0" => {
+            let mut writer = PropWriter::new(value);
+            writer.set_str(&long);
+            assert_eq!(writer.len(), long.len() + 1);
+            writer.response()
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap_err();
+    assert!(matches!(err, DevTreeError::OutputBufferTooSmall { .. }));
+
+    // Rewriting a value in place.
+    let size = Serializer::modify(&fdt, &mut out.0, |tok| match tok {
+        ModifyParsedTok::Prop(prop, _, value) if prop.prop_buf == b"riscv-virtio,qemu\0" => {
+            PropWriter::new(value).set_str("acme,board").response()
+        }
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(
+        node_props(&modified, "")[3],
+        ("model", &b"acme,board\0"[..])
+    );
+}
+
+#[test]
+fn sorted_output() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let options = ModifyOptions {
+        sorted: true,
+        ..ModifyOptions::default()
+    };
+    let mut out = OutBuf::new();
+    let size =
+        Serializer::modify_with_options(&fdt, &mut out.0, &options, |_| ModifyTokenResponse::Pass)
+            .unwrap();
+    assert_eq!(size, FDT.len());
+    assert_ne!(&out.0[..size], FDT);
+    let sorted = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+    assert_eq!(canonical(&sorted), canonical_copy(&fdt));
+
+    // Each node's properties and children are in order.
+    let mut last_prop: Vec<&[u8]> = vec![b""];
+    let mut last_child: Vec<&[u8]> = vec![b""];
+    let mut copy = OutBuf::new();
+    Serializer::modify(&sorted, &mut copy.0, |tok| {
+        match tok {
+            ModifyParsedTok::BeginNode(node, _) => {
+                let last = last_child.last_mut().unwrap();
+                assert!(*last <= node.name);
+                *last = node.name;
+                last_prop.push(b"");
+                last_child.push(b"");
+            }
+            ModifyParsedTok::Prop(_, name, _) => {
+                let last = last_prop.last_mut().unwrap();
+                assert!(*last <= name);
+                *last = name;
+            }
+            ModifyParsedTok::EndNode => {
+                last_prop.pop();
+                last_child.pop();
+            }
+            ModifyParsedTok::Nop | ModifyParsedTok::End(_) => (),
+        }
+        ModifyTokenResponse::Pass
+    })
+    .unwrap();
+    assert!(last_child.len() == 1);
+
+    // The order doesn't depend on the source's, and Nops are dropped.
+    let size = Serializer::modify_with_options(&sorted, &mut copy.0, &options, |_| {
+        ModifyTokenResponse::Pass
+    })
+    .unwrap();
+    assert_eq!(&copy.0[..size], sorted.buf());
+    let nops = fdt_with_nops();
+    let nops = unsafe { DevTree::new(&nops.0[..FDT.len()]) }.unwrap();
+    let size = Serializer::modify_with_options(&nops, &mut copy.0, &options, |_| {
+        ModifyTokenResponse::Pass
+    })
+    .unwrap();
+    let mut expected = OutBuf::new();
+    let expected_size =
+        Serializer::modify_with_options(&fdt, &mut expected.0, &options, |tok| match tok {
+            ModifyParsedTok::Prop(_, b"model", _) => ModifyTokenResponse::Drop,
+            _ => ModifyTokenResponse::Pass,
+        })
+        .unwrap();
+    assert_eq!(&copy.0[..size], &expected.0[..expected_size]);
+
+    // Callbacks and the offset map see the sorted tokens.
+    let mut mappings = [Default::default(); 128];
+    let map = OffsetMap::new(&mut mappings);
+    let options = ModifyOptions {
+        offset_map: Some(&map),
+        ..options
+    };
+    let size = Serializer::modify_with_options(&fdt, &mut copy.0, &options, |tok| match tok {
+        ModifyParsedTok::BeginNode(node, _) if node.name == b"cpus" => ModifyTokenResponse::Drop,
+        _ => ModifyTokenResponse::Pass,
+    })
+    .unwrap();
+    let pruned = unsafe { DevTree::new(&copy.0[..size]) }.unwrap();
+    assert!(pruned
+        .nodes()
+        .all(|node| Ok(node.name()? != "cpus"))
+        .unwrap());
+    assert_eq!(
+        map.output_offset(fdt.off_dt_struct()),
+        Some(pruned.off_dt_struct())
+    );
+    assert_eq!(
+        map.len(),
+        pruned.nodes().count().unwrap() + pruned.props().count().unwrap()
+    );
+    // The mappings are in source order, for lookups.
+    assert!(map
+        .iter()
+        .zip(map.iter().skip(1))
+        .all(|(a, b)| a.source < b.source));
+}
+
+#[test]
+fn sorted_offset_map() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut mappings = [OffsetMapping::default(); 256];
+    let map = OffsetMap::new(&mut mappings);
+    let options = ModifyOptions {
+        sorted: true,
+        offset_map: Some(&map),
+        ..ModifyOptions::default()
+    };
+    let mut out = OutBuf::new();
+    let size =
+        Serializer::modify_with_options(&fdt, &mut out.0, &options, |_| ModifyTokenResponse::Pass)
+            .unwrap();
+    let sorted = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+
+    // Every node and property is found, though most were written out of source order.
+    let tok_at = |fdt: &DevTree, offset| {
+        let mut iter = fdt.parse_iter();
+        iter.offset = offset;
+        match iter.next().unwrap().unwrap() {
+            ParsedTok::BeginNode(node) => Some(node.name.to_vec()),
+            ParsedTok::Prop(prop) => Some(prop.prop_buf.to_vec()),
+            _ => None,
+        }
+    };
+    let mut iter = fdt.parse_iter();
+    let mut count = 0;
+    loop {
+        let source = iter.offset;
+        match iter.next().unwrap() {
+            Some(ParsedTok::BeginNode(_)) | Some(ParsedTok::Prop(_)) => {
+                let output = map.output_offset(source).unwrap();
+                assert_eq!(tok_at(&fdt, source), tok_at(&sorted, output));
+                count += 1;
+            }
+            Some(_) => (),
+            None => break,
+        }
+    }
+    assert_eq!(map.len(), count);
+    assert!(map
+        .iter()
+        .zip(map.iter().skip(1))
+        .all(|(a, b)| a.source < b.source));
+}
+
+#[test]
+fn sorted_size_limit() {
+    // One entry too many, counting the root. The properties are spread over nodes so the
+    // builder's duplicate checks stay quick.
+    let nodes = MAX_SORTED_ENTRIES / 16;
+    let mut buf = vec![0u32; 32 * MAX_SORTED_ENTRIES];
+    let out =
+        unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4) };
+    let mut builder = FdtBuilder::new(out);
+    builder.begin_node("").unwrap();
+    for i in 0..nodes {
+        builder.begin_node(&format!("node{}", i)).unwrap();
+        for j in 0..15 {
+            builder.prop_u32(&format!("prop{}", j), j).unwrap();
+        }
+        builder.end_node().unwrap();
+    }
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    assert_eq!(1 + 16 * nodes, MAX_SORTED_ENTRIES + 1);
+
+    let fdt = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    let options = ModifyOptions {
+        sorted: true,
+        ..ModifyOptions::default()
+    };
+    let mut sorted = vec![0u8; size];
+    assert_eq!(
+        Serializer::modify_with_options(&fdt, &mut sorted, &options, |_| {
+            ModifyTokenResponse::Pass
+        }),
+        Err(DevTreeError::InvalidParameter(
+            "Device tree is too large to sort"
+        ))
+    );
+}
+
+#[test]
+fn catalog() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let bindings = unsafe { DevTree::new(BINDINGS_FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    let mut writer = DevTreeCatalogWriter::new(&mut out.0, 256).unwrap();
+    assert_eq!(writer.push(&fdt).unwrap(), 0);
+    assert_eq!(writer.push(&bindings).unwrap(), 3840);
+    let size = writer.finish();
+    assert_eq!(size, 3840 + BINDINGS_FDT.len());
+    assert!(out.0[FDT.len()..3840].iter().all(|&byte| byte == 0));
+
+    // Erased flash after the last tree ends the catalog.
+    for byte in &mut out.0[size..] {
+        *byte = 0xff;
+    }
+    let trees: Vec<DevTree> = unsafe { DevTreeCatalogIter::new(&out.0, 256) }
+        .unwrap()
+        .iterator()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(trees.len(), 2);
+    assert_eq!(node_names(&trees[0]), node_names(&fdt));
+    assert_eq!(node_names(&trees[1]), node_names(&bindings));
+
+    // Sizes are reported relative to the whole catalog.
+    let mut out = OutBuf::new();
+    let mut writer = DevTreeCatalogWriter::new(&mut out.0, 4096).unwrap();
+    writer.push(&fdt).unwrap();
+    writer.push(&bindings).unwrap();
+    assert!(matches!(
+        writer.push(&bindings),
+        Err(DevTreeError::OutputBufferTooSmall { needed, available: 8192 }) if needed > 8192
+    ));
+
+    assert!(matches!(
+        DevTreeCatalogWriter::new(&mut out.0, 6),
+        Err(DevTreeError::InvalidParameter(_))
+    ));
+}
+
+#[test]
+fn quirks() {
+    fn rename_interrupts<'r>(
+        _node: &DevTreeNode,
+        tok: ModifyParsedTok<'_, 'r>,
+    ) -> fdt_rs::error::Result<ModifyTokenResponse<'r>> {
+        Ok(match tok {
+            ModifyParsedTok::Prop(prop, b"interrupts", _) => {
+                ModifyTokenResponse::Replace(ReplacementTok::Prop {
+                    name: "legacy-interrupts",
+                    value: prop.prop_buf,
+                })
+            }
+            _ => ModifyTokenResponse::Pass,
+        })
+    }
+    fn drop_node<'r>(
+        _node: &DevTreeNode,
+        tok: ModifyParsedTok,
+    ) -> fdt_rs::error::Result<ModifyTokenResponse<'r>> {
+        Ok(match tok {
+            ModifyParsedTok::BeginNode(..) => ModifyTokenResponse::Drop,
+            _ => ModifyTokenResponse::Pass,
+        })
+    }
+    fn fail<'r>(
+        node: &DevTreeNode,
+        _tok: ModifyParsedTok,
+    ) -> fdt_rs::error::Result<ModifyTokenResponse<'r>> {
+        assert_eq!(node.name().unwrap(), "test@100000");
+        Err(DevTreeError::ParseError)
+    }
+
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut out = OutBuf::new();
+    // The test node's most specific compatible string picks its quirk.
+    let quirks: &[(&str, QuirkHandler)] = &[
+        ("ns16550a", rename_interrupts),
+        ("syscon", fail),
+        ("sifive,test1", drop_node),
+        ("virtio,mmio", drop_node),
+    ];
+    let size = QuirkRegistry::new(&fdt, quirks)
+        .apply(&mut out.0, &ModifyOptions::default())
+        .unwrap();
+    let modified = unsafe { DevTree::new(&out.0[..size]) }.unwrap();
+
+    let prop = |path: &str, name: &str| {
+        let node = modified.node_at_path(path).unwrap().unwrap();
+        node.props().find(|p| Ok(p.name()? == name)).unwrap()
+    };
+    assert!(prop("/uart@10000000", "interrupts").is_none());
+    let legacy = prop("/uart@10000000", "legacy-interrupts").unwrap();
+    assert_eq!(legacy.u32(0).unwrap(), 10);
+    // Nodes without a quirk are unchanged.
+    assert!(prop("/rtc@101000", "interrupts").is_some());
+    let names = node_names(&modified);
+    assert!(!names
+        .iter()
+        .any(|name| name.starts_with("test@") || name.starts_with("virtio_mmio@")));
+    assert_eq!(names.len(), node_names(&fdt).len() - 9);
+
+    let quirks: &[(&str, QuirkHandler)] = &[("syscon", fail)];
+    assert_eq!(
+        QuirkRegistry::new(&fdt, quirks)
+            .apply(&mut out.0, &ModifyOptions::default())
+            .err(),
+        Some(DevTreeError::ParseError)
+    );
+}
@@ -1,9 +1,17 @@
 extern crate fdt_rs;
 
+use std::convert::TryInto;
+
 use fdt_rs::base::DevTree;
+use fdt_rs::bindings::{match_compatible, BootInfo, Interrupt, MemoryRange};
 use fdt_rs::error::{DevTreeError, Result};
 use fdt_rs::index::DevTreeIndex;
+use fdt_rs::infer::*;
+use fdt_rs::modify::{DevTreeModifier, FdtBuilder, MemReservation, MetadataValue};
+use fdt_rs::name::*;
 use fdt_rs::prelude::*;
+use fdt_rs::scratch::ScratchArena;
+use fdt_rs::spec::{fdt_header, fdt_reserve_entry};
 
 /// Fallible Basic Iterator
 ///
@@ -81,6 +89,112 @@ fn test_readsize_advice() {
     }
 }
 
+#[test]
+fn truncated_buffers() {
+    let full = unsafe { DevTree::new(FDT) }.unwrap();
+    let truncated = Some(DevTreeError::Truncated {
+        needed: FDT.len(),
+        available: 48,
+    });
+    assert_eq!(
+        unsafe { DevTree::new_complete(&FDT[..48]) }.err(),
+        truncated
+    );
+    assert!(unsafe { DevTree::new_complete(FDT) }.unwrap().is_complete());
+    assert_eq!(
+        unsafe { DevTree::new_partial(&FDT[..16]) }.err(),
+        Some(DevTreeError::Truncated {
+            needed: DevTree::MIN_HEADER_SIZE,
+            available: 16,
+        })
+    );
+
+    // Only the header fits.
+    let fdt = unsafe { DevTree::new_partial(&FDT[..48]) }.unwrap();
+    assert!(!fdt.is_complete());
+    assert_eq!(fdt.totalsize(), FDT.len());
+    assert_eq!(fdt.boot_cpuid_phys(), full.boot_cpuid_phys());
+    assert!(!fdt.has_struct_block());
+    assert!(!fdt.has_strings_block());
+    assert_eq!(fdt.nodes().next().err(), truncated);
+
+    // The structure block fits, but the strings block doesn't.
+    let len = full.off_dt_strings();
+    let fdt = unsafe { DevTree::new_partial(&FDT[..len]) }.unwrap();
+    assert!(fdt.has_struct_block());
+    assert!(!fdt.has_strings_block());
+    assert_eq!(fdt.nodes().count().unwrap(), full.nodes().count().unwrap());
+    assert!(fdt.props().next().unwrap().unwrap().name().is_err());
+
+    // Parsing stops where the structure block is cut.
+    let len = full.off_dt_struct() + 256;
+    let fdt = unsafe { DevTree::new_partial(&FDT[..len]) }.unwrap();
+    assert!(!fdt.has_struct_block());
+    let mut nodes = fdt.nodes();
+    let err = loop {
+        if let Err(err) = nodes.next() {
+            break err;
+        }
+    };
+    assert_eq!(
+        err,
+        DevTreeError::Truncated {
+            needed: FDT.len(),
+            available: len,
+        }
+    );
+
+    let fdt = unsafe { DevTree::new_partial(FDT) }.unwrap();
+    assert!(fdt.is_complete() && fdt.has_struct_block() && fdt.has_strings_block());
+
+    // A totalsize too small for the header is corrupt, not a short tree.
+    let mut buf = [0u32; 16];
+    buf[0] = 0xd00d_feedu32.to_be();
+    buf[1] = 8u32.to_be();
+    let tiny = unsafe { core::slice::from_raw_parts(buf.as_ptr() as *const u8, 64) };
+    assert_eq!(
+        unsafe { DevTree::new_partial(tiny) }.err(),
+        Some(DevTreeError::ParseError)
+    );
+    assert_eq!(
+        unsafe { DevTree::new_complete(tiny) }.err(),
+        Some(DevTreeError::ParseError)
+    );
+    assert_eq!(
+        unsafe { DevTree::from_raw_pointer(tiny.as_ptr()) }.err(),
+        Some(DevTreeError::ParseError)
+    );
+}
+
+#[test]
+fn copy_to() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut buf = vec![0u32; FDT.len() / 4 + 2];
+    let out =
+        unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4) };
+    {
+        let copy = fdt.copy_to(out).unwrap();
+        assert_eq!(copy.buf(), FDT);
+        assert!(copy != fdt);
+        assert_eq!(copy.nodes().count().unwrap(), fdt.nodes().count().unwrap());
+    }
+
+    assert!(matches!(
+        fdt.copy_to(&mut out[4..FDT.len()]),
+        Err(DevTreeError::OutputBufferTooSmall { needed, available })
+            if needed == FDT.len() && available == FDT.len() - 4
+    ));
+    assert!(matches!(
+        fdt.copy_to(&mut out[1..]),
+        Err(DevTreeError::InvalidParameter(_))
+    ));
+    let partial = unsafe { DevTree::new_partial(&FDT[..64]) }.unwrap();
+    assert!(matches!(
+        partial.copy_to(out),
+        Err(DevTreeError::Truncated { available: 64, .. })
+    ));
+}
+
 #[test]
 fn reserved_entries_iter() {
     unsafe {
@@ -89,6 +203,38 @@ fn reserved_entries_iter() {
     }
 }
 
+#[test]
+fn header_and_reserve_entry_views() {
+    let blob = unsafe { DevTree::new(FDT) }.unwrap();
+    let header = blob.header();
+    assert_eq!(u32::from(header.magic), 0xd00d_feed);
+    assert_eq!(u32::from(header.totalsize) as usize, blob.totalsize());
+    assert_eq!(
+        u32::from(header.off_dt_struct) as usize,
+        blob.off_dt_struct()
+    );
+    assert_eq!(u32::from(header.version), blob.version());
+    assert_eq!(header.to_bytes(), FDT[..fdt_header::SIZE]);
+
+    // The views needn't be aligned.
+    let mut unaligned = vec![0u8; 1];
+    unaligned.extend_from_slice(&FDT[..fdt_header::SIZE]);
+    assert_eq!(fdt_header::from_bytes(&unaligned[1..]).unwrap(), header);
+    assert_eq!(
+        fdt_header::from_bytes(&FDT[..fdt_header::SIZE - 1]).unwrap_err(),
+        DevTreeError::ParseError
+    );
+
+    let entry = fdt_reserve_entry::new(0x8000_0000, 0x1000);
+    let bytes = entry.to_bytes();
+    assert_eq!(bytes[..8], 0x8000_0000u64.to_be_bytes());
+    assert_eq!(bytes[8..], 0x1000u64.to_be_bytes());
+    assert_eq!(fdt_reserve_entry::from_bytes(&bytes).unwrap(), entry);
+    let reservation = MemReservation::from(entry);
+    assert_eq!(reservation, MemReservation::new(0x8000_0000, 0x1000));
+    assert_eq!(fdt_reserve_entry::from(reservation), entry);
+}
+
 #[test]
 fn nodes_iter() {
     unsafe {
@@ -229,6 +375,50 @@ fn node_prop_iter() {
     }
 }
 
+#[test]
+fn string_lists() {
+    let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    let test = devtree.node_at_path("/test@100000").unwrap().unwrap();
+    let compatible = test.props().find(|p| Ok(p.name()? == "compatible"));
+    let strings: Vec<_> = compatible
+        .unwrap()
+        .unwrap()
+        .iter_str()
+        .iterator()
+        .collect::<Result<_>>()
+        .unwrap();
+    assert_eq!(strings, ["sifive,test1", "sifive,test0", "syscon"]);
+
+    let mut buf = [0u32; 64];
+    let out = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 256) };
+    let mut builder = FdtBuilder::new(out);
+    builder.begin_node("").unwrap();
+    builder.prop_bytes("clock-names", b"\0bus\0").unwrap();
+    builder.prop_bytes("reg-names", b"ctrl\0dma").unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let devtree = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    let prop = |name: &str| {
+        devtree
+            .props()
+            .find(|p| Ok(p.name()? == name))
+            .unwrap()
+            .unwrap()
+    };
+
+    // Empty strings are kept.
+    let strings: Vec<_> = prop("clock-names")
+        .iter_str()
+        .iterator()
+        .collect::<Result<_>>()
+        .unwrap();
+    assert_eq!(strings, ["", "bus"]);
+    // An unterminated string is an error, after the strings before it.
+    let mut strings = prop("reg-names").iter_str();
+    assert_eq!(strings.next().unwrap(), Some("ctrl"));
+    assert_eq!(strings.next(), Err(DevTreeError::ParseError));
+}
+
 #[test]
 fn next_compatible_finds_initial_node() {
     unsafe {
@@ -278,6 +468,462 @@ fn find_all_compatible() {
     }
 }
 
+#[test]
+fn compatible_nodes_match_any_string() {
+    let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    let names = |compat: &str| -> Vec<&str> {
+        devtree
+            .compatible_nodes(compat)
+            .map(|node| node.name())
+            .iterator()
+            .collect::<Result<_>>()
+            .unwrap()
+    };
+    assert_eq!(names("virtio,mmio").len(), 8);
+    // test@100000 is compatible with "sifive,test1", "sifive,test0" and "syscon".
+    assert_eq!(names("sifive,test0"), ["test@100000"]);
+    assert_eq!(names("syscon"), ["test@100000"]);
+    assert!(names("sifive,test").is_empty());
+    assert!(names("syscon\0").is_empty());
+
+    let index = get_fdt_index();
+    let names: Vec<&str> = index
+        .index
+        .compatible_nodes("syscon")
+        .map(|node| node.name().unwrap())
+        .collect();
+    assert_eq!(names, ["test@100000"]);
+}
+
+#[test]
+fn match_table() {
+    let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    let test = devtree.node_at_path("/test@100000").unwrap().unwrap();
+    // The earliest compatible string wins over the table's order.
+    let table = [("syscon", 1), ("sifive,test0", 2), ("sifive,test0", 3)];
+    assert_eq!(
+        test.match_table(&table).unwrap(),
+        Some(&("sifive,test0", 2))
+    );
+    assert_eq!(test.match_table(&table[..1]).unwrap(), Some(&("syscon", 1)));
+    assert_eq!(test.match_table(&[("sifive,test", 1)]).unwrap(), None);
+
+    let cpus = devtree.node_at_path("/cpus").unwrap().unwrap();
+    assert_eq!(cpus.match_table(&table).unwrap(), None);
+    assert_eq!(match_compatible(b"", &table), None);
+}
+
+#[test]
+fn node_paths() {
+    let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut buf = [0u8; 256];
+    let mut iter = devtree.nodes();
+    let mut paths = Vec::new();
+    while let Some(node) = iter.next().unwrap() {
+        let mut scratch = ScratchArena::new(&mut buf);
+        paths.push(node.path(&mut scratch).unwrap().to_string());
+    }
+    assert_eq!(paths[0], "/");
+    assert_eq!(paths[1], "/flash@20000000");
+    assert_eq!(paths[19], "/cpus/cpu-map/cluster0/core0");
+    assert_eq!(paths[20], "/cpus/cpu@0");
+    assert_eq!(paths[26], "/soc/clint@2000000");
+
+    // Not enough room for the path.
+    let node = devtree.nodes().nth(19).unwrap().unwrap();
+    let mut buf = [0u8; 27];
+    assert_eq!(
+        node.path(&mut ScratchArena::new(&mut buf)),
+        Err(DevTreeError::NotEnoughMemory)
+    );
+}
+
+#[test]
+fn node_at_path() {
+    let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    let name_at = |path: &str| {
+        devtree
+            .node_at_path(path)
+            .unwrap()
+            .map(|node| node.name().unwrap().to_string())
+    };
+    assert_eq!(name_at("/").as_deref(), Some(""));
+    assert_eq!(
+        name_at("/soc/pci@30000000").as_deref(),
+        Some("pci@30000000")
+    );
+    assert_eq!(
+        name_at("/cpus/cpu-map/cluster0/core0").as_deref(),
+        Some("core0")
+    );
+    // Components without a unit address match any unit address, as with libfdt.
+    assert_eq!(name_at("/soc/pci").as_deref(), Some("pci@30000000"));
+    assert_eq!(
+        name_at("/virtio_mmio").as_deref(),
+        Some("virtio_mmio@10008000")
+    );
+    assert_eq!(name_at("/soc/pci@40000000"), None);
+    assert_eq!(name_at("/soc/cpus"), None);
+
+    // The node's path round trips.
+    let mut buf = [0u8; 64];
+    let node = devtree
+        .node_at_path("/cpus/cpu@0/interrupt-controller")
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        node.path(&mut ScratchArena::new(&mut buf)).unwrap(),
+        "/cpus/cpu@0/interrupt-controller"
+    );
+    assert!(matches!(
+        devtree.node_at_path("soc"),
+        Err(DevTreeError::InvalidParameter("Path must be absolute"))
+    ));
+}
+
+#[test]
+fn node_by_phandle() {
+    let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut count = 0;
+    let mut iter = devtree.props();
+    while let Some(prop) = iter.next().unwrap() {
+        if prop.name().unwrap() == "phandle" {
+            let node = devtree.node_by_phandle(prop.phandle(0).unwrap()).unwrap();
+            assert!(node == Some(prop.node()));
+            count += 1;
+        }
+    }
+    assert_eq!(count, 4);
+
+    // The interrupt-parent of the UART is the PLIC.
+    let uart = devtree.node_at_path("/uart@10000000").unwrap().unwrap();
+    let parent = uart.props().find(|p| Ok(p.name()? == "interrupt-parent"));
+    let parent = parent.unwrap().unwrap().phandle(0).unwrap();
+    let plic = devtree.node_by_phandle(parent).unwrap().unwrap();
+    assert_eq!(plic.name().unwrap(), "interrupt-controller@c000000");
+    assert!(devtree.node_by_phandle(0xdead).unwrap().is_none());
+}
+
+#[test]
+fn index_node_by_phandle() {
+    let index = get_fdt_index();
+    let mut count = 0;
+    for prop in index.index.props() {
+        if prop.name().unwrap() == "phandle" {
+            let node = index.index.node_by_phandle(prop.phandle(0).unwrap());
+            assert!(node == Some(prop.node()));
+            count += 1;
+        }
+    }
+    assert_eq!(count, 4);
+    assert!(index.index.node_by_phandle(0).is_none());
+    assert!(index.index.node_by_phandle(0xdead).is_none());
+
+    // `phandle` takes precedence over `linux,phandle`, and the first duplicate wins.
+    let mut buf = vec![0u32; 1024];
+    let out =
+        unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4) };
+    let mut builder = FdtBuilder::new(out);
+    builder.begin_node("").unwrap();
+    for (name, phandle, legacy) in &[("a", 3, 9), ("b", 0, 2), ("c", 3, 0)] {
+        builder.begin_node(name).unwrap();
+        if *legacy != 0 {
+            builder.prop_u32("linux,phandle", *legacy).unwrap();
+        }
+        if *phandle != 0 {
+            builder.prop_u32("phandle", *phandle).unwrap();
+        }
+        builder.end_node().unwrap();
+    }
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let fdt = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    let layout = DevTreeIndex::get_layout(&fdt).unwrap();
+    let mut vec = vec![0u8; layout.size() + layout.align()];
+    let index = DevTreeIndex::new(fdt, &mut vec).unwrap();
+    let name = |phandle| index.node_by_phandle(phandle).map(|n| n.name().unwrap());
+    assert_eq!(name(3), Some("a"));
+    assert_eq!(name(2), Some("b"));
+    assert_eq!(name(9), None);
+}
+
+#[test]
+fn index_child_by_name() {
+    let index = get_fdt_index();
+    let root = index.index.root();
+    let child_name = |name: &str| root.child_by_name(name).map(|n| n.name().unwrap());
+    assert_eq!(child_name("cpus"), Some("cpus"));
+    assert_eq!(child_name("uart@10000000"), Some("uart@10000000"));
+    // As with the base API, the first match in tree order wins.
+    assert_eq!(child_name("virtio_mmio"), Some("virtio_mmio@10008000"));
+    assert_eq!(child_name("uart@20000000"), None);
+    assert_eq!(child_name("pci"), None);
+    let soc = root.child_by_name("soc").unwrap();
+    assert_eq!(
+        soc.child_by_name("pci").unwrap().name().unwrap(),
+        "pci@30000000"
+    );
+    for node in index.index.nodes() {
+        assert_eq!(node.num_children(), node.children().count());
+        for child in node.children() {
+            assert!(node.child_by_name(child.name().unwrap()).unwrap() == child);
+        }
+    }
+
+    // A wide node, whose children aren't in name order.
+    let mut buf = vec![0u32; 4096];
+    let out =
+        unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4) };
+    let mut builder = FdtBuilder::new(out);
+    builder.begin_node("").unwrap();
+    builder.prop_str("compatible", "test,wide").unwrap();
+    for i in (0..300).rev() {
+        builder.begin_node(&format!("dev@{:x}", i)).unwrap();
+        builder.end_node().unwrap();
+    }
+    for name in &["dev", "dev-x", "dev0", "de", "devices"] {
+        builder.begin_node(name).unwrap();
+        builder.end_node().unwrap();
+    }
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let wide = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    let layout = DevTreeIndex::get_layout(&wide).unwrap();
+    let mut vec = vec![0u8; layout.size() + layout.align()];
+    let index = DevTreeIndex::new(wide, &mut vec).unwrap();
+    let root = index.root();
+    let child_name = |name: &str| root.child_by_name(name).map(|n| n.name().unwrap());
+    assert_eq!(root.num_children(), 305);
+    for i in 0..300 {
+        let name = format!("dev@{:x}", i);
+        assert_eq!(child_name(&name), Some(name.as_str()));
+    }
+    assert_eq!(child_name("dev"), Some("dev@12b"));
+    assert_eq!(child_name("dev-x"), Some("dev-x"));
+    assert_eq!(child_name("de"), Some("de"));
+    assert_eq!(child_name("devices"), Some("devices"));
+    assert_eq!(child_name("dev@12c"), None);
+    assert_eq!(child_name("d"), None);
+}
+
+#[test]
+fn phandle_lists() {
+    let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    let index = get_fdt_index();
+    let plic = devtree
+        .node_at_path("/soc/interrupt-controller@c000000")
+        .unwrap()
+        .unwrap();
+    let plic_phandle = plic.props().find(|p| Ok(p.name()? == "phandle"));
+    let plic_phandle = plic_phandle.unwrap().unwrap().phandle(0).unwrap();
+
+    let uart = devtree.node_at_path("/uart@10000000").unwrap().unwrap();
+    let prop = |name: &str| {
+        uart.props()
+            .find(|p| Ok(p.name()? == name))
+            .unwrap()
+            .unwrap()
+    };
+    let phandles: Vec<_> = prop("interrupt-parent")
+        .as_phandle_list()
+        .unwrap()
+        .collect();
+    assert_eq!(phandles, [plic_phandle]);
+    // The base API can't tell cells which aren't phandles apart.
+    assert_eq!(prop("reg").as_phandle_list().unwrap().count(), 4);
+    assert!(matches!(
+        prop("compatible").as_phandle_list(),
+        Err(DevTreeError::ParseError)
+    ));
+
+    // The index checks every phandle refers to a node.
+    let uart = index
+        .index
+        .nodes()
+        .find(|node| node.name().unwrap() == "uart@10000000")
+        .unwrap();
+    let prop = |name: &str| uart.props().find(|p| p.name().unwrap() == name).unwrap();
+    let phandles: Vec<_> = prop("interrupt-parent")
+        .as_phandle_list()
+        .unwrap()
+        .collect();
+    assert_eq!(phandles, [plic_phandle]);
+    assert!(matches!(
+        prop("reg").as_phandle_list(),
+        Err(DevTreeError::ParseError)
+    ));
+}
+
+#[test]
+fn cell_iters() {
+    let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    let uart = devtree.node_at_path("/uart@10000000").unwrap().unwrap();
+    let prop = |name: &str| {
+        uart.props()
+            .find(|p| Ok(p.name()? == name))
+            .unwrap()
+            .unwrap()
+    };
+    let cells: Vec<_> = prop("reg").iter_u32().unwrap().collect();
+    assert_eq!(cells, [0, 0x1000_0000, 0, 0x100]);
+    let cells: Vec<_> = prop("interrupts").iter_u32().unwrap().collect();
+    assert_eq!(cells, [10]);
+    assert!(matches!(
+        prop("compatible").iter_u32(),
+        Err(DevTreeError::ParseError)
+    ));
+
+    let reg = prop("reg");
+    let values: Vec<_> = reg.iter_u64().unwrap().collect();
+    assert_eq!(values, [0x1000_0000, 0x100]);
+    assert_eq!(reg.u64_at_cell(0).unwrap(), 0x1000_0000);
+    assert_eq!(reg.u64_at_cell(1).unwrap(), 0x1000_0000_0000_0000);
+    assert_eq!(reg.u64_at_cell(2).unwrap(), 0x100);
+    assert_eq!(reg.u64_at_cell(3), Err(DevTreeError::InvalidOffset));
+    assert!(matches!(
+        prop("interrupts").iter_u64(),
+        Err(DevTreeError::ParseError)
+    ));
+}
+
+#[test]
+fn node_get_path() {
+    let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut nodes = devtree.nodes();
+    let mut mem = [0u8; 256];
+    let mut buf = [0u8; 64];
+    while let Some(node) = nodes.next().unwrap() {
+        let path = node.path(&mut ScratchArena::new(&mut mem)).unwrap();
+        assert_eq!(node.get_path(&mut buf).unwrap(), path);
+        // Paths of other nodes which don't fit don't matter.
+        assert_eq!(node.get_path(&mut buf[..path.len()]).unwrap(), path);
+        assert_eq!(
+            node.get_path(&mut buf[..path.len() - 1]),
+            Err(DevTreeError::OutputBufferTooSmall {
+                needed: path.len(),
+                available: path.len() - 1,
+            })
+        );
+    }
+}
+
+#[test]
+fn node_parents() {
+    let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    let root = devtree.root().unwrap().unwrap();
+    assert!(root.parent().unwrap().is_none());
+    let cpus = devtree.node_at_path("/cpus").unwrap().unwrap();
+    assert!(cpus.parent().unwrap() == Some(root));
+    let cpu = devtree.node_at_path("/cpus/cpu@0").unwrap().unwrap();
+    assert!(cpu.parent().unwrap() == Some(cpus.clone()));
+    let intc = devtree
+        .node_at_path("/cpus/cpu@0/interrupt-controller")
+        .unwrap()
+        .unwrap();
+    assert!(intc.parent().unwrap() == Some(cpu));
+    let cpu_map = devtree.node_at_path("/cpus/cpu-map").unwrap().unwrap();
+    assert!(cpu_map.parent().unwrap() == Some(cpus));
+}
+
+#[test]
+fn node_children() {
+    let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    let names = |path: &str| -> Vec<&str> {
+        let node = devtree.node_at_path(path).unwrap().unwrap();
+        node.children()
+            .map(|child| child.name())
+            .iterator()
+            .collect::<Result<_>>()
+            .unwrap()
+    };
+    assert_eq!(names("/cpus"), ["cpu-map", "cpu@0"]);
+    assert_eq!(names("/cpus/cpu-map"), ["cluster0"]);
+    assert!(names("/uart@10000000").is_empty());
+
+    // The children of every node match those of the index.
+    let index = get_fdt_index();
+    let mut nodes = devtree.nodes();
+    let mut index_nodes = index.index.nodes();
+    while let Some(node) = nodes.next().unwrap() {
+        let index_node = index_nodes.next().unwrap();
+        let children: Vec<&str> = node
+            .children()
+            .map(|child| child.name())
+            .iterator()
+            .collect::<Result<_>>()
+            .unwrap();
+        let index_children: Vec<&str> = index_node.children().map(|c| c.name().unwrap()).collect();
+        assert_eq!(children, index_children);
+    }
+}
+
+#[test]
+fn nodes_with_depth() {
+    let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut nodes = devtree.nodes();
+    let mut with_depth = devtree.nodes_with_depth();
+    let mut buf = [0u8; 256];
+    while let Some((depth, node)) = with_depth.next().unwrap() {
+        assert!(nodes.next().unwrap() == Some(node.clone()));
+        let path = node.path(&mut ScratchArena::new(&mut buf)).unwrap();
+        let components = path.split('/').filter(|c| !c.is_empty()).count();
+        assert_eq!(depth, components + 1, "{}", path);
+    }
+    assert!(nodes.next().unwrap().is_none());
+
+    let (depth, root) = devtree.nodes_with_depth().next().unwrap().unwrap();
+    assert_eq!((depth, root.name().unwrap()), (1, ""));
+    let deepest = devtree
+        .nodes_with_depth()
+        .map(|(depth, _)| Ok(depth))
+        .max()
+        .unwrap();
+    assert_eq!(deepest, Some(5));
+}
+
+#[test]
+fn node_byte_ranges() {
+    let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    let tok = |off: usize| u32::from_be_bytes(FDT[off..off + 4].try_into().unwrap());
+    let range_of = |idx: usize| {
+        let node = devtree.nodes().nth(idx).unwrap().unwrap();
+        node.byte_range().unwrap()
+    };
+
+    // The root node spans the whole structure block, less the End token.
+    let root = range_of(0);
+    let struct_end = devtree.off_dt_struct() + devtree.size_dt_struct() as usize;
+    assert_eq!(root, devtree.off_dt_struct()..struct_end - 4);
+
+    let cpus = range_of(16);
+    assert_eq!(tok(cpus.start), 1);
+    assert_eq!(tok(cpus.end - 4), 2);
+    for child in [17, 19, 20] {
+        let range = range_of(child);
+        assert!(cpus.start < range.start && range.end <= cpus.end);
+    }
+    assert!(range_of(17).end <= range_of(20).start);
+}
+
+#[test]
+fn scratch_arena_allocations() {
+    let mut buf = [0xffu8; 64];
+    let mut scratch = ScratchArena::new(&mut buf);
+    let bytes = scratch.alloc_bytes(3).unwrap();
+    let words = scratch.alloc_slice(4, 7u32).unwrap();
+    assert_eq!(bytes, &[0, 0, 0]);
+    assert_eq!(words, &[7; 4]);
+    assert_eq!(words.as_ptr() as usize % core::mem::align_of::<u32>(), 0);
+
+    let remaining = scratch.remaining();
+    assert_eq!(
+        scratch.alloc_bytes(remaining + 1),
+        Err(DevTreeError::NotEnoughMemory)
+    );
+    // A failed allocation leaves the arena as it was.
+    assert_eq!(scratch.alloc_bytes(remaining).unwrap().len(), remaining);
+}
+
 pub mod index_tests {
     use super::*;
 
@@ -370,6 +1016,100 @@ pub mod index_tests {
         test_prop_iteration(&get_fdt_index());
     }
 
+    fn serialize_index(idx: &FdtIndex) -> Vec<u8> {
+        let mut blob = vec![0u8; idx.index.serialized_size()];
+        let size = idx.index.serialize(&mut blob).unwrap();
+        assert_eq!(size, blob.len());
+        blob
+    }
+
+    // Test that a serialized index reloads to an identical index.
+    #[test]
+    fn serialize_and_load_index() {
+        let blob = serialize_index(&get_fdt_index());
+        unsafe {
+            let devtree = DevTree::new(FDT).unwrap();
+            let layout = DevTreeIndex::get_serialized_layout(&blob).unwrap();
+            assert_eq!(layout, DevTreeIndex::get_layout(&devtree).unwrap());
+            let mut vec = vec![0u8; layout.size() + layout.align()];
+            let slice = core::slice::from_raw_parts_mut(vec.as_mut_ptr(), vec.len());
+            let idx = FdtIndex {
+                index: DevTreeIndex::load(devtree, &blob, slice).unwrap(),
+                _vec: vec,
+            };
+
+            test_index_dfs(&idx);
+            test_prop_iteration(&idx);
+            test_root_prop_iteration(&idx);
+            assert_eq!(idx.index.root().children().count(), 18);
+            assert_eq!(serialize_index(&idx), blob);
+        }
+    }
+
+    // Test that a serialized index can't be loaded for another device tree.
+    #[test]
+    fn load_index_for_modified_tree_fails() {
+        let blob = serialize_index(&get_fdt_index());
+
+        #[repr(align(4))]
+        struct Aligned([u8; 8192]);
+        let mut modified = Aligned([0; 8192]);
+        let modified = &mut modified.0[..FDT.len()];
+        modified.copy_from_slice(FDT);
+        // Change the last byte of the strings block.
+        modified[FDT.len() - 2] ^= 1;
+
+        unsafe {
+            let devtree = DevTree::new(modified).unwrap();
+            let layout = DevTreeIndex::get_serialized_layout(&blob).unwrap();
+            let mut vec = vec![0u8; layout.size() + layout.align()];
+            assert_eq!(
+                DevTreeIndex::load(devtree, &blob, vec.as_mut_slice()),
+                Err(DevTreeError::IndexMismatch)
+            );
+        }
+    }
+
+    // Test that a corrupt serialized index can't be loaded.
+    #[test]
+    fn load_corrupt_index_fails() {
+        let blob = serialize_index(&get_fdt_index());
+        let be32 =
+            |blob: &[u8], off: usize| u32::from_be_bytes(blob[off..off + 4].try_into().unwrap());
+        // The root's BeginNode is first, followed by its first Prop.
+        let name = 28 + 4;
+        let value = 28 + 12 + 4;
+        assert_eq!(be32(&blob, 28 + 12), 3);
+        let corruptions: [(usize, u32); 7] = [
+            // A misaligned value.
+            (value, be32(&blob, value) + 2),
+            // A value whose end overflows.
+            (value, 0xffff_fffc),
+            // A value of the wrong length.
+            (value + 4, be32(&blob, value + 4) + 4),
+            // A value which isn't a property's, but the root node's name.
+            (value, be32(&blob, name)),
+            // A name which isn't a node's, but the property's value.
+            (name, be32(&blob, value)),
+            // Too many nodes, and too few properties.
+            (20, be32(&blob, 20) + 1),
+            (24, be32(&blob, 24) - 1),
+        ];
+        for &(off, val) in corruptions.iter() {
+            let mut corrupt = blob.clone();
+            corrupt[off..off + 4].copy_from_slice(&val.to_be_bytes());
+            let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+            let layout = DevTreeIndex::get_layout(&devtree).unwrap();
+            let mut vec = vec![0u8; 2 * (layout.size() + layout.align())];
+            assert_eq!(
+                DevTreeIndex::load(devtree, &corrupt, vec.as_mut_slice()),
+                Err(DevTreeError::ParseError),
+                "corrupting offset {}",
+                off
+            );
+        }
+    }
+
     pub fn test_prop_iteration(idx: &FdtIndex) {
         let iter = idx.index.props();
         assert_eq!(iter.count(), 105);
@@ -393,3 +1133,493 @@ pub mod index_tests {
         assert_eq!(iter.count(), DFS_NODES.len());
     }
 }
+
+#[cfg(feature = "std")]
+pub mod cache_tests {
+    use super::*;
+    use fdt_rs::base::CachedDevTree;
+
+    fn path_name(cached: &CachedDevTree, path: &str) -> Option<String> {
+        cached
+            .node_by_path(path)
+            .unwrap()
+            .map(|node| node.name().unwrap().to_string())
+    }
+
+    #[test]
+    fn node_by_path() {
+        let cached = CachedDevTree::new(unsafe { DevTree::new(FDT) }.unwrap());
+        // Look up each path twice, the second time from the cache.
+        for _ in 0..2 {
+            assert_eq!(path_name(&cached, "/").as_deref(), Some(""));
+            assert_eq!(path_name(&cached, "/soc").as_deref(), Some("soc"));
+            assert_eq!(
+                path_name(&cached, "/cpus/cpu-map/cluster0/core0").as_deref(),
+                Some("core0")
+            );
+            assert_eq!(
+                path_name(&cached, "/memory").as_deref(),
+                Some("memory@80000000")
+            );
+            assert_eq!(
+                path_name(&cached, "/soc/pci@30000000/").as_deref(),
+                Some("pci@30000000")
+            );
+            assert_eq!(path_name(&cached, "/cpu@0"), None);
+            assert_eq!(path_name(&cached, "/soc/cpus"), None);
+        }
+        assert!(cached.node_by_path("soc").is_err());
+    }
+
+    #[test]
+    fn node_by_phandle() {
+        let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+        let cached = CachedDevTree::new(fdt);
+
+        let mut count = 0;
+        let mut iter = fdt.props();
+        while let Some(prop) = iter.next().unwrap() {
+            if prop.name().unwrap() == "phandle" {
+                let node = cached.node_by_phandle(prop.phandle(0).unwrap()).unwrap();
+                assert!(node == Some(prop.node()));
+                count += 1;
+            }
+        }
+        assert!(count > 0);
+        assert!(cached.node_by_phandle(0xdead).unwrap().is_none());
+    }
+
+    #[test]
+    fn lookups_from_many_threads() {
+        let cached = CachedDevTree::new(unsafe { DevTree::new(FDT) }.unwrap());
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    assert_eq!(path_name(&cached, "/soc").as_deref(), Some("soc"));
+                    assert!(cached.node_by_phandle(1).unwrap().is_some());
+                });
+            }
+        });
+    }
+}
+
+#[test]
+fn name_matching() {
+    assert_eq!(base_name(b"uart@10000000"), b"uart");
+    assert_eq!(base_name(b"chosen"), b"chosen");
+    assert_eq!(unit_address(b"uart@10000000"), Some(&b"10000000"[..]));
+    assert_eq!(unit_address(b"chosen"), None);
+
+    // Trees before version 16 name each node by its full path.
+    assert_eq!(last_component(b"/soc/uart@10000000\0"), b"uart@10000000");
+    assert!(node_name_eq(b"/soc/uart@10000000", b"uart@10000000"));
+    assert!(!node_name_eq(b"uart@10000000", b"uart@10000"));
+    assert!(!node_name_eq(b"UART", b"uart"));
+
+    assert!(node_name_matches(b"uart@10000000", "uart"));
+    assert!(node_name_matches(b"uart@10000000", "uart@10000000"));
+    assert!(!node_name_matches(b"uart@10000000", "uart@1"));
+    assert!(!node_name_matches(b"uart2", "uart"));
+    assert!(!node_name_matches(b"uart", "uart@10000000"));
+
+    assert!(device_type_matches(b"memory\0", "memory"));
+    assert!(device_type_matches(b"PCI", "pci"));
+    assert!(!device_type_matches(b"memory-controller", "memory"));
+}
+
+#[test]
+fn node_name_parts() {
+    let devtree = unsafe { DevTree::new(FDT) }.unwrap();
+    let uart = devtree.node_at_path("/uart@10000000").unwrap().unwrap();
+    assert_eq!(uart.name_only().unwrap(), "uart");
+    assert_eq!(uart.unit_address().unwrap(), Some("10000000"));
+    assert!(uart.name_matches("uart").unwrap());
+    assert!(uart.name_matches("uart@10000000").unwrap());
+    assert!(!uart.name_matches("uart@1").unwrap());
+    let chosen = devtree.node_at_path("/chosen").unwrap().unwrap();
+    assert_eq!(chosen.name_only().unwrap(), "chosen");
+    assert_eq!(chosen.unit_address().unwrap(), None);
+
+    let index = get_fdt_index();
+    let uart = index
+        .index
+        .nodes()
+        .find(|node| node.name_matches("uart"))
+        .unwrap();
+    assert_eq!(uart.name().unwrap(), "uart@10000000");
+    assert_eq!(uart.name_only().unwrap(), "uart");
+    assert_eq!(uart.unit_address().unwrap(), Some("10000000"));
+}
+
+#[test]
+fn infer_types() {
+    assert_eq!(infer_value_type(b""), PropType::Empty);
+    assert_eq!(infer_value_type(b"okay\0"), PropType::String);
+    assert_eq!(infer_value_type(b"a\0b c\0"), PropType::StringList);
+    // Empty and unprintable strings aren't strings.
+    assert_eq!(infer_value_type(b"ab\0\0cd\0\0"), PropType::U32Array);
+    assert_eq!(infer_value_type(b"\x01\0"), PropType::Bytes);
+    assert_eq!(infer_value_type(b"abc"), PropType::Bytes);
+    assert_eq!(
+        infer_prop_type("linux,initrd-start", &[0; 8]),
+        PropType::U64Array
+    );
+    assert_eq!(
+        infer_prop_type("linux,initrd-start", &[0; 4]),
+        PropType::U32Array
+    );
+    assert_eq!(infer_prop_type("reg", &[0; 8]), PropType::U32Array);
+
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let inferred = |name: &str| {
+        let prop = fdt
+            .props()
+            .find(|p| Ok(p.name()? == name))
+            .unwrap()
+            .unwrap();
+        prop.inferred_type().unwrap()
+    };
+    assert_eq!(inferred("model"), PropType::String);
+    assert_eq!(inferred("reg"), PropType::U32Array);
+    assert_eq!(inferred("bootargs"), PropType::Bytes);
+    assert_eq!(inferred("interrupt-controller"), PropType::Empty);
+}
+
+#[test]
+fn typed_values() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let prop = |path: &str, name: &str| {
+        fdt.node_at_path(path)
+            .unwrap()
+            .unwrap()
+            .props()
+            .find(|p| Ok(p.name()? == name))
+            .unwrap()
+            .unwrap()
+    };
+    assert_eq!(
+        prop("/", "model").value_typed().unwrap(),
+        PropValue::Str("riscv-virtio,qemu")
+    );
+    assert_eq!(
+        prop("/", "#address-cells").value_typed().unwrap(),
+        PropValue::U32(2)
+    );
+    assert_eq!(
+        prop("/soc/interrupt-controller@c000000", "interrupt-controller")
+            .value_typed()
+            .unwrap(),
+        PropValue::Empty
+    );
+    let reg = prop("/memory@80000000", "reg");
+    assert_eq!(reg.value_typed().unwrap(), PropValue::CellArray(reg.raw()));
+    let bootargs = prop("/chosen", "bootargs");
+    assert_eq!(bootargs.value_typed().unwrap(), PropValue::Bytes(b"\0"));
+    match prop("/test@100000", "compatible").value_typed().unwrap() {
+        PropValue::StrList(strings) => assert_eq!(
+            strings.iterator().collect::<Result<Vec<_>>>().unwrap(),
+            ["sifive,test1", "sifive,test0", "syscon"]
+        ),
+        value => panic!("Unexpected value {:?}", value),
+    }
+
+    // An explicit type overrides the guess, if the value fits it.
+    assert_eq!(
+        bootargs.value_as(PropType::String).unwrap(),
+        PropValue::Str("")
+    );
+    assert_eq!(
+        reg.value_as(PropType::U64Array).unwrap(),
+        PropValue::CellArray(reg.raw())
+    );
+    let timebase = prop("/cpus", "timebase-frequency");
+    assert_eq!(
+        timebase.value_as(PropType::U64Array).err(),
+        Some(DevTreeError::ParseError)
+    );
+    assert_eq!(
+        prop("/", "model").value_as(PropType::Empty).err(),
+        Some(DevTreeError::ParseError)
+    );
+}
+
+#[test]
+fn format_props() {
+    let format = |name: &str, value: &[u8]| {
+        let mut line = String::new();
+        format_prop(name, value, &mut line).unwrap();
+        line
+    };
+    assert_eq!(format("interrupt-controller", b""), "interrupt-controller");
+    assert_eq!(
+        format("compatible", b"ns16550a\0serial\0"),
+        "compatible = \"ns16550a\", \"serial\""
+    );
+    assert_eq!(format("label", b"a\"b\\c\0"), "label = \"a\\\"b\\\\c\"");
+    assert_eq!(
+        format("reg", &[0x10, 0, 0, 0, 0, 0, 1, 0]),
+        "reg = <0x10000000 0x100>"
+    );
+    assert_eq!(
+        format("linux,initrd-start", &[0, 0, 0, 1, 0, 0, 0, 0]),
+        "linux,initrd-start = /bits/ 64 <0x100000000>"
+    );
+    assert_eq!(format("mac", &[2, 0, 0xab]), "mac = [02 00 ab]");
+    assert_eq!(format("zero", &[0; 4]), "zero = <0x0>");
+}
+
+#[test]
+fn canonical_dump() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut dump = String::new();
+    fdt.write_canonical(&mut dump).unwrap();
+    assert_eq!(dump, include_str!("riscv64-virt.canonical"));
+}
+
+#[test]
+fn boot_info() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let info = BootInfo::from_devtree(&fdt).unwrap();
+    assert_eq!(info.memory(), [MemoryRange::new(0x8000_0000, 0x800_0000)]);
+    assert_eq!(info.initrd, None);
+    assert_eq!(info.bootargs, Some(""));
+    let (stdout, options) = info.stdout.clone().unwrap();
+    assert_eq!(stdout.name().unwrap(), "uart@10000000");
+    assert_eq!(options, None);
+    assert_eq!(info.boot_cpuid_phys, 0);
+    assert_eq!(info.boot_cpu.unwrap().name().unwrap(), "cpu@0");
+    assert_eq!(info.timebase_frequency, Some(10_000_000));
+
+    // The boot CPU's own timebase-frequency takes precedence over that of /cpus.
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 4).unwrap();
+    modifier
+        .set_prop(
+            "/chosen",
+            "linux,initrd-start",
+            MetadataValue::U64(0x8400_0000),
+        )
+        .unwrap()
+        .set_prop(
+            "/chosen",
+            "linux,initrd-end",
+            MetadataValue::U32(0x8410_0000),
+        )
+        .unwrap()
+        .set_prop("/chosen", "bootargs", MetadataValue::Str("console=ttyS0"))
+        .unwrap()
+        .set_prop(
+            "/cpus/cpu@0",
+            "timebase-frequency",
+            MetadataValue::U32(24_000_000),
+        )
+        .unwrap();
+    let mut buf = vec![0u32; FDT.len() / 2];
+    let out =
+        unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4) };
+    let size = modifier.apply(&fdt, out).unwrap();
+    let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    let info = BootInfo::from_devtree(&modified).unwrap();
+    assert_eq!(info.initrd, Some(MemoryRange::new(0x8400_0000, 0x10_0000)));
+    assert_eq!(info.bootargs, Some("console=ttyS0"));
+    assert_eq!(info.timebase_frequency, Some(24_000_000));
+
+    // An initrd without an end is malformed.
+    let mut modifier = DevTreeModifier::new(&mut scratch, 1).unwrap();
+    modifier
+        .set_prop(
+            "/chosen",
+            "linux,initrd-start",
+            MetadataValue::U32(0x8400_0000),
+        )
+        .unwrap();
+    let size = modifier.apply(&fdt, out).unwrap();
+    let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    assert!(matches!(
+        BootInfo::from_devtree(&modified),
+        Err(DevTreeError::ParseError)
+    ));
+}
+
+#[test]
+fn frequencies() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    assert_eq!(fdt.timebase_frequency().unwrap(), Some(10_000_000));
+    let cpu = fdt.node_at_path("/cpus/cpu@0").unwrap().unwrap();
+    assert_eq!(cpu.timebase_frequency().unwrap(), Some(10_000_000));
+    assert_eq!(cpu.clock_frequency().unwrap(), None);
+    let uart = fdt.node_at_path("/uart@10000000").unwrap().unwrap();
+    assert_eq!(uart.clock_frequency().unwrap(), Some(0x38_4000));
+
+    // Without a value for /cpus, that of a CPU is used. Two cell values are read in full, and
+    // values of any other size are malformed.
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 3).unwrap();
+    modifier
+        .delete_prop("/cpus", "timebase-frequency")
+        .unwrap()
+        .set_prop(
+            "/cpus/cpu@0",
+            "timebase-frequency",
+            MetadataValue::U64(0x1_0000_0000),
+        )
+        .unwrap()
+        .set_prop(
+            "/uart@10000000",
+            "clock-frequency",
+            MetadataValue::Bytes(&[0, 1, 2]),
+        )
+        .unwrap();
+    let mut buf = vec![0u32; FDT.len() / 2];
+    let out =
+        unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4) };
+    let size = modifier.apply(&fdt, out).unwrap();
+    let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+    assert_eq!(modified.timebase_frequency().unwrap(), Some(0x1_0000_0000));
+    let uart = modified.node_at_path("/uart@10000000").unwrap().unwrap();
+    assert!(matches!(
+        uart.clock_frequency(),
+        Err(DevTreeError::ParseError)
+    ));
+}
+
+#[test]
+fn interrupts() {
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let node = |path: &str| fdt.node_at_path(path).unwrap().unwrap();
+    let interrupts = |path: &str| -> Vec<(String, Vec<u32>)> {
+        let irqs: Vec<Interrupt> = node(path)
+            .interrupts()
+            .unwrap()
+            .unwrap()
+            .iterator()
+            .collect::<Result<_>>()
+            .unwrap();
+        irqs.iter()
+            .map(|irq| {
+                let cells = (0..irq.num_cells()).map(|i| irq.cell(i).unwrap());
+                (
+                    irq.controller().name().unwrap().to_string(),
+                    cells.collect(),
+                )
+            })
+            .collect()
+    };
+
+    let plic = node("/soc/interrupt-controller@c000000");
+    assert!(node("/uart@10000000").interrupt_parent().unwrap().unwrap() == plic);
+    assert_eq!(
+        interrupts("/uart@10000000"),
+        [("interrupt-controller@c000000".to_string(), vec![10])]
+    );
+    // Each entry of interrupts-extended names its controller.
+    let cpu_intc = |cell| ("interrupt-controller".to_string(), vec![cell]);
+    assert_eq!(interrupts("/soc/clint@2000000"), [cpu_intc(3), cpu_intc(7)]);
+    assert_eq!(
+        interrupts("/soc/interrupt-controller@c000000"),
+        [cpu_intc(11), cpu_intc(9)]
+    );
+    assert!(node("/memory@80000000").interrupts().unwrap().is_none());
+    // The walk up the tree ends at the root, which has no #interrupt-cells.
+    assert!(node("/memory@80000000")
+        .interrupt_parent()
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn chosen() {
+    let mut mem = [0u8; 512];
+    let mut scratch = ScratchArena::new(&mut mem);
+    let mut modifier = DevTreeModifier::new(&mut scratch, 3).unwrap();
+    modifier
+        .set_prop(
+            "/chosen",
+            "linux,initrd-start",
+            MetadataValue::U64(0x1_8400_0000),
+        )
+        .unwrap()
+        .set_prop(
+            "/chosen",
+            "linux,initrd-end",
+            MetadataValue::U64(0x1_8480_0000),
+        )
+        .unwrap()
+        .set_prop(
+            "/chosen",
+            "stdout-path",
+            MetadataValue::Str("/uart@10000000:115200n8"),
+        )
+        .unwrap();
+    let fdt = unsafe { DevTree::new(FDT) }.unwrap();
+    let mut buf = vec![0u32; FDT.len() / 2];
+    let out =
+        unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4) };
+    let size = modifier.apply(&fdt, out).unwrap();
+    let modified = unsafe { DevTree::new(&out[..size]) }.unwrap();
+
+    let chosen = modified.chosen().unwrap().unwrap();
+    assert_eq!(chosen.node().name().unwrap(), "chosen");
+    assert_eq!(chosen.bootargs().unwrap(), Some(""));
+    assert_eq!(
+        chosen.initrd_range().unwrap(),
+        Some(MemoryRange::new(0x1_8400_0000, 0x80_0000))
+    );
+    let (stdout, options) = chosen.stdout_path().unwrap().unwrap();
+    assert_eq!(stdout.name().unwrap(), "uart@10000000");
+    assert_eq!(options, Some("115200n8"));
+}
+
+#[test]
+fn cpus() {
+    let mut buf = [0u32; 256];
+    let out = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 1024) };
+    let mut builder = FdtBuilder::new(out);
+    builder.begin_node("").unwrap();
+    builder.begin_node("cpus").unwrap();
+    builder.prop_u32("#address-cells", 2).unwrap();
+    builder.prop_u32("#size-cells", 0).unwrap();
+    builder.begin_node("cpu-map").unwrap();
+    builder.end_node().unwrap();
+    for (name, id, status) in [
+        ("cpu@0", 0u64, "okay"),
+        ("cpu@100000001", 0x1_0000_0001, "disabled"),
+    ] {
+        builder.begin_node(name).unwrap();
+        builder.prop_str("device_type", "cpu").unwrap();
+        builder
+            .prop_str("compatible", "arm,cortex-a53\0arm,armv8")
+            .unwrap();
+        builder.prop_str("enable-method", "psci").unwrap();
+        builder.prop_str("status", status).unwrap();
+        let reg = id.to_be_bytes();
+        builder.prop_bytes("reg", &reg).unwrap();
+        builder.end_node().unwrap();
+    }
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let size = builder.finish().unwrap();
+    let fdt = unsafe { DevTree::new(&out[..size]) }.unwrap();
+
+    let cpus = fdt.cpus().unwrap().unwrap();
+    let list: Vec<_> = cpus.iter().iterator().collect::<Result<_>>().unwrap();
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0].node().name().unwrap(), "cpu@0");
+    // IDs are sized by /cpus' #address-cells, without sizes.
+    assert_eq!(list[1].id().unwrap(), 0x1_0000_0001);
+    assert_eq!(list[1].thread_id(1).unwrap(), None);
+    assert!(list[0].is_enabled().unwrap());
+    assert!(!list[1].is_enabled().unwrap());
+    assert_eq!(list[1].compatible().unwrap(), Some("arm,cortex-a53"));
+    assert_eq!(list[1].enable_method().unwrap(), Some("psci"));
+    let cpu = cpus.by_id(0x1_0000_0001).unwrap().unwrap();
+    assert_eq!(cpu.node().name().unwrap(), "cpu@100000001");
+    assert!(cpus.by_id(1).unwrap().is_none());
+
+    let virt = unsafe { DevTree::new(FDT) }.unwrap();
+    let cpus = virt.cpus().unwrap().unwrap();
+    assert_eq!(cpus.node().name().unwrap(), "cpus");
+    assert_eq!(cpus.iter().count().unwrap(), 1);
+}